@@ -3,13 +3,16 @@ extern crate criterion;
 
 use criterion::{black_box, Criterion};
 use rust_bert::gpt2::{
-    Gpt2ConfigResources, Gpt2MergesResources, Gpt2ModelResources, Gpt2VocabResources,
+    GPT2LMHeadModel, Gpt2Config, Gpt2ConfigResources, Gpt2MergesResources, Gpt2ModelResources,
+    Gpt2VocabResources,
 };
 use rust_bert::pipelines::common::{ModelResource, ModelType};
+use rust_bert::pipelines::generation_utils::Cache;
 use rust_bert::pipelines::text_generation::{TextGenerationConfig, TextGenerationModel};
-use rust_bert::resources::RemoteResource;
+use rust_bert::resources::{RemoteResource, ResourceProvider};
+use rust_bert::Config;
 use std::time::{Duration, Instant};
-use tch::Device;
+use tch::{nn, no_grad, Device, Kind, Tensor};
 
 fn create_text_generation_model() -> TextGenerationModel {
     let config = TextGenerationConfig {
@@ -52,6 +55,77 @@ fn generation_forward_pass(iters: u64, model: &TextGenerationModel, data: &[&str
     duration
 }
 
+fn create_gpt2_model() -> (GPT2LMHeadModel, nn::VarStore) {
+    let config_path = RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2)
+        .get_local_path()
+        .unwrap();
+    let weights_path = RemoteResource::from_pretrained(Gpt2ModelResources::GPT2)
+        .get_local_path()
+        .unwrap();
+    let config = Gpt2Config::from_file(config_path);
+    let mut vs = nn::VarStore::new(Device::cuda_if_available());
+    let model = GPT2LMHeadModel::new(vs.root(), &config);
+    vs.load(weights_path).unwrap();
+    (model, vs)
+}
+
+// Greedy decoding of `new_tokens` tokens, either feeding only the last token with the cached
+// keys and values of the previous positions or re-computing the full sequence at every step.
+fn incremental_decoding(
+    iters: u64,
+    model: &GPT2LMHeadModel,
+    prompt: &Tensor,
+    new_tokens: i64,
+    use_cache: bool,
+) -> Duration {
+    let mut duration = Duration::new(0, 0);
+    for _i in 0..iters {
+        let start = Instant::now();
+        no_grad(|| {
+            let mut input_ids = prompt.shallow_clone();
+            let mut next_input = prompt.shallow_clone();
+            let mut past = None;
+            for _ in 0..new_tokens {
+                let output = if use_cache {
+                    model.forward_t(
+                        Some(&next_input),
+                        past.as_ref(),
+                        None,
+                        None,
+                        None,
+                        None,
+                        false,
+                    )
+                } else {
+                    model.forward_t(Some(&input_ids), None, None, None, None, None, false)
+                }
+                .unwrap();
+                let next_token = output.lm_logits.select(1, -1).argmax(-1, true);
+                input_ids = Tensor::cat(&[&input_ids, &next_token], 1);
+                if let Cache::GPT2Cache(cache) = output.cache {
+                    past = cache;
+                }
+                next_input = next_token;
+            }
+        });
+        duration = duration.checked_add(start.elapsed()).unwrap();
+    }
+    duration
+}
+
+fn bench_kv_cache(c: &mut Criterion) {
+    let (model, vs) = create_gpt2_model();
+
+    //    Define input: 256 tokens prompt followed by 128 generated tokens
+    let prompt = Tensor::randint(50257, [1, 256], (Kind::Int64, vs.device()));
+    c.bench_function("Incremental decoding with cache", |b| {
+        b.iter_custom(|iters| black_box(incremental_decoding(iters, &model, &prompt, 128, true)))
+    });
+    c.bench_function("Incremental decoding without cache", |b| {
+        b.iter_custom(|iters| black_box(incremental_decoding(iters, &model, &prompt, 128, false)))
+    });
+}
+
 fn bench_generation(c: &mut Criterion) {
     let model = create_text_generation_model();
 
@@ -65,7 +139,7 @@ fn bench_generation(c: &mut Criterion) {
 criterion_group! {
 name = benches;
 config = Criterion::default().sample_size(10);
-targets = bench_generation
+targets = bench_generation, bench_kv_cache
 }
 
 criterion_main!(benches);
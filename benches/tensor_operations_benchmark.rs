@@ -16,6 +16,50 @@ fn matrix_multiply(iters: u64, input: &Tensor, weights: &Tensor) -> Duration {
     duration
 }
 
+fn repetition_penalty_loop(logits: &mut Tensor, prev_output_tokens: &Tensor, penalty: f64) {
+    for i in 0..prev_output_tokens.size()[0] {
+        for token_position in 0..prev_output_tokens.size()[1] {
+            let token = prev_output_tokens.int64_value(&[i, token_position]);
+            let value = logits.double_value(&[i, token]);
+            let updated_value = if value < 0f64 {
+                value * penalty
+            } else {
+                value / penalty
+            };
+            let _ = logits.get(i).index_fill_(
+                0,
+                &Tensor::from_slice(&[token]).to_device(logits.device()),
+                updated_value,
+            );
+        }
+    }
+}
+
+fn repetition_penalty_vectorized(logits: &mut Tensor, prev_output_tokens: &Tensor, penalty: f64) {
+    let previous_token_logits = logits.gather(1, prev_output_tokens, false);
+    let penalized_logits = (&previous_token_logits * penalty).where_self(
+        &previous_token_logits.lt(0.0),
+        &(&previous_token_logits / penalty),
+    );
+    let _ = logits.scatter_(1, prev_output_tokens, &penalized_logits);
+}
+
+fn repetition_penalty(
+    iters: u64,
+    logits: &Tensor,
+    prev_output_tokens: &Tensor,
+    penalty_fn: fn(&mut Tensor, &Tensor, f64),
+) -> Duration {
+    let mut duration = Duration::new(0, 0);
+    for _i in 0..iters {
+        let mut logits = logits.copy();
+        let start = Instant::now();
+        penalty_fn(&mut logits, prev_output_tokens, 1.2);
+        duration = duration.checked_add(start.elapsed()).unwrap();
+    }
+    duration
+}
+
 fn bench_tensor_ops(c: &mut Criterion) {
     let input = Tensor::rand([32, 128, 512], (Kind::Float, Device::cuda_if_available()));
     let weights = Tensor::rand([512, 512], (Kind::Float, Device::cuda_if_available()));
@@ -24,6 +68,30 @@ fn bench_tensor_ops(c: &mut Criterion) {
     c.bench_function("Matrix multiply ", |b| {
         b.iter_custom(|iters| black_box(matrix_multiply(iters, &input, &weights)))
     });
+
+    let logits = Tensor::randn([8, 50257], (Kind::Float, Device::cuda_if_available()));
+    let prev_output_tokens =
+        Tensor::randint(50257, [8, 512], (Kind::Int64, Device::cuda_if_available()));
+    c.bench_function("Repetition penalty (loop)", |b| {
+        b.iter_custom(|iters| {
+            black_box(repetition_penalty(
+                iters,
+                &logits,
+                &prev_output_tokens,
+                repetition_penalty_loop,
+            ))
+        })
+    });
+    c.bench_function("Repetition penalty (vectorized)", |b| {
+        b.iter_custom(|iters| {
+            black_box(repetition_penalty(
+                iters,
+                &logits,
+                &prev_output_tokens,
+                repetition_penalty_vectorized,
+            ))
+        })
+    });
 }
 
 criterion_group! {
@@ -0,0 +1,413 @@
+// Copyright 2021 EleutherAI, The HuggingFace Inc. team.
+// Copyright 2024 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use tch::{Device, Kind, Tensor};
+
+#[derive(Clone, Debug, Serialize, Deserialize, Copy, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+/// # Position encoding used by a transformer model
+pub enum PositionEncoding {
+    /// Fixed sinusoidal absolute position embeddings added to the token embeddings
+    Absolute,
+    /// Learned absolute position embeddings added to the token embeddings
+    Learned,
+    /// Rotary position embeddings applied to the queries and keys ([Su et al., 2021](https://arxiv.org/abs/2104.09864))
+    Rotary {
+        /// Base of the geometric progression of the rotation frequencies (10000 in most models)
+        base: f64,
+        /// Optional scaling of the rotation frequencies to extend the context beyond the training length
+        scaling: Option<RopeScaling>,
+    },
+    /// Linear biases added to the attention scores, proportional to the distance between the query and key positions ([Press et al., 2021](https://arxiv.org/abs/2108.12409))
+    #[serde(rename = "alibi")]
+    ALiBi,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Copy, PartialEq)]
+/// # YaRN scaling of rotary position embeddings
+/// Interpolates the low rotation frequencies while keeping the high frequencies unchanged, allowing
+/// a model to attend over sequences longer than its training context ([Peng et al., 2023](https://arxiv.org/abs/2309.00071)).
+pub struct RopeScaling {
+    /// Context extension factor
+    pub factor: f64,
+    /// Maximum sequence length used during training
+    pub original_max_position_embeddings: i64,
+}
+
+#[derive(Debug)]
+/// # Rotary position embeddings
+/// Rotates pairs of query and key features by an angle proportional to their position, so that
+/// attention scores only depend on the relative distance between tokens.
+pub struct RotaryEmbedding {
+    rotary_dim: i64,
+    inv_freq: Tensor,
+    attention_scaling: f64,
+}
+
+impl RotaryEmbedding {
+    /// Creates rotary embeddings applied to the first `rotary_dim` features of each attention head
+    ///
+    /// # Arguments
+    ///
+    /// * `rotary_dim` - Number of features rotated in each head (must be even)
+    /// * `base` - Base of the geometric progression of the rotation frequencies
+    /// * `scaling` - Optional YaRN scaling of the rotation frequencies
+    /// * `device` - Device on which the rotation frequencies are stored
+    pub fn new(
+        rotary_dim: i64,
+        base: f64,
+        scaling: Option<RopeScaling>,
+        device: Device,
+    ) -> RotaryEmbedding {
+        let pos_freqs = Tensor::pow_scalar(
+            base,
+            &(Tensor::arange_start_step(0, rotary_dim, 2, (Kind::Double, device))
+                / rotary_dim as f64),
+        );
+        let (inv_freq, attention_scaling) = match scaling {
+            Some(scaling) if scaling.factor > 1.0 => {
+                yarn_inv_freq(&pos_freqs, rotary_dim, base, scaling)
+            }
+            _ => (pos_freqs.reciprocal(), 1.0),
+        };
+        RotaryEmbedding {
+            rotary_dim,
+            inv_freq: inv_freq.to_kind(Kind::Float),
+            attention_scaling,
+        }
+    }
+
+    /// Applies the rotary embeddings to queries and keys of shape (*batch size*, *number of heads*, *sequence length*, *head dimension*)
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - Query tensor
+    /// * `key` - Key tensor
+    /// * `position_ids` - Positions of the tokens, of shape (*batch size*, *sequence length*) or (*sequence length*)
+    ///
+    /// # Returns
+    ///
+    /// * `(Tensor, Tensor)` - Rotated query and key tensors
+    pub fn apply_rotary_embeddings(
+        &self,
+        query: &Tensor,
+        key: &Tensor,
+        position_ids: &Tensor,
+    ) -> (Tensor, Tensor) {
+        let (sin, cos) = self.sin_cos(position_ids, query.kind());
+        (
+            apply_rotation(query, &sin, &cos, self.rotary_dim),
+            apply_rotation(key, &sin, &cos, self.rotary_dim),
+        )
+    }
+
+    fn sin_cos(&self, position_ids: &Tensor, kind: Kind) -> (Tensor, Tensor) {
+        let position_ids = if position_ids.dim() == 1 {
+            position_ids.unsqueeze(0)
+        } else {
+            position_ids.shallow_clone()
+        };
+        // (batch size, sequence length, rotary_dim / 2)
+        let freqs = position_ids
+            .to_kind(Kind::Float)
+            .unsqueeze(-1)
+            .to_device(self.inv_freq.device())
+            * &self.inv_freq;
+        // (batch size, 1, sequence length, rotary_dim), broadcast over the heads
+        let emb = Tensor::cat(&[&freqs, &freqs], -1).unsqueeze(1);
+        (
+            (emb.sin() * self.attention_scaling).to_kind(kind),
+            (emb.cos() * self.attention_scaling).to_kind(kind),
+        )
+    }
+}
+
+fn rotate_half(x: &Tensor) -> Tensor {
+    let half_dim = x.size()[x.dim() - 1] / 2;
+    let x1 = x.narrow(-1, 0, half_dim);
+    let x2 = x.narrow(-1, half_dim, half_dim);
+    Tensor::cat(&[-x2, x1], -1)
+}
+
+fn apply_rotation(x: &Tensor, sin: &Tensor, cos: &Tensor, rotary_dim: i64) -> Tensor {
+    let head_dim = x.size()[x.dim() - 1];
+    if rotary_dim < head_dim {
+        let x_rot = x.narrow(-1, 0, rotary_dim);
+        let x_pass = x.narrow(-1, rotary_dim, head_dim - rotary_dim);
+        let x_rot = &x_rot * cos + rotate_half(&x_rot) * sin;
+        Tensor::cat(&[x_rot, x_pass], -1)
+    } else {
+        x * cos + rotate_half(x) * sin
+    }
+}
+
+/// Computes the ALiBi head-specific slopes, a geometric sequence starting at `2^(-8 / num_heads)`.
+/// For a number of heads that is not a power of 2, the slopes of the closest lower power of 2 are
+/// completed with interleaved slopes of the next power of 2.
+///
+/// # Arguments
+///
+/// * `num_heads` - Number of attention heads
+///
+/// # Returns
+///
+/// * `Tensor` - Slopes of shape (*num_heads*)
+pub fn compute_alibi_slopes(num_heads: i64) -> Tensor {
+    let power_of_2_slopes = |n: i64| -> Vec<f64> {
+        let start = 2f64.powf(-(2f64.powf(-((n as f64).log2() - 3.0))));
+        (0..n).map(|i| start * start.powi(i as i32)).collect()
+    };
+    let closest_power_of_2 = 2i64.pow((num_heads as f64).log2().floor() as u32);
+    let mut slopes = power_of_2_slopes(closest_power_of_2);
+    if closest_power_of_2 < num_heads {
+        slopes.extend(
+            power_of_2_slopes(2 * closest_power_of_2)
+                .into_iter()
+                .step_by(2)
+                .take((num_heads - closest_power_of_2) as usize),
+        );
+    }
+    Tensor::from_slice(&slopes).to_kind(Kind::Float)
+}
+
+/// Computes the ALiBi bias for bidirectional attention, equal to `-slope * |i - j|` for a query at
+/// position `i` and a key at position `j`.
+///
+/// # Arguments
+///
+/// * `slopes` - Head-specific slopes of shape (*num_heads*)
+/// * `query_length` - Number of query positions
+/// * `key_length` - Number of key positions
+///
+/// # Returns
+///
+/// * `Tensor` - Bias of shape (*num_heads*, *query_length*, *key_length*)
+pub fn compute_alibi_bias(slopes: &Tensor, query_length: i64, key_length: i64) -> Tensor {
+    let device = slopes.device();
+    let relative_distance = Tensor::arange(key_length, (Kind::Int64, device)).unsqueeze(0)
+        - Tensor::arange(query_length, (Kind::Int64, device)).unsqueeze(1);
+    -relative_distance.abs().to_kind(slopes.kind()).unsqueeze(0) * slopes.view([-1, 1, 1])
+}
+
+/// Adds the ALiBi bias to attention scores of shape (*batch size*, *num_heads*, *query_length*, *key_length*)
+///
+/// # Arguments
+///
+/// * `attention_scores` - Attention scores, updated in place
+/// * `slopes` - Head-specific slopes of shape (*num_heads*)
+pub fn apply_alibi_bias(attention_scores: &mut Tensor, slopes: &Tensor) {
+    let size = attention_scores.size();
+    let bias = compute_alibi_bias(
+        &slopes.to_device(attention_scores.device()),
+        size[size.len() - 2],
+        size[size.len() - 1],
+    );
+    *attention_scores += bias.to_kind(attention_scores.kind());
+}
+
+/// Repeats the key or value heads for grouped query attention, where each key/value head is shared
+/// by `n_rep` consecutive query heads (equivalent to `repeat_interleave` along the heads dimension).
+///
+/// # Arguments
+///
+/// * `hidden_states` - Key or value states of shape (*batch size*, *num_key_value_heads*, *sequence_length*, *head_dim*)
+/// * `n_rep` - Number of query heads per key/value head
+///
+/// # Returns
+///
+/// * `Tensor` - States of shape (*batch size*, *num_key_value_heads x n_rep*, *sequence_length*, *head_dim*)
+pub fn repeat_kv(hidden_states: &Tensor, n_rep: i64) -> Tensor {
+    if n_rep == 1 {
+        return hidden_states.shallow_clone();
+    }
+    let size = hidden_states.size();
+    let (batch_size, num_key_value_heads, sequence_length, head_dim) =
+        (size[0], size[1], size[2], size[3]);
+    hidden_states
+        .unsqueeze(2)
+        .expand(
+            [
+                batch_size,
+                num_key_value_heads,
+                n_rep,
+                sequence_length,
+                head_dim,
+            ],
+            false,
+        )
+        .reshape([
+            batch_size,
+            num_key_value_heads * n_rep,
+            sequence_length,
+            head_dim,
+        ])
+}
+
+/// Computes the YaRN rotation frequencies, blending interpolated (scaled) frequencies for the
+/// dimensions rotating slowly with respect to the training context and extrapolated (original)
+/// frequencies for the fast-rotating dimensions. Returns the frequencies and the attention scaling.
+fn yarn_inv_freq(
+    pos_freqs: &Tensor,
+    rotary_dim: i64,
+    base: f64,
+    scaling: RopeScaling,
+) -> (Tensor, f64) {
+    let (beta_fast, beta_slow) = (32f64, 1f64);
+    let correction_dim = |num_rotations: f64| {
+        (rotary_dim as f64
+            * (scaling.original_max_position_embeddings as f64 / (num_rotations * 2.0 * PI)).ln())
+            / (2.0 * base.ln())
+    };
+    let low = correction_dim(beta_fast).floor().max(0.0);
+    let high = correction_dim(beta_slow)
+        .ceil()
+        .min((rotary_dim - 1) as f64);
+    let high = if low == high { high + 0.001 } else { high };
+
+    let ramp = ((Tensor::arange(rotary_dim / 2, (Kind::Double, pos_freqs.device())) - low)
+        / (high - low))
+        .clamp(0.0, 1.0);
+    let extrapolation_factor = 1.0 - ramp;
+    let inv_freq_extrapolation = pos_freqs.reciprocal();
+    let inv_freq_interpolation = (pos_freqs * scaling.factor).reciprocal();
+    let inv_freq = &inv_freq_interpolation * (1.0 - &extrapolation_factor)
+        + &inv_freq_extrapolation * &extrapolation_factor;
+    (inv_freq, 0.1 * scaling.factor.ln() + 1.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rotary_embeddings_relative_scores() {
+        let rotary_embedding = RotaryEmbedding::new(8, 10000.0, None, Device::Cpu);
+        let query = Tensor::randn([1, 1, 1, 8], (Kind::Float, Device::Cpu));
+        let key = Tensor::randn([1, 1, 1, 8], (Kind::Float, Device::Cpu));
+
+        // The attention score only depends on the distance between the query and key positions
+        let score = |query_position: i64, key_position: i64| {
+            let (rotated_query, _) = rotary_embedding.apply_rotary_embeddings(
+                &query,
+                &key,
+                &Tensor::from_slice(&[query_position]),
+            );
+            let (_, rotated_key) = rotary_embedding.apply_rotary_embeddings(
+                &query,
+                &key,
+                &Tensor::from_slice(&[key_position]),
+            );
+            (rotated_query * rotated_key)
+                .sum(Kind::Float)
+                .double_value(&[])
+        };
+        assert!((score(3, 1) - score(7, 5)).abs() < 1e-4);
+        assert!((score(0, 0) - (&query * &key).sum(Kind::Float).double_value(&[])).abs() < 1e-4);
+    }
+
+    #[test]
+    fn rotary_embeddings_partial_rotation() {
+        let rotary_embedding = RotaryEmbedding::new(4, 10000.0, None, Device::Cpu);
+        let query = Tensor::randn([2, 3, 5, 8], (Kind::Float, Device::Cpu));
+        let position_ids = Tensor::arange(5, (Kind::Int64, Device::Cpu));
+
+        let (rotated_query, rotated_key) =
+            rotary_embedding.apply_rotary_embeddings(&query, &query, &position_ids);
+
+        assert_eq!(rotated_query.size(), query.size());
+        assert!(rotated_query.allclose(&rotated_key, 1e-6, 1e-6, false));
+        // Features beyond the rotary dimension are left unchanged
+        assert!(rotated_query.narrow(-1, 4, 4).allclose(
+            &query.narrow(-1, 4, 4),
+            1e-6,
+            1e-6,
+            false
+        ));
+    }
+
+    #[test]
+    fn yarn_scaling_interpolates_low_frequencies() {
+        let scaling = RopeScaling {
+            factor: 4.0,
+            original_max_position_embeddings: 2048,
+        };
+        let reference = RotaryEmbedding::new(64, 10000.0, None, Device::Cpu);
+        let scaled = RotaryEmbedding::new(64, 10000.0, Some(scaling), Device::Cpu);
+
+        let highest_frequency_ratio =
+            scaled.inv_freq.double_value(&[0]) / reference.inv_freq.double_value(&[0]);
+        let lowest_frequency_ratio =
+            scaled.inv_freq.double_value(&[31]) / reference.inv_freq.double_value(&[31]);
+        assert!((highest_frequency_ratio - 1.0).abs() < 1e-6);
+        assert!((lowest_frequency_ratio - 0.25).abs() < 1e-6);
+        assert!(scaled.attention_scaling > 1.0);
+    }
+
+    #[test]
+    fn repeat_kv_shares_heads() {
+        let key = Tensor::randn([2, 2, 5, 8], (Kind::Float, Device::Cpu));
+
+        let repeated_key = repeat_kv(&key, 3);
+
+        assert_eq!(repeated_key.size(), &[2, 6, 5, 8]);
+        assert!(repeated_key.equal(&key.repeat_interleave_self_int(3, 1, None)));
+        assert!(repeated_key.get(1).get(4).equal(&key.get(1).get(1)));
+        assert!(repeat_kv(&key, 1).equal(&key));
+    }
+
+    #[test]
+    fn alibi_slopes() {
+        let slopes = compute_alibi_slopes(8);
+        let expected_slopes = Tensor::from_slice(&[
+            0.5f32, 0.25, 0.125, 0.0625, 0.03125, 0.015625, 0.0078125, 0.00390625,
+        ]);
+        assert!(slopes.allclose(&expected_slopes, 1e-6, 1e-6, false));
+
+        // Non power of 2: slopes for 4 heads, completed with the odd slopes for 8 heads
+        let slopes = compute_alibi_slopes(6);
+        let expected_slopes = Tensor::from_slice(&[
+            0.25f32,
+            0.0625,
+            0.015625,
+            0.00390625,
+            2f32.powf(-1.0),
+            2f32.powf(-3.0),
+        ]);
+        assert!(slopes.allclose(&expected_slopes, 1e-6, 1e-6, false));
+    }
+
+    #[test]
+    fn alibi_bias_decreases_with_distance() {
+        let slopes = compute_alibi_slopes(4);
+        let mut attention_scores = Tensor::zeros([1, 4, 6, 6], (Kind::Float, Device::Cpu));
+        apply_alibi_bias(&mut attention_scores, &slopes);
+
+        for head in 0..4 {
+            let query_position = 2;
+            let scores = attention_scores.get(0).get(head).get(query_position);
+            assert_eq!(scores.double_value(&[query_position]), 0.0);
+            for distance in 1..4 {
+                let score = scores.double_value(&[query_position + distance]);
+                let closer_score = scores.double_value(&[query_position + distance - 1]);
+                assert!(score < closer_score);
+            }
+            for distance in 1..3 {
+                assert_eq!(
+                    scores.double_value(&[query_position - distance]),
+                    scores.double_value(&[query_position + distance])
+                );
+            }
+        }
+    }
+}
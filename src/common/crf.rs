@@ -0,0 +1,265 @@
+// Copyright 2024 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Linear-chain Conditional Random Field
+//! Structured output layer for token classification ([Lafferty et al., 2001](https://repository.upenn.edu/cis_papers/159/)).
+//! The score of a tag sequence is the sum of the emission scores of its tags (the logits of the token classifier) and
+//! of learned transition scores between consecutive tags (and from the start / to the end of the sequence). Decoding
+//! the highest scoring sequence with the Viterbi algorithm avoids invalid label sequences (e.g. an `I-PER` tag
+//! following a `B-LOC` tag) predicted by independent per-token argmax.
+//!
+//! The emissions have shape `(batch size, sequence length, number of tags)`. The mask (shape
+//! `(batch size, sequence length)`) flags the valid positions of every sequence: padding is expected at the end
+//! of the sequences and the first position of every sequence must be valid.
+//!
+//! ```no_run
+//! use rust_bert::crf::CRFLayer;
+//! use tch::{nn, Device, Kind, Tensor};
+//!
+//! let vs = nn::VarStore::new(Device::Cpu);
+//! let crf = CRFLayer::new(vs.root(), 9);
+//!
+//! let emissions = Tensor::rand([2, 12, 9], (Kind::Float, Device::Cpu));
+//! let mask = Tensor::ones([2, 12], (Kind::Int64, Device::Cpu));
+//! let tags = Tensor::zeros([2, 12], (Kind::Int64, Device::Cpu));
+//!
+//! let loss = crf.neg_log_likelihood(&emissions, &tags, &mask);
+//! let (best_paths, best_scores) = crf.viterbi_decode(&emissions, &mask);
+//! ```
+
+use std::borrow::Borrow;
+use tch::nn::{Init, Path};
+use tch::{Device, Kind, Tensor};
+
+/// # Linear-chain CRF layer
+/// Holds the transition scores between tags: `transitions[i][j]` is the score of tag `j` following tag `i`,
+/// `start_transitions` and `end_transitions` the scores of the first and last tags of a sequence.
+#[derive(Debug)]
+pub struct CRFLayer {
+    pub transitions: Tensor,
+    pub start_transitions: Tensor,
+    pub end_transitions: Tensor,
+    pub num_tags: i64,
+}
+
+impl CRFLayer {
+    /// Creates a new CRF layer for `num_tags` tags, with transition scores initialized uniformly in `[-0.1, 0.1]`.
+    /// The variables are stored at `p.transitions`, `p.start_transitions` and `p.end_transitions`.
+    pub fn new<'p, P>(p: P, num_tags: i64) -> CRFLayer
+    where
+        P: Borrow<Path<'p>>,
+    {
+        let p = p.borrow();
+        let init = Init::Uniform { lo: -0.1, up: 0.1 };
+        let transitions = p.var("transitions", &[num_tags, num_tags], init);
+        let start_transitions = p.var("start_transitions", &[num_tags], init);
+        let end_transitions = p.var("end_transitions", &[num_tags], init);
+        CRFLayer {
+            transitions,
+            start_transitions,
+            end_transitions,
+            num_tags,
+        }
+    }
+
+    /// Decodes the highest scoring tag sequences with the Viterbi algorithm.
+    ///
+    /// # Arguments
+    ///
+    /// * `emissions` - Emission scores of shape (*batch size*, *sequence_length*, *num_tags*)
+    /// * `mask` - Mask of the valid positions of shape (*batch size*, *sequence_length*)
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<Vec<i64>>` tags of the best sequence, with one tag per valid position of every input
+    /// * `Tensor` scores of the best sequences of shape (*batch size*)
+    pub fn viterbi_decode(&self, emissions: &Tensor, mask: &Tensor) -> (Vec<Vec<i64>>, Tensor) {
+        let mask = mask.to_kind(Kind::Bool);
+        let sequence_length = emissions.size()[1];
+
+        let mut score = &self.start_transitions + emissions.select(1, 0);
+        let mut history = Vec::with_capacity(sequence_length.max(1) as usize - 1);
+        for position in 1..sequence_length {
+            let next_score =
+                score.unsqueeze(2) + &self.transitions + emissions.select(1, position).unsqueeze(1);
+            let (next_score, best_previous_tags) = next_score.max_dim(1, false);
+            score = next_score.where_self(&mask.select(1, position).unsqueeze(1), &score);
+            history.push(best_previous_tags.to_device(Device::Cpu));
+        }
+        score += &self.end_transitions;
+        let (best_scores, best_last_tags) = score.max_dim(1, false);
+
+        let sequence_lengths = mask
+            .sum_dim_intlist([1].as_slice(), false, Kind::Int64)
+            .to_device(Device::Cpu);
+        let best_last_tags = best_last_tags.to_device(Device::Cpu);
+        let best_paths = (0..emissions.size()[0])
+            .map(|batch_index| {
+                let length = sequence_lengths.int64_value(&[batch_index]);
+                let mut best_tag = best_last_tags.int64_value(&[batch_index]);
+                let mut best_path = vec![best_tag];
+                for best_previous_tags in history[..(length - 1) as usize].iter().rev() {
+                    best_tag = best_previous_tags.int64_value(&[batch_index, best_tag]);
+                    best_path.push(best_tag);
+                }
+                best_path.reverse();
+                best_path
+            })
+            .collect();
+        (best_paths, best_scores)
+    }
+
+    /// Computes the negative log-likelihood of the gold tag sequences, averaged over the batch. This is the
+    /// training loss of the layer: the log-partition function (computed with the forward algorithm) minus the
+    /// score of the gold sequences.
+    ///
+    /// # Arguments
+    ///
+    /// * `emissions` - Emission scores of shape (*batch size*, *sequence_length*, *num_tags*)
+    /// * `tags` - Gold tags of shape (*batch size*, *sequence_length*). The tags of the masked positions are ignored.
+    /// * `mask` - Mask of the valid positions of shape (*batch size*, *sequence_length*)
+    ///
+    /// # Returns
+    ///
+    /// * `Tensor` scalar negative log-likelihood
+    pub fn neg_log_likelihood(&self, emissions: &Tensor, tags: &Tensor, mask: &Tensor) -> Tensor {
+        let gold_scores = self.sequence_scores(emissions, tags, mask);
+        let log_partition = self.log_partition(emissions, mask);
+        (log_partition - gold_scores).mean(Kind::Float)
+    }
+
+    /// Scores of the tag sequences `tags`, of shape (*batch size*)
+    fn sequence_scores(&self, emissions: &Tensor, tags: &Tensor, mask: &Tensor) -> Tensor {
+        let float_mask = mask.to_kind(emissions.kind());
+        let tags = tags.masked_fill(&mask.to_kind(Kind::Bool).logical_not(), 0);
+        let sequence_length = emissions.size()[1];
+        let emission_score = |position: i64| {
+            emissions
+                .select(1, position)
+                .gather(1, &tags.select(1, position).unsqueeze(1), false)
+                .squeeze_dim(1)
+        };
+
+        let mut score =
+            self.start_transitions.index_select(0, &tags.select(1, 0)) + emission_score(0);
+        for position in 1..sequence_length {
+            let transition_score = self
+                .transitions
+                .index_select(0, &tags.select(1, position - 1))
+                .gather(1, &tags.select(1, position).unsqueeze(1), false)
+                .squeeze_dim(1);
+            score += (transition_score + emission_score(position)) * float_mask.select(1, position);
+        }
+        let last_positions = mask.sum_dim_intlist([1].as_slice(), true, Kind::Int64) - 1;
+        let last_tags = tags.gather(1, &last_positions, false).squeeze_dim(1);
+        score + self.end_transitions.index_select(0, &last_tags)
+    }
+
+    /// Log-sum-exp of the scores of all possible tag sequences, of shape (*batch size*)
+    fn log_partition(&self, emissions: &Tensor, mask: &Tensor) -> Tensor {
+        let mask = mask.to_kind(Kind::Bool);
+        let sequence_length = emissions.size()[1];
+
+        let mut score = &self.start_transitions + emissions.select(1, 0);
+        for position in 1..sequence_length {
+            let next_score = (score.unsqueeze(2)
+                + &self.transitions
+                + emissions.select(1, position).unsqueeze(1))
+            .logsumexp([1].as_slice(), false);
+            score = next_score.where_self(&mask.select(1, position).unsqueeze(1), &score);
+        }
+        (score + &self.end_transitions).logsumexp([1].as_slice(), false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tch::nn::VarStore;
+
+    /// Scores of all the tag sequences of a single (unpadded) sequence, indexed by the sequence in base `num_tags`
+    fn all_sequence_scores(crf: &CRFLayer, emissions: &Tensor) -> Vec<(Vec<i64>, f64)> {
+        let (sequence_length, num_tags) = (emissions.size()[0], crf.num_tags);
+        (0..num_tags.pow(sequence_length as u32))
+            .map(|index| {
+                let tags = (0..sequence_length)
+                    .map(|position| index / num_tags.pow(position as u32) % num_tags)
+                    .collect::<Vec<i64>>();
+                let mut score = crf.start_transitions.double_value(&[tags[0]])
+                    + crf.end_transitions.double_value(&[tags[tags.len() - 1]]);
+                for (position, tag) in tags.iter().enumerate() {
+                    score += emissions.double_value(&[position as i64, *tag]);
+                    if position > 0 {
+                        score += crf.transitions.double_value(&[tags[position - 1], *tag]);
+                    }
+                }
+                (tags, score)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn viterbi_decoding_matches_exhaustive_search() {
+        tch::manual_seed(42);
+        let vs = VarStore::new(Device::Cpu);
+        let crf = CRFLayer::new(vs.root(), 3);
+        let emissions = Tensor::randn([2, 4, 3], (Kind::Float, Device::Cpu));
+        // The second sequence is padded after its third position
+        let mask = Tensor::from_slice2(&[[1i64, 1, 1, 1], [1, 1, 1, 0]]);
+
+        let (best_paths, best_scores) = crf.viterbi_decode(&emissions, &mask);
+        for (batch_index, length) in [(0, 4), (1, 3)] {
+            let (expected_path, expected_score) =
+                all_sequence_scores(&crf, &emissions.get(batch_index).narrow(0, 0, length))
+                    .into_iter()
+                    .max_by(|(_, score_1), (_, score_2)| score_1.total_cmp(score_2))
+                    .unwrap();
+            assert_eq!(best_paths[batch_index as usize], expected_path);
+            assert!((best_scores.double_value(&[batch_index]) - expected_score).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn negative_log_likelihood_matches_exhaustive_partition() {
+        tch::manual_seed(42);
+        let vs = VarStore::new(Device::Cpu);
+        let crf = CRFLayer::new(vs.root(), 3);
+        let emissions = Tensor::randn([1, 4, 3], (Kind::Float, Device::Cpu));
+        let tags = Tensor::from_slice2(&[[0i64, 2, 1, 1]]);
+        let mask = Tensor::ones([1, 4], (Kind::Int64, Device::Cpu));
+
+        let scores = all_sequence_scores(&crf, &emissions.get(0));
+        let log_partition = scores
+            .iter()
+            .map(|(_, score)| score.exp())
+            .sum::<f64>()
+            .ln();
+        let gold_score = scores
+            .iter()
+            .find(|(path, _)| path == &vec![0, 2, 1, 1])
+            .unwrap()
+            .1;
+
+        let nll = crf.neg_log_likelihood(&emissions, &tags, &mask);
+        assert!((nll.double_value(&[]) - (log_partition - gold_score)).abs() < 1e-4);
+
+        // The loss is differentiable with respect to the transition scores
+        nll.backward();
+        assert!(
+            crf.transitions
+                .grad()
+                .abs()
+                .sum(Kind::Float)
+                .double_value(&[])
+                > 0.0
+        );
+    }
+}
@@ -0,0 +1,299 @@
+// Copyright 2024 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Low-Rank Adaptation (LoRA)
+//! Implementation of the adapters of [Hu et al., 2021](https://arxiv.org/abs/2106.09685) for parameter-efficient
+//! fine-tuning. A `LoraLinear` layer adds a trainable rank-decomposition `B * A` (of rank `r`) to a frozen linear
+//! layer: the adapted weight is `W + alpha / r * B * A`. `B` is initialized with zeros, so that the adapted layer is
+//! initially equal to the base layer.
+//!
+//! The variables of a layer created at path `p` follow the naming of the base linear layer (`p.weight` and `p.bias`),
+//! the adapters being stored as `p.lora_a.weight` and `p.lora_b.weight`: base model checkpoints can be loaded in
+//! models using `LoraLinear` layers. For deployment, the adapters are folded into the base weights with
+//! `LoraLinear::merge` or, for all layers of a `VarStore`, with `merge_lora_weights`.
+//!
+//! ```no_run
+//! # fn main() -> anyhow::Result<()> {
+//! use rust_bert::lora::{merge_lora_weights, LoraConfig, LoraLinear};
+//! use tch::nn::{Module, OptimizerConfig};
+//! use tch::{nn, Device, Kind, Tensor};
+//!
+//! let vs = nn::VarStore::new(Device::Cpu);
+//! let lora_config = LoraConfig {
+//!     rank: 8,
+//!     alpha: 16.0,
+//! };
+//! let layer = LoraLinear::new(vs.root() / "query", 768, 768, &lora_config);
+//!
+//! // Only the adapters are updated by the optimizer
+//! let mut optimizer = nn::Adam::default().build(&vs, 1e-4)?;
+//! let input = Tensor::rand([4, 768], (Kind::Float, Device::Cpu));
+//! let loss = layer.forward(&input).pow_tensor_scalar(2).mean(Kind::Float);
+//! optimizer.backward_step(&loss);
+//!
+//! merge_lora_weights(&vs, &lora_config)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::RustBertError;
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use tch::nn::{Init, LinearConfig, Module, Path, VarStore};
+use tch::{nn, no_grad, Tensor};
+
+/// # LoRA adapters configuration
+/// Set as the `lora` field of the BERT and DistilBERT configurations to add adapters to the query and value
+/// projections of the self-attention layers.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LoraConfig {
+    /// Rank `r` of the adapters
+    pub rank: i64,
+    /// Scaling numerator: the adapters output is scaled by `alpha / r`
+    pub alpha: f64,
+}
+
+impl LoraConfig {
+    /// Scaling factor of the adapters output (`alpha / r`)
+    pub fn scaling(&self) -> f64 {
+        self.alpha / self.rank as f64
+    }
+}
+
+/// # Linear layer with LoRA adapters
+/// Frozen linear layer with trainable low-rank adapters. The adapters can be disabled (e.g. to compare
+/// the outputs of the base and adapted layers) by setting `enabled` to false.
+#[derive(Debug)]
+pub struct LoraLinear {
+    pub base: nn::Linear,
+    pub lora_a: nn::Linear,
+    pub lora_b: nn::Linear,
+    pub scaling: f64,
+    pub enabled: bool,
+}
+
+impl LoraLinear {
+    /// Creates a new linear layer with adapters at path `p`. The weights of the base layer do not require
+    /// gradients: they are not updated during the fine-tuning (unless unfrozen with `VarStore::unfreeze`).
+    pub fn new<'p, P>(p: P, in_dim: i64, out_dim: i64, config: &LoraConfig) -> LoraLinear
+    where
+        P: Borrow<Path<'p>>,
+    {
+        let p = p.borrow();
+        let base = nn::linear(p, in_dim, out_dim, Default::default());
+        let _ = base.ws.set_requires_grad(false);
+        if let Some(bs) = &base.bs {
+            let _ = bs.set_requires_grad(false);
+        }
+        let lora_a = nn::linear(
+            p / "lora_a",
+            in_dim,
+            config.rank,
+            LinearConfig {
+                bias: false,
+                ..Default::default()
+            },
+        );
+        let lora_b = nn::linear(
+            p / "lora_b",
+            config.rank,
+            out_dim,
+            LinearConfig {
+                ws_init: Init::Const(0.),
+                bias: false,
+                ..Default::default()
+            },
+        );
+        LoraLinear {
+            base,
+            lora_a,
+            lora_b,
+            scaling: config.scaling(),
+            enabled: true,
+        }
+    }
+
+    /// Folds the adapters into the base weights and disables them. The output of the layer is unchanged
+    /// and no longer requires the adapters computation.
+    pub fn merge(&mut self) {
+        if self.enabled {
+            fold_adapter(
+                &self.base.ws,
+                &self.lora_a.ws,
+                &self.lora_b.ws,
+                self.scaling,
+            );
+            self.enabled = false;
+        }
+    }
+}
+
+impl Module for LoraLinear {
+    fn forward(&self, xs: &Tensor) -> Tensor {
+        let output = self.base.forward(xs);
+        if self.enabled {
+            output + self.lora_b.forward(&self.lora_a.forward(xs)) * self.scaling
+        } else {
+            output
+        }
+    }
+}
+
+/// Linear projection of a model layer, with LoRA adapters if the model configuration enables them
+#[derive(Debug)]
+pub(crate) enum LoraOrLinear {
+    Linear(nn::Linear),
+    Lora(LoraLinear),
+}
+
+impl LoraOrLinear {
+    pub(crate) fn new<'p, P>(
+        p: P,
+        in_dim: i64,
+        out_dim: i64,
+        config: Option<&LoraConfig>,
+    ) -> LoraOrLinear
+    where
+        P: Borrow<Path<'p>>,
+    {
+        match config {
+            Some(config) => LoraOrLinear::Lora(LoraLinear::new(p, in_dim, out_dim, config)),
+            None => LoraOrLinear::Linear(nn::linear(p, in_dim, out_dim, Default::default())),
+        }
+    }
+}
+
+impl Module for LoraOrLinear {
+    fn forward(&self, xs: &Tensor) -> Tensor {
+        match self {
+            LoraOrLinear::Linear(linear) => linear.forward(xs),
+            LoraOrLinear::Lora(lora_linear) => lora_linear.forward(xs),
+        }
+    }
+}
+
+/// Adds `scaling * B * A` to the base weight and resets `B` to zero, so that the adapter no longer contributes
+fn fold_adapter(base_weight: &Tensor, lora_a: &Tensor, lora_b: &Tensor, scaling: f64) {
+    no_grad(|| {
+        let mut base_weight = base_weight.shallow_clone();
+        let mut lora_b = lora_b.shallow_clone();
+        base_weight += lora_b.matmul(lora_a) * scaling;
+        let _ = lora_b.zero_();
+    });
+}
+
+/// Folds the adapters of all the `LoraLinear` layers of a `VarStore` into their base weights, for deployment.
+/// The adapters of a layer (`p.lora_a.weight` and `p.lora_b.weight`) are added to its base weight (`p.weight`)
+/// and `p.lora_b.weight` is reset to zero, so that the outputs of the layers are unchanged. Returns the number
+/// of merged layers.
+///
+/// # Arguments
+///
+/// * `vs` - `VarStore` holding the base weights and adapters
+/// * `config` - `LoraConfig` used to create the adapters
+pub fn merge_lora_weights(vs: &VarStore, config: &LoraConfig) -> Result<usize, RustBertError> {
+    let variables = vs.variables();
+    let mut num_merged_layers = 0;
+    for (name, lora_a) in &variables {
+        let prefix = match name.strip_suffix(".lora_a.weight") {
+            Some(prefix) => prefix,
+            None => continue,
+        };
+        let (lora_b, base_weight) = match (
+            variables.get(&format!("{prefix}.lora_b.weight")),
+            variables.get(&format!("{prefix}.weight")),
+        ) {
+            (Some(lora_b), Some(base_weight)) => (lora_b, base_weight),
+            _ => {
+                return Err(RustBertError::ValueError(format!(
+                    "Missing LoRA adapter or base weight for {prefix}"
+                )));
+            }
+        };
+        fold_adapter(base_weight, lora_a, lora_b, config.scaling());
+        num_merged_layers += 1;
+    }
+    Ok(num_merged_layers)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tch::{Device, Kind};
+
+    #[test]
+    fn lora_linear_forward_and_merge() {
+        let vs = VarStore::new(Device::Cpu);
+        let config = LoraConfig {
+            rank: 2,
+            alpha: 4.0,
+        };
+        let mut layer = LoraLinear::new(vs.root() / "dense", 6, 3, &config);
+        let input = Tensor::rand([4, 6], (Kind::Float, Device::Cpu));
+
+        // The adapted layer is initially equal to the base layer
+        assert!(layer.forward(&input).equal(&layer.base.forward(&input)));
+        assert!(!layer.base.ws.requires_grad());
+        assert!(layer.lora_a.ws.requires_grad() & layer.lora_b.ws.requires_grad());
+
+        no_grad(|| {
+            let _ = layer
+                .lora_b
+                .ws
+                .copy_(&Tensor::rand([3, 2], (Kind::Float, Device::Cpu)));
+        });
+        let adapted_weight = &layer.base.ws + layer.lora_b.ws.matmul(&layer.lora_a.ws) * 2.0;
+        let expected_output = input.matmul(&adapted_weight.tr()) + layer.base.bs.as_ref().unwrap();
+        let output = layer.forward(&input);
+        assert!(output.allclose(&expected_output, 1e-5, 1e-6, false));
+
+        layer.merge();
+        assert!(!layer.enabled);
+        assert!(layer.base.ws.allclose(&adapted_weight, 1e-5, 1e-6, false));
+        assert!(layer
+            .forward(&input)
+            .allclose(&expected_output, 1e-5, 1e-6, false));
+    }
+
+    #[test]
+    fn merge_var_store_lora_weights() -> anyhow::Result<()> {
+        let vs = VarStore::new(Device::Cpu);
+        let config = LoraConfig {
+            rank: 2,
+            alpha: 2.0,
+        };
+        let layers = ["query", "value"]
+            .iter()
+            .map(|name| LoraLinear::new(vs.root() / "attention" / *name, 4, 4, &config))
+            .collect::<Vec<LoraLinear>>();
+        let input = Tensor::rand([3, 4], (Kind::Float, Device::Cpu));
+        for layer in &layers {
+            let _ = no_grad(|| layer.lora_b.ws.shallow_clone().fill_(0.5));
+        }
+        let expected_outputs = layers
+            .iter()
+            .map(|layer| layer.forward(&input))
+            .collect::<Vec<Tensor>>();
+
+        assert_eq!(merge_lora_weights(&vs, &config)?, 2);
+        for (layer, expected_output) in layers.iter().zip(expected_outputs) {
+            // The adapters are folded in the base weights and no longer contribute
+            assert!(layer
+                .base
+                .forward(&input)
+                .allclose(&expected_output, 1e-5, 1e-6, false));
+            assert!(layer
+                .forward(&input)
+                .allclose(&expected_output, 1e-5, 1e-6, false));
+        }
+        Ok(())
+    }
+}
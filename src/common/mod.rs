@@ -1,12 +1,20 @@
 pub(crate) mod activations;
+pub(crate) mod attention_utils;
 pub mod config;
+pub mod crf;
 pub(crate) mod dropout;
 pub(crate) mod embeddings;
 pub mod error;
 pub(crate) mod kind;
 pub(crate) mod linear;
+pub mod lora;
+pub(crate) mod moe;
+pub(crate) mod normalization;
 pub mod resources;
 pub(crate) mod summary;
+pub(crate) mod weights;
 
 pub use activations::Activation;
+pub use attention_utils::{PositionEncoding, RopeScaling};
 pub use config::Config;
+pub use normalization::NormType;
@@ -0,0 +1,194 @@
+// Copyright 2024 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Borrow;
+use tch::nn::{LinearConfig, ModuleT};
+use tch::{nn, Kind, Tensor};
+
+#[derive(Debug)]
+/// # Mixture-of-experts feed-forward network
+/// Sparse feed-forward layer made of `num_experts` parallel feed-forward networks (experts). A linear router
+/// scores the experts for every token: each token is processed by its `num_experts_per_tok` best experts and the
+/// expert outputs are combined with the (renormalized) router probabilities. The capacity of the layer grows with
+/// the number of experts while the computation per token only depends on `num_experts_per_tok`.
+pub struct MoEFeedForwardNetwork<E: ModuleT> {
+    router: nn::Linear,
+    experts: Vec<E>,
+    num_experts_per_tok: usize,
+}
+
+impl<E: ModuleT> MoEFeedForwardNetwork<E> {
+    /// Creates a new mixture-of-experts layer. `num_experts_per_tok` is expected to be in `[1, num_experts]`
+    /// (checked by the model configuration).
+    ///
+    /// # Arguments
+    ///
+    /// * `p` - Variable store path for the root of the layer
+    /// * `dim` - Hidden size of the inputs and outputs of the layer
+    /// * `num_experts` - Number of experts
+    /// * `num_experts_per_tok` - Number of experts processing every token
+    /// * `expert` - Constructor of the experts, called with the variable store path of every expert. The experts
+    /// should map inputs of size `dim` to outputs of size `dim`.
+    pub fn new<'p, P, F>(
+        p: P,
+        dim: i64,
+        num_experts: i64,
+        num_experts_per_tok: i64,
+        expert: F,
+    ) -> MoEFeedForwardNetwork<E>
+    where
+        P: Borrow<nn::Path<'p>>,
+        F: Fn(nn::Path) -> E,
+    {
+        let p = p.borrow();
+        let router = nn::linear(
+            p / "router",
+            dim,
+            num_experts,
+            LinearConfig {
+                bias: false,
+                ..Default::default()
+            },
+        );
+        let p_experts = p / "experts";
+        let experts = (0..num_experts)
+            .map(|expert_index| expert(&p_experts / expert_index))
+            .collect();
+        MoEFeedForwardNetwork {
+            router,
+            experts,
+            num_experts_per_tok: num_experts_per_tok as usize,
+        }
+    }
+
+    /// Forward pass through the layer, returning the combined expert outputs (with the shape of the input) and the
+    /// load-balancing auxiliary loss of the router.
+    ///
+    /// The auxiliary loss of the [Switch Transformer](https://arxiv.org/abs/2101.03961) is
+    /// `num_experts * sum_i(f_i * P_i)`, where `f_i` is the fraction of the token assignments routed to expert `i`
+    /// and `P_i` the average router probability of expert `i`. It is equal to 1 for a uniform routing and
+    /// should be added to the training loss (scaled by a small coefficient) to prevent the router from collapsing
+    /// on a few experts.
+    pub fn forward_t(&self, input: &Tensor, train: bool) -> (Tensor, Tensor) {
+        let input_shape = input.size();
+        let num_experts = self.experts.len() as i64;
+        let hidden_states = input.reshape([-1, *input_shape.last().unwrap()]);
+
+        let router_probabilities = hidden_states.apply(&self.router).softmax(-1, Kind::Float);
+        let (routing_weights, selected_experts) =
+            router_probabilities.topk(self.num_experts_per_tok as i64, -1, true, false);
+        let routing_weights = (&routing_weights
+            / routing_weights.sum_dim_intlist([-1].as_slice(), true, Kind::Float))
+        .to_kind(input.kind());
+
+        let mut output = hidden_states.zeros_like();
+        for (expert_index, expert) in self.experts.iter().enumerate() {
+            let expert_mask = selected_experts.eq(expert_index as i64);
+            let token_indices = expert_mask.any_dim(-1, false).nonzero().squeeze_dim(-1);
+            if token_indices.numel() == 0 {
+                continue;
+            }
+            let expert_weights = (&routing_weights * expert_mask.to_kind(routing_weights.kind()))
+                .sum_dim_intlist([-1].as_slice(), true, routing_weights.kind())
+                .index_select(0, &token_indices);
+            let expert_output = expert
+                .forward_t(&hidden_states.index_select(0, &token_indices), train)
+                * expert_weights;
+            output = output.index_add(0, &token_indices, &expert_output);
+        }
+
+        let expert_assignment_fraction = selected_experts
+            .one_hot(num_experts)
+            .to_kind(Kind::Float)
+            .sum_dim_intlist([1].as_slice(), false, Kind::Float)
+            .mean_dim([0].as_slice(), false, Kind::Float)
+            / self.num_experts_per_tok as f64;
+        let router_probability_per_expert =
+            router_probabilities.mean_dim([0].as_slice(), false, Kind::Float);
+        let aux_loss = (expert_assignment_fraction * router_probability_per_expert)
+            .sum(Kind::Float)
+            * num_experts;
+
+        (output.view(input_shape.as_slice()), aux_loss)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tch::{Device, Kind};
+
+    #[test]
+    fn moe_routing_matches_dense_computation() {
+        tch::manual_seed(42);
+        let vs = nn::VarStore::new(Device::Cpu);
+        let moe = MoEFeedForwardNetwork::new(vs.root(), 8, 4, 2, |p| {
+            nn::seq_t()
+                .add(nn::linear(&p / "lin1", 8, 16, Default::default()))
+                .add_fn(|x| x.relu())
+                .add(nn::linear(&p / "lin2", 16, 8, Default::default()))
+        });
+        let input = Tensor::randn([2, 3, 8], (Kind::Float, Device::Cpu));
+
+        let (output, aux_loss) = moe.forward_t(&input, false);
+        assert_eq!(output.size(), &[2, 3, 8]);
+
+        // Reference: every expert applied to every token, weighted by the renormalized top-2 router probabilities
+        let hidden_states = input.view([-1, 8]);
+        let probabilities = hidden_states.apply(&moe.router).softmax(-1, Kind::Float);
+        let (top_probabilities, top_experts) = probabilities.topk(2, -1, true, false);
+        let dense_weights = Tensor::zeros([6, 4], (Kind::Float, Device::Cpu)).scatter(
+            1,
+            &top_experts,
+            &(&top_probabilities
+                / top_probabilities.sum_dim_intlist([-1].as_slice(), true, Kind::Float)),
+        );
+        let expert_outputs = Tensor::stack(
+            &moe.experts
+                .iter()
+                .map(|expert| expert.forward_t(&hidden_states, false))
+                .collect::<Vec<Tensor>>(),
+            1,
+        );
+        let expected_output = (expert_outputs * dense_weights.unsqueeze(-1))
+            .sum_dim_intlist([1].as_slice(), false, Kind::Float)
+            .view([2, 3, 8]);
+        assert!(output.allclose(&expected_output, 1e-5, 1e-6, false));
+
+        // The auxiliary loss is bounded by the number of experts
+        let aux_loss = aux_loss.double_value(&[]);
+        assert!((0.0..=4.0).contains(&aux_loss));
+    }
+
+    #[test]
+    fn moe_load_balancing_loss() {
+        let vs = nn::VarStore::new(Device::Cpu);
+        let moe = MoEFeedForwardNetwork::new(vs.root(), 4, 2, 1, |p| {
+            nn::linear(p, 4, 4, Default::default())
+        });
+        let input = Tensor::ones([5, 4], (Kind::Float, Device::Cpu));
+
+        // A router ignoring its inputs gives uniform probabilities: P = (1/2, 1/2)
+        let _ = tch::no_grad(|| moe.router.ws.shallow_clone().zero_());
+        let (_, aux_loss) = moe.forward_t(&input, false);
+        assert!((aux_loss.double_value(&[]) - 1.0).abs() < 1e-6);
+
+        // A router collapsed on the first expert: f = (1, 0), P = (p, 1 - p) with p close to 1
+        let (_, collapsed_aux_loss) = tch::no_grad(|| {
+            let _ = moe.router.ws.shallow_clone().copy_(&Tensor::from_slice2(&[
+                [10f32, 0., 0., 0.],
+                [-10., 0., 0., 0.],
+            ]));
+            moe.forward_t(&input, false)
+        });
+        assert!(collapsed_aux_loss.double_value(&[]) > 1.5);
+    }
+}
@@ -0,0 +1,116 @@
+// Copyright 2024 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use tch::nn::{Init, Module};
+use tch::{nn, Kind, Tensor};
+
+#[derive(Clone, Debug, Serialize, Deserialize, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// # Normalization layer used by a transformer model
+pub enum NormType {
+    /// Layer normalization, centering and scaling the hidden states with learned weight and bias
+    LayerNorm,
+    /// Root mean square normalization, scaling the hidden states by their root mean square with a learned weight ([Zhang and Sennrich, 2019](https://arxiv.org/abs/1910.07467))
+    #[serde(rename = "rms_norm")]
+    RMSNorm,
+}
+
+#[derive(Debug)]
+/// # Root mean square normalization layer
+/// Normalization without mean-centering and bias, used by T5 and LLaMA models among others.
+/// Computations are done in full precision for numerical stability.
+pub struct RMSNorm {
+    scale: Tensor,
+    eps: f64,
+}
+
+impl RMSNorm {
+    pub fn new<'p, P>(p: P, hidden_size: i64, eps: f64) -> RMSNorm
+    where
+        P: Borrow<nn::Path<'p>>,
+    {
+        let scale = p.borrow().var("weight", &[hidden_size], Init::Const(1.0));
+        RMSNorm { scale, eps }
+    }
+}
+
+impl Module for RMSNorm {
+    fn forward(&self, x: &Tensor) -> Tensor {
+        let input_type = x.kind();
+        let x = x.to_kind(Kind::Float);
+        let variance = x
+            .pow_tensor_scalar(2.0_f64)
+            .mean_dim([-1].as_slice(), true, Kind::Float);
+        let x = x * (variance + self.eps).rsqrt();
+        (&self.scale * x).to_kind(input_type)
+    }
+}
+
+#[derive(Debug)]
+/// Normalization layer built from a `NormType`
+pub(crate) enum Normalization {
+    LayerNorm(nn::LayerNorm),
+    RMSNorm(RMSNorm),
+}
+
+impl Normalization {
+    pub(crate) fn new<'p, P>(p: P, norm_type: NormType, hidden_size: i64, eps: f64) -> Self
+    where
+        P: Borrow<nn::Path<'p>>,
+    {
+        match norm_type {
+            NormType::LayerNorm => {
+                let layer_norm_config = nn::LayerNormConfig {
+                    eps,
+                    ..Default::default()
+                };
+                Normalization::LayerNorm(nn::layer_norm(p, vec![hidden_size], layer_norm_config))
+            }
+            NormType::RMSNorm => Normalization::RMSNorm(RMSNorm::new(p, hidden_size, eps)),
+        }
+    }
+}
+
+impl Module for Normalization {
+    fn forward(&self, x: &Tensor) -> Tensor {
+        match self {
+            Normalization::LayerNorm(layer_norm) => x.apply(layer_norm),
+            Normalization::RMSNorm(rms_norm) => x.apply(rms_norm),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tch::Device;
+
+    #[test]
+    fn rms_norm_unit_root_mean_square() {
+        let vs = nn::VarStore::new(Device::Cpu);
+        let rms_norm = RMSNorm::new(vs.root(), 8, 1e-6);
+        let input = Tensor::randn([2, 3, 8], (Kind::Float, Device::Cpu)) * 5.0 + 2.0;
+
+        let output = input.apply(&rms_norm);
+
+        let root_mean_square = output
+            .pow_tensor_scalar(2.0_f64)
+            .mean_dim([-1].as_slice(), false, Kind::Float)
+            .sqrt();
+        assert!(root_mean_square.allclose(&root_mean_square.ones_like(), 1e-4, 1e-4, false));
+        // Unlike layer normalization, the mean is not removed
+        let input_direction = &input / input.norm_scalaropt_dim(2, [-1].as_slice(), true);
+        let output_direction = &output / output.norm_scalaropt_dim(2, [-1].as_slice(), true);
+        assert!(input_direction.allclose(&output_direction, 1e-5, 1e-5, false));
+    }
+}
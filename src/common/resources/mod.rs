@@ -23,6 +23,8 @@ mod buffer;
 mod local;
 
 use crate::common::error::RustBertError;
+use crate::common::weights::is_safetensors_path;
+pub use crate::common::weights::{load_weights_from_safetensors, WeightsLoadingReport};
 pub use buffer::BufferResource;
 pub use local::LocalResource;
 use std::fmt::Debug;
@@ -81,7 +83,11 @@ impl<T: ResourceProvider + ?Sized> ResourceProvider for Box<T> {
     }
 }
 
-/// Load the provided `VarStore` with model weights from the provided `ResourceProvider`
+/// Load the provided `VarStore` with model weights from the provided `ResourceProvider`.
+/// Files with a `.safetensors` extension (or the `.safetensors.index.json` index of a sharded checkpoint)
+/// are loaded with `load_weights_from_safetensors`, other files are expected in the libtorch format.
+/// Loading a safetensors checkpoint returns an error if variables of the model are missing from the checkpoint, and
+/// logs a warning listing the checkpoint tensors that are not used by the model.
 pub fn load_weights(
     rp: &(impl ResourceProvider + ?Sized),
     vs: &mut VarStore,
@@ -89,9 +95,28 @@ pub fn load_weights(
     device: Device,
 ) -> Result<(), RustBertError> {
     match rp.get_resource()? {
-        Resource::Buffer(mut data) => vs.load_from_stream(std::io::Cursor::new(data.deref_mut())),
-        Resource::PathBuf(path) => vs.load(path),
-    }?;
+        Resource::Buffer(mut data) => {
+            vs.load_from_stream(std::io::Cursor::new(data.deref_mut()))?
+        }
+        Resource::PathBuf(path) if is_safetensors_path(&path) => {
+            let report = load_weights_from_safetensors(vs, &path)?;
+            if !report.unexpected_keys.is_empty() {
+                log::warn!(
+                    "Tensors of {} not used by the model: {}",
+                    path.display(),
+                    report.unexpected_keys.join(", ")
+                );
+            }
+            if !report.missing_keys.is_empty() {
+                return Err(RustBertError::ValueError(format!(
+                    "Variables missing from {}: {}",
+                    path.display(),
+                    report.missing_keys.join(", ")
+                )));
+            }
+        }
+        Resource::PathBuf(path) => vs.load(path)?,
+    };
     cast_var_store(vs, kind, device);
     Ok(())
 }
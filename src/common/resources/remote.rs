@@ -3,7 +3,9 @@ use crate::common::error::RustBertError;
 use cached_path::{Cache, Options, ProgressBar};
 use dirs::cache_dir;
 use lazy_static::lazy_static;
-use std::path::PathBuf;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
 
 /// # Remote resource that will be downloaded and cached locally on demand
 #[derive(PartialEq, Eq, Clone, Debug)]
@@ -66,13 +68,120 @@ impl RemoteResource {
         let url = name_url_tuple.1.to_string();
         RemoteResource { url, cache_subdir }
     }
+
+    /// Creates a new RemoteResource for a file of a HuggingFace Hub model repository. Will define a
+    /// local path pointing to ~/.cache/.rustbert/repository (or ~/.cache/.rustbert/repository/revision
+    /// for a specific revision). Note that this does not download the resource (only declares the remote
+    /// and local locations). The revision is percent-encoded in the URL (e.g. `refs/pr/1` for a pull request).
+    /// Files stored with Git LFS on the Hub are verified against their SHA-256 checksum once downloaded.
+    /// # Arguments
+    /// * `repository` - `&str` Name of the model repository on the Hub (e.g. `gpt2` or `distilgpt2`)
+    /// * `revision` - `Option<&str>` Optional branch, tag or commit of the repository (defaults to `main`)
+    /// * `file_name` - `&str` Name of the file in the repository
+    /// # Returns
+    /// * `RemoteResource` RemoteResource object
+    /// # Example
+    /// ```no_run
+    /// use rust_bert::resources::RemoteResource;
+    /// let model_resource = RemoteResource::from_hub("distilgpt2", None, "rust_model.ot");
+    /// ```
+    pub fn from_hub(repository: &str, revision: Option<&str>, file_name: &str) -> RemoteResource {
+        let url = format!(
+            "https://huggingface.co/{repository}/resolve/{}/{file_name}",
+            percent_encode(revision.unwrap_or("main"))
+        );
+        let cache_subdir = match revision {
+            Some(revision) => format!("{repository}/{revision}"),
+            None => repository.to_string(),
+        };
+        RemoteResource { url, cache_subdir }
+    }
+}
+
+/// Percent-encodes all characters of a URL path segment except the unreserved characters of RFC 3986.
+fn percent_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+fn is_hub_url(url: &str) -> bool {
+    url.starts_with("https://huggingface.co/") && url.contains("/resolve/")
+}
+
+/// Parses the `X-Linked-Etag` header returned by the Hub for Git LFS files, holding the SHA-256
+/// of the file content. Returns `None` for other ETags (e.g. the Git blob hash of small files).
+fn parse_linked_etag(value: &str) -> Option<String> {
+    let etag = value.trim_start_matches("W/").trim_matches('"');
+    if etag.len() == 64 && etag.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        Some(etag.to_ascii_lowercase())
+    } else {
+        None
+    }
+}
+
+fn get_hub_sha256(url: &str) -> Result<Option<String>, RustBertError> {
+    let client = reqwest::blocking::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .map_err(|error| RustBertError::IOError(error.to_string()))?;
+    let response = client
+        .head(url)
+        .send()
+        .map_err(|error| RustBertError::IOError(format!("Could not reach {url}: {error}")))?;
+    Ok(response
+        .headers()
+        .get("x-linked-etag")
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_linked_etag))
+}
+
+fn sha256_file(path: &Path) -> Result<String, RustBertError> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Verifies a file downloaded from the Hub against the SHA-256 advertised by the Hub. A marker
+/// file is written next to the cached file once verified so that the check runs once per download.
+fn verify_hub_checksum(url: &str, path: &Path) -> Result<(), RustBertError> {
+    let mut marker_path = path.as_os_str().to_owned();
+    marker_path.push(".sha256");
+    let marker_path = PathBuf::from(marker_path);
+    if marker_path.exists() {
+        return Ok(());
+    }
+    let checksum = match get_hub_sha256(url)? {
+        Some(expected) => {
+            let actual = sha256_file(path)?;
+            if actual != expected {
+                fs::remove_file(path)?;
+                return Err(RustBertError::IOError(format!(
+                    "SHA-256 checksum mismatch for {url}: expected {expected}, got {actual}"
+                )));
+            }
+            actual
+        }
+        None => String::new(),
+    };
+    fs::write(marker_path, checksum)?;
+    Ok(())
 }
 
 impl ResourceProvider for RemoteResource {
     /// Gets the local path for a remote resource.
     ///
     /// The remote resource is downloaded and cached. Then the path
-    /// to the local cache is returned.
+    /// to the local cache is returned. Files served by the HuggingFace Hub with a SHA-256
+    /// checksum (Git LFS files) are verified the first time they are accessed: a mismatching
+    /// file is removed from the cache and an error is returned.
     ///
     /// # Returns
     ///
@@ -91,6 +200,9 @@ impl ResourceProvider for RemoteResource {
     fn get_local_path(&self) -> Result<PathBuf, RustBertError> {
         let cached_path = CACHE
             .cached_path_with_options(&self.url, &Options::default().subdir(&self.cache_subdir))?;
+        if is_hub_url(&self.url) {
+            verify_hub_checksum(&self.url, &cached_path)?;
+        }
         Ok(cached_path)
     }
 
@@ -134,3 +246,68 @@ fn _get_cache_directory() -> PathBuf {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hub_resource_url() {
+        let resource = RemoteResource::from_hub("distilgpt2", None, "rust_model.ot");
+        assert_eq!(
+            resource.url,
+            "https://huggingface.co/distilgpt2/resolve/main/rust_model.ot"
+        );
+        assert_eq!(resource.cache_subdir, "distilgpt2");
+
+        let resource =
+            RemoteResource::from_hub("openai-community/gpt2", Some("v1.0"), "config.json");
+        assert_eq!(
+            resource.url,
+            "https://huggingface.co/openai-community/gpt2/resolve/v1.0/config.json"
+        );
+        assert_eq!(resource.cache_subdir, "openai-community/gpt2/v1.0");
+
+        let resource = RemoteResource::from_hub("gpt2", Some("refs/pr/1"), "config.json");
+        assert_eq!(
+            resource.url,
+            "https://huggingface.co/gpt2/resolve/refs%2Fpr%2F1/config.json"
+        );
+        assert_eq!(resource.cache_subdir, "gpt2/refs/pr/1");
+    }
+
+    #[test]
+    fn percent_encoding() {
+        assert_eq!(percent_encode("main"), "main");
+        assert_eq!(percent_encode("v1.0-rc_1~2"), "v1.0-rc_1~2");
+        assert_eq!(percent_encode("refs/pr/1"), "refs%2Fpr%2F1");
+        assert_eq!(percent_encode("a b?é"), "a%20b%3F%C3%A9");
+    }
+
+    #[test]
+    fn hub_checksum() -> anyhow::Result<()> {
+        let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert!(is_hub_url(
+            "https://huggingface.co/distilgpt2/resolve/main/rust_model.ot"
+        ));
+        assert!(!is_hub_url("http://config_json_location"));
+        assert_eq!(
+            parse_linked_etag(&format!("\"{sha256}\"")),
+            Some(sha256.to_string())
+        );
+        assert_eq!(
+            parse_linked_etag(&format!("W/\"{}\"", sha256.to_uppercase())),
+            Some(sha256.to_string())
+        );
+        assert_eq!(
+            parse_linked_etag("\"a9993e364706816aba3e25717850c26c9cd0d89d\""),
+            None
+        );
+
+        let directory = tempfile::tempdir()?;
+        let path = directory.path().join("file.txt");
+        fs::write(&path, "abc")?;
+        assert_eq!(sha256_file(&path)?, sha256);
+        Ok(())
+    }
+}
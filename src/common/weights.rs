@@ -0,0 +1,273 @@
+// Copyright 2024 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::RustBertError;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+use tch::nn::VarStore;
+use tch::Tensor;
+
+/// Returns true if the path points to a safetensors file or to the index of a sharded safetensors checkpoint
+pub(crate) fn is_safetensors_path(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|file_name| file_name.to_str())
+        .map_or(false, |file_name| {
+            file_name.ends_with(".safetensors") | file_name.ends_with(".safetensors.index.json")
+        })
+}
+
+/// Reads the tensors of a safetensors file, or of all the shards listed in the `weight_map` of a
+/// `model.safetensors.index.json` index (shards are expected in the directory of the index).
+fn read_safetensors(path: &Path) -> Result<HashMap<String, Tensor>, RustBertError> {
+    if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+        return Ok(Tensor::read_safetensors(path)?.into_iter().collect());
+    }
+    let index: serde_json::Value = serde_json::from_str(&fs::read_to_string(path)?)
+        .map_err(|error| RustBertError::IOError(format!("Invalid safetensors index: {error}")))?;
+    let shard_files = index
+        .get("weight_map")
+        .and_then(|weight_map| weight_map.as_object())
+        .ok_or_else(|| {
+            RustBertError::IOError(format!(
+                "Missing `weight_map` in safetensors index {}",
+                path.display()
+            ))
+        })?
+        .values()
+        .filter_map(|shard_file| shard_file.as_str())
+        .collect::<BTreeSet<&str>>();
+    let shards_directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut named_tensors = HashMap::new();
+    for shard_file in shard_files {
+        named_tensors.extend(Tensor::read_safetensors(shards_directory.join(shard_file))?);
+    }
+    Ok(named_tensors)
+}
+
+/// Candidate names of a variable in a checkpoint: its own name and the name without its first
+/// component (checkpoints of a base model loaded in a model with a head differ by the prefix of the
+/// base model), each followed by the same name with the legacy `gamma`/`beta` layer normalization
+/// parameter names.
+fn candidate_names(name: &str) -> Vec<String> {
+    let mut names = vec![name];
+    if let Some((_, unprefixed_name)) = name.split_once('.') {
+        names.push(unprefixed_name);
+    }
+    names
+        .into_iter()
+        .flat_map(|name| {
+            let legacy_name = if let Some(prefix) = name.strip_suffix(".weight") {
+                Some(format!("{prefix}.gamma"))
+            } else {
+                name.strip_suffix(".bias")
+                    .map(|prefix| format!("{prefix}.beta"))
+            };
+            std::iter::once(name.to_string()).chain(legacy_name)
+        })
+        .collect()
+}
+
+/// # Weights loading report
+/// Result of the loading of a checkpoint in a `VarStore`, listing the variables of the model not found in
+/// the checkpoint (which keep their initial value) and the tensors of the checkpoint not used by the model.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WeightsLoadingReport {
+    /// Names of the model variables not found in the checkpoint
+    pub missing_keys: Vec<String>,
+    /// Names of the checkpoint tensors not loaded in any variable
+    pub unexpected_keys: Vec<String>,
+}
+
+/// Load the provided `VarStore` with model weights from a safetensors file or from the
+/// `model.safetensors.index.json` index of a sharded checkpoint.
+///
+/// Variables are matched with the checkpoint tensors by name. When a name is not found, the legacy
+/// layer normalization names (`gamma` and `beta`) and names differing by the prefix of the base
+/// model (e.g. `transformer.h.0.attn.c_attn.weight` and `h.0.attn.c_attn.weight`) are used.
+/// The tensors are converted to the precision of the variables they are loaded in. The names of the missing
+/// and unexpected tensors are returned in a `WeightsLoadingReport`: the missing variables keep their initial value.
+///
+/// # Arguments
+///
+/// * `vs` - `VarStore` to load the weights into
+/// * `path` - Path to a `.safetensors` file or to a `.safetensors.index.json` index
+///
+/// # Example
+///
+/// ```no_run
+/// # fn main() -> anyhow::Result<()> {
+/// use rust_bert::gpt2::{GPT2LMHeadModel, Gpt2Config};
+/// use rust_bert::resources::load_weights_from_safetensors;
+/// use rust_bert::Config;
+/// use std::path::Path;
+/// use tch::{nn, Device};
+///
+/// let config = Gpt2Config::from_file("path/to/config.json");
+/// let mut vs = nn::VarStore::new(Device::Cpu);
+/// let model = GPT2LMHeadModel::new(vs.root(), &config);
+/// let report = load_weights_from_safetensors(&mut vs, Path::new("path/to/model.safetensors"))?;
+/// assert!(report.missing_keys.is_empty());
+/// # Ok(())
+/// # }
+/// ```
+pub fn load_weights_from_safetensors(
+    vs: &mut VarStore,
+    path: &Path,
+) -> Result<WeightsLoadingReport, RustBertError> {
+    let named_tensors = read_safetensors(path)?;
+    let mut used_tensors = BTreeSet::new();
+    let mut missing_keys = BTreeSet::new();
+    for (name, mut variable) in vs.variables() {
+        let source = candidate_names(&name)
+            .into_iter()
+            .chain(
+                // The checkpoint may have an additional prefix (e.g. `model.`)
+                named_tensors
+                    .keys()
+                    .filter(|key| key.split_once('.').map(|(_, key)| key) == Some(name.as_str()))
+                    .cloned(),
+            )
+            .find(|candidate| named_tensors.contains_key(candidate));
+        match source {
+            Some(source) => {
+                let tensor = &named_tensors[&source];
+                if tensor.size() != variable.size() {
+                    return Err(RustBertError::ValueError(format!(
+                        "Shape mismatch for {name}: {:?} in the model, {:?} in the checkpoint ({source})",
+                        variable.size(),
+                        tensor.size()
+                    )));
+                }
+                tch::no_grad(|| variable.f_copy_(&tensor.to_kind(variable.kind())))?;
+                used_tensors.insert(source);
+            }
+            None => {
+                missing_keys.insert(name);
+            }
+        }
+    }
+    let unexpected_keys = named_tensors
+        .into_keys()
+        .filter(|key| !used_tensors.contains(key))
+        .collect::<BTreeSet<String>>();
+    Ok(WeightsLoadingReport {
+        missing_keys: missing_keys.into_iter().collect(),
+        unexpected_keys: unexpected_keys.into_iter().collect(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resources::{load_weights, LocalResource};
+    use tch::{Device, Kind};
+
+    #[test]
+    fn load_sharded_safetensors_with_name_mapping() -> anyhow::Result<()> {
+        let directory = tempfile::tempdir()?;
+        let embeddings = Tensor::rand([4, 2], (Kind::Float, Device::Cpu));
+        let norm_weight = Tensor::rand([2], (Kind::Float, Device::Cpu));
+        let norm_bias = Tensor::rand([2], (Kind::Float, Device::Cpu));
+        Tensor::write_safetensors(
+            &[("embeddings.weight", &embeddings)],
+            directory.path().join("model-00001-of-00002.safetensors"),
+        )?;
+        Tensor::write_safetensors(
+            &[
+                ("norm.gamma", &norm_weight),
+                ("norm.beta", &norm_bias),
+                ("pooler.weight", &norm_bias),
+            ],
+            directory.path().join("model-00002-of-00002.safetensors"),
+        )?;
+        let index_path = directory.path().join("model.safetensors.index.json");
+        fs::write(
+            &index_path,
+            r#"{"metadata": {}, "weight_map": {
+                "embeddings.weight": "model-00001-of-00002.safetensors",
+                "norm.gamma": "model-00002-of-00002.safetensors",
+                "norm.beta": "model-00002-of-00002.safetensors",
+                "pooler.weight": "model-00002-of-00002.safetensors"
+            }}"#,
+        )?;
+
+        let mut vs = VarStore::new(Device::Cpu);
+        let root = vs.root() / "transformer";
+        let _ = (&root / "embeddings").zeros("weight", &[4, 2]);
+        let _ = (&root / "norm").zeros("weight", &[2]);
+        let _ = (&root / "norm").zeros("bias", &[2]);
+        let _ = root.zeros("lm_head", &[2]);
+
+        assert!(is_safetensors_path(&index_path));
+        let report = load_weights_from_safetensors(&mut vs, &index_path)?;
+        assert_eq!(report.missing_keys, ["transformer.lm_head"]);
+        assert_eq!(report.unexpected_keys, ["pooler.weight"]);
+
+        let variables = vs.variables();
+        assert!(variables["transformer.embeddings.weight"].equal(&embeddings));
+        assert!(variables["transformer.norm.weight"].equal(&norm_weight));
+        assert!(variables["transformer.norm.bias"].equal(&norm_bias));
+        // Missing variables keep their initial value
+        assert!(
+            variables["transformer.lm_head"].equal(&Tensor::zeros([2], (Kind::Float, Device::Cpu)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn load_weights_rejects_missing_variables() -> anyhow::Result<()> {
+        let directory = tempfile::tempdir()?;
+        let weight = Tensor::rand([3, 2], (Kind::Float, Device::Cpu));
+        let path = directory.path().join("model.safetensors");
+        Tensor::write_safetensors(
+            &[("linear.weight", &weight), ("pooler.weight", &weight)],
+            &path,
+        )?;
+        let resource = LocalResource::from(path);
+
+        // Unexpected tensors are ignored (with a warning)
+        let mut vs = VarStore::new(Device::Cpu);
+        let _ = (vs.root() / "linear").zeros("weight", &[3, 2]);
+        load_weights(&resource, &mut vs, None, Device::Cpu)?;
+        assert!(vs.variables()["linear.weight"].equal(&weight));
+
+        // Variables missing from the checkpoint are an error
+        let mut vs = VarStore::new(Device::Cpu);
+        let _ = (vs.root() / "linear").zeros("weight", &[3, 2]);
+        let _ = (vs.root() / "linear").zeros("bias", &[3]);
+        assert!(matches!(
+            load_weights(&resource, &mut vs, None, Device::Cpu),
+            Err(RustBertError::ValueError(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn load_safetensors_keeps_variable_precision() -> anyhow::Result<()> {
+        let directory = tempfile::tempdir()?;
+        let weight = Tensor::rand([3, 2], (Kind::Float, Device::Cpu));
+        let path = directory.path().join("model.safetensors");
+        Tensor::write_safetensors(&[("linear.weight", &weight.to_kind(Kind::Half))], &path)?;
+
+        let mut vs = VarStore::new(Device::Cpu);
+        let _ = (vs.root() / "linear").zeros("weight", &[3, 2]);
+        let report = load_weights_from_safetensors(&mut vs, &path)?;
+        assert_eq!(report, WeightsLoadingReport::default());
+
+        // The half precision checkpoint is converted to the precision of the variable
+        let variables = vs.variables();
+        let variable = &variables["linear.weight"];
+        assert_eq!(variable.kind(), Kind::Float);
+        assert!(variable.allclose(&weight, 1e-2, 1e-3, false));
+        Ok(())
+    }
+}
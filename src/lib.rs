@@ -667,10 +667,10 @@
 //!```no_run
 //! # use rust_bert::pipelines::masked_language::MaskedToken;
 //! let output = vec![
-//!    vec![MaskedToken { text: String::from("college"), id: 2267, score: 8.091}],
+//!    vec![MaskedToken { text: String::from("college"), id: 2267, score: 8.091, sequence: String::from("Hello I am a college student")}],
 //!    vec![
-//!        MaskedToken { text: String::from("capital"), id: 3007, score: 16.7249},
-//!        MaskedToken { text: String::from("located"), id: 2284, score: 9.0452}
+//!        MaskedToken { text: String::from("capital"), id: 3007, score: 16.7249, sequence: String::from("Paris is the capital of France. It is <mask> in Europe.")},
+//!        MaskedToken { text: String::from("located"), id: 2284, score: 9.0452, sequence: String::from("Paris is the <mask> of France. It is located in Europe.")}
 //!    ]
 //! ]
 //! # ;
@@ -731,9 +731,11 @@ mod common;
 pub mod models;
 pub mod pipelines;
 
+pub use common::crf;
 pub use common::error::RustBertError;
+pub use common::lora;
 pub use common::resources;
-pub use common::{Activation, Config};
+pub use common::{Activation, Config, NormType, PositionEncoding, RopeScaling};
 pub use models::{
     albert, bart, bert, deberta, deberta_v2, distilbert, electra, fnet, gpt2, gpt_j, gpt_neo,
     longformer, longt5, m2m_100, marian, mbart, mobilebert, nllb, openai_gpt, pegasus, prophetnet,
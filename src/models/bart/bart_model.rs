@@ -1000,7 +1000,7 @@ impl BartGenerator {
         let config_path = generate_config.config_resource.get_local_path()?;
         let device = generate_config.device;
 
-        generate_config.validate();
+        generate_config.validate()?;
         let mut var_store = nn::VarStore::new(device);
         let config = BartConfig::from_file(config_path);
         let model = BartForConditionalGeneration::new(var_store.root(), &config);
@@ -1128,6 +1128,9 @@ impl PrivateLanguageGenerator for BartGenerator {
         Ok(LMModelOutput {
             lm_logits: base_model_output.decoder_output,
             cache: Cache::BARTCache(base_model_output.cache),
+            last_hidden_state: None,
+            all_hidden_states: None,
+            all_attentions: None,
         })
     }
 
@@ -14,6 +14,7 @@
 use crate::bert::bert_model::BertConfig;
 use crate::common::activations::TensorFunction;
 use crate::common::dropout::Dropout;
+use crate::common::lora::LoraOrLinear;
 use std::borrow::Borrow;
 use tch::{nn, Tensor};
 
@@ -23,9 +24,9 @@ pub struct BertSelfAttention {
     attention_head_size: i64,
     dropout: Dropout,
     output_attentions: bool,
-    query: nn::Linear,
+    query: LoraOrLinear,
     key: nn::Linear,
-    value: nn::Linear,
+    value: LoraOrLinear,
 }
 
 impl BertSelfAttention {
@@ -40,11 +41,11 @@ impl BertSelfAttention {
         );
         let p = p.borrow();
 
-        let query = nn::linear(
+        let query = LoraOrLinear::new(
             p / "query",
             config.hidden_size,
             config.hidden_size,
-            Default::default(),
+            config.lora.as_ref(),
         );
         let key = nn::linear(
             p / "key",
@@ -52,11 +53,11 @@ impl BertSelfAttention {
             config.hidden_size,
             Default::default(),
         );
-        let value = nn::linear(
+        let value = LoraOrLinear::new(
             p / "value",
             config.hidden_size,
             config.hidden_size,
-            Default::default(),
+            config.lora.as_ref(),
         );
 
         let dropout = Dropout::new(config.attention_probs_dropout_prob);
@@ -13,9 +13,11 @@
 
 use crate::bert::encoder::{BertEncoder, BertPooler};
 use crate::common::activations::Activation;
+use crate::common::crf::CRFLayer;
 use crate::common::dropout::Dropout;
 use crate::common::embeddings::get_shape_and_device_from_ids_embeddings_pair;
 use crate::common::linear::{linear_no_bias, LinearNoBias};
+use crate::common::lora::LoraConfig;
 use crate::{
     bert::embeddings::{BertEmbedding, BertEmbeddings},
     common::activations::TensorFunction,
@@ -155,6 +157,10 @@ pub struct BertConfig {
     pub is_decoder: Option<bool>,
     pub id2label: Option<HashMap<i64, String>>,
     pub label2id: Option<HashMap<String, i64>>,
+    /// Adds a linear-chain CRF layer on top of the token classification head (`BertForTokenClassification`)
+    pub use_crf: Option<bool>,
+    /// Adds LoRA adapters to the query and value projections of the self-attention layers
+    pub lora: Option<LoraConfig>,
 }
 
 impl Config for BertConfig {}
@@ -178,6 +184,8 @@ impl Default for BertConfig {
             is_decoder: None,
             id2label: None,
             label2id: None,
+            use_crf: None,
+            lora: None,
         }
     }
 }
@@ -958,6 +966,7 @@ pub struct BertForTokenClassification {
     bert: BertModel<BertEmbeddings>,
     dropout: Dropout,
     classifier: nn::Linear,
+    crf: Option<CRFLayer>,
 }
 
 impl BertForTokenClassification {
@@ -1008,14 +1017,27 @@ impl BertForTokenClassification {
             num_labels,
             Default::default(),
         );
+        let crf = if config.use_crf.unwrap_or(false) {
+            Some(CRFLayer::new(p / "crf", num_labels))
+        } else {
+            None
+        };
 
         Ok(BertForTokenClassification {
             bert,
             dropout,
             classifier,
+            crf,
         })
     }
 
+    /// Returns the CRF layer of the model if it was created with `use_crf` set to true. The logits of the model
+    /// are the emission scores of the CRF layer: the labels should be decoded with `CRFLayer::viterbi_decode`
+    /// and the model trained with `CRFLayer::neg_log_likelihood`.
+    pub fn crf(&self) -> Option<&CRFLayer> {
+        self.crf.as_ref()
+    }
+
     /// Forward pass through the model
     ///
     /// # Arguments
@@ -10,21 +10,60 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::common::attention_utils::{
+    apply_alibi_bias, compute_alibi_bias, compute_alibi_slopes, repeat_kv, PositionEncoding,
+    RotaryEmbedding,
+};
 use crate::common::dropout::Dropout;
+use crate::common::lora::LoraOrLinear;
 use crate::distilbert::distilbert_model::DistilBertConfig;
 use std::borrow::Borrow;
-use tch::{nn, Tensor};
+use tch::{nn, Device, Kind, Tensor};
+
+#[derive(Debug)]
+/// ALiBi slopes, with the bias precomputed up to the maximum number of positions of the model
+struct ALiBi {
+    slopes: Tensor,
+    bias: Tensor,
+}
+
+impl ALiBi {
+    fn new(n_heads: i64, max_position_embeddings: i64, device: Device) -> ALiBi {
+        let slopes = compute_alibi_slopes(n_heads).to_device(device);
+        let bias = compute_alibi_bias(&slopes, max_position_embeddings, max_position_embeddings);
+        ALiBi { slopes, bias }
+    }
+
+    fn add_bias(&self, attention_scores: &mut Tensor) {
+        let size = attention_scores.size();
+        let (query_length, key_length) = (size[2], size[3]);
+        let max_length = self.bias.size()[1];
+        if (query_length <= max_length) & (key_length <= max_length) {
+            *attention_scores += self
+                .bias
+                .slice(1, 0, query_length, 1)
+                .slice(2, 0, key_length, 1)
+                .to_kind(attention_scores.kind());
+        } else {
+            // The bias only depends on relative positions and extrapolates beyond the maximum length
+            apply_alibi_bias(attention_scores, &self.slopes);
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct MultiHeadSelfAttention {
     n_heads: i64,
+    num_key_value_heads: i64,
     dim_per_head: i64,
     dropout: Dropout,
     output_attentions: bool,
-    q_lin: nn::Linear,
+    q_lin: LoraOrLinear,
     k_lin: nn::Linear,
-    v_lin: nn::Linear,
+    v_lin: LoraOrLinear,
     out_lin: nn::Linear,
+    rotary_embedding: Option<RotaryEmbedding>,
+    alibi: Option<ALiBi>,
 }
 
 impl MultiHeadSelfAttention {
@@ -33,28 +72,52 @@ impl MultiHeadSelfAttention {
         P: Borrow<nn::Path<'p>>,
     {
         let p = p.borrow();
-        let q_lin = nn::linear(p / "q_lin", config.dim, config.dim, Default::default());
-        let k_lin = nn::linear(p / "k_lin", config.dim, config.dim, Default::default());
-        let v_lin = nn::linear(p / "v_lin", config.dim, config.dim, Default::default());
+        let dim_per_head = config.dim / config.n_heads;
+        let num_key_value_heads = config.num_key_value_heads.unwrap_or(config.n_heads);
+        let kv_dim = num_key_value_heads * dim_per_head;
+
+        let q_lin = LoraOrLinear::new(p / "q_lin", config.dim, config.dim, config.lora.as_ref());
+        let k_lin = nn::linear(p / "k_lin", config.dim, kv_dim, Default::default());
+        let v_lin = LoraOrLinear::new(p / "v_lin", config.dim, kv_dim, config.lora.as_ref());
         let out_lin = nn::linear(p / "out_lin", config.dim, config.dim, Default::default());
 
         let dropout = Dropout::new(config.attention_dropout);
         let output_attentions = config.output_attentions.unwrap_or(false);
+        let rotary_embedding = match config.get_position_encoding() {
+            PositionEncoding::Rotary { base, scaling } => Some(RotaryEmbedding::new(
+                dim_per_head,
+                base,
+                scaling,
+                p.device(),
+            )),
+            _ => None,
+        };
+        let alibi = match config.get_position_encoding() {
+            PositionEncoding::ALiBi => Some(ALiBi::new(
+                config.n_heads,
+                config.max_position_embeddings,
+                p.device(),
+            )),
+            _ => None,
+        };
 
         MultiHeadSelfAttention {
             n_heads: config.n_heads,
-            dim_per_head: config.dim / config.n_heads,
+            num_key_value_heads,
+            dim_per_head,
             dropout,
             output_attentions,
             q_lin,
             k_lin,
             v_lin,
             out_lin,
+            rotary_embedding,
+            alibi,
         }
     }
 
-    fn split_heads(&self, x: Tensor, bs: i64, dim_per_head: i64) -> Tensor {
-        x.view((bs, -1, self.n_heads, dim_per_head)).transpose(1, 2)
+    fn split_heads(&self, x: Tensor, bs: i64, n_heads: i64, dim_per_head: i64) -> Tensor {
+        x.view((bs, -1, n_heads, dim_per_head)).transpose(1, 2)
     }
 
     fn flatten(&self, x: Tensor, bs: i64, dim_per_head: i64) -> Tensor {
@@ -74,20 +137,46 @@ impl MultiHeadSelfAttention {
         let bs = query.size()[0];
         let k_length = key.size()[1];
 
-        let q = self.split_heads(query.apply(&self.q_lin), bs, self.dim_per_head);
-        let k = self.split_heads(key.apply(&self.k_lin), bs, self.dim_per_head);
-        let v = self.split_heads(value.apply(&self.v_lin), bs, self.dim_per_head);
+        let q = self.split_heads(
+            query.apply(&self.q_lin),
+            bs,
+            self.n_heads,
+            self.dim_per_head,
+        );
+        let k = self.split_heads(
+            key.apply(&self.k_lin),
+            bs,
+            self.num_key_value_heads,
+            self.dim_per_head,
+        );
+        let v = self.split_heads(
+            value.apply(&self.v_lin),
+            bs,
+            self.num_key_value_heads,
+            self.dim_per_head,
+        );
+        let (q, k) = if let Some(rotary_embedding) = &self.rotary_embedding {
+            let position_ids = Tensor::arange(k_length, (Kind::Int64, q.device()));
+            rotary_embedding.apply_rotary_embeddings(&q, &k, &position_ids)
+        } else {
+            (q, k)
+        };
+        let n_rep = self.n_heads / self.num_key_value_heads;
+        let (k, v) = (repeat_kv(&k, n_rep), repeat_kv(&v, n_rep));
         let q: Tensor = q / (self.dim_per_head as f64).sqrt();
 
+        let mut unmasked_scores = q.matmul(&k.transpose(2, 3));
+        if let Some(alibi) = &self.alibi {
+            alibi.add_bias(&mut unmasked_scores);
+        }
         let scores = if let Some(mask) = mask {
-            let unmasked_scores = q.matmul(&k.transpose(2, 3));
             let mask = mask
                 .le_tensor(&(mask.zeros_like() + 0.1))
                 .view((bs, 1i64, 1i64, k_length))
                 .expand_as(&unmasked_scores);
             unmasked_scores.masked_fill(&mask, f64::NEG_INFINITY)
         } else {
-            q.matmul(&k.transpose(2, 3))
+            unmasked_scores
         };
 
         let weights = scores
@@ -13,8 +13,11 @@
 extern crate tch;
 
 use self::tch::{nn, Tensor};
-use crate::common::activations::Activation;
+use crate::common::activations::{Activation, TensorFunction};
+use crate::common::attention_utils::PositionEncoding;
 use crate::common::dropout::Dropout;
+use crate::common::lora::LoraConfig;
+use crate::common::normalization::NormType;
 use crate::distilbert::embeddings::DistilBertEmbedding;
 use crate::distilbert::transformer::{DistilBertTransformerOutput, Transformer};
 use crate::{Config, RustBertError};
@@ -99,11 +102,74 @@ impl DistilBertVocabResources {
     );
 }
 
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, Serialize, Deserialize, Copy)]
+/// # Activation function of the DistilBERT feed-forward layers
+/// Extends the common activation functions with gated linear units.
+pub enum DistilBertActivation {
+    /// Gaussian Error Linear Unit ([Hendrycks et al., 2016,](https://arxiv.org/abs/1606.08415))
+    gelu,
+    /// Rectified Linear Unit
+    relu,
+    /// Swish ([Ramachandran, 2017](https://arxiv.org/abs/1710.05941))
+    swish,
+    /// Mish ([Misra, 2019](https://arxiv.org/abs/1908.08681))
+    mish,
+    /// Gaussian Error Linear Unit (New) ([Hendrycks et al., 2016,](https://arxiv.org/abs/1606.08415))
+    gelu_new,
+    /// Tanh
+    tanh,
+    /// Identity
+    identity,
+    /// Swish-gated linear unit ([Shazeer, 2020](https://arxiv.org/abs/2002.05202)): the swish activation of a gate projection is multiplied element-wise with the input projection
+    swiglu,
+    /// GELU-gated linear unit ([Shazeer, 2020](https://arxiv.org/abs/2002.05202)): the GELU activation of a gate projection is multiplied element-wise with the input projection
+    geglu,
+}
+
+impl DistilBertActivation {
+    /// Returns the activation function. For gated linear units, this is the activation applied to the gate projection.
+    pub fn get_function(&self) -> TensorFunction {
+        match self {
+            DistilBertActivation::gelu | DistilBertActivation::geglu => Activation::gelu,
+            DistilBertActivation::relu => Activation::relu,
+            DistilBertActivation::swish | DistilBertActivation::swiglu => Activation::swish,
+            DistilBertActivation::mish => Activation::mish,
+            DistilBertActivation::gelu_new => Activation::gelu_new,
+            DistilBertActivation::tanh => Activation::tanh,
+            DistilBertActivation::identity => Activation::identity,
+        }
+        .get_function()
+    }
+
+    /// Returns `true` for gated linear units, requiring an additional gate projection in the feed-forward layers
+    pub fn is_gated(&self) -> bool {
+        matches!(
+            self,
+            DistilBertActivation::swiglu | DistilBertActivation::geglu
+        )
+    }
+}
+
+impl From<Activation> for DistilBertActivation {
+    fn from(activation: Activation) -> Self {
+        match activation {
+            Activation::gelu => DistilBertActivation::gelu,
+            Activation::relu => DistilBertActivation::relu,
+            Activation::swish => DistilBertActivation::swish,
+            Activation::mish => DistilBertActivation::mish,
+            Activation::gelu_new => DistilBertActivation::gelu_new,
+            Activation::tanh => DistilBertActivation::tanh,
+            Activation::identity => DistilBertActivation::identity,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 /// # DistilBERT model configuration
 /// Defines the DistilBERT model architecture (e.g. number of layers, hidden layer size, label mapping...)
 pub struct DistilBertConfig {
-    pub activation: Activation,
+    pub activation: DistilBertActivation,
     pub attention_dropout: f64,
     pub dim: i64,
     pub dropout: f64,
@@ -115,9 +181,26 @@ pub struct DistilBertConfig {
     pub max_position_embeddings: i64,
     pub n_heads: i64,
     pub n_layers: i64,
+    /// Normalization layers of the transformer blocks, defaults to `NormType::LayerNorm`.
+    pub norm_type: Option<NormType>,
+    /// Normalize the inputs of the attention and feed-forward layers (Pre-Norm) instead of the residual outputs (Post-Norm).
+    /// Defaults to `true` for `NormType::RMSNorm` and to `false` (original DistilBERT architecture) otherwise.
+    /// Pre-Norm models apply a final normalization (`transformer.final_layer_norm`) to the output of the last block.
+    pub norm_first: Option<bool>,
+    /// Number of key/value heads for grouped query attention (must divide `n_heads`). Defaults to `n_heads` (multi-head attention), `1` corresponds to multi-query attention.
+    pub num_key_value_heads: Option<i64>,
+    /// Replace the feed-forward layers of the transformer blocks by mixture-of-experts layers. Defaults to `false`.
+    pub use_moe: Option<bool>,
+    /// Number of experts of the mixture-of-experts layers (if `use_moe`), defaults to 8.
+    pub num_experts: Option<i64>,
+    /// Number of experts processing every token in the mixture-of-experts layers (if `use_moe`), defaults to 2.
+    pub num_experts_per_tok: Option<i64>,
+    /// Adds LoRA adapters to the query and value projections of the self-attention layers
+    pub lora: Option<LoraConfig>,
     pub output_attentions: Option<bool>,
     pub output_hidden_states: Option<bool>,
     pub output_past: Option<bool>,
+    pub position_encoding: Option<PositionEncoding>,
     pub qa_dropout: f64,
     pub seq_classif_dropout: f64,
     pub sinusoidal_pos_embds: bool,
@@ -127,10 +210,51 @@ pub struct DistilBertConfig {
 
 impl Config for DistilBertConfig {}
 
+impl DistilBertConfig {
+    /// Checks the consistency of the configuration, returning a `RustBertError::InvalidConfigurationError`
+    /// describing the first invalid parameter otherwise. Called by the pipelines when loading the configuration.
+    pub fn validate(&self) -> Result<(), RustBertError> {
+        let num_key_value_heads = self.num_key_value_heads.unwrap_or(self.n_heads);
+        if (num_key_value_heads <= 0) || (self.n_heads % num_key_value_heads != 0) {
+            return Err(RustBertError::InvalidConfigurationError(format!(
+                "num_key_value_heads must be a positive divisor of n_heads = {}, got {num_key_value_heads}",
+                self.n_heads
+            )));
+        }
+        if self.use_moe.unwrap_or(false) {
+            let num_experts = self.num_experts.unwrap_or(8);
+            let num_experts_per_tok = self.num_experts_per_tok.unwrap_or(2);
+            if !(1..=num_experts).contains(&num_experts_per_tok) {
+                return Err(RustBertError::InvalidConfigurationError(format!(
+                    "num_experts_per_tok must be in [1, num_experts = {num_experts}], got {num_experts_per_tok}"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Pre-Norm transformer blocks, defaulting to `true` for `NormType::RMSNorm` and to `false` otherwise
+    pub(crate) fn is_norm_first(&self) -> bool {
+        self.norm_first
+            .unwrap_or(self.norm_type == Some(NormType::RMSNorm))
+    }
+
+    /// Position encoding of the model, defaulting to absolute (sinusoidal or learned) position
+    /// embeddings based on `sinusoidal_pos_embds` if not provided
+    pub(crate) fn get_position_encoding(&self) -> PositionEncoding {
+        self.position_encoding
+            .unwrap_or(if self.sinusoidal_pos_embds {
+                PositionEncoding::Absolute
+            } else {
+                PositionEncoding::Learned
+            })
+    }
+}
+
 impl Default for DistilBertConfig {
     fn default() -> Self {
         DistilBertConfig {
-            activation: Activation::gelu,
+            activation: DistilBertActivation::gelu,
             attention_dropout: 0.1,
             dim: 768,
             dropout: 0.1,
@@ -142,9 +266,17 @@ impl Default for DistilBertConfig {
             max_position_embeddings: 512,
             n_heads: 12,
             n_layers: 6,
+            norm_type: None,
+            norm_first: None,
+            num_key_value_heads: None,
+            use_moe: None,
+            num_experts: None,
+            num_experts_per_tok: None,
+            lora: None,
             output_attentions: None,
             output_hidden_states: None,
             output_past: None,
+            position_encoding: None,
             qa_dropout: 0.1,
             seq_classif_dropout: 0.2,
             sinusoidal_pos_embds: false,
@@ -215,6 +347,7 @@ impl DistilBertModel {
     ///   - `hidden_state` - `Tensor` of shape (*batch size*, *sequence_length*, *hidden_size*)
     ///   - `all_hidden_states` - `Option<Vec<Tensor>>` of length *num_hidden_layers* with shape (*batch size*, *sequence_length*, *hidden_size*)
     ///   - `all_attentions` - `Option<Vec<Tensor>>` of length *num_hidden_layers* with shape (*batch size*, *sequence_length*, *hidden_size*)
+    ///   - `router_aux_loss` - `Option<Tensor>` load-balancing auxiliary loss of the mixture-of-experts layers (if `use_moe`)
     ///
     /// # Example
     ///
@@ -392,6 +525,7 @@ impl DistilBertModelClassifier {
             logits,
             all_hidden_states: base_model_output.all_hidden_states,
             all_attentions: base_model_output.all_attentions,
+            router_aux_loss: base_model_output.router_aux_loss,
         })
     }
 }
@@ -528,6 +662,7 @@ impl DistilBertModelMaskedLM {
             prediction_scores,
             all_hidden_states: base_model_output.all_hidden_states,
             all_attentions: base_model_output.all_attentions,
+            router_aux_loss: base_model_output.router_aux_loss,
         })
     }
 }
@@ -651,6 +786,7 @@ impl DistilBertForQuestionAnswering {
             end_logits,
             all_hidden_states: base_model_output.all_hidden_states,
             all_attentions: base_model_output.all_attentions,
+            router_aux_loss: base_model_output.router_aux_loss,
         })
     }
 }
@@ -781,6 +917,7 @@ impl DistilBertForTokenClassification {
             logits,
             all_hidden_states: base_model_output.all_hidden_states,
             all_attentions: base_model_output.all_attentions,
+            router_aux_loss: base_model_output.router_aux_loss,
         })
     }
 }
@@ -797,6 +934,8 @@ pub struct DistilBertMaskedLMOutput {
     pub all_hidden_states: Option<Vec<Tensor>>,
     /// Attention weights for all intermediate layers
     pub all_attentions: Option<Vec<Tensor>>,
+    /// Load-balancing auxiliary loss of the mixture-of-experts layers summed over the layers (if `use_moe`)
+    pub router_aux_loss: Option<Tensor>,
 }
 
 /// Container for the DistilBERT sequence classification model output
@@ -807,6 +946,8 @@ pub struct DistilBertSequenceClassificationOutput {
     pub all_hidden_states: Option<Vec<Tensor>>,
     /// Attention weights for all intermediate layers
     pub all_attentions: Option<Vec<Tensor>>,
+    /// Load-balancing auxiliary loss of the mixture-of-experts layers summed over the layers (if `use_moe`)
+    pub router_aux_loss: Option<Tensor>,
 }
 
 /// Container for the DistilBERT token classification model output
@@ -817,6 +958,8 @@ pub struct DistilBertTokenClassificationOutput {
     pub all_hidden_states: Option<Vec<Tensor>>,
     /// Attention weights for all intermediate layers
     pub all_attentions: Option<Vec<Tensor>>,
+    /// Load-balancing auxiliary loss of the mixture-of-experts layers summed over the layers (if `use_moe`)
+    pub router_aux_loss: Option<Tensor>,
 }
 
 /// Container for the DistilBERT question answering model output
@@ -829,4 +972,6 @@ pub struct DistilBertQuestionAnsweringOutput {
     pub all_hidden_states: Option<Vec<Tensor>>,
     /// Attention weights for all intermediate layers
     pub all_attentions: Option<Vec<Tensor>>,
+    /// Load-balancing auxiliary loss of the mixture-of-experts layers summed over the layers (if `use_moe`)
+    pub router_aux_loss: Option<Tensor>,
 }
@@ -10,6 +10,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::common::attention_utils::PositionEncoding;
 use crate::common::dropout::Dropout;
 use crate::common::embeddings::process_ids_embeddings_pair;
 use crate::distilbert::distilbert_model::DistilBertConfig;
@@ -77,7 +78,7 @@ where
 #[derive(Debug)]
 pub struct DistilBertEmbedding {
     word_embeddings: nn::Embedding,
-    position_embeddings: nn::Embedding,
+    position_embeddings: Option<nn::Embedding>,
     layer_norm: nn::LayerNorm,
     dropout: Dropout,
 }
@@ -100,14 +101,20 @@ impl DistilBertEmbedding {
             config.dim,
             embedding_config,
         );
-        let position_embeddings: nn::Embedding = match config.sinusoidal_pos_embds {
-            false => embedding(
+        let position_embeddings = match config.get_position_encoding() {
+            PositionEncoding::Learned => Some(embedding(
                 p / "position_embeddings",
                 config.max_position_embeddings,
                 config.dim,
                 embedding_config,
-            ),
-            true => create_sinusoidal_embeddings(config, p / "position_embeddings", p.device()),
+            )),
+            PositionEncoding::Absolute => Some(create_sinusoidal_embeddings(
+                config,
+                p / "position_embeddings",
+                p.device(),
+            )),
+            // Relative position information is injected in the attention layers
+            PositionEncoding::Rotary { .. } | PositionEncoding::ALiBi => None,
         };
         let layer_norm_config = nn::LayerNormConfig {
             eps: 1e-12,
@@ -142,14 +149,16 @@ impl DistilBertEmbedding {
             process_ids_embeddings_pair(input_ids, input_embeds, &self.word_embeddings)?;
         let word_embeds = input_embeds.unwrap_or_else(|| calc_input_embeddings.as_ref().unwrap());
 
-        let seq_length = input_size[1];
-        let position_ids = Tensor::arange(seq_length, (Kind::Int64, device));
-        let position_ids = position_ids
-            .unsqueeze(0)
-            .expand(input_size.as_slice(), true);
-        let position_embed = position_ids.apply(&self.position_embeddings);
-
-        let embeddings = word_embeds + position_embed;
+        let embeddings = if let Some(position_embeddings) = &self.position_embeddings {
+            let seq_length = input_size[1];
+            let position_ids = Tensor::arange(seq_length, (Kind::Int64, device));
+            let position_ids = position_ids
+                .unsqueeze(0)
+                .expand(input_size.as_slice(), true);
+            word_embeds + position_ids.apply(position_embeddings)
+        } else {
+            word_embeds.shallow_clone()
+        };
         Ok(embeddings
             .apply(&self.layer_norm)
             .apply_t(&self.dropout, train))
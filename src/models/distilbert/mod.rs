@@ -59,9 +59,10 @@ mod embeddings;
 mod transformer;
 
 pub use distilbert_model::{
-    DistilBertConfig, DistilBertConfigResources, DistilBertForQuestionAnswering,
-    DistilBertForSentenceEmbeddings, DistilBertForTokenClassification, DistilBertMaskedLMOutput,
-    DistilBertModel, DistilBertModelClassifier, DistilBertModelMaskedLM, DistilBertModelResources,
+    DistilBertActivation, DistilBertConfig, DistilBertConfigResources,
+    DistilBertForQuestionAnswering, DistilBertForSentenceEmbeddings,
+    DistilBertForTokenClassification, DistilBertMaskedLMOutput, DistilBertModel,
+    DistilBertModelClassifier, DistilBertModelMaskedLM, DistilBertModelResources,
     DistilBertQuestionAnsweringOutput, DistilBertSequenceClassificationOutput,
     DistilBertTokenClassificationOutput, DistilBertVocabResources,
 };
@@ -12,14 +12,18 @@
 
 use crate::common::activations::TensorFunction;
 use crate::common::dropout::Dropout;
+use crate::common::moe::MoEFeedForwardNetwork;
+use crate::common::normalization::{NormType, Normalization};
 use crate::distilbert::attention::MultiHeadSelfAttention;
 use crate::distilbert::distilbert_model::DistilBertConfig;
 use std::borrow::{Borrow, BorrowMut};
-use tch::nn::LayerNorm;
+use tch::nn::ModuleT;
 use tch::{nn, Tensor};
 
+#[derive(Debug)]
 pub struct FeedForwardNetwork {
     lin1: nn::Linear,
+    lin_gate: Option<nn::Linear>,
     lin2: nn::Linear,
     dropout: Dropout,
     activation: TensorFunction,
@@ -31,40 +35,76 @@ impl FeedForwardNetwork {
         P: Borrow<nn::Path<'p>>,
     {
         let p = p.borrow();
-        let lin1 = nn::linear(
-            p / "lin1",
-            config.dim,
-            config.hidden_dim,
-            Default::default(),
-        );
-        let lin2 = nn::linear(
-            p / "lin2",
-            config.hidden_dim,
-            config.dim,
-            Default::default(),
-        );
+        // Gated linear units use 2/3 of the hidden dimension to keep the number of parameters
+        // of the feed-forward layer unchanged with the additional gate projection
+        let hidden_dim = if config.activation.is_gated() {
+            2 * config.hidden_dim / 3
+        } else {
+            config.hidden_dim
+        };
+        let lin1 = nn::linear(p / "lin1", config.dim, hidden_dim, Default::default());
+        let lin_gate = if config.activation.is_gated() {
+            Some(nn::linear(
+                p / "lin_gate",
+                config.dim,
+                hidden_dim,
+                Default::default(),
+            ))
+        } else {
+            None
+        };
+        let lin2 = nn::linear(p / "lin2", hidden_dim, config.dim, Default::default());
         let dropout = Dropout::new(config.dropout);
         let activation = config.activation.get_function();
         FeedForwardNetwork {
             lin1,
+            lin_gate,
             lin2,
             dropout,
             activation,
         }
     }
+}
 
-    pub fn forward_t(&self, input: &Tensor, train: bool) -> Tensor {
-        (self.activation.get_fn())(&input.apply(&self.lin1))
+impl ModuleT for FeedForwardNetwork {
+    fn forward_t(&self, input: &Tensor, train: bool) -> Tensor {
+        let hidden_states = match &self.lin_gate {
+            Some(lin_gate) => {
+                (self.activation.get_fn())(&input.apply(lin_gate)) * input.apply(&self.lin1)
+            }
+            None => (self.activation.get_fn())(&input.apply(&self.lin1)),
+        };
+        hidden_states
             .apply(&self.lin2)
             .apply_t(&self.dropout, train)
     }
 }
 
+/// Dense or mixture-of-experts feed-forward layer of a transformer block
+enum FeedForwardLayer {
+    Dense(FeedForwardNetwork),
+    MoE(MoEFeedForwardNetwork<FeedForwardNetwork>),
+}
+
+impl FeedForwardLayer {
+    /// Returns the output of the layer and the load-balancing auxiliary loss of mixture-of-experts layers
+    fn forward_t(&self, input: &Tensor, train: bool) -> (Tensor, Option<Tensor>) {
+        match self {
+            FeedForwardLayer::Dense(ffn) => (ffn.forward_t(input, train), None),
+            FeedForwardLayer::MoE(ffn) => {
+                let (output, aux_loss) = ffn.forward_t(input, train);
+                (output, Some(aux_loss))
+            }
+        }
+    }
+}
+
 pub struct TransformerBlock {
     attention: MultiHeadSelfAttention,
-    sa_layer_norm: LayerNorm,
-    ffn: FeedForwardNetwork,
-    output_layer_norm: LayerNorm,
+    sa_layer_norm: Normalization,
+    ffn: FeedForwardLayer,
+    output_layer_norm: Normalization,
+    norm_first: bool,
 }
 
 impl TransformerBlock {
@@ -75,34 +115,61 @@ impl TransformerBlock {
         let p = p.borrow();
 
         let attention = MultiHeadSelfAttention::new(p / "attention", config);
-        let layer_norm_config = nn::LayerNormConfig {
-            eps: 1e-12,
-            ..Default::default()
+        let norm_type = config.norm_type.unwrap_or(NormType::LayerNorm);
+        let sa_layer_norm = Normalization::new(p / "sa_layer_norm", norm_type, config.dim, 1e-12);
+        let ffn = if config.use_moe.unwrap_or(false) {
+            FeedForwardLayer::MoE(MoEFeedForwardNetwork::new(
+                p / "ffn",
+                config.dim,
+                config.num_experts.unwrap_or(8),
+                config.num_experts_per_tok.unwrap_or(2),
+                |p| FeedForwardNetwork::new(p, config),
+            ))
+        } else {
+            FeedForwardLayer::Dense(FeedForwardNetwork::new(p / "ffn", config))
         };
-        let sa_layer_norm =
-            nn::layer_norm(p / "sa_layer_norm", vec![config.dim], layer_norm_config);
-        let ffn = FeedForwardNetwork::new(p / "ffn", config);
         let output_layer_norm =
-            nn::layer_norm(p / "output_layer_norm", vec![config.dim], layer_norm_config);
+            Normalization::new(p / "output_layer_norm", norm_type, config.dim, 1e-12);
+        let norm_first = config.is_norm_first();
 
         TransformerBlock {
             attention,
             sa_layer_norm,
             ffn,
             output_layer_norm,
+            norm_first,
         }
     }
 
+    /// Returns the output of the block, the attention weights (if `output_attentions`) and the load-balancing
+    /// auxiliary loss of the feed-forward layer (if `use_moe`)
     pub fn forward_t(
         &self,
         input: &Tensor,
         mask: Option<&Tensor>,
         train: bool,
-    ) -> (Tensor, Option<Tensor>) {
-        let (output, sa_weights) = self.attention.forward_t(input, input, input, mask, train);
-        let output = (input + &output).apply(&self.sa_layer_norm);
-        let output = (&output + self.ffn.forward_t(&output, train)).apply(&self.output_layer_norm);
-        (output, sa_weights)
+    ) -> (Tensor, Option<Tensor>, Option<Tensor>) {
+        if self.norm_first {
+            let normalized_input = input.apply(&self.sa_layer_norm);
+            let (output, sa_weights) = self.attention.forward_t(
+                &normalized_input,
+                &normalized_input,
+                &normalized_input,
+                mask,
+                train,
+            );
+            let output = input + output;
+            let (ffn_output, aux_loss) = self
+                .ffn
+                .forward_t(&output.apply(&self.output_layer_norm), train);
+            (output + ffn_output, sa_weights, aux_loss)
+        } else {
+            let (output, sa_weights) = self.attention.forward_t(input, input, input, mask, train);
+            let output = (input + &output).apply(&self.sa_layer_norm);
+            let (ffn_output, aux_loss) = self.ffn.forward_t(&output, train);
+            let output = (output + ffn_output).apply(&self.output_layer_norm);
+            (output, sa_weights, aux_loss)
+        }
     }
 }
 
@@ -110,6 +177,7 @@ pub struct Transformer {
     output_attentions: bool,
     output_hidden_states: bool,
     layers: Vec<TransformerBlock>,
+    final_layer_norm: Option<Normalization>,
 }
 
 impl Transformer {
@@ -117,19 +185,30 @@ impl Transformer {
     where
         P: Borrow<nn::Path<'p>>,
     {
-        let p = p.borrow() / "layer";
+        let p = p.borrow();
         let output_attentions = config.output_attentions.unwrap_or(false);
         let output_hidden_states = config.output_hidden_states.unwrap_or(false);
 
+        let p_layers = p / "layer";
         let mut layers: Vec<TransformerBlock> = vec![];
         for layer_index in 0..config.n_layers {
-            layers.push(TransformerBlock::new(&p / layer_index, config));
+            layers.push(TransformerBlock::new(&p_layers / layer_index, config));
         }
+        // The residual stream of Pre-Norm blocks is not normalized: a final normalization is applied after the last block
+        let final_layer_norm = config.is_norm_first().then(|| {
+            Normalization::new(
+                p / "final_layer_norm",
+                config.norm_type.unwrap_or(NormType::LayerNorm),
+                config.dim,
+                1e-12,
+            )
+        });
 
         Transformer {
             output_attentions,
             output_hidden_states,
             layers,
+            final_layer_norm,
         }
     }
 
@@ -153,6 +232,7 @@ impl Transformer {
         // let mut hidden_state = input.copy();
         let mut hidden_state: Option<Tensor> = None;
         let mut attention_weights: Option<Tensor>;
+        let mut router_aux_loss: Option<Tensor> = None;
 
         for layer in &self.layers {
             let temp = if let Some(hidden_state) = &hidden_state {
@@ -163,6 +243,12 @@ impl Transformer {
 
             hidden_state = Some(temp.0);
             attention_weights = temp.1;
+            if let Some(aux_loss) = temp.2 {
+                router_aux_loss = Some(match router_aux_loss {
+                    Some(router_aux_loss) => router_aux_loss + aux_loss,
+                    None => aux_loss,
+                });
+            }
             if let Some(attentions) = all_attentions.borrow_mut() {
                 attentions.push(std::mem::take(&mut attention_weights.unwrap()));
             };
@@ -171,10 +257,16 @@ impl Transformer {
             };
         }
 
+        let hidden_state = match &self.final_layer_norm {
+            Some(final_layer_norm) => hidden_state.unwrap().apply(final_layer_norm),
+            None => hidden_state.unwrap(),
+        };
+
         DistilBertTransformerOutput {
-            hidden_state: hidden_state.unwrap(),
+            hidden_state,
             all_hidden_states,
             all_attentions,
+            router_aux_loss,
         }
     }
 }
@@ -187,4 +279,6 @@ pub struct DistilBertTransformerOutput {
     pub all_hidden_states: Option<Vec<Tensor>>,
     /// Attention weights for all intermediate layers
     pub all_attentions: Option<Vec<Tensor>>,
+    /// Load-balancing auxiliary loss of the mixture-of-experts layers summed over the layers (if `use_moe`)
+    pub router_aux_loss: Option<Tensor>,
 }
@@ -193,6 +193,8 @@ impl ElectraModel {
             is_decoder: None,
             id2label: config.id2label.clone(),
             label2id: config.label2id.clone(),
+            use_crf: None,
+            lora: None,
         };
         let encoder = BertEncoder::new(p / "encoder", &bert_config);
         ElectraModel {
@@ -20,10 +20,16 @@ use crate::pipelines::common::{ModelType, TokenizerOption};
 use crate::pipelines::generation_utils::private_generation_utils::{
     PreparedInput, PrivateLanguageGenerator,
 };
-use crate::pipelines::generation_utils::{Cache, GenerateConfig, LMModelOutput, LanguageGenerator};
+use crate::pipelines::generation_utils::{
+    decode_generated_indices, Cache, GenerateConfig, GenerateOptions, GeneratedTextOutput,
+    LMModelOutput, LanguageGenerator,
+};
+#[cfg(feature = "remote")]
+use crate::{pipelines::common::ModelResource, resources::RemoteResource};
 use crate::{Config, RustBertError};
 use serde::{Deserialize, Serialize};
 use std::borrow::{Borrow, BorrowMut};
+use std::sync::atomic::{AtomicU64, Ordering};
 use tch::kind::Kind::Int64;
 use tch::nn::embedding;
 use tch::{nn, Device, Kind, Tensor};
@@ -558,6 +564,9 @@ impl GPT2LMHeadModel {
         Ok(LMModelOutput {
             lm_logits,
             cache: Cache::GPT2Cache(base_model_output.cache),
+            last_hidden_state: Some(base_model_output.output),
+            all_hidden_states: base_model_output.all_hidden_states,
+            all_attentions: base_model_output.all_attentions,
         })
     }
 }
@@ -575,6 +584,31 @@ pub struct Gpt2ModelOutput {
     pub all_attentions: Option<Vec<Tensor>>,
 }
 
+/// Source of the identifiers tying the prefix caches to the state of a `GPT2Generator`
+static NEXT_PREFIX_CACHE_ID: AtomicU64 = AtomicU64::new(0);
+
+fn next_prefix_cache_id() -> u64 {
+    NEXT_PREFIX_CACHE_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// # Cache of a prompt prefix
+/// Keys and values pre-computed by `GPT2Generator::build_prefix_cache` for a prefix shared by several prompts
+/// (e.g. a system prompt), used by `GPT2Generator::generate_with_prefix` to only process the prompt suffixes.
+/// A cache can only be used with the generator that built it, and is invalidated if the variables of the
+/// generator are modified (e.g. when the generator is moved to another device).
+pub struct PrefixCache {
+    prefix_ids: Tensor,
+    past: Vec<Tensor>,
+    generator_id: u64,
+}
+
+impl PrefixCache {
+    /// Number of tokens of the cached prefix
+    pub fn prefix_length(&self) -> i64 {
+        self.prefix_ids.size()[1]
+    }
+}
+
 /// # Language generation model based on the GPT2 architecture
 pub struct GPT2Generator {
     model: GPT2LMHeadModel,
@@ -588,6 +622,10 @@ pub struct GPT2Generator {
     vocab_size: i64,
     decoder_start_id: Option<i64>,
     max_position_embeddings: i64,
+    n_layer: i64,
+    n_head: i64,
+    head_dim: i64,
+    prefix_cache_id: u64,
 }
 
 impl GPT2Generator {
@@ -640,6 +678,38 @@ impl GPT2Generator {
         Self::new_with_tokenizer(generate_config, tokenizer)
     }
 
+    /// Build a new `GPT2Generator` from a model repository of the HuggingFace Hub, downloading (and caching)
+    /// its configuration, vocabulary, merges and weights (`rust_model.ot`) files. The other generation settings
+    /// are set to their default value.
+    ///
+    /// # Arguments
+    ///
+    /// * `model_name` - Name of the model repository on the Hub (e.g. `gpt2`, `gpt2-medium` or `distilgpt2`)
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::gpt2::GPT2Generator;
+    ///
+    /// let gpt2_generator = GPT2Generator::from_pretrained("distilgpt2")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "remote")]
+    pub fn from_pretrained(model_name: &str) -> Result<GPT2Generator, RustBertError> {
+        let hub_resource =
+            |file_name| Box::new(RemoteResource::from_hub(model_name, None, file_name));
+        let generate_config = GenerateConfig {
+            model_resource: ModelResource::Torch(hub_resource("rust_model.ot")),
+            config_resource: hub_resource("config.json"),
+            vocab_resource: hub_resource("vocab.json"),
+            merges_resource: Some(hub_resource("merges.txt")),
+            ..Default::default()
+        };
+        Self::new(generate_config)
+    }
+
     pub fn new_with_tokenizer(
         generate_config: GenerateConfig,
         tokenizer: TokenizerOption,
@@ -647,7 +717,7 @@ impl GPT2Generator {
         let config_path = generate_config.config_resource.get_local_path()?;
         let device = generate_config.device;
 
-        generate_config.validate();
+        generate_config.validate()?;
         let mut var_store = nn::VarStore::new(device);
 
         let config = Gpt2Config::from_file(config_path);
@@ -666,6 +736,9 @@ impl GPT2Generator {
         let is_encoder_decoder = false;
         let vocab_size = config.vocab_size;
         let decoder_start_id = config.decoder_start_token_id;
+        let n_layer = config.n_layer;
+        let n_head = config.n_head;
+        let head_dim = config.n_embd / config.n_head;
 
         Ok(GPT2Generator {
             model,
@@ -679,8 +752,241 @@ impl GPT2Generator {
             vocab_size,
             decoder_start_id,
             max_position_embeddings,
+            n_layer,
+            n_head,
+            head_dim,
+            prefix_cache_id: next_prefix_cache_id(),
+        })
+    }
+
+    /// Overrides the beginning of sequence token id of the generator (by default, the BOS token of the tokenizer).
+    pub fn with_bos_token_id(mut self, bos_token_id: Option<i64>) -> Self {
+        self.bos_token_id = bos_token_id;
+        self
+    }
+
+    /// Overrides the end of sequence token ids of the generator (by default, the EOS token of the tokenizer).
+    /// The generation of a sequence stops when one of these tokens is generated. Token names can be converted
+    /// to ids with `get_token_id`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::gpt2::GPT2Generator;
+    /// use rust_bert::pipelines::generation_utils::LanguageGenerator;
+    ///
+    /// let generator = GPT2Generator::new(Default::default())?;
+    /// let eos_token_id = generator.get_token_id("<|stop|>")?;
+    /// let generator = generator.with_eos_token_ids(Some(vec![eos_token_id]));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_eos_token_ids(mut self, eos_token_ids: Option<Vec<i64>>) -> Self {
+        self.eos_token_ids = eos_token_ids.filter(|eos_token_ids| !eos_token_ids.is_empty());
+        self
+    }
+
+    /// Overrides the padding token id of the generator (by default, the PAD token of the tokenizer). Prompts
+    /// are padded with the first end of sequence token if no padding token is set.
+    pub fn with_pad_token_id(mut self, pad_token_id: Option<i64>) -> Self {
+        self.pad_token_id = pad_token_id;
+        self
+    }
+
+    /// Runs the model on a prompt to pre-compute its cache of past keys and values, that can be re-used
+    /// to generate several continuations of the prompt with `generate_with_cache`.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt_text` - Text prompt, encoded without special tokens
+    ///
+    /// # Returns
+    /// * `Tensor` Prompt token ids of shape (*1*, *prompt length*)
+    /// * `Vec<Tensor>` Cached keys and values for every layer, holding all positions of the prompt
+    /// * `Tensor` Logits of the token following the prompt, of shape (*1*, *vocab size*)
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::gpt2::GPT2Generator;
+    ///
+    /// let gpt2_generator = GPT2Generator::new(Default::default())?;
+    /// let (prompt_ids, past, next_token_logits) =
+    ///     gpt2_generator.forward_prompt("The weather today is")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn forward_prompt(
+        &self,
+        prompt_text: &str,
+    ) -> Result<(Tensor, Vec<Tensor>, Tensor), RustBertError> {
+        let token_ids = self
+            .tokenizer
+            .convert_tokens_to_ids(&self.tokenizer.tokenize(prompt_text));
+        if token_ids.is_empty() {
+            return Err(RustBertError::ValueError(
+                "The prompt must contain at least one token".to_string(),
+            ));
+        }
+        let input_ids = Tensor::from_slice(&token_ids)
+            .unsqueeze(0)
+            .to(self.get_device());
+        let model_output = tch::no_grad(|| {
+            self.model
+                .forward_t(Some(&input_ids), None, None, None, None, None, false)
+        })?;
+        let past = match model_output.cache {
+            Cache::GPT2Cache(Some(past)) => past,
+            _ => unreachable!(),
+        };
+        Ok((input_ids, past, model_output.lm_logits.select(1, -1)))
+    }
+
+    /// Pre-computes the cache of a prefix shared by several prompts (e.g. a system prompt or instructions),
+    /// that can be re-used by `generate_with_prefix` to avoid processing the prefix for every request.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix_text` - Text of the prefix, encoded without special tokens
+    ///
+    /// # Returns
+    /// * `PrefixCache` Cache of the prefix, only valid for this generator
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::gpt2::GPT2Generator;
+    /// use rust_bert::pipelines::generation_utils::GenerateOptions;
+    ///
+    /// let gpt2_generator = GPT2Generator::new(Default::default())?;
+    /// let prefix_cache =
+    ///     gpt2_generator.build_prefix_cache("Answer the following question in one sentence.")?;
+    /// let generate_options = GenerateOptions {
+    ///     max_new_tokens: Some(32),
+    ///     ..Default::default()
+    /// };
+    ///
+    /// for question in [" What is the capital of France?", " Who wrote Hamlet?"] {
+    ///     let output = gpt2_generator.generate_with_prefix(
+    ///         &prefix_cache,
+    ///         &[question],
+    ///         Some(generate_options),
+    ///     )?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn build_prefix_cache(&self, prefix_text: &str) -> Result<PrefixCache, RustBertError> {
+        let (prefix_ids, past, _) = self.forward_prompt(prefix_text)?;
+        Ok(PrefixCache {
+            prefix_ids,
+            past,
+            generator_id: self.prefix_cache_id,
         })
     }
+
+    /// Generates text for prompts starting with a cached prefix: only the prompt suffixes are processed before
+    /// the generation starts. The prefix cache is broadcast to all prompts, that are padded between the prefix
+    /// and their suffix. As the prompts include the prefix, the maximum generation length should be set with
+    /// `max_new_tokens` rather than `max_length`.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix_cache` - `PrefixCache` built by `build_prefix_cache` on this generator
+    /// * `prompt_suffixes` - Prompt texts following the prefix, encoded without special tokens
+    /// * `generate_options` - `Option<GenerateOptions>` Optional set of generate options. If not (or partially) provided, will use the settings provided when creating the generator
+    ///
+    /// # Returns
+    /// * `Vec<GeneratedTextOutput>` Vector of length *number_of_prompts* x *num_return_sequences* containing the generated texts
+    /// (including the prefix if `echo_prompt` is true).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::gpt2::GPT2Generator;
+    /// use rust_bert::pipelines::generation_utils::GenerateOptions;
+    ///
+    /// let gpt2_generator = GPT2Generator::new(Default::default())?;
+    /// let prefix_cache = gpt2_generator.build_prefix_cache("Once upon a time,")?;
+    /// let generate_options = GenerateOptions {
+    ///     max_new_tokens: Some(16),
+    ///     ..Default::default()
+    /// };
+    ///
+    /// let output = gpt2_generator.generate_with_prefix(
+    ///     &prefix_cache,
+    ///     &[" a dragon", " in a small village, a princess"],
+    ///     Some(generate_options),
+    /// )?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn generate_with_prefix<S>(
+        &self,
+        prefix_cache: &PrefixCache,
+        prompt_suffixes: &[S],
+        generate_options: Option<GenerateOptions>,
+    ) -> Result<Vec<GeneratedTextOutput>, RustBertError>
+    where
+        S: AsRef<str> + Send + Sync,
+    {
+        if prefix_cache.generator_id != self.prefix_cache_id {
+            return Err(RustBertError::ValueError(
+                "The prefix cache was built by another generator, or before the variables of this generator \
+                were modified (e.g. moved to another device): it must be built again"
+                    .to_string(),
+            ));
+        }
+        if prompt_suffixes.is_empty() {
+            return Ok(Vec::new());
+        }
+        let pad_token_id = self
+            .pad_token_id
+            .or_else(|| self.eos_token_ids.as_ref().map(|eos_ids| eos_ids[0]));
+        let (suffix_ids, suffix_attention_mask) =
+            self.encode_prompt_text(prompt_suffixes, None, pad_token_id);
+        if suffix_ids.size()[1] == 0 {
+            return Err(RustBertError::ValueError(
+                "At least one prompt suffix must contain a token".to_string(),
+            ));
+        }
+        let num_prompts = prompt_suffixes.len() as i64;
+        let prefix_length = prefix_cache.prefix_length();
+        let input_ids = Tensor::cat(
+            &[
+                prefix_cache
+                    .prefix_ids
+                    .expand([num_prompts, prefix_length], false),
+                suffix_ids,
+            ],
+            1,
+        );
+        let attention_mask = Tensor::cat(
+            &[
+                Tensor::ones([num_prompts, prefix_length], (Int64, self.get_device())),
+                suffix_attention_mask,
+            ],
+            1,
+        );
+        let past = Cache::GPT2Cache(Some(
+            prefix_cache
+                .past
+                .iter()
+                .map(|layer_past| layer_past.expand([-1, num_prompts, -1, -1, -1], false))
+                .collect(),
+        ));
+        let (indices_outputs, _) =
+            self.generate_with_cache(input_ids, Some(attention_mask), past, generate_options)?;
+        Ok(decode_generated_indices(
+            self,
+            indices_outputs,
+            generate_options,
+        ))
+    }
 }
 
 impl PrivateLanguageGenerator for GPT2Generator {
@@ -694,6 +1000,8 @@ impl PrivateLanguageGenerator for GPT2Generator {
         self.var_store.device()
     }
     fn get_var_store_mut(&mut self) -> Result<&mut nn::VarStore, RustBertError> {
+        // The variables may be modified: the existing prefix caches are invalidated
+        self.prefix_cache_id = next_prefix_cache_id();
         Ok(&mut self.var_store)
     }
     fn get_config(&self) -> &GenerateConfig {
@@ -824,6 +1132,37 @@ impl PrivateLanguageGenerator for GPT2Generator {
             }
         }
     }
+
+    fn get_cache_length(&self, past: &Cache, num_prompts: i64) -> Result<i64, RustBertError> {
+        let layer_pasts = match past {
+            Cache::None | Cache::GPT2Cache(None) => return Ok(0),
+            Cache::GPT2Cache(Some(layer_pasts)) => layer_pasts,
+            _ => {
+                return Err(RustBertError::ValueError(
+                    "Cache not compatible with GPT2 Model".into(),
+                ))
+            }
+        };
+        if layer_pasts.len() as i64 != self.n_layer {
+            return Err(RustBertError::ValueError(format!(
+                "The cache contains {} layers, expected {} (n_layer)",
+                layer_pasts.len(),
+                self.n_layer
+            )));
+        }
+        let cache_length = layer_pasts[0].size().get(3).copied().unwrap_or(-1);
+        for (layer_index, layer_past) in layer_pasts.iter().enumerate() {
+            let shape = layer_past.size();
+            if shape != [2, num_prompts, self.n_head, cache_length, self.head_dim] {
+                return Err(RustBertError::ValueError(format!(
+                    "Invalid cache shape {shape:?} for layer {layer_index}, expected [2, {num_prompts} (number of prompts), \
+                    {} (n_head), {cache_length} (cache length), {} (head dimension)]",
+                    self.n_head, self.head_dim
+                )));
+            }
+        }
+        Ok(cache_length)
+    }
 }
 
 impl LanguageGenerator for GPT2Generator {}
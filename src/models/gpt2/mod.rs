@@ -61,5 +61,5 @@ pub(crate) mod transformer;
 
 pub use gpt2_model::{
     GPT2Generator, GPT2LMHeadModel, Gpt2Config, Gpt2ConfigResources, Gpt2MergesResources,
-    Gpt2Model, Gpt2ModelOutput, Gpt2ModelResources, Gpt2VocabResources,
+    Gpt2Model, Gpt2ModelOutput, Gpt2ModelResources, Gpt2VocabResources, PrefixCache,
 };
@@ -512,6 +512,9 @@ impl GptJLMHeadModel {
         Ok(LMModelOutput {
             lm_logits,
             cache: Cache::GPTJCache(base_model_output.cache),
+            last_hidden_state: Some(base_model_output.output),
+            all_hidden_states: base_model_output.all_hidden_states,
+            all_attentions: base_model_output.all_attentions,
         })
     }
 }
@@ -601,7 +604,7 @@ impl GptJGenerator {
         let config_path = generate_config.config_resource.get_local_path()?;
         let device = generate_config.device;
 
-        generate_config.validate();
+        generate_config.validate()?;
         let mut var_store = nn::VarStore::new(device);
 
         let config = GptJConfig::from_file(config_path);
@@ -724,6 +727,9 @@ impl PrivateLanguageGenerator for GptJGenerator {
         Ok(LMModelOutput {
             lm_logits,
             cache: Cache::GPTJCache(base_model_output.cache),
+            last_hidden_state: Some(base_model_output.output),
+            all_hidden_states: base_model_output.all_hidden_states,
+            all_attentions: base_model_output.all_attentions,
         })
     }
 
@@ -668,7 +668,7 @@ impl GptNeoGenerator {
         let config_path = generate_config.config_resource.get_local_path()?;
         let device = generate_config.device;
 
-        generate_config.validate();
+        generate_config.validate()?;
         let mut var_store = nn::VarStore::new(device);
         let config = GptNeoConfig::from_file(config_path);
         let model = GptNeoForCausalLM::new(var_store.root(), &config)?;
@@ -783,6 +783,9 @@ impl PrivateLanguageGenerator for GptNeoGenerator {
         Ok(LMModelOutput {
             lm_logits: base_model_output.lm_logits,
             cache: Cache::GPTNeoCache(base_model_output.next_cache),
+            last_hidden_state: None,
+            all_hidden_states: base_model_output.all_hidden_states,
+            all_attentions: base_model_output.all_attentions,
         })
     }
     fn prepare_inputs_for_generation<'a>(
@@ -590,7 +590,7 @@ impl LongT5Generator {
         let config_path = generate_config.config_resource.get_local_path()?;
         let device = generate_config.device;
 
-        generate_config.validate();
+        generate_config.validate()?;
         let mut var_store = nn::VarStore::new(device);
 
         let config = LongT5Config::from_file(config_path);
@@ -713,6 +713,9 @@ impl PrivateLanguageGenerator for LongT5Generator {
         Ok(LMModelOutput {
             lm_logits: base_model_output.decoder_output,
             cache: Cache::LongT5Cache(base_model_output.next_cache),
+            last_hidden_state: None,
+            all_hidden_states: None,
+            all_attentions: None,
         })
     }
 
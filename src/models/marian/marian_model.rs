@@ -756,7 +756,7 @@ impl MarianGenerator {
         let config_path = generate_config.config_resource.get_local_path()?;
         let device = generate_config.device;
 
-        generate_config.validate();
+        generate_config.validate()?;
         let mut var_store = nn::VarStore::new(device);
 
         let config = BartConfig::from_file(config_path);
@@ -878,6 +878,9 @@ impl PrivateLanguageGenerator for MarianGenerator {
         Ok(LMModelOutput {
             lm_logits: base_model_output.decoder_output,
             cache: Cache::BARTCache(base_model_output.cache),
+            last_hidden_state: None,
+            all_hidden_states: None,
+            all_attentions: None,
         })
     }
 
@@ -886,7 +889,6 @@ impl PrivateLanguageGenerator for MarianGenerator {
         scores: &mut Tensor,
         current_length: i64,
         max_length: Option<i64>,
-        _forced_bos_token_id: Option<i64>,
     ) {
         let _ = scores.index_fill_(
             1,
@@ -407,6 +407,9 @@ impl OpenAIGPTLMHeadModel {
         Ok(LMModelOutput {
             lm_logits,
             cache: Cache::None,
+            last_hidden_state: Some(base_model_output.hidden_state),
+            all_hidden_states: base_model_output.all_hidden_states,
+            all_attentions: base_model_output.all_attentions,
         })
     }
 }
@@ -490,7 +493,7 @@ impl OpenAIGenerator {
         generate_config: GenerateConfig,
         tokenizer: TokenizerOption,
     ) -> Result<OpenAIGenerator, RustBertError> {
-        generate_config.validate();
+        generate_config.validate()?;
 
         let config_path = generate_config.config_resource.get_local_path()?;
         let device = generate_config.device;
@@ -527,6 +530,41 @@ impl OpenAIGenerator {
             max_position_embeddings,
         })
     }
+
+    /// Overrides the beginning of sequence token id of the generator (by default, the BOS token of the tokenizer).
+    pub fn with_bos_token_id(mut self, bos_token_id: Option<i64>) -> Self {
+        self.bos_token_id = bos_token_id;
+        self
+    }
+
+    /// Overrides the end of sequence token ids of the generator (by default, the EOS token of the tokenizer).
+    /// The generation of a sequence stops when one of these tokens is generated. Token names can be converted
+    /// to ids with `get_token_id`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::openai_gpt::OpenAIGenerator;
+    /// use rust_bert::pipelines::generation_utils::LanguageGenerator;
+    ///
+    /// let generator = OpenAIGenerator::new(Default::default())?;
+    /// let eos_token_id = generator.get_token_id("<|stop|>")?;
+    /// let generator = generator.with_eos_token_ids(Some(vec![eos_token_id]));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_eos_token_ids(mut self, eos_token_ids: Option<Vec<i64>>) -> Self {
+        self.eos_token_ids = eos_token_ids.filter(|eos_token_ids| !eos_token_ids.is_empty());
+        self
+    }
+
+    /// Overrides the padding token id of the generator (by default, the PAD token of the tokenizer). Prompts
+    /// are padded with the first end of sequence token if no padding token is set.
+    pub fn with_pad_token_id(mut self, pad_token_id: Option<i64>) -> Self {
+        self.pad_token_id = pad_token_id;
+        self
+    }
 }
 
 impl PrivateLanguageGenerator for OpenAIGenerator {
@@ -915,7 +915,7 @@ impl ProphetNetConditionalGenerator {
         let config_path = generate_config.config_resource.get_local_path()?;
         let device = generate_config.device;
 
-        generate_config.validate();
+        generate_config.validate()?;
         let mut var_store = nn::VarStore::new(device);
         let config = ProphetNetConfig::from_file(config_path);
         let model = ProphetNetForConditionalGeneration::new(var_store.root(), &config)?;
@@ -1033,6 +1033,9 @@ impl PrivateLanguageGenerator for ProphetNetConditionalGenerator {
         Ok(LMModelOutput {
             lm_logits: base_model_output.logits,
             cache: Cache::ProphetNetCache(base_model_output.next_decoder_cache),
+            last_hidden_state: None,
+            all_hidden_states: None,
+            all_attentions: None,
         })
     }
 
@@ -1052,7 +1052,7 @@ impl ReformerGenerator {
         let config_path = generate_config.config_resource.get_local_path()?;
         let device = generate_config.device;
 
-        generate_config.validate();
+        generate_config.validate()?;
         let mut var_store = nn::VarStore::new(device);
         let config = ReformerConfig::from_file(config_path);
         let model = ReformerModelWithLMHead::new(var_store.root(), &config)?;
@@ -1161,6 +1161,9 @@ impl PrivateLanguageGenerator for ReformerGenerator {
         Ok(LMModelOutput {
             lm_logits: output.logits,
             cache: Cache::ReformerCache(output.next_cache),
+            last_hidden_state: None,
+            all_hidden_states: output.all_hidden_states,
+            all_attentions: output.all_attentions,
         })
     }
 
@@ -787,6 +787,9 @@ impl XLNetLMHeadModel {
         Ok(LMModelOutput {
             lm_logits,
             cache: Cache::XLNetCache(base_model_output.next_cache),
+            last_hidden_state: None,
+            all_hidden_states: None,
+            all_attentions: None,
         })
     }
 }
@@ -1555,7 +1558,7 @@ impl XLNetGenerator {
         let config_path = generate_config.config_resource.get_local_path()?;
         let device = generate_config.device;
 
-        generate_config.validate();
+        generate_config.validate()?;
         let mut var_store = nn::VarStore::new(device);
 
         let config = XLNetConfig::from_file(config_path);
@@ -0,0 +1,236 @@
+// Copyright 2018 The Google AI Language Team Authors, Facebook AI Research authors.
+// Copyright 2018 Google AI, Google Brain and Carnegie Mellon University Authors and the HuggingFace Inc. team.
+// Copyright 2019 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Beam search utilities
+//! Book-keeping of the finished hypotheses for beam search decoding, used by the `LanguageGenerator`.
+
+use ordered_float::OrderedFloat;
+use tch::Tensor;
+
+/// # Pool of finished hypotheses for a single input
+/// Keeps the `num_beams` best finished sequences (with their length-normalized score and optional
+/// token-level scores) and decides when the beam search for this input can stop.
+#[derive(Debug)]
+pub(crate) struct BeamHypotheses {
+    max_length: Option<i64>,
+    length_penalty: f64,
+    early_stopping: bool,
+    num_beams: i64,
+    pub(crate) beams: Vec<(f64, Tensor, Option<Tensor>)>,
+    worst_score: f64,
+}
+
+impl Clone for BeamHypotheses {
+    fn clone(&self) -> Self {
+        BeamHypotheses {
+            max_length: self.max_length,
+            length_penalty: self.length_penalty,
+            early_stopping: self.early_stopping,
+            num_beams: self.num_beams,
+            beams: self
+                .beams
+                .iter()
+                .map(|(score, tensor, scores_tensor)| {
+                    (
+                        *score,
+                        tensor.copy(),
+                        scores_tensor
+                            .as_ref()
+                            .map(|scores_tensor| scores_tensor.copy()),
+                    )
+                })
+                .collect::<Vec<(f64, Tensor, Option<Tensor>)>>(),
+            worst_score: self.worst_score,
+        }
+    }
+}
+
+impl BeamHypotheses {
+    pub(crate) fn new(
+        num_beams: i64,
+        max_length: Option<i64>,
+        length_penalty: f64,
+        early_stopping: bool,
+    ) -> BeamHypotheses {
+        BeamHypotheses {
+            max_length: max_length.map(|max_length| max_length - 1),
+            length_penalty,
+            early_stopping,
+            num_beams,
+            beams: Vec::with_capacity(num_beams as usize + 1),
+            worst_score: 1e9f64,
+        }
+    }
+
+    pub(crate) fn len(&self) -> i64 {
+        self.beams.len() as i64
+    }
+
+    pub(crate) fn add(
+        &mut self,
+        hypothesis: Tensor,
+        sum_log_probabilities: f64,
+        token_scores: Option<Tensor>,
+    ) {
+        let score =
+            sum_log_probabilities / ((hypothesis.size()[0] as f64).powf(self.length_penalty));
+        if (self.len() < self.num_beams) | (score > self.worst_score) {
+            let token_scores = token_scores.map(|scores_tensor| {
+                scores_tensor.squeeze_dim(0).diff::<Tensor>(
+                    1,
+                    0,
+                    Some(Tensor::zeros(
+                        [1],
+                        (scores_tensor.kind(), scores_tensor.device()),
+                    )),
+                    None,
+                )
+            });
+            self.beams.push((score, hypothesis, token_scores));
+            if self.len() > self.num_beams {
+                let (worst_score_position, _) = self
+                    .beams
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, (score, _, _))| OrderedFloat(*score))
+                    .unwrap();
+                let _ = self.beams.remove(worst_score_position);
+            }
+            self.worst_score = self
+                .beams
+                .iter()
+                .min_by_key(|(score, _, _)| OrderedFloat(*score))
+                .unwrap()
+                .0;
+        }
+    }
+
+    pub(crate) fn is_done(&self, best_sum_log_probabilities: f64, current_length: i64) -> bool {
+        if self.len() < self.num_beams {
+            false
+        } else if self.early_stopping {
+            true
+        } else {
+            self.worst_score
+                >= best_sum_log_probabilities / (current_length as f64).powf(self.length_penalty)
+        }
+    }
+}
+
+/// Selects the `num_sequences` best finished sequences of an input from the hypotheses of its beam groups.
+/// Sequences are interleaved across groups: the best sequence of every group (sorted by score) is returned first,
+/// followed by the second best sequence of every group, etc. For a single group, the sequences are sorted by score.
+pub(crate) fn select_group_hypotheses(
+    group_hypotheses: &[BeamHypotheses],
+    num_sequences: usize,
+) -> Vec<(f64, Tensor, Option<Tensor>)> {
+    let mut sorted_groups = group_hypotheses
+        .iter()
+        .map(|hypotheses| {
+            let mut beams = hypotheses.clone().beams;
+            beams.sort_by_key(|(score, _, _)| OrderedFloat(-*score));
+            beams.into_iter()
+        })
+        .collect::<Vec<_>>();
+    let mut selected_hypotheses = Vec::with_capacity(num_sequences);
+    while selected_hypotheses.len() < num_sequences {
+        let mut rank_hypotheses = sorted_groups
+            .iter_mut()
+            .filter_map(|group_beams| group_beams.next())
+            .collect::<Vec<_>>();
+        if rank_hypotheses.is_empty() {
+            break;
+        }
+        rank_hypotheses.sort_by_key(|(score, _, _)| OrderedFloat(-*score));
+        selected_hypotheses.extend(rank_hypotheses);
+    }
+    selected_hypotheses.truncate(num_sequences);
+    selected_hypotheses
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tch::{Device, Kind};
+
+    fn hypothesis(length: i64) -> Tensor {
+        Tensor::ones([length], (Kind::Int64, Device::Cpu))
+    }
+
+    #[test]
+    fn beam_hypotheses_keeps_best_scores() {
+        let mut hypotheses = BeamHypotheses::new(2, Some(10), 1.0, false);
+        hypotheses.add(hypothesis(4), -4.0, None);
+        hypotheses.add(hypothesis(4), -2.0, None);
+        hypotheses.add(hypothesis(4), -8.0, None);
+        hypotheses.add(hypothesis(4), -1.0, None);
+
+        assert_eq!(hypotheses.len(), 2);
+        let mut scores = hypotheses
+            .beams
+            .iter()
+            .map(|(score, _, _)| *score)
+            .collect::<Vec<f64>>();
+        scores.sort_by(|a, b| b.partial_cmp(a).unwrap());
+        assert_eq!(scores, vec![-0.25, -0.5]);
+    }
+
+    #[test]
+    fn beam_hypotheses_is_done() {
+        let mut hypotheses = BeamHypotheses::new(2, Some(10), 1.0, false);
+        hypotheses.add(hypothesis(4), -2.0, None);
+        assert!(!hypotheses.is_done(-1.0, 4));
+
+        hypotheses.add(hypothesis(4), -4.0, None);
+        // Worst finished score is -1.0, a running beam could still reach -0.5
+        assert!(!hypotheses.is_done(-2.0, 4));
+        // No running beam can reach the worst finished score anymore
+        assert!(hypotheses.is_done(-8.0, 4));
+
+        let mut early_stopping_hypotheses = BeamHypotheses::new(1, Some(10), 1.0, true);
+        early_stopping_hypotheses.add(hypothesis(4), -100.0, None);
+        assert!(early_stopping_hypotheses.is_done(0.0, 4));
+    }
+
+    #[test]
+    fn group_hypotheses_interleaved_selection() {
+        let mut first_group = BeamHypotheses::new(2, Some(10), 1.0, false);
+        first_group.add(hypothesis(1), -1.0, None);
+        first_group.add(hypothesis(1), -2.0, None);
+        let mut second_group = BeamHypotheses::new(2, Some(10), 1.0, false);
+        second_group.add(hypothesis(1), -3.0, None);
+        second_group.add(hypothesis(1), -4.0, None);
+        let groups = [first_group, second_group];
+
+        let scores = |selected: Vec<(f64, Tensor, Option<Tensor>)>| {
+            selected
+                .iter()
+                .map(|(score, _, _)| *score)
+                .collect::<Vec<f64>>()
+        };
+        // The best sequence of every group comes first
+        assert_eq!(
+            scores(select_group_hypotheses(&groups, 4)),
+            vec![-1.0, -3.0, -2.0, -4.0]
+        );
+        assert_eq!(
+            scores(select_group_hypotheses(&groups, 2)),
+            vec![-1.0, -3.0]
+        );
+        // A single group is sorted by score
+        assert_eq!(
+            scores(select_group_hypotheses(&groups[..1], 2)),
+            vec![-1.0, -2.0]
+        );
+    }
+}
@@ -336,6 +336,15 @@ impl ConfigOption {
         }
     }
 
+    /// Checks the consistency of the loaded configuration, returning a `RustBertError::InvalidConfigurationError`
+    /// for invalid model parameters.
+    pub fn validate(&self) -> Result<(), RustBertError> {
+        match self {
+            Self::DistilBert(config) => config.validate(),
+            _ => Ok(()),
+        }
+    }
+
     pub fn get_label_mapping(&self) -> &HashMap<i64, String> {
         match self {
             Self::Bart(config) => config
@@ -2350,8 +2359,18 @@ impl TokenizerOption {
     }
 }
 
+/// Casts the variables of a `VarStore` to the requested precision. If no precision is provided, models
+/// on CPU are cast to full precision and models on other devices keep the precision of the loaded weights.
+/// Half precision (`Kind::Half` and `Kind::BFloat16`) is only supported on CUDA devices: models on CPU fall
+/// back to full precision with a warning (emitted with the `log` crate).
 pub fn cast_var_store(varstore: &mut VarStore, kind: Option<Kind>, device: Device) {
     match (kind, device) {
+        (Some(kind @ (Kind::Half | Kind::BFloat16)), Device::Cpu) => {
+            log::warn!(
+                "{kind:?} precision is not supported on CPU, falling back to full precision"
+            );
+            varstore.set_kind(Kind::Float)
+        }
         (Some(kind), _) => varstore.set_kind(kind),
         (None, Device::Cpu) => varstore.set_kind(Kind::Float),
         (None, _) => {}
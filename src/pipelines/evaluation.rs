@@ -0,0 +1,111 @@
+// Copyright 2024 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Language model evaluation
+//! Evaluation metrics of decoder-only language models on a corpus of texts.
+//!
+//! The perplexity of a corpus is the exponential of the average negative log-likelihood of its tokens. Every text is
+//! scored independently (the first token of every text has no context and is not scored) and the negative
+//! log-likelihoods are averaged over all the scored tokens of the corpus, so that longer texts weigh more. Texts longer
+//! than the evaluation window are scored with a sliding window, as for `LanguageGenerator::perplexity`.
+//!
+//! ```no_run
+//! # fn main() -> anyhow::Result<()> {
+//! use rust_bert::gpt2::GPT2Generator;
+//! use rust_bert::pipelines::evaluation::compute_perplexity;
+//!
+//! let model = GPT2Generator::new(Default::default())?;
+//! let texts = [
+//!     "The quick brown fox jumps over the lazy dog.",
+//!     "Language models assign probabilities to sequences of tokens.",
+//! ];
+//! let perplexity = compute_perplexity(&model, &texts, 512, None)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::pipelines::generation_utils::private_generation_utils::PrivateLanguageGenerator;
+use crate::pipelines::generation_utils::{
+    sliding_window_negative_log_likelihood, LanguageGenerator,
+};
+use crate::RustBertError;
+
+/// Computes the perplexity of a language model on a corpus of texts (exponential of the average negative
+/// log-likelihood of the tokens of all texts, the first token of every text excepted). Texts of a single token are
+/// ignored.
+///
+/// # Arguments
+///
+/// * `model` - Decoder-only language model
+/// * `texts` - Texts of the corpus
+/// * `stride` - Number of tokens scored by every window after the first one, for texts longer than the evaluation
+///   window. Must be lower than the window size for texts longer than the window.
+/// * `max_length` - Optional size (in tokens) of the evaluation window, at least 2. Defaults to (and is bounded by) the
+///   model context size.
+///
+/// # Returns
+/// * `f64` Perplexity of the corpus
+pub fn compute_perplexity<T, S>(
+    model: &T,
+    texts: &[S],
+    stride: usize,
+    max_length: Option<usize>,
+) -> Result<f64, RustBertError>
+where
+    T: LanguageGenerator + ?Sized,
+    S: AsRef<str>,
+{
+    if model.is_encoder_decoder() {
+        return Err(RustBertError::InvalidConfigurationError(
+            "Perplexity computation is only supported by decoder-only models".to_string(),
+        ));
+    }
+    if let Some(max_length) = max_length {
+        if max_length < 2 {
+            return Err(RustBertError::InvalidConfigurationError(format!(
+                "The evaluation window should cover at least 2 tokens, got {max_length}"
+            )));
+        }
+    }
+    let context_size = model
+        .get_max_positions_embeddings()
+        .map(|max_positions| max_positions as usize);
+    let window_size = match (max_length, context_size) {
+        (Some(max_length), Some(context_size)) => Some(max_length.min(context_size)),
+        (max_length, context_size) => max_length.or(context_size),
+    };
+
+    let tokenizer = model.get_tokenizer();
+    let mut negative_log_likelihood = 0f64;
+    let mut num_scored_tokens = 0;
+    for text in texts {
+        let token_ids = tokenizer.convert_tokens_to_ids(&tokenizer.tokenize(text.as_ref()));
+        let num_tokens = token_ids.len();
+        if num_tokens < 2 {
+            continue;
+        }
+        let window_size = window_size.unwrap_or(num_tokens);
+        if (stride == 0) | ((num_tokens > window_size) & (stride >= window_size)) {
+            return Err(RustBertError::ValueError(format!(
+                "The stride should be strictly positive and lower than the evaluation window ({window_size}), got {stride}"
+            )));
+        }
+        negative_log_likelihood +=
+            sliding_window_negative_log_likelihood(model, &token_ids, window_size, stride)?;
+        num_scored_tokens += num_tokens - 1;
+    }
+    if num_scored_tokens == 0 {
+        return Err(RustBertError::ValueError(
+            "The perplexity requires at least one text of at least 2 tokens".to_string(),
+        ));
+    }
+    Ok((negative_log_likelihood / num_scored_tokens as f64).exp())
+}
@@ -66,6 +66,10 @@
 //! # ;
 //! ```
 
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
 use tch::kind::Kind::Int64;
 use tch::{no_grad, Device, Kind, Tensor};
 
@@ -74,15 +78,20 @@ use crate::common::resources::ResourceProvider;
 use crate::gpt_j::LayerState as GPTJLayerState;
 use crate::gpt_neo::LayerState as GPTNeoLayerState;
 use crate::pipelines::generation_utils::private_generation_utils::{
-    InternalGenerateOptions, PrivateLanguageGenerator,
+    find_stop_sequence, max_cosine_similarity, partial_stop_sequence_length,
+    InternalGenerateOptions, LogitsProcessors, PreparedGenerationInputs, PrivateLanguageGenerator,
 };
 use crate::prophetnet::LayerState as ProphetNetLayerState;
 use crate::reformer::LayerState as ReformerLayerState;
 use crate::t5::LayerState as T5LayerState;
 use crate::xlnet::LayerState as XLNetLayerState;
 
-use self::ordered_float::OrderedFloat;
 use crate::pipelines::common::{ModelResource, ModelType, TokenizerOption};
+use crate::pipelines::logits_processors::{
+    bad_word_ids_from_strings, LogitsProcessor, RepetitionPenaltyScope,
+};
+use crate::pipelines::sampling::{Mirostat, MirostatSampler};
+use crate::pipelines::token_healing::TokenHealingIndex;
 
 extern crate ordered_float;
 #[cfg(feature = "onnx")]
@@ -131,6 +140,7 @@ pub struct GenerateConfig {
     /// Number of sequences to return for each prompt text (default: 1)
     pub num_return_sequences: i64,
     /// Number of beam groups for diverse beam generation. If provided and higher than 1, will split the beams into beam subgroups leading to more diverse generation.
+    /// `num_beams` must be a multiple of the number of groups and sampling is not supported. The returned sequences are interleaved across groups (best sequence of each group first).
     pub num_beam_groups: Option<i64>,
     /// Diversity penalty for diverse beam search. High values will enforce more difference between beam groups (default: 5.5)
     pub diversity_penalty: Option<f64>,
@@ -174,52 +184,156 @@ impl Default for GenerateConfig {
 }
 
 impl GenerateConfig {
-    pub(crate) fn validate(&self) {
-        assert!(self.temperature > 0f64, "temperature must positive");
-        assert!(
-            (self.top_p >= 0f64) & (self.top_p <= 1f64),
-            "top_p must be 0 and 1"
-        );
-        assert!(
-            self.repetition_penalty >= 1f64,
-            "repetition_penalty must be greater than 1"
-        );
-        assert!(
-            self.length_penalty > 0f64,
-            "length_penalty must be strictly greater than 0"
-        );
-        assert!(
-            self.num_return_sequences > 0i64,
-            "num_return_sequences must be strictly greater than 0"
-        );
-        assert!(
-            self.num_beams > 0i64,
-            "num_beams must be strictly greater than 0"
-        );
+    /// Checks that the generation parameters are within their valid ranges, returning a
+    /// `RustBertError::InvalidConfigurationError` describing the first invalid parameter otherwise.
+    pub fn validate(&self) -> Result<(), RustBertError> {
+        validate_generation_parameters(
+            self.temperature,
+            self.top_p,
+            self.repetition_penalty,
+            self.length_penalty,
+            self.num_return_sequences,
+            self.num_beams,
+            self.do_sample,
+            self.num_beam_groups,
+        )
+    }
+}
 
-        if !self.do_sample {
-            if self.num_beams == 1 {
-                assert_eq!(
-                    self.num_return_sequences, 1,
-                    "num_return_sequences must be set to 1 for greedy decoding"
-                )
-            } else {
-                assert!(
-                    self.num_beams >= self.num_return_sequences,
-                    "num_return_sequences must be lower than the number of beams"
-                )
-            }
+#[allow(clippy::too_many_arguments)]
+fn validate_generation_parameters(
+    temperature: f64,
+    top_p: f64,
+    repetition_penalty: f64,
+    length_penalty: f64,
+    num_return_sequences: i64,
+    num_beams: i64,
+    do_sample: bool,
+    num_beam_groups: Option<i64>,
+) -> Result<(), RustBertError> {
+    if temperature <= 0f64 {
+        return Err(RustBertError::InvalidConfigurationError(format!(
+            "temperature must be strictly positive, got {temperature}"
+        )));
+    }
+    if !(0f64..=1f64).contains(&top_p) {
+        return Err(RustBertError::InvalidConfigurationError(format!(
+            "top_p must be between 0 and 1, got {top_p}"
+        )));
+    }
+    if repetition_penalty < 1f64 {
+        return Err(RustBertError::InvalidConfigurationError(format!(
+            "repetition_penalty must be greater than or equal to 1, got {repetition_penalty}"
+        )));
+    }
+    if length_penalty <= 0f64 {
+        return Err(RustBertError::InvalidConfigurationError(format!(
+            "length_penalty must be strictly greater than 0, got {length_penalty}"
+        )));
+    }
+    if num_return_sequences <= 0i64 {
+        return Err(RustBertError::InvalidConfigurationError(format!(
+            "num_return_sequences must be strictly greater than 0, got {num_return_sequences}"
+        )));
+    }
+    if num_beams <= 0i64 {
+        return Err(RustBertError::InvalidConfigurationError(format!(
+            "num_beams must be strictly greater than 0, got {num_beams}"
+        )));
+    }
+
+    if !do_sample {
+        if (num_beams == 1) & (num_return_sequences != 1) {
+            return Err(RustBertError::InvalidConfigurationError(format!(
+                "num_return_sequences must be set to 1 for greedy decoding, got {num_return_sequences}"
+            )));
         }
-        if let Some(num_beam_groups_value) = self.num_beam_groups {
-            if num_beam_groups_value > 1 {
-                assert_eq!(
-                    self.num_beams % num_beam_groups_value,
-                    0,
-                    "num_beam_groups must be a multiple of num_beam_groups"
-                )
-            }
+        if num_beams < num_return_sequences {
+            return Err(RustBertError::InvalidConfigurationError(format!(
+                "num_return_sequences ({num_return_sequences}) must be lower than the number of beams ({num_beams})"
+            )));
         }
     }
+    if let Some(num_beam_groups) = num_beam_groups {
+        if (num_beam_groups > 1) & (num_beams % num_beam_groups != 0) {
+            return Err(RustBertError::InvalidConfigurationError(format!(
+                "num_beams ({num_beams}) must be a multiple of num_beam_groups ({num_beam_groups})"
+            )));
+        }
+        if (num_beam_groups > 1) & do_sample {
+            return Err(RustBertError::InvalidConfigurationError(format!(
+                "diverse beam search (num_beam_groups = {num_beam_groups}) does not support sampling, do_sample must be set to false"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// # Contrastive search token selection
+/// Selects the next token of every sequence among its `top_k` most likely tokens ([Su et al.](https://arxiv.org/abs/2202.06417)),
+/// maximizing `(1 - penalty_alpha) * probability - penalty_alpha * max_similarity`. `max_similarity` (degeneration penalty)
+/// is the maximum cosine similarity between the hidden state of the candidate and the hidden states of the previous tokens.
+///
+/// # Arguments
+///
+/// * `next_token_logits` - Logits of the next token, of shape `(batch size, vocab size)`
+/// * `top_k` - Number of candidate tokens
+/// * `penalty_alpha` - Weight of the degeneration penalty (between 0 and 1)
+/// * `hidden_states` - Hidden states of the candidate tokens (last layer), of shape `(batch size * top_k, hidden size)`,
+/// for the candidates sorted by decreasing probability
+/// * `past_hidden_states` - Hidden states of the previous tokens, of shape `(batch size, context length, hidden size)`
+/// * `past_mask` - Optional mask of the previous tokens (padding positions set to 0 are ignored), of shape `(batch size, context length)`
+///
+/// # Returns
+/// * `Tensor` Selected token ids, of shape `(batch size)`
+///
+/// # Example
+///
+/// ```no_run
+/// use rust_bert::pipelines::generation_utils::contrastive_search;
+/// use tch::{Device, Kind, Tensor};
+///
+/// let next_token_logits = Tensor::randn([2, 50257], (Kind::Float, Device::Cpu));
+/// let hidden_states = Tensor::randn([2 * 4, 768], (Kind::Float, Device::Cpu));
+/// let past_hidden_states = Tensor::randn([2, 10, 768], (Kind::Float, Device::Cpu));
+/// let next_tokens = contrastive_search(
+///     &next_token_logits,
+///     4,
+///     0.6,
+///     &hidden_states,
+///     &past_hidden_states,
+///     None,
+/// );
+/// ```
+pub fn contrastive_search(
+    next_token_logits: &Tensor,
+    top_k: i64,
+    penalty_alpha: f64,
+    hidden_states: &Tensor,
+    past_hidden_states: &Tensor,
+    past_mask: Option<&Tensor>,
+) -> Tensor {
+    let (top_k_probabilities, top_k_tokens) = next_token_logits
+        .softmax(-1, Kind::Float)
+        .topk(top_k, -1, true, true);
+    let candidate_indices = Tensor::arange(
+        *next_token_logits.size().first().unwrap(),
+        (Kind::Int64, next_token_logits.device()),
+    )
+    .repeat_interleave_self_int(top_k, None, None);
+    let degeneration_penalty = max_cosine_similarity(
+        &past_hidden_states.index_select(0, &candidate_indices),
+        hidden_states,
+        past_mask
+            .map(|past_mask| past_mask.index_select(0, &candidate_indices))
+            .as_ref(),
+    );
+    let candidate_scores = top_k_probabilities.view([-1]) * (1.0 - penalty_alpha)
+        - degeneration_penalty.to_kind(Kind::Float) * penalty_alpha;
+    let selected_candidates = candidate_scores.view([-1, top_k]).argmax(-1, true);
+    top_k_tokens
+        .gather(1, &selected_candidates, false)
+        .squeeze_dim(-1)
 }
 
 #[derive(Debug)]
@@ -243,18 +357,33 @@ pub(crate) mod private_generation_utils {
     use std::cmp::{max, min};
     use std::collections::HashMap;
     use std::convert::TryFrom;
+    use std::f64::consts::LN_2;
     use std::mem;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Instant;
 
     use rust_tokenizers::tokenizer::{truncate_sequences, TruncationStrategy};
     use tch::{nn, Device, Kind, Tensor};
 
+    use crate::pipelines::beam_search::{select_group_hypotheses, BeamHypotheses};
     use crate::pipelines::common::TokenizerOption;
     use crate::pipelines::generation_utils::{
-        BeamHypotheses, Cache, GenerateConfig, LMModelOutput, PrefixAllowedFunction,
+        contrastive_search, Cache, FinishReason, GenerateConfig, LMModelOutput,
+        PrefixAllowedFunction,
+    };
+    use crate::pipelines::logits_processors::{
+        AllowedTokensLogitsProcessor, BadWordsLogitsProcessor, ForcedBOSTokenLogitsProcessor,
+        ForcedEOSTokenLogitsProcessor, FrequencyPresencePenaltyLogitsProcessor,
+        LogitBiasLogitsProcessor, LogitsProcessor, MinLengthLogitsProcessor,
+        NoRepeatNGramLogitsProcessor, PrefixAllowedTokensLogitsProcessor,
+        RepetitionPenaltyLogitsProcessor, RepetitionPenaltyScope,
+        SuppressTokensAtBeginLogitsProcessor, SuppressTokensLogitsProcessor,
+        TemperatureLogitsWarper, TopKLogitsWarper, TopPLogitsWarper, TypicalLogitsWarper,
     };
 
-    use super::ordered_float::OrderedFloat;
-    use crate::common::kind::{get_negative_infinity, get_positive_infinity};
+    use crate::common::kind::get_negative_infinity;
+    use crate::pipelines::sampling::{Mirostat, MirostatSampler};
+    use crate::pipelines::token_healing::TokenHealingLogitsProcessor;
     use crate::RustBertError;
 
     pub struct InternalGenerateOptions<'a> {
@@ -265,6 +394,10 @@ pub(crate) mod private_generation_utils {
         pub top_k: i64,
         pub top_p: f64,
         pub repetition_penalty: f64,
+        pub repetition_penalty_scope: RepetitionPenaltyScope,
+        pub frequency_penalty: Option<f64>,
+        pub presence_penalty: Option<f64>,
+        pub include_prompt_in_penalty: bool,
         pub no_repeat_ngram_size: i64,
         pub pad_token_id: Option<i64>,
         pub eos_token_ids: Option<Vec<i64>>,
@@ -274,8 +407,95 @@ pub(crate) mod private_generation_utils {
         pub length_penalty: f64,
         pub num_beam_groups: Option<i64>,
         pub diversity_penalty: Option<f64>,
+        pub penalty_alpha: Option<f64>,
+        pub typical_p: Option<f64>,
+        pub mirostat: Option<Mirostat>,
         pub forced_bos_token_id: Option<i64>,
+        pub forced_eos_token_id: Option<i64>,
         pub bad_word_ids: Option<&'a Vec<Vec<i64>>>,
+        pub logit_bias: Option<&'a HashMap<i64, f64>>,
+        pub suppress_tokens: Option<&'a Vec<i64>>,
+        pub begin_suppress_tokens: Option<&'a Vec<i64>>,
+        pub allowed_token_ids: Option<&'a Vec<i64>>,
+        /// Tokens allowed as first generated token for every prompt, if token healing is enabled
+        pub healing_tokens: Option<Vec<Option<Vec<i64>>>>,
+        pub stop_sequences: Option<&'a Vec<String>>,
+        pub logits_processors: Option<&'a [Box<dyn LogitsProcessor + 'a>]>,
+        pub cancellation_flag: Option<&'a AtomicBool>,
+        pub deadline: Option<Instant>,
+        pub output_attentions: bool,
+        pub output_hidden_states: bool,
+    }
+
+    impl InternalGenerateOptions<'_> {
+        /// Returns the reason for interrupting the generation after the current step, if the generation was
+        /// cancelled with the cancellation flag or if the time limit is exceeded. Only reads host-side state.
+        pub fn interruption(&self) -> Option<FinishReason> {
+            if self
+                .cancellation_flag
+                .map_or(false, |flag| flag.load(Ordering::Relaxed))
+            {
+                Some(FinishReason::Cancelled)
+            } else if self
+                .deadline
+                .map_or(false, |deadline| Instant::now() >= deadline)
+            {
+                Some(FinishReason::TimeLimit)
+            } else {
+                None
+            }
+        }
+
+        /// Containers for the attention weights and hidden states of every generation step, if requested
+        pub fn model_outputs(&self) -> (Option<Vec<Vec<Tensor>>>, Option<Vec<Vec<Tensor>>>) {
+            (
+                self.output_attentions.then(Vec::new),
+                self.output_hidden_states.then(Vec::new),
+            )
+        }
+    }
+
+    /// State of the contrastive search between decoding steps
+    pub struct ContrastiveSearchState {
+        penalty_alpha: f64,
+        top_k: i64,
+        /// Index of every sequence, and of the sequence of every candidate (`top_k` candidates per sequence)
+        batch_indices: Tensor,
+        candidate_indices: Tensor,
+        /// Last hidden states of the previous tokens, of shape (*batch size*, *sequence length*, *hidden size*)
+        context_hidden_states: Tensor,
+        /// Logits of the next token, computed by the forward pass of the selected candidates
+        next_token_logits: Option<Tensor>,
+    }
+
+    impl ContrastiveSearchState {
+        /// Returns the initial state of the contrastive search, if enabled (strictly positive `penalty_alpha`)
+        pub fn new(
+            gen_opt: &InternalGenerateOptions,
+            batch_size: i64,
+            device: Device,
+        ) -> Option<ContrastiveSearchState> {
+            let penalty_alpha = gen_opt
+                .penalty_alpha
+                .filter(|penalty_alpha| *penalty_alpha > 0.0)?;
+            let batch_indices = Tensor::arange(batch_size, (Kind::Int64, device));
+            let candidate_indices =
+                batch_indices.repeat_interleave_self_int(gen_opt.top_k, None, None);
+            Some(ContrastiveSearchState {
+                penalty_alpha,
+                top_k: gen_opt.top_k,
+                batch_indices,
+                candidate_indices,
+                context_hidden_states: Tensor::new(),
+                next_token_logits: None,
+            })
+        }
+
+        fn missing_hidden_states() -> RustBertError {
+            RustBertError::ValueError(
+                "Contrastive search requires a model returning its last hidden states".to_string(),
+            )
+        }
     }
 
     pub struct PreparedInput<'a> {
@@ -287,10 +507,351 @@ pub(crate) mod private_generation_utils {
         pub prepared_past: Cache,
     }
 
+    pub struct PreparedGenerationInputs<'a> {
+        pub input_ids: Tensor,
+        pub attention_mask: Tensor,
+        pub encoder_outputs: Option<Tensor>,
+        pub cur_len: i64,
+        pub batch_size: i64,
+        pub gen_opt: InternalGenerateOptions<'a>,
+        pub prefix_allowed_tokens_fn: Option<PrefixAllowedFunction<'a>>,
+        pub output_scores: bool,
+    }
+
+    /// Processors applied during a generation, built from the generation options in the order
+    /// documented at the module level.
+    pub struct LogitsProcessors<'a> {
+        penalties: Vec<Box<dyn LogitsProcessor + 'a>>,
+        constraints: Vec<Box<dyn LogitsProcessor + 'a>>,
+        custom_processors: &'a [Box<dyn LogitsProcessor + 'a>],
+        temperature: TemperatureLogitsWarper,
+        warpers: Vec<Box<dyn LogitsProcessor + 'a>>,
+    }
+
+    impl<'a> LogitsProcessors<'a> {
+        /// Builds the processors for a generation. `cur_len` is the length of the sequences before
+        /// the first token is generated.
+        pub fn new(
+            gen_opt: &InternalGenerateOptions<'a>,
+            prefix_allowed_tokens_fn: Option<PrefixAllowedFunction<'a>>,
+            num_beams: i64,
+            cur_len: i64,
+        ) -> Self {
+            let mut penalties: Vec<Box<dyn LogitsProcessor + 'a>> = vec![];
+            if gen_opt.repetition_penalty > 1f64 {
+                penalties.push(Box::new(RepetitionPenaltyLogitsProcessor::new_with_scope(
+                    gen_opt.repetition_penalty,
+                    gen_opt.repetition_penalty_scope,
+                    cur_len,
+                )));
+            }
+            if gen_opt.frequency_penalty.is_some() | gen_opt.presence_penalty.is_some() {
+                penalties.push(Box::new(FrequencyPresencePenaltyLogitsProcessor::new(
+                    gen_opt.frequency_penalty.unwrap_or(0.0),
+                    gen_opt.presence_penalty.unwrap_or(0.0),
+                    if gen_opt.include_prompt_in_penalty {
+                        0
+                    } else {
+                        cur_len
+                    },
+                )));
+            }
+
+            let mut constraints: Vec<Box<dyn LogitsProcessor + 'a>> = vec![];
+            if let Some(logit_bias) = gen_opt.logit_bias {
+                constraints.push(Box::new(LogitBiasLogitsProcessor::new(logit_bias)));
+            }
+            if let Some(bad_word_ids) = gen_opt.bad_word_ids {
+                constraints.push(Box::new(BadWordsLogitsProcessor::new(bad_word_ids.clone())));
+            }
+            if let Some(suppress_tokens) = gen_opt.suppress_tokens {
+                constraints.push(Box::new(SuppressTokensLogitsProcessor::new(
+                    suppress_tokens.clone(),
+                )));
+            }
+            if let Some(begin_suppress_tokens) = gen_opt.begin_suppress_tokens {
+                constraints.push(Box::new(SuppressTokensAtBeginLogitsProcessor::new(
+                    begin_suppress_tokens.clone(),
+                    cur_len,
+                )));
+            }
+            if let Some(allowed_token_ids) = gen_opt.allowed_token_ids {
+                // The EOS tokens remain allowed, unless they are banned with `suppress_tokens`
+                let allowed_token_ids = allowed_token_ids
+                    .iter()
+                    .chain(gen_opt.eos_token_ids.iter().flatten())
+                    .copied()
+                    .collect();
+                constraints.push(Box::new(AllowedTokensLogitsProcessor::new(
+                    allowed_token_ids,
+                )));
+            }
+            if let Some(healing_tokens) = &gen_opt.healing_tokens {
+                constraints.push(Box::new(TokenHealingLogitsProcessor::new(
+                    healing_tokens.clone(),
+                    cur_len,
+                )));
+            }
+            if gen_opt.no_repeat_ngram_size > 0 {
+                constraints.push(Box::new(NoRepeatNGramLogitsProcessor::new(
+                    gen_opt.no_repeat_ngram_size,
+                )));
+            }
+            if let Some(prefix_allowed_tokens_fn) = prefix_allowed_tokens_fn {
+                constraints.push(Box::new(PrefixAllowedTokensLogitsProcessor::new(
+                    prefix_allowed_tokens_fn,
+                    num_beams,
+                )));
+            }
+            if let Some(eos_token_ids) = &gen_opt.eos_token_ids {
+                constraints.push(Box::new(MinLengthLogitsProcessor::new(
+                    gen_opt.min_length,
+                    eos_token_ids.clone(),
+                )));
+            }
+            if let Some(forced_bos_token_id) = gen_opt.forced_bos_token_id {
+                constraints.push(Box::new(ForcedBOSTokenLogitsProcessor::new(
+                    forced_bos_token_id,
+                    cur_len,
+                )));
+            }
+            if let (Some(forced_eos_token_id), Some(max_length)) =
+                (gen_opt.forced_eos_token_id, gen_opt.max_length)
+            {
+                constraints.push(Box::new(ForcedEOSTokenLogitsProcessor::new(
+                    forced_eos_token_id,
+                    max_length,
+                )));
+            }
+
+            // Sampling filters (beam sampling keeps at least 2 tokens per beam)
+            let mut warpers: Vec<Box<dyn LogitsProcessor + 'a>> = vec![];
+            if gen_opt.do_sample {
+                let min_tokens_to_keep = if num_beams > 1 { 2 } else { 1 };
+                if gen_opt.top_k > 0 {
+                    warpers.push(Box::new(TopKLogitsWarper::new(
+                        gen_opt.top_k,
+                        min_tokens_to_keep,
+                    )));
+                }
+                if gen_opt.top_p < 1f64 {
+                    warpers.push(Box::new(TopPLogitsWarper::new(
+                        gen_opt.top_p,
+                        min_tokens_to_keep,
+                    )));
+                }
+                if let Some(typical_p) = gen_opt.typical_p {
+                    warpers.push(Box::new(TypicalLogitsWarper::new(
+                        typical_p,
+                        min_tokens_to_keep,
+                    )));
+                }
+            }
+
+            LogitsProcessors {
+                penalties,
+                constraints,
+                custom_processors: gen_opt.logits_processors.unwrap_or(&[]),
+                temperature: TemperatureLogitsWarper::new(gen_opt.temperature),
+                warpers,
+            }
+        }
+
+        /// Applies the penalties, modifying the raw logits
+        pub fn apply_penalties(&self, input_ids: &Tensor, logits: &mut Tensor) {
+            for processor in &self.penalties {
+                processor.process(input_ids, logits);
+            }
+        }
+
+        /// Applies the built-in constraints followed by the custom processors
+        pub fn apply_constraints(&self, input_ids: &Tensor, scores: &mut Tensor) {
+            for processor in self.constraints.iter().chain(self.custom_processors) {
+                processor.process(input_ids, scores);
+            }
+        }
+
+        /// Scales the logits by the inverse of the temperature
+        pub fn apply_temperature(&self, input_ids: &Tensor, logits: &mut Tensor) {
+            self.temperature.process(input_ids, logits);
+        }
+
+        /// Applies the sampling filters (top-k, top-p and typical sampling)
+        pub fn apply_warpers(&self, input_ids: &Tensor, scores: &mut Tensor) {
+            for warper in &self.warpers {
+                warper.process(input_ids, scores);
+            }
+        }
+    }
+
     pub struct GeneratedOutputWithScores {
         pub indices: Tensor,
         pub scores: Option<Vec<f64>>,
         pub token_scores: Option<Vec<Vec<f64>>>,
+        /// Finish reason of every sequence, not tracked by beam search
+        pub finish_reasons: Option<Vec<FinishReason>>,
+        /// Number of tokens generated for every sequence, not tracked by beam search
+        pub num_generated_tokens: Option<Vec<usize>>,
+        /// Attention weights and hidden states of every layer for every generation step, not collected by
+        /// contrastive search
+        pub attentions: Option<Vec<Vec<Tensor>>>,
+        pub hidden_states: Option<Vec<Vec<Tensor>>>,
+        pub past: Cache,
+    }
+
+    /// Penalizes the logits of tokens already present in `prev_output_tokens` (shape
+    /// `(batch size, sequence length)`): negative logits are multiplied by the penalty and positive
+    /// logits divided by it. The update is done with tensor operations on the logits device.
+    pub(crate) fn apply_repetition_penalty(
+        logits: &mut Tensor,
+        prev_output_tokens: &Tensor,
+        repetition_penalty: f64,
+    ) {
+        let prev_output_tokens = prev_output_tokens.to_device(logits.device());
+        let previous_token_logits = logits.gather(1, &prev_output_tokens, false);
+        let penalized_logits = (&previous_token_logits * repetition_penalty).where_self(
+            &previous_token_logits.lt(0.0),
+            &(&previous_token_logits / repetition_penalty),
+        );
+        let _ = logits.scatter_(1, &prev_output_tokens, &penalized_logits);
+    }
+
+    /// Returns, for each hypothesis, the tokens that would complete an n-gram of size
+    /// `no_repeat_ngram_size` already present in the hypothesis.
+    pub(crate) fn get_banned_ngram_tokens(
+        input_ids: &Tensor,
+        no_repeat_ngram_size: i64,
+        cur_len: i64,
+    ) -> Vec<Vec<i64>> {
+        //        Ported from hugging face's transformers and fairseq (https://github.com/pytorch/fairseq/blob/master/fairseq/sequence_generator.py)
+        let num_hypothesis = *input_ids.size().first().unwrap();
+        if cur_len + 1 < no_repeat_ngram_size {
+            return vec![vec![]; num_hypothesis as usize];
+        }
+        let input_ids = input_ids.to(Device::Cpu);
+        let mut banned_tokens: Vec<Vec<i64>> = Vec::with_capacity(num_hypothesis as usize);
+        for hypothesis_index in 0..num_hypothesis {
+            let hypothesis_input_ids = input_ids
+                .get(hypothesis_index)
+                .iter::<i64>()
+                .unwrap()
+                .collect::<Vec<i64>>();
+            let mut generated_ngram: HashMap<&[i64], Vec<i64>> = HashMap::new();
+            for ngram in hypothesis_input_ids.windows(no_repeat_ngram_size as usize) {
+                let (key, value) = ngram.split_at(no_repeat_ngram_size as usize - 1);
+                let banned_values = generated_ngram.entry(key).or_default();
+                if !banned_values.contains(&value[0]) {
+                    banned_values.push(value[0]);
+                }
+            }
+            let query =
+                &hypothesis_input_ids[cur_len as usize + 1 - no_repeat_ngram_size as usize..];
+            banned_tokens.push(generated_ngram.get(query).cloned().unwrap_or_default());
+        }
+        banned_tokens
+    }
+
+    /// Returns the byte position of the first stop sequence found in `text` after `start`.
+    pub(crate) fn find_stop_sequence(
+        text: &str,
+        start: usize,
+        stop_sequences: &[String],
+    ) -> Option<usize> {
+        let mut start = start.min(text.len());
+        while !text.is_char_boundary(start) {
+            start -= 1;
+        }
+        stop_sequences
+            .iter()
+            .filter(|stop_sequence| !stop_sequence.is_empty())
+            .filter_map(|stop_sequence| text[start..].find(stop_sequence.as_str()))
+            .min()
+            .map(|position| start + position)
+    }
+
+    /// Returns the length of the longest suffix of `text` that is the beginning of a stop sequence,
+    /// i.e. the text that may still turn into a stop sequence with the next tokens.
+    pub(crate) fn partial_stop_sequence_length(text: &str, stop_sequences: &[String]) -> usize {
+        stop_sequences
+            .iter()
+            .flat_map(|stop_sequence| {
+                stop_sequence
+                    .char_indices()
+                    .skip(1)
+                    .map(move |(position, _)| &stop_sequence[..position])
+            })
+            .filter(|stop_sequence_prefix| text.ends_with(stop_sequence_prefix))
+            .map(str::len)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Scales the logits in place by the inverse of the temperature. Temperatures lower than 1
+    /// sharpen the distribution, higher temperatures flatten it.
+    pub(crate) fn apply_temperature(logits: &mut Tensor, temperature: f64) {
+        if temperature != 1f64 {
+            *logits /= temperature;
+        }
+    }
+
+    /// Locally typical sampling filter ([Meister et al.](https://arxiv.org/abs/2202.00666)). Tokens are sorted by the
+    /// absolute difference between their information content (`-log(p)`) and the entropy of the distribution. The most
+    /// typical tokens are kept until their cumulative probability reaches `typical_p`, the logits of the other tokens
+    /// are set to `filter_value` (at least `min_tokens_to_keep` tokens are kept).
+    pub(crate) fn typical_sampling_filter(
+        logits: &mut Tensor,
+        typical_p: f64,
+        min_tokens_to_keep: i64,
+        filter_value: f64,
+    ) {
+        if typical_p >= 1f64 {
+            return;
+        }
+        let vocab_size = *logits.size().last().unwrap();
+        let log_probabilities = logits.log_softmax(-1, Kind::Float);
+        let probabilities = log_probabilities.exp();
+        // Tokens with a null probability do not contribute to the entropy (avoids `0 * -inf`)
+        let entropy = -(&probabilities * log_probabilities.masked_fill(&probabilities.eq(0), 0))
+            .sum_dim_intlist([-1].as_slice(), true, Kind::Float);
+        let deviation = (-&log_probabilities - entropy).abs();
+
+        let (sorted_deviation, sorted_indices) = deviation.sort(-1, false);
+        let cumulative_probabilities = probabilities
+            .gather(-1, &sorted_indices, false)
+            .cumsum(-1, Kind::Float);
+        let last_index = cumulative_probabilities
+            .lt(typical_p)
+            .sum_dim_intlist([-1].as_slice(), true, Kind::Int64)
+            .clamp_max(vocab_size - 1);
+        let sorted_indices_to_remove =
+            sorted_deviation.gt_tensor(&sorted_deviation.gather(-1, &last_index, false));
+        let _ = sorted_indices_to_remove
+            .slice(-1, 0, min(min_tokens_to_keep, vocab_size), 1)
+            .fill_(0);
+        let indices_to_remove =
+            sorted_indices_to_remove.scatter(-1, &sorted_indices, &sorted_indices_to_remove);
+        let _ = logits.masked_fill_(&indices_to_remove, filter_value);
+    }
+
+    /// Maximum cosine similarity between the hidden state of every candidate token (shape `(batch size, hidden size)`)
+    /// and the hidden states of its context (shape `(batch size, context length, hidden size)`). Context positions
+    /// with a null `context_mask` (e.g. padding) are ignored. Used as degeneration penalty by contrastive search.
+    pub(crate) fn max_cosine_similarity(
+        context_hidden_states: &Tensor,
+        candidate_hidden_states: &Tensor,
+        context_mask: Option<&Tensor>,
+    ) -> Tensor {
+        let similarity = Tensor::cosine_similarity(
+            context_hidden_states,
+            &candidate_hidden_states.unsqueeze(1),
+            -1,
+            1e-8,
+        );
+        let similarity = match context_mask {
+            Some(context_mask) => similarity.masked_fill(&context_mask.eq(0), -1.0),
+            None => similarity,
+        };
+        similarity.max_dim(-1, false).0
     }
 
     pub trait PrivateLanguageGenerator {
@@ -328,32 +889,10 @@ pub(crate) mod private_generation_utils {
 
         fn prepare_scores_for_generation(
             &self,
-            scores: &mut Tensor,
-            current_length: i64,
-            max_length: Option<i64>,
-            forced_bos_token_id: Option<i64>,
+            _scores: &mut Tensor,
+            _current_length: i64,
+            _max_length: Option<i64>,
         ) {
-            if current_length == 1 {
-                if let Some(forced_bos_token_id) =
-                    forced_bos_token_id.or(self.get_forced_bos_token_id())
-                {
-                    force_token_id_generation(
-                        scores,
-                        &[forced_bos_token_id],
-                        self.get_vocab_size(),
-                    );
-                }
-            } else if let Some(max_length) = max_length {
-                if let Some(forced_eos_token_id) = self.get_forced_eos_token_id() {
-                    if current_length == max_length - 1 {
-                        force_token_id_generation(
-                            scores,
-                            &[forced_eos_token_id],
-                            self.get_vocab_size(),
-                        );
-                    }
-                }
-            }
         }
 
         fn encode(&self, _input_ids: &Tensor, _attention_mask: Option<&Tensor>) -> Option<Tensor> {
@@ -377,12 +916,15 @@ pub(crate) mod private_generation_utils {
             }
         }
 
+        /// Encodes a batch of prompts, padded to the longest prompt (on the left for decoder-only
+        /// models so that the last position of every row is a prompt token). Returns the token ids
+        /// and the attention mask, masking the padding positions.
         fn encode_prompt_text<S>(
             &self,
             prompt_text: &[S],
             max_len: Option<i64>,
             pad_token_id: Option<i64>,
-        ) -> Tensor
+        ) -> (Tensor, Tensor)
         where
             S: AsRef<str> + Send + Sync,
         {
@@ -454,162 +996,36 @@ pub(crate) mod private_generation_utils {
                 None => self._get_tokenizer().get_unk_id(),
             };
 
-            let token_ids = token_ids
+            let (token_ids, attention_masks): (Vec<Tensor>, Vec<Tensor>) = token_ids
                 .into_iter()
                 .map(|mut input| {
-                    let mut temp = vec![pad_token; max_len - input.len()];
+                    let padding_length = max_len - input.len();
+                    let mut temp = vec![pad_token; padding_length];
+                    let mut padding_mask = vec![0i64; padding_length];
+                    let mut attention_mask = vec![1i64; input.len()];
                     if self.is_encoder_decoder() {
                         input.extend(temp);
-                        input
+                        attention_mask.extend(padding_mask);
+                        (input, attention_mask)
                     } else {
                         // Pad left for causal generation
                         temp.extend(input);
-                        temp
+                        padding_mask.extend(attention_mask);
+                        (temp, padding_mask)
                     }
                 })
-                .map(|tokens| Tensor::from_slice(&tokens).to(self.get_device()))
-                .collect::<Vec<Tensor>>();
-
-            Tensor::stack(&token_ids, 0)
-        }
-
-        fn enforce_repetition_penalty(
-            &self,
-            next_token_logits: &mut Tensor,
-            batch_size: i64,
-            num_beams: i64,
-            prev_output_tokens: &Tensor,
-            repetition_penalty: f64,
-        ) {
-            for i in 0..(batch_size * num_beams) {
-                for token_position in 0..prev_output_tokens.get(i).size()[0] {
-                    let token = prev_output_tokens.get(i).int64_value(&[token_position]);
-                    let updated_value = &next_token_logits.double_value(&[i, token]);
-                    if updated_value < &0f64 {
-                        let _ = next_token_logits.get(i).index_fill_(
-                            0,
-                            &Tensor::from_slice(&[token])
-                                .to_kind(Kind::Int64)
-                                .to_device(next_token_logits.device()),
-                            updated_value * repetition_penalty,
-                        );
-                    } else {
-                        let _ = next_token_logits.get(i).index_fill_(
-                            0,
-                            &Tensor::from_slice(&[token])
-                                .to_kind(Kind::Int64)
-                                .to_device(next_token_logits.device()),
-                            updated_value / repetition_penalty,
-                        );
-                    }
-                }
-            }
-        }
-
-        fn get_banned_tokens(
-            &self,
-            input_ids: &Tensor,
-            no_repeat_ngram_size: i64,
-            cur_len: i64,
-        ) -> Vec<Vec<i64>> {
-            //        Ported from hugging face's transformers and fairseq (https://github.com/pytorch/fairseq/blob/master/fairseq/sequence_generator.py)
-            if cur_len + 1 < no_repeat_ngram_size {
-                vec![vec![]]
-            } else {
-                let input_ids = input_ids.to(Device::Cpu);
-                let num_hypothesis = *input_ids.size().first().unwrap();
-                let mut banned_tokens: Vec<Vec<i64>> = Vec::with_capacity(num_hypothesis as usize);
-                for hypothesis_index in 0..num_hypothesis {
-                    let hypothesis_input_ids = input_ids.get(hypothesis_index);
-                    let mut generated_ngram: HashMap<Vec<i64>, Vec<i64>> = HashMap::new();
-                    let input: Vec<i64> = (0..hypothesis_input_ids.size1().unwrap()).collect();
-                    let hypothesis_input_ids = hypothesis_input_ids
-                        .iter::<i64>()
-                        .unwrap()
-                        .collect::<Vec<i64>>();
-                    let query = &hypothesis_input_ids
-                        [cur_len as usize + 1 - no_repeat_ngram_size as usize..]
-                        .to_vec();
-                    for ngram in input
-                        .windows(no_repeat_ngram_size as usize)
-                        .map(|win| (*win.first().unwrap(), *win.last().unwrap()))
-                    {
-                        let ngram = &hypothesis_input_ids[ngram.0 as usize..ngram.1 as usize + 1];
-                        let key = ngram[..no_repeat_ngram_size as usize - 1].to_vec();
-                        let value = *ngram.last().unwrap();
-                        generated_ngram
-                            .entry(key)
-                            .or_insert_with(|| vec![value])
-                            .push(value);
-                    }
-                    let hypothesis_banned_tokens = match generated_ngram.get(query) {
-                        Some(banned_tokens) => banned_tokens.clone(),
-                        None => vec![],
-                    };
-                    banned_tokens.push(hypothesis_banned_tokens);
-                }
-                banned_tokens
-            }
-        }
+                .map(|(tokens, attention_mask)| {
+                    (
+                        Tensor::from_slice(&tokens).to(self.get_device()),
+                        Tensor::from_slice(&attention_mask).to(self.get_device()),
+                    )
+                })
+                .unzip();
 
-        fn top_k_top_p_filtering(
-            &self,
-            logits: &mut Tensor,
-            top_k: i64,
-            top_p: f64,
-            min_tokens_to_keep: i64,
-        ) {
-            //        Nucleus and top-k filtering introduced by Holtzman et al. (http://arxiv.org/abs/1904.09751)
-            //        Ported from https://gist.github.com/thomwolf/1a5a29f6962089e871b94cbd09daf317
-            let vocab_size = *logits.size().last().unwrap();
-            if top_k > 0 {
-                let top_k = vocab_size - min(max(top_k, min_tokens_to_keep), vocab_size);
-                let (_, indices_to_remove) = logits.topk(top_k, -1, false, false);
-                for index in 0..*logits.size().first().unwrap() {
-                    let _ = logits.get(index).index_fill_(
-                        0,
-                        &indices_to_remove.get(index),
-                        f64::NEG_INFINITY,
-                    );
-                }
-            }
-            if top_p < 1f64 {
-                let (sorted_logits, sorted_indices) = logits.sort(-1, true);
-                let cumulative_probabilities = sorted_logits
-                    .softmax(-1, sorted_logits.kind())
-                    .cumsum(-1, sorted_logits.kind());
-                let mut sorted_indices_to_remove =
-                    cumulative_probabilities.ge(top_p).to_kind(Kind::Int64);
-                if min_tokens_to_keep > 1 {
-                    let _ = sorted_indices_to_remove.index_fill_(
-                        1,
-                        &Tensor::arange_start(
-                            0,
-                            min_tokens_to_keep + 1,
-                            (Kind::Int64, logits.device()),
-                        ),
-                        0,
-                    );
-                }
-                let _ = sorted_indices_to_remove.index_copy_(
-                    1,
-                    &Tensor::arange_start(1, vocab_size, (Kind::Int64, logits.device())),
-                    &sorted_indices_to_remove
-                        .slice(1, 0, vocab_size - 1, 1)
-                        .copy(),
-                );
-                let _ = sorted_indices_to_remove.index_fill_(
-                    1,
-                    &Tensor::from_slice(&[0])
-                        .to_kind(Kind::Int64)
-                        .to_device(sorted_indices_to_remove.device()),
-                    0,
-                );
-                let indices_to_remove = sorted_indices_to_remove
-                    .scatter(1, &sorted_indices, &sorted_indices_to_remove)
-                    .to_kind(Kind::Bool);
-                let _ = logits.masked_fill_(&indices_to_remove, f64::NEG_INFINITY);
-            }
+            (
+                Tensor::stack(&token_ids, 0),
+                Tensor::stack(&attention_masks, 0),
+            )
         }
 
         fn run_hamming_diversity_penalty(
@@ -646,179 +1062,223 @@ pub(crate) mod private_generation_utils {
             }
         }
 
-        fn apply_prefix_allowed_tokens_function(
+        /// Returns the indices of the unfinished sentences whose generated text (after the first
+        /// `prompt_length` tokens) contains a stop sequence.
+        fn get_stopped_sentences(
             &self,
-            prefix_allowed_tokens_fn: &dyn Fn(i64, &Tensor) -> Vec<i64>,
-            num_beams: i64,
             input_ids: &Tensor,
-            scores: &mut Tensor,
-        ) {
-            let mask = scores.new_full(
-                scores.size().as_slice(),
-                get_positive_infinity(scores.kind()).unwrap(),
-                (scores.kind(), scores.device()),
+            unfinished_sentences: &Tensor,
+            prompt_length: i64,
+            stop_sequences: &[String],
+        ) -> Vec<i64> {
+            // A stop sequence completed by the last token spans at most as many tokens as it has
+            // characters: only the end of the generated sequence needs to be decoded.
+            let max_stop_sequence_length = stop_sequences
+                .iter()
+                .map(|stop_sequence| stop_sequence.chars().count())
+                .max()
+                .unwrap_or(0) as i64;
+            let tail_start = max(
+                prompt_length,
+                input_ids.size()[1] - max_stop_sequence_length,
             );
-            for idx in 0..scores.size()[0] {
-                let batch_id = idx / num_beams;
-                let allowed_tokens: Vec<i64> =
-                    prefix_allowed_tokens_fn(batch_id, &input_ids.get(idx));
-                let _ = mask.get(idx).index_fill_(
-                    0,
-                    &Tensor::from_slice(allowed_tokens.as_slice()).to(scores.device()),
-                    0,
-                );
-            }
-            let _ = scores.subtract_(&mask);
+            let tokenizer = self._get_tokenizer();
+            unfinished_sentences
+                .iter::<i64>()
+                .unwrap()
+                .enumerate()
+                .filter(|(_, unfinished)| *unfinished > 0)
+                .filter_map(|(sentence_index, _)| {
+                    let tail_ids = input_ids
+                        .get(sentence_index as i64)
+                        .slice(0, tail_start, None, 1)
+                        .iter::<i64>()
+                        .unwrap()
+                        .collect::<Vec<i64>>();
+                    let tail_text = tokenizer.decode(&tail_ids, true, false);
+                    find_stop_sequence(&tail_text, 0, stop_sequences).map(|_| sentence_index as i64)
+                })
+                .collect()
         }
 
-        fn split_bad_word_ids<'a>(
+        /// Applies the penalties and constraints to the logits of the next token (greedy, sampling and contrastive search)
+        fn process_next_token_logits(
             &self,
-            bad_word_ids: Option<&'a Vec<Vec<i64>>>,
-        ) -> (Option<Vec<i64>>, Option<Vec<&'a Vec<i64>>>) {
-            if let Some(bad_word_ids) = bad_word_ids {
-                let mut bad_word_ids_length_1 = vec![];
-                let mut bad_word_ids_length_greater_than_1 = vec![];
-                for bad_word in bad_word_ids {
-                    if bad_word.len() == 1 {
-                        bad_word_ids_length_1.push(bad_word[0]);
-                    } else {
-                        bad_word_ids_length_greater_than_1.push(bad_word);
-                    }
-                }
-                let bad_word_ids_length_1 = if !bad_word_ids_length_1.is_empty() {
-                    Some(bad_word_ids_length_1)
-                } else {
-                    None
-                };
-                let bad_word_ids_length_greater_than_1 =
-                    if !bad_word_ids_length_greater_than_1.is_empty() {
-                        Some(bad_word_ids_length_greater_than_1)
-                    } else {
-                        None
-                    };
-                (bad_word_ids_length_1, bad_word_ids_length_greater_than_1)
-            } else {
-                (None, None)
-            }
-        }
-
-        fn tokens_match(&self, prev_tokens: &[i64], tokens: &[i64]) -> bool {
-            if tokens.is_empty() {
-                true
-            } else if tokens.len() > prev_tokens.len() {
-                false
-            } else {
-                &prev_tokens[prev_tokens.len() - tokens.len()..] == tokens
-            }
+            next_token_logits: &mut Tensor,
+            input_ids: &Tensor,
+            current_length: i64,
+            gen_opt: &InternalGenerateOptions,
+            logits_processors: &LogitsProcessors,
+        ) {
+            logits_processors.apply_penalties(input_ids, next_token_logits);
+            self.prepare_scores_for_generation(
+                next_token_logits,
+                current_length,
+                gen_opt.max_length,
+            );
+            logits_processors.apply_constraints(input_ids, next_token_logits);
         }
 
-        fn calc_static_bad_word_mask(
+        fn get_next_token_no_beam_search(
             &self,
-            scores: &Tensor,
-            bad_words_id_length_1: &[i64],
+            next_token_logits: &mut Tensor,
+            input_ids: &Tensor,
+            current_length: i64,
+            gen_opt: &InternalGenerateOptions,
+            logits_processors: &LogitsProcessors,
+            mirostat_samplers: Option<&mut [MirostatSampler]>,
         ) -> Tensor {
-            let mut static_bad_words_mask =
-                Tensor::zeros([scores.size()[1]], (Kind::Int8, scores.device()));
-            let _ = static_bad_words_mask.index_fill_(
-                0,
-                &Tensor::from_slice(bad_words_id_length_1).to_device(scores.device()),
-                1,
+            self.process_next_token_logits(
+                next_token_logits,
+                input_ids,
+                current_length,
+                gen_opt,
+                logits_processors,
             );
-            static_bad_words_mask.unsqueeze(0).totype(Kind::Bool)
-        }
 
-        fn get_dynamic_bad_word_ids(
-            &self,
-            prev_tokens: &[Vec<i64>],
-            bad_word_ids_length_greater_than_1: &[&Vec<i64>],
-        ) -> Vec<Vec<i64>> {
-            let mut banned_tokens = Vec::new();
-            for prev_token_sequence in prev_tokens {
-                let mut sequence_banned_tokens = Vec::new();
-                for bad_word_ids in bad_word_ids_length_greater_than_1 {
-                    if self
-                        .tokens_match(prev_token_sequence, &bad_word_ids[..bad_word_ids.len() - 1])
-                    {
-                        sequence_banned_tokens.push(*bad_word_ids.last().unwrap());
+            // Top-k and top-p sampling
+            if gen_opt.do_sample {
+                logits_processors.apply_temperature(input_ids, next_token_logits);
+                if let Some(mirostat_samplers) = mirostat_samplers {
+                    // Mirostat sampling replaces the top-k, top-p and typical filtering. The state of every
+                    // sequence is updated with the surprise of its sampled token.
+                    for (row, sampler) in mirostat_samplers.iter().enumerate() {
+                        sampler.filter_logits(&mut next_token_logits.get(row as i64));
+                    }
+                    let log_probabilities = next_token_logits.log_softmax(-1, Kind::Float);
+                    let next_token = log_probabilities.exp().multinomial(1, false);
+                    let surprises = Vec::<f64>::try_from(
+                        log_probabilities
+                            .gather(1, &next_token, false)
+                            .squeeze_dim(1)
+                            / -LN_2,
+                    )
+                    .unwrap();
+                    for (sampler, surprise) in mirostat_samplers.iter_mut().zip(surprises) {
+                        sampler.update(surprise);
                     }
+                    return next_token.squeeze_dim(1);
                 }
-                banned_tokens.push(sequence_banned_tokens);
+                logits_processors.apply_warpers(input_ids, next_token_logits);
+                let probabilities = next_token_logits.softmax(-1, next_token_logits.kind());
+                probabilities.multinomial(1, false).squeeze_dim(1)
+            } else {
+                next_token_logits.argmax(-1, false)
             }
-
-            banned_tokens
         }
 
-        fn ban_bad_words(
+        /// Contrastive search step ([Su et al.](https://arxiv.org/abs/2202.06417)): the `top_k` most likely tokens are
+        /// evaluated with an additional forward pass and the token maximizing
+        /// `(1 - penalty_alpha) * probability - penalty_alpha * max_similarity` is selected, where `max_similarity` is the
+        /// maximum cosine similarity between the hidden state of the candidate and the hidden states of the previous tokens.
+        /// The past, the context hidden states and the next token logits are updated with the output of the selected candidates.
+        fn get_next_token_contrastive_search(
             &self,
-            dynamic_bad_words: Option<&Vec<&Vec<i64>>>,
-            static_bad_words_mask: Option<&Tensor>,
-            token_ids: &Tensor,
-            scores: &mut Tensor,
-        ) {
-            let longest_bad_word = dynamic_bad_words
-                .iter()
-                .map(|bad_word| bad_word.len())
-                .max()
-                .unwrap() as i64;
-
-            let last_token_ids = token_ids.slice(1, -longest_bad_word, None, 1);
-            let mut prev_tokens = Vec::new();
-            for sequence_idx in 0..token_ids.size()[0] {
-                prev_tokens.push(
-                    last_token_ids
-                        .get(sequence_idx)
-                        .iter::<i64>()
-                        .unwrap()
-                        .collect::<Vec<i64>>(),
-                )
-            }
-
-            let dynamic_bad_words_mask = if let Some(dynamic_bad_words) = dynamic_bad_words {
-                let dynamic_banned_tokens =
-                    self.get_dynamic_bad_word_ids(&prev_tokens, dynamic_bad_words);
-                let dynamic_banned_mask =
-                    Tensor::zeros(scores.size().as_slice(), (Kind::Int, scores.device()));
-                for (sequence_index, sequence_ban_tokens) in
-                    dynamic_banned_tokens.iter().enumerate()
-                {
-                    if !sequence_ban_tokens.is_empty() {
-                        let _ = dynamic_banned_mask.get(sequence_index as i64).index_fill_(
-                            0,
-                            &Tensor::from_slice(sequence_ban_tokens).to_device(scores.device()),
-                            1,
-                        );
-                    }
-                }
-                Some(dynamic_banned_mask.to_kind(Kind::Bool))
+            state: &mut ContrastiveSearchState,
+            next_token_logits: &mut Tensor,
+            input_ids: &Tensor,
+            attention_mask: &Tensor,
+            past: &mut Cache,
+            encoder_outputs: &mut Option<Tensor>,
+            current_length: i64,
+            gen_opt: &InternalGenerateOptions,
+            logits_processors: &LogitsProcessors,
+        ) -> Result<Tensor, RustBertError> {
+            self.process_next_token_logits(
+                next_token_logits,
+                input_ids,
+                current_length,
+                gen_opt,
+                logits_processors,
+            );
+            let (_, top_k_tokens) = next_token_logits.topk(state.top_k, -1, true, true);
+
+            // Forward pass of the candidate tokens, re-using the past of their sequence
+            *encoder_outputs =
+                self.reorder_cache(past, encoder_outputs.take(), &state.candidate_indices);
+            let candidate_attention_mask = attention_mask.index_select(0, &state.candidate_indices);
+            let candidate_attention_mask = if self.is_encoder_decoder() {
+                candidate_attention_mask
             } else {
-                None
+                Tensor::cat(
+                    &[
+                        &candidate_attention_mask,
+                        &candidate_attention_mask.ones_like().narrow(-1, 0, 1),
+                    ],
+                    -1,
+                )
             };
-
-            let combined_bad_word_mask = {
-                if let (Some(static_mask), Some(dynamic_mask)) =
-                    (static_bad_words_mask, &dynamic_bad_words_mask)
-                {
-                    Some(static_mask.bitwise_or_tensor(dynamic_mask))
-                } else {
-                    None
-                }
-            };
-
-            let bad_word_mask = if combined_bad_word_mask.is_some() {
-                combined_bad_word_mask.as_ref()
-            } else if static_bad_words_mask.is_some() {
-                static_bad_words_mask
-            } else if dynamic_bad_words_mask.is_some() {
-                dynamic_bad_words_mask.as_ref()
-            } else {
+            let prepared_input = self.prepare_inputs_for_generation(
+                Tensor::cat(
+                    &[
+                        input_ids.index_select(0, &state.candidate_indices),
+                        top_k_tokens.view([-1, 1]),
+                    ],
+                    -1,
+                ),
+                encoder_outputs.as_ref(),
+                mem::replace(past, Cache::None),
+                candidate_attention_mask,
+            );
+            let output = self.forward_t(
+                prepared_input.prepared_input.as_ref(),
+                prepared_input.prepared_past,
+                prepared_input.prepared_attention_mask.as_ref(),
+                None,
+                prepared_input.prepared_position_ids.as_ref(),
+                None,
+                prepared_input.prepared_encoder_output,
+                prepared_input.prepared_decoder_input.as_ref(),
+                false,
+            )?;
+            let candidate_hidden_states = output
+                .last_hidden_state
+                .ok_or_else(ContrastiveSearchState::missing_hidden_states)?
+                .select(1, -1);
+
+            // Degeneration penalty: maximum similarity with the (non-padding) previous tokens
+            let context_length = state.context_hidden_states.size()[1];
+            let context_mask = if self.is_encoder_decoder() {
                 None
+            } else {
+                Some(attention_mask.slice(1, -context_length, None, 1))
             };
-
-            if let Some(bad_word_mask) = bad_word_mask {
-                let _ = scores.masked_fill_(bad_word_mask, f64::NEG_INFINITY);
-            }
+            let next_token = contrastive_search(
+                next_token_logits,
+                state.top_k,
+                state.penalty_alpha,
+                &candidate_hidden_states,
+                &state.context_hidden_states,
+                context_mask.as_ref(),
+            );
+            let selected_candidates = top_k_tokens
+                .eq_tensor(&next_token.unsqueeze(-1))
+                .to_kind(Kind::Int64)
+                .argmax(-1, false);
+            let selected_indices = &state.batch_indices * state.top_k + &selected_candidates;
+
+            // Keep the past, hidden states and next token logits of the selected candidates
+            *past = output.cache;
+            *encoder_outputs = self.reorder_cache(past, encoder_outputs.take(), &selected_indices);
+            state.context_hidden_states = Tensor::cat(
+                &[
+                    &state.context_hidden_states,
+                    &candidate_hidden_states
+                        .index_select(0, &selected_indices)
+                        .unsqueeze(1),
+                ],
+                1,
+            );
+            state.next_token_logits = Some(
+                output
+                    .lm_logits
+                    .select(1, -1)
+                    .index_select(0, &selected_indices),
+            );
+            Ok(next_token)
         }
 
+        /// Greedy decoding, sampling and contrastive search (if `penalty_alpha` is strictly positive)
         fn generate_no_beam_search(
             &self,
             input_ids: Tensor,
@@ -826,140 +1286,110 @@ pub(crate) mod private_generation_utils {
             cur_len: i64,
             batch_size: i64,
             attention_mask: Tensor,
+            mut past: Cache,
             gen_opt: InternalGenerateOptions,
             prefix_allowed_tokens_fn: Option<PrefixAllowedFunction>,
             output_scores: bool,
-        ) -> GeneratedOutputWithScores {
+        ) -> Result<GeneratedOutputWithScores, RustBertError> {
             let mut unfinished_sentences =
                 Tensor::ones([batch_size], (Kind::Int64, self.get_device()));
             let mut sentence_lengths: Tensor =
                 Tensor::ones([batch_size], (Kind::Int64, self.get_device()));
-            let (bad_word_ids_length_1, bad_word_ids_length_greater_than_1) =
-                self.split_bad_word_ids(gen_opt.bad_word_ids);
-            let mut static_bad_words_mask: Option<Tensor> = None;
+            // Codes of the `FinishReason` of every sentence, kept on the device to avoid additional synchronizations
+            let mut finish_reasons = Tensor::full(
+                [batch_size],
+                FinishReason::MaxLength as i64,
+                (Kind::Int64, self.get_device()),
+            );
+            let logits_processors =
+                LogitsProcessors::new(&gen_opt, prefix_allowed_tokens_fn, 1, cur_len);
+            let eos_token_ids = gen_opt.eos_token_ids.as_ref().map(|eos_token_ids| {
+                Tensor::from_slice(eos_token_ids).to_device(input_ids.device())
+            });
             let mut attention_mask = attention_mask.copy();
             let mut input_ids = input_ids.copy();
-            let mut past: Cache = Cache::None;
-            let mut outputs: Tensor;
+            let mut encoder_outputs = encoder_outputs;
             let mut current_length = cur_len;
             let mut token_scores_output: Option<Vec<Tensor>> =
                 if output_scores { Some(vec![]) } else { None };
+            let mut contrastive_search_state =
+                ContrastiveSearchState::new(&gen_opt, batch_size, input_ids.device());
+            // The attention weights and hidden states of the candidates of contrastive search are not returned
+            let (mut attentions_output, mut hidden_states_output) =
+                if contrastive_search_state.is_some() {
+                    (None, None)
+                } else {
+                    gen_opt.model_outputs()
+                };
+            let mut mirostat_samplers = gen_opt
+                .mirostat
+                .map(|mirostat| vec![MirostatSampler::new(mirostat); batch_size as usize]);
 
             loop {
-                let prepared_input = self.prepare_inputs_for_generation(
-                    input_ids.copy(),
-                    encoder_outputs.as_ref(),
-                    past,
-                    attention_mask.copy(),
-                );
-                let temp = self
-                    .forward_t(
-                        prepared_input.prepared_input.as_ref(),
-                        prepared_input.prepared_past,
-                        prepared_input.prepared_attention_mask.as_ref(),
-                        None,
-                        prepared_input.prepared_position_ids.as_ref(),
-                        None,
-                        prepared_input.prepared_encoder_output,
-                        prepared_input.prepared_decoder_input.as_ref(),
-                        false,
-                    )
-                    .unwrap();
-                outputs = temp.lm_logits;
-                past = temp.cache;
-
-                let mut next_token_logits = outputs.select(1, -1);
-                // Reduce probability for repeated inputs
-                if gen_opt.repetition_penalty > 1f64 {
-                    self.enforce_repetition_penalty(
-                        &mut next_token_logits,
-                        batch_size,
-                        1,
-                        &input_ids,
-                        gen_opt.repetition_penalty,
-                    )
-                }
-
-                // Get bad word_ids and set their probability to 0
-                if gen_opt.bad_word_ids.is_some() {
-                    // Calculate static bad words masks if not set yet
-                    if let Some(bad_word_ids_length_1) = &bad_word_ids_length_1 {
-                        if static_bad_words_mask.is_none() {
-                            static_bad_words_mask = Some(self.calc_static_bad_word_mask(
-                                &next_token_logits,
-                                bad_word_ids_length_1,
-                            ));
+                // The logits of the next token are computed by the forward pass of the candidates for contrastive search
+                let pending_logits = contrastive_search_state
+                    .as_mut()
+                    .and_then(|state| state.next_token_logits.take());
+                let mut next_token_logits = match pending_logits {
+                    Some(next_token_logits) => next_token_logits,
+                    None => {
+                        let prepared_input = self.prepare_inputs_for_generation(
+                            input_ids.copy(),
+                            encoder_outputs.as_ref(),
+                            past,
+                            attention_mask.copy(),
+                        );
+                        let temp = self.forward_t(
+                            prepared_input.prepared_input.as_ref(),
+                            prepared_input.prepared_past,
+                            prepared_input.prepared_attention_mask.as_ref(),
+                            None,
+                            prepared_input.prepared_position_ids.as_ref(),
+                            None,
+                            prepared_input.prepared_encoder_output,
+                            prepared_input.prepared_decoder_input.as_ref(),
+                            false,
+                        )?;
+                        past = temp.cache;
+                        if let Some(state) = contrastive_search_state.as_mut() {
+                            state.context_hidden_states = temp
+                                .last_hidden_state
+                                .ok_or_else(ContrastiveSearchState::missing_hidden_states)?;
+                        }
+                        if let (Some(attentions), Some(step_attentions)) =
+                            (attentions_output.as_mut(), temp.all_attentions)
+                        {
+                            attentions.push(step_attentions);
+                        }
+                        if let (Some(hidden_states), Some(step_hidden_states)) =
+                            (hidden_states_output.as_mut(), temp.all_hidden_states)
+                        {
+                            hidden_states.push(step_hidden_states);
                         }
+                        temp.lm_logits.select(1, -1)
                     }
-                    self.ban_bad_words(
-                        bad_word_ids_length_greater_than_1.as_ref(),
-                        static_bad_words_mask.as_ref(),
-                        &input_ids,
-                        &mut next_token_logits,
-                    );
-                }
+                };
 
-                // Get banned tokens and set their probability to 0
-                if gen_opt.no_repeat_ngram_size > 0 {
-                    let banned_tokens = self.get_banned_tokens(
+                let next_token = match contrastive_search_state.as_mut() {
+                    Some(state) => self.get_next_token_contrastive_search(
+                        state,
+                        &mut next_token_logits,
                         &input_ids,
-                        gen_opt.no_repeat_ngram_size,
+                        &attention_mask,
+                        &mut past,
+                        &mut encoder_outputs,
                         current_length,
-                    );
-                    for (batch_index, index_banned_token) in
-                        (0..banned_tokens.len() as i64).zip(banned_tokens)
-                    {
-                        let _ = next_token_logits.get(batch_index).index_fill_(
-                            0,
-                            &Tensor::from_slice(&index_banned_token)
-                                .to_device(next_token_logits.device()),
-                            f64::NEG_INFINITY,
-                        );
-                    }
-                }
-
-                // Apply custom prefix constraint function
-                if let Some(prefix_allowed_tokens_function) = prefix_allowed_tokens_fn {
-                    self.apply_prefix_allowed_tokens_function(
-                        prefix_allowed_tokens_function,
-                        1,
-                        &input_ids,
-                        &mut next_token_logits,
-                    )
-                }
-
-                // Do not allow eos token if min length is not reached
-                if (gen_opt.eos_token_ids.is_some()) & (current_length < gen_opt.min_length) {
-                    let _ = next_token_logits.index_fill_(
-                        1,
-                        &Tensor::from_slice(gen_opt.eos_token_ids.as_ref().unwrap())
-                            .to(next_token_logits.device()),
-                        f64::NEG_INFINITY,
-                    );
-                }
-
-                self.prepare_scores_for_generation(
-                    &mut next_token_logits,
-                    current_length,
-                    gen_opt.max_length,
-                    gen_opt.forced_bos_token_id,
-                );
-
-                // Top-k and top-p sampling
-                let next_token = if gen_opt.do_sample {
-                    if gen_opt.temperature > 1f64 {
-                        next_token_logits /= gen_opt.temperature;
-                    }
-                    self.top_k_top_p_filtering(
+                        &gen_opt,
+                        &logits_processors,
+                    )?,
+                    None => self.get_next_token_no_beam_search(
                         &mut next_token_logits,
-                        gen_opt.top_k,
-                        gen_opt.top_p,
-                        1,
-                    );
-                    let probabilities = next_token_logits.softmax(-1, next_token_logits.kind());
-                    probabilities.multinomial(1, false).squeeze_dim(1)
-                } else {
-                    next_token_logits.argmax(-1, false)
+                        &input_ids,
+                        current_length,
+                        &gen_opt,
+                        &logits_processors,
+                        mirostat_samplers.as_deref_mut(),
+                    ),
                 };
 
                 if let Some(prev_scores) = token_scores_output.as_mut() {
@@ -968,38 +1398,70 @@ pub(crate) mod private_generation_utils {
                         next_token_logits
                             .log_softmax(-1, next_token_logits.kind())
                             .gather(1, &next_token.reshape([-1, 1]), false)
-                            .squeeze()
+                            .squeeze_dim(-1)
                             .masked_fill(&finished_mask, 0),
                     );
                 };
 
                 // Add tokens to unfinished sentences
-                let tokens_to_add = match &gen_opt.eos_token_ids {
-                    Some(_) => {
+                let tokens_to_add = match gen_opt.pad_token_id {
+                    Some(pad_token_id) => {
                         next_token * &unfinished_sentences
-                            - gen_opt.pad_token_id.unwrap() * (&unfinished_sentences - 1)
+                            - pad_token_id * (&unfinished_sentences - 1)
                     }
                     None => next_token,
                 };
 
                 input_ids = Tensor::cat(&[input_ids, tokens_to_add.unsqueeze(-1)], -1);
-                if gen_opt.eos_token_ids.is_some() {
-                    for eos_token_id in gen_opt.eos_token_ids.as_ref().unwrap() {
-                        let sentence_with_eos =
-                            tokens_to_add.eq(*eos_token_id).to_kind(Kind::Int64);
-                        let sentence_with_eos: Tensor = sentence_with_eos * &unfinished_sentences;
-                        let _ = sentence_lengths.masked_fill_(
-                            &sentence_with_eos
-                                .to_kind(Kind::Bool)
-                                .to_device(sentence_lengths.device()),
+                if let Some(eos_token_ids) = &eos_token_ids {
+                    // Sequences are finished by any of the EOS tokens
+                    let sentence_with_eos =
+                        Tensor::isin(&tokens_to_add, eos_token_ids, false, false)
+                            .to_kind(Kind::Int64);
+                    let sentence_with_eos: Tensor = sentence_with_eos * &unfinished_sentences;
+                    let _ = sentence_lengths.masked_fill_(
+                        &sentence_with_eos
+                            .to_kind(Kind::Bool)
+                            .to_device(sentence_lengths.device()),
+                        current_length + 1,
+                    );
+                    let _ = finish_reasons.masked_fill_(
+                        &sentence_with_eos
+                            .to_kind(Kind::Bool)
+                            .to_device(finish_reasons.device()),
+                        FinishReason::Eos as i64,
+                    );
+                    unfinished_sentences = -unfinished_sentences * (sentence_with_eos - 1);
+                }
+                if let Some(stop_sequences) = gen_opt.stop_sequences {
+                    let stopped_sentences = self.get_stopped_sentences(
+                        &input_ids,
+                        &unfinished_sentences,
+                        cur_len,
+                        stop_sequences,
+                    );
+                    if !stopped_sentences.is_empty() {
+                        let stopped_sentences = Tensor::from_slice(&stopped_sentences);
+                        let _ = unfinished_sentences.index_fill_(
+                            0,
+                            &stopped_sentences.to_device(unfinished_sentences.device()),
+                            0,
+                        );
+                        let _ = sentence_lengths.index_fill_(
+                            0,
+                            &stopped_sentences.to_device(sentence_lengths.device()),
                             current_length + 1,
                         );
-                        unfinished_sentences = -unfinished_sentences * (sentence_with_eos - 1);
-                    }
-                    if i64::try_from(unfinished_sentences.max()).unwrap() == 0 {
-                        break;
+                        let _ = finish_reasons.index_fill_(
+                            0,
+                            &stopped_sentences.to_device(finish_reasons.device()),
+                            FinishReason::StopSequence as i64,
+                        );
                     }
                 }
+                if i64::try_from(unfinished_sentences.max()).unwrap() == 0 {
+                    break;
+                }
                 if !self.is_encoder_decoder() {
                     attention_mask = Tensor::cat(
                         &[
@@ -1025,6 +1487,18 @@ pub(crate) mod private_generation_utils {
                         break;
                     }
                 }
+                if let Some(interruption) = gen_opt.interruption() {
+                    let unfinished_mask = unfinished_sentences.to_kind(Kind::Bool);
+                    let _ = sentence_lengths.masked_fill_(
+                        &unfinished_mask.to_device(sentence_lengths.device()),
+                        current_length,
+                    );
+                    let _ = finish_reasons.masked_fill_(
+                        &unfinished_mask.to_device(finish_reasons.device()),
+                        interruption as i64,
+                    );
+                    break;
+                }
             }
             let scores_output = token_scores_output.as_ref().map(|scores_tensor| {
                 (Tensor::stack(scores_tensor, 1).sum_dim_intlist(
@@ -1049,11 +1523,23 @@ pub(crate) mod private_generation_utils {
                     })
                     .collect()
             });
-            GeneratedOutputWithScores {
+            Ok(GeneratedOutputWithScores {
                 indices: input_ids,
                 scores: scores_output,
                 token_scores: token_scores_output,
-            }
+                finish_reasons: Some(FinishReason::from_codes(&finish_reasons)),
+                num_generated_tokens: Some(
+                    (sentence_lengths - cur_len)
+                        .iter::<i64>()
+                        .unwrap()
+                        .map(|num_tokens| num_tokens as usize)
+                        .collect(),
+                ),
+                attentions: attentions_output.filter(|attentions| !attentions.is_empty()),
+                hidden_states: hidden_states_output
+                    .filter(|hidden_states| !hidden_states.is_empty()),
+                past,
+            })
         }
 
         fn generate_beam_search(
@@ -1063,6 +1549,7 @@ pub(crate) mod private_generation_utils {
             cur_len: i64,
             batch_size: i64,
             mut attention_mask: Tensor,
+            mut past: Cache,
             gen_opt: InternalGenerateOptions,
             prefix_allowed_tokens_fn: Option<PrefixAllowedFunction>,
             output_scores: bool,
@@ -1070,14 +1557,17 @@ pub(crate) mod private_generation_utils {
             let num_beam_groups = gen_opt.num_beam_groups.unwrap_or(1);
             let num_sub_beams = gen_opt.num_beams / num_beam_groups;
             let diversity_penalty = gen_opt.diversity_penalty.unwrap_or(5.5);
-            let (bad_word_ids_length_1, bad_word_ids_length_greater_than_1) =
-                self.split_bad_word_ids(gen_opt.bad_word_ids);
-            let mut static_bad_words_mask: Option<Tensor> = None;
+            let logits_processors =
+                LogitsProcessors::new(&gen_opt, prefix_allowed_tokens_fn, num_sub_beams, cur_len);
+            let eos_token_ids = gen_opt.eos_token_ids.as_ref().map(|eos_token_ids| {
+                Tensor::from_slice(eos_token_ids).to_device(input_ids.device())
+            });
 
-            let mut hypotheses = (0..batch_size)
+            // Finished hypotheses are tracked for every beam group of every input
+            let mut hypotheses = (0..batch_size * num_beam_groups)
                 .map(|_| {
                     BeamHypotheses::new(
-                        gen_opt.num_beams,
+                        num_sub_beams,
                         gen_opt.max_length,
                         gen_opt.length_penalty,
                         gen_opt.early_stopping,
@@ -1105,10 +1595,10 @@ pub(crate) mod private_generation_utils {
             );
             let mut saved_beam_scores: Option<Vec<Tensor>> =
                 if output_scores { Some(vec![]) } else { None };
+            let (mut attentions_output, mut hidden_states_output) = gen_opt.model_outputs();
             let mut current_tokens = Tensor::new();
 
-            let mut past: Cache = Cache::None;
-            let mut done = vec![false; batch_size as usize];
+            let mut done = vec![false; (batch_size * num_beam_groups) as usize];
 
             let mut outputs: Tensor;
             let mut encoder_outputs = encoder_outputs;
@@ -1142,6 +1632,16 @@ pub(crate) mod private_generation_utils {
                     .unwrap();
                 outputs = temp.lm_logits;
                 past = temp.cache;
+                if let (Some(attentions), Some(step_attentions)) =
+                    (attentions_output.as_mut(), temp.all_attentions)
+                {
+                    attentions.push(step_attentions);
+                }
+                if let (Some(hidden_states), Some(step_hidden_states)) =
+                    (hidden_states_output.as_mut(), temp.all_hidden_states)
+                {
+                    hidden_states.push(step_hidden_states);
+                }
 
                 for beam_group_index in 0..num_beam_groups {
                     let group_start_index = beam_group_index * num_sub_beams;
@@ -1175,76 +1675,21 @@ pub(crate) mod private_generation_utils {
                             .select(1, -1)
                             .index_select(0, batch_group_indices.as_ref().unwrap())
                     };
-                    // Reduce probability for repeated inputs
-                    if gen_opt.repetition_penalty > 1f64 {
-                        self.enforce_repetition_penalty(
-                            &mut next_token_logits,
-                            batch_size,
-                            1,
-                            group_input_ids.as_ref().unwrap_or(&input_ids),
-                            gen_opt.repetition_penalty,
-                        )
-                    }
+                    let group_input_ids = group_input_ids.as_ref().unwrap_or(&input_ids);
+                    logits_processors.apply_penalties(group_input_ids, &mut next_token_logits);
 
-                    if gen_opt.temperature > 1f64 {
-                        next_token_logits /= gen_opt.temperature;
+                    if gen_opt.do_sample {
+                        logits_processors
+                            .apply_temperature(group_input_ids, &mut next_token_logits);
                     }
                     self.prepare_scores_for_generation(
                         &mut next_token_logits,
                         current_length,
                         gen_opt.max_length,
-                        gen_opt.forced_bos_token_id,
                     );
 
                     let mut scores = next_token_logits.log_softmax(-1, next_token_logits.kind());
 
-                    // Do not allow eos token if min length is not reached
-                    if (gen_opt.eos_token_ids.is_some()) & (current_length < gen_opt.min_length) {
-                        let _ = scores.index_fill_(
-                            1,
-                            &Tensor::from_slice(gen_opt.eos_token_ids.as_ref().unwrap())
-                                .to(scores.device()),
-                            f64::NEG_INFINITY,
-                        );
-                    }
-
-                    // Get bad word_ids and set their probability to 0
-                    if gen_opt.bad_word_ids.is_some() {
-                        // Calculate static bad words masks if not set yet
-                        if let Some(bad_word_ids_length_1) = &bad_word_ids_length_1 {
-                            if static_bad_words_mask.is_none() {
-                                static_bad_words_mask = Some(
-                                    self.calc_static_bad_word_mask(&scores, bad_word_ids_length_1),
-                                );
-                            }
-                        }
-                        self.ban_bad_words(
-                            bad_word_ids_length_greater_than_1.as_ref(),
-                            static_bad_words_mask.as_ref(),
-                            group_input_ids.as_ref().unwrap_or(&input_ids),
-                            &mut scores,
-                        );
-                    }
-
-                    // Get repeated tokens and set their probability to 0
-                    if gen_opt.no_repeat_ngram_size > 0 {
-                        let banned_tokens = self.get_banned_tokens(
-                            group_input_ids.as_ref().unwrap_or(&input_ids),
-                            gen_opt.no_repeat_ngram_size,
-                            current_length,
-                        );
-                        for (batch_index, index_banned_token) in
-                            (0..banned_tokens.len() as i64).zip(banned_tokens)
-                        {
-                            let _ = scores.get(batch_index).index_fill_(
-                                0,
-                                &Tensor::from_slice(&index_banned_token)
-                                    .to_device(next_token_logits.device()),
-                                f64::NEG_INFINITY,
-                            );
-                        }
-                    }
-
                     // Update scores with diversity penalty
                     if num_beam_groups > 1 {
                         self.run_hamming_diversity_penalty(
@@ -1258,15 +1703,7 @@ pub(crate) mod private_generation_utils {
                         );
                     }
 
-                    // Apply custom prefix constraint function
-                    if let Some(prefix_allowed_tokens_function) = prefix_allowed_tokens_fn {
-                        self.apply_prefix_allowed_tokens_function(
-                            prefix_allowed_tokens_function,
-                            num_sub_beams,
-                            &input_ids,
-                            &mut scores,
-                        )
-                    }
+                    logits_processors.apply_constraints(group_input_ids, &mut scores);
 
                     let mut next_scores: Tensor = &scores
                         + (if num_beam_groups > 1 {
@@ -1279,12 +1716,7 @@ pub(crate) mod private_generation_utils {
                         });
 
                     let (next_scores, next_tokens) = if gen_opt.do_sample {
-                        self.top_k_top_p_filtering(
-                            &mut next_scores,
-                            gen_opt.top_k,
-                            gen_opt.top_p,
-                            2,
-                        );
+                        logits_processors.apply_warpers(group_input_ids, &mut next_scores);
                         let _scores = next_scores
                             .contiguous()
                             .view((batch_size, group_size * vocab_size));
@@ -1302,7 +1734,6 @@ pub(crate) mod private_generation_utils {
                         _scores.topk(2 * group_size, 1, true, true)
                     };
 
-                    let eos_token_ids = gen_opt.eos_token_ids.as_ref();
                     let beam_ids_tensor = &next_tokens.divide_scalar_mode(vocab_size, "floor");
                     let effective_beam_ids_tensor =
                         (&next_tokens.ones_like().cumsum(0, Kind::Int64) - 1) * group_size
@@ -1310,8 +1741,9 @@ pub(crate) mod private_generation_utils {
                     let token_id_tensor = &next_tokens - beam_ids_tensor * vocab_size;
                     let (max_scores, _) = next_scores.max_dim(1, false);
                     let mut eos_mask = token_id_tensor.ones_like();
-                    if let Some(eos_token_id) = eos_token_ids {
-                        eos_mask -= token_id_tensor.eq(eos_token_id[0]).to_kind(Kind::Int64);
+                    if let Some(eos_token_ids) = &eos_token_ids {
+                        eos_mask -= Tensor::isin(&token_id_tensor, eos_token_ids, false, false)
+                            .to_kind(Kind::Int64);
                     }
                     let eos_mask2 = eos_mask
                         .cumsum(1, Kind::Int64)
@@ -1327,15 +1759,19 @@ pub(crate) mod private_generation_utils {
                     for eos_idx in 0..eos_pos.size()[0] {
                         let eos_data = eos_pos.get(eos_idx);
                         let batch_index = eos_data.int64_value(&[0]);
-                        if !done[batch_index as usize] {
+                        let hypotheses_index =
+                            (batch_index * num_beam_groups + beam_group_index) as usize;
+                        if !done[hypotheses_index] {
                             let beam_index_pos = eos_data.int64_value(&[1]);
                             let is_beam_token_worse_than_top_num_beams =
-                                beam_index_pos >= gen_opt.num_beams;
+                                beam_index_pos >= group_size;
                             if is_beam_token_worse_than_top_num_beams {
                                 continue;
                             }
-                            let effective_beam_id = effective_beam_ids_tensor
-                                .int64_value(&[batch_index, beam_index_pos]);
+                            // Position of the beam in the (non-grouped) input ids
+                            let effective_beam_id = batch_index * gen_opt.num_beams
+                                + group_start_index
+                                + beam_ids_tensor.int64_value(&[batch_index, beam_index_pos]);
                             let beam_token_score =
                                 next_scores.double_value(&[batch_index, beam_index_pos]);
                             let saved_beam_scores =
@@ -1344,7 +1780,7 @@ pub(crate) mod private_generation_utils {
                                         .get(effective_beam_id)
                                         .copy()
                                 });
-                            hypotheses[batch_index as usize].add(
+                            hypotheses[hypotheses_index].add(
                                 input_ids.get(effective_beam_id).copy(),
                                 beam_token_score,
                                 saved_beam_scores,
@@ -1353,19 +1789,21 @@ pub(crate) mod private_generation_utils {
                     }
 
                     for batch_index in 0..batch_size {
-                        if done[batch_index as usize] {
+                        let hypotheses_index =
+                            (batch_index * num_beam_groups + beam_group_index) as usize;
+                        if done[hypotheses_index] {
                             let _ = group_beam_scores
-                                .narrow(0, batch_index * gen_opt.num_beams, gen_opt.num_beams)
+                                .narrow(0, batch_index * group_size, group_size)
                                 .fill_(0f64);
                             let _ = group_beam_tokens
-                                .narrow(0, batch_index * gen_opt.num_beams, gen_opt.num_beams)
+                                .narrow(0, batch_index * group_size, group_size)
                                 .fill_(gen_opt.pad_token_id.unwrap());
                             let _ = group_beam_indices
-                                .narrow(0, batch_index * gen_opt.num_beams, gen_opt.num_beams)
+                                .narrow(0, batch_index * group_size, group_size)
                                 .fill_(0);
                             continue;
                         } else {
-                            done[batch_index as usize] |= hypotheses[batch_index as usize]
+                            done[hypotheses_index] |= hypotheses[hypotheses_index]
                                 .is_done(max_scores.double_value(&[batch_index]), current_length);
                         }
                     }
@@ -1423,6 +1861,9 @@ pub(crate) mod private_generation_utils {
                         break;
                     }
                 }
+                if gen_opt.interruption().is_some() {
+                    break;
+                }
                 encoder_outputs = self.reorder_cache(&mut past, encoder_outputs, &beam_indices);
 
                 if !self.is_encoder_decoder() {
@@ -1448,18 +1889,19 @@ pub(crate) mod private_generation_utils {
                 if batch_index == batch_size {
                     break;
                 }
-                if done[batch_index as usize] {
-                    batch_index += 1;
-                    continue;
-                }
                 for beam_index in 0..gen_opt.num_beams {
+                    let hypotheses_index =
+                        (batch_index * num_beam_groups + beam_index / num_sub_beams) as usize;
+                    if done[hypotheses_index] {
+                        continue;
+                    }
                     let effective_beam_id = batch_index * gen_opt.num_beams + beam_index;
                     let beam_saved_token_scores = saved_beam_scores.as_mut().map(|saved_tokens| {
                         mem::replace(&mut saved_tokens[effective_beam_id as usize], Tensor::new())
                     });
                     let final_score = f64::try_from(beam_scores.get(effective_beam_id)).unwrap();
                     let final_tokens = input_ids.get(effective_beam_id);
-                    hypotheses[batch_index as usize].add(
+                    hypotheses[hypotheses_index].add(
                         final_tokens,
                         final_score,
                         beam_saved_token_scores,
@@ -1490,17 +1932,20 @@ pub(crate) mod private_generation_utils {
             } else {
                 None
             };
-            for (hypothesis_index, hypothesis) in hypotheses.iter().enumerate() {
-                let mut sorted_hypotheses = hypothesis.clone();
-                sorted_hypotheses
-                    .beams
-                    .sort_by_key(|(score, _, _)| OrderedFloat(*score));
-                for j in 0..output_num_return_sequences_per_batch {
+            for (batch_index, group_hypotheses) in
+                hypotheses.chunks(num_beam_groups as usize).enumerate()
+            {
+                // The returned sequences are interleaved across beam groups
+                let best_hypotheses = select_group_hypotheses(
+                    group_hypotheses,
+                    output_num_return_sequences_per_batch as usize,
+                );
+                for (j, (best_score, best_hyp, best_token_scores)) in
+                    best_hypotheses.into_iter().enumerate()
+                {
                     let effective_batch_index =
-                        output_num_return_sequences_per_batch * hypothesis_index as i64 + j;
+                        output_num_return_sequences_per_batch * batch_index as i64 + j as i64;
 
-                    let (best_score, best_hyp, best_token_scores) =
-                        sorted_hypotheses.beams.pop().unwrap();
                     let _ = sentence_lengths.index_fill_(
                         0,
                         &Tensor::from_slice(&[effective_batch_index]).to(sentence_lengths.device()),
@@ -1571,6 +2016,12 @@ pub(crate) mod private_generation_utils {
                 indices: decoded,
                 scores: scores_output,
                 token_scores: token_scores_output,
+                finish_reasons: None,
+                num_generated_tokens: None,
+                attentions: attentions_output.filter(|attentions| !attentions.is_empty()),
+                hidden_states: hidden_states_output
+                    .filter(|hidden_states| !hidden_states.is_empty()),
+                past: Cache::None,
             }
         }
 
@@ -1587,6 +2038,62 @@ pub(crate) mod private_generation_utils {
                 }
             }
         }
+
+        /// Validates a cache provided to start the generation against the model configuration and
+        /// the number of prompts, returning the number of positions it holds.
+        fn get_cache_length(&self, past: &Cache, _num_prompts: i64) -> Result<i64, RustBertError> {
+            match past {
+                Cache::None => Ok(0),
+                _ => Err(RustBertError::ValueError(
+                    "Generation from a cache is not supported for this model".to_string(),
+                )),
+            }
+        }
+
+        /// Prepares a cache provided to start the generation, holding `cache_length` positions for
+        /// each of the `num_prompts` prompts: the cache is expanded to the generated sequences and
+        /// extended with the prompt tokens it does not hold (except for the last one, processed
+        /// by the first generation step).
+        fn prepare_initial_cache(
+            &self,
+            mut past: Cache,
+            input_ids: &Tensor,
+            attention_mask: &Tensor,
+            num_prompts: i64,
+            cache_length: i64,
+        ) -> Result<Cache, RustBertError> {
+            if cache_length == 0 {
+                return Ok(Cache::None);
+            }
+            let num_sequences = input_ids.size()[0];
+            if num_sequences > num_prompts {
+                let expanded_indices =
+                    Tensor::arange(num_prompts, (Kind::Int64, input_ids.device()))
+                        .repeat_interleave_self_int(num_sequences / num_prompts, None, None);
+                self.reorder_cache(&mut past, None, &expanded_indices);
+            }
+            let prompt_length = input_ids.size()[1];
+            if cache_length < prompt_length - 1 {
+                let attention_mask = attention_mask.slice(1, 0, prompt_length - 1, 1);
+                let position_ids = (attention_mask.cumsum(-1, Kind::Int64) - 1)
+                    .masked_fill(&attention_mask.eq(0), 1)
+                    .slice(1, cache_length, None, 1);
+                past = self
+                    .forward_t(
+                        Some(&input_ids.slice(1, cache_length, prompt_length - 1, 1)),
+                        past,
+                        Some(&attention_mask),
+                        None,
+                        Some(&position_ids),
+                        None,
+                        None,
+                        None,
+                        false,
+                    )?
+                    .cache;
+            }
+            Ok(past)
+        }
     }
 
     pub fn force_token_id_generation(scores: &mut Tensor, token_ids: &[i64], vocab_size: i64) {
@@ -1602,12 +2109,49 @@ pub(crate) mod private_generation_utils {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// # Finish reason
+/// Reason why the generation of a sequence stopped
+pub enum FinishReason {
+    /// An end of sequence token was generated
+    Eos = 0,
+    /// The maximum length was reached
+    MaxLength = 1,
+    /// A stop sequence was generated
+    StopSequence = 2,
+    /// The generation was cancelled with the `cancellation_flag` of the `GenerateOptions`
+    Cancelled = 3,
+    /// The generation exceeded the `max_time` of the `GenerateOptions`
+    TimeLimit = 4,
+}
+
+impl FinishReason {
+    /// Converts a tensor of finish reason codes (the enum discriminants) to finish reasons
+    fn from_codes(codes: &Tensor) -> Vec<FinishReason> {
+        codes
+            .iter::<i64>()
+            .unwrap()
+            .map(|code| match code {
+                0 => FinishReason::Eos,
+                2 => FinishReason::StopSequence,
+                3 => FinishReason::Cancelled,
+                4 => FinishReason::TimeLimit,
+                _ => FinishReason::MaxLength,
+            })
+            .collect()
+    }
+}
+
 #[derive(Debug, Clone)]
 /// # Generated text output
 /// Contains generated text and an optional log-likelihood score for the generated sequence
 pub struct GeneratedTextOutput {
     pub text: String,
     pub score: Option<f64>,
+    /// Reason why the generation of the sequence stopped (not available for beam search)
+    pub finish_reason: Option<FinishReason>,
+    /// Number of generated tokens, including the end of sequence token (not available for beam search)
+    pub num_generated_tokens: Option<usize>,
 }
 
 #[derive(Debug, Clone)]
@@ -1617,13 +2161,141 @@ pub struct GeneratedIndicesOutput {
     pub indices: Vec<i64>,
     pub score: Option<f64>,
     pub token_scores: Option<Vec<f64>>,
+    /// Reason why the generation of the sequence stopped (not available for beam search)
+    pub finish_reason: Option<FinishReason>,
+    /// Number of generated tokens, including the end of sequence token and excluding the padding added after the end
+    /// of the sequence (not available for beam search)
+    pub num_generated_tokens: Option<usize>,
+    /// Number of leading `indices` belonging to the prompt, including the left padding of prompts shorter than the
+    /// longest prompt of the batch: the continuation starts at `indices[prompt_length..]`. Equal to 0 if the prompt is
+    /// not echoed (`echo_prompt = false`) and for encoder-decoder models.
+    pub prompt_length: usize,
+}
+
+#[derive(Debug)]
+/// # Model outputs collected during the generation
+/// Attention weights and hidden states of every generation step, returned by `LanguageGenerator::generate_with_model_outputs`
+/// if `output_attentions` or `output_hidden_states` are set in the `GenerateOptions`.
+pub struct GeneratedModelOutputs {
+    /// Attention weights of every layer for every generation step, of shape (*batch size x number of beams*,
+    /// *number of heads*, *query length*, *key length*). The query length is the number of positions processed by the
+    /// step: the (non-cached) prompt length for the first step and 1 for the following steps. For beam search, the
+    /// rows are the beams of the step (before their re-ordering). `None` if not requested, for contrastive search and
+    /// for models not returning their attentions.
+    pub attentions: Option<Vec<Vec<Tensor>>>,
+    /// Hidden states of every layer for every generation step, of shape (*batch size x number of beams*,
+    /// *query length*, *hidden size*). `None` if not requested, for contrastive search and for models not returning
+    /// their hidden states.
+    pub hidden_states: Option<Vec<Vec<Tensor>>>,
+}
+
+#[derive(Debug, Clone)]
+/// # Generated token
+/// Token generated by a single decoding step of `LanguageGenerator::generate_iter`
+pub struct GeneratedToken {
+    /// Token id
+    pub id: i64,
+    /// Text added to the generated text by this token. Byte-level tokens forming a multi-byte character
+    /// have an empty text until the character is complete, and the text of a token starting a stop sequence is held back.
+    pub text: String,
+    /// Log-probability of the token (after application of the generation constraints, temperature and sampling filters)
+    pub log_prob: f64,
+}
+
+#[derive(Debug, Clone)]
+/// # Continuation score
+/// Log-likelihood of a continuation given a prompt, computed by `LanguageGenerator::score_texts`
+pub struct ContinuationScore {
+    /// Token ids of the continuation
+    pub token_ids: Vec<i64>,
+    /// Log-probability of every continuation token given the previous tokens
+    pub token_scores: Vec<f64>,
+    /// Log-likelihood of the continuation (sum of the token log-probabilities)
+    pub score: f64,
 }
 
-pub type PrefixAllowedFunction<'a> = &'a dyn Fn(i64, &Tensor) -> Vec<i64>;
 /// Type alias for a function defining allowed tokens based on current tokens generated.
 /// This function should take a `batch_id` and associated tensor of already generated tokens and
 /// should return a vector of allowed tokens. This is useful for controlled generation, i.e.
 /// deterministic generation of a token continuation if a sequence of token occurs.
+/// The function is called for every hypothesis (every beam for beam search) at each generation step.
+/// An empty vector leaves the next token of the hypothesis unconstrained.
+///
+/// # Grammar-constrained generation
+/// The generation can be constrained to follow a grammar or schema by tracking the state of a finite automaton
+/// over the tokens already generated. For a finite set of valid continuations, the automaton is a trie of their
+/// token ids: the allowed tokens are the children of the node reached by the generated tokens, and the EOS token
+/// once a leaf is reached. Note that the tensor passed to the function contains the tokens of the prompt for decoder-only
+/// models (preceded by padding tokens for batched prompts of different lengths).
+///
+/// ```no_run
+/// # fn main() -> anyhow::Result<()> {
+/// use rust_bert::gpt2::GPT2Generator;
+/// use rust_bert::pipelines::generation_utils::{
+///     GenerateConfig, GenerateOptions, LanguageGenerator,
+/// };
+/// use std::collections::HashMap;
+/// use tch::Tensor;
+///
+/// #[derive(Default)]
+/// struct TokenTrie(HashMap<i64, TokenTrie>);
+///
+/// impl TokenTrie {
+///     fn insert(&mut self, token_ids: &[i64]) {
+///         if let Some((first, rest)) = token_ids.split_first() {
+///             self.0.entry(*first).or_default().insert(rest);
+///         }
+///     }
+///
+///     fn allowed_tokens(&self, token_ids: &[i64], eos_token_id: i64) -> Vec<i64> {
+///         let mut node = self;
+///         for token_id in token_ids {
+///             match node.0.get(token_id) {
+///                 Some(child) => node = child,
+///                 None => return vec![eos_token_id],
+///             }
+///         }
+///         if node.0.is_empty() {
+///             vec![eos_token_id]
+///         } else {
+///             node.0.keys().copied().collect()
+///         }
+///     }
+/// }
+///
+/// let generate_config = GenerateConfig {
+///     do_sample: false,
+///     num_beams: 3,
+///     ..Default::default()
+/// };
+/// let gpt2_generator = GPT2Generator::new(generate_config)?;
+/// let tokenizer = gpt2_generator.get_tokenizer();
+///
+/// let mut trie = TokenTrie::default();
+/// for date in [" 2023-12-31", " 2024-01-01"] {
+///     trie.insert(&tokenizer.convert_tokens_to_ids(&tokenizer.tokenize(date)));
+/// }
+/// let eos_token_id = tokenizer.get_eos_id().unwrap();
+/// let prompt = "The next day is";
+/// let prompt_length = tokenizer.tokenize(prompt).len() as i64;
+///
+/// let allowed_tokens = |_batch_id: i64, token_ids: &Tensor| {
+///     let generated_ids = token_ids
+///         .slice(0, prompt_length, None, 1)
+///         .iter::<i64>()
+///         .unwrap()
+///         .collect::<Vec<i64>>();
+///     trie.allowed_tokens(&generated_ids, eos_token_id)
+/// };
+/// let generate_options = GenerateOptions {
+///     prefix_allowed_tokens_fn: Some(&allowed_tokens),
+///     ..Default::default()
+/// };
+/// let output = gpt2_generator.generate(Some(&[prompt]), Some(generate_options))?;
+/// # Ok(())
+/// # }
+/// ```
+pub type PrefixAllowedFunction<'a> = &'a dyn Fn(i64, &Tensor) -> Vec<i64>;
 
 #[derive(Clone, Copy, Default)]
 /// # Generation options for text generation.
@@ -1636,6 +2308,7 @@ pub struct GenerateOptions<'a> {
     /// Maximum sequence length
     pub max_length: Option<i64>,
     /// Maximum number of new tokens to generate (useful for causal generation models).
+    /// The total length of the sequences generated by decoder-only models is also bounded by the model context size.
     /// Only one of `max_length` and `max_new_tokens` should be provided.
     /// When both are given, `max_new_tokens` is ignored and the `max_length` setting is used.
     pub max_new_tokens: Option<i64>,
@@ -1656,22 +2329,95 @@ pub struct GenerateOptions<'a> {
     pub top_p: Option<f64>,
     /// Repetition penalty (mostly useful for CTRL decoders). Values higher than 1 will penalize tokens that have been already generated.
     pub repetition_penalty: Option<f64>,
+    /// Tokens considered by the repetition penalty: all tokens (default), generated tokens only or prompt tokens only.
+    /// For encoder-decoder models, the prompt of the decoder is made of the decoder start tokens.
+    pub repetition_penalty_scope: Option<RepetitionPenaltyScope>,
+    /// Frequency penalty: the score of every token is reduced by `frequency_penalty` times its number of occurrences
+    /// in the generated tokens (applied after the repetition penalty).
+    pub frequency_penalty: Option<f64>,
+    /// Presence penalty: the score of every token already generated is reduced by `presence_penalty` (applied after the repetition penalty).
+    pub presence_penalty: Option<f64>,
+    /// Flag indicating if the prompt tokens are counted by the frequency and presence penalties (only the generated tokens are counted by default)
+    pub include_prompt_in_penalty: bool,
     /// Exponential penalty based on the length of the hypotheses generated
     pub length_penalty: Option<f64>,
     /// Number of allowed repetitions of n-grams. Values higher than 0 turn on this feature
     pub no_repeat_ngram_size: Option<i64>,
     /// Diversity penalty for diverse beam search. High values will enforce more difference between beam groups
     pub diversity_penalty: Option<f64>,
+    /// Degeneration penalty for [contrastive search, Su et al.](https://arxiv.org/abs/2202.06417). Only applies when
+    /// `penalty_alpha > 0`: the next token is selected among the `top_k` most likely candidates by maximizing
+    /// `(1 - penalty_alpha) * probability - penalty_alpha * max_similarity`, where `max_similarity` is the maximum cosine similarity
+    /// between the hidden state of the candidate and the hidden states of the previous tokens.
+    /// Requires `do_sample = false`, `num_beams = 1`, `top_k > 1` and a model returning its last hidden states (GPT2, GPT-J, OpenAI GPT).
+    pub penalty_alpha: Option<f64>,
+    /// Probability mass for [locally typical sampling, Meister et al.](https://arxiv.org/abs/2202.00666). Only applies when
+    /// `do_sample = true`: keeps the tokens whose information content is the closest to the entropy of the distribution
+    /// until their cumulative probability reaches `typical_p` (between 0 and 1, 1 disables the filter). Applied after
+    /// temperature, top-k and top-p filtering.
+    pub typical_p: Option<f64>,
+    /// Settings for [Mirostat sampling, Basu et al.](https://arxiv.org/abs/2007.14966) (see the `sampling` module). Only applies
+    /// when `do_sample = true`: the next token distribution of every sequence is truncated so that the surprise of the sampled
+    /// tokens stays close to the target `tau`. Replaces the top-k, top-p and typical filtering (the temperature is still applied).
+    /// Requires `num_beams = 1`.
+    pub mirostat: Option<Mirostat>,
     /// Decoder start token id
     pub decoder_start_token_id: Option<i64>,
-    /// Forced first token generated
+    /// Forced first token generated (overrides the model default, if any)
     pub forced_bos_token_id: Option<i64>,
-    /// Function to control the generation process. The function should take a `batch_id` (i64) and a tensor of token_ids already generated and returns a `Vec<i64>` of allowed tokens.
+    /// Forced last token generated when the `max_length` is reached (overrides the model default, if any)
+    pub forced_eos_token_id: Option<i64>,
+    /// Function to control the generation process. The function should take a `batch_id` (i64) and a tensor of token_ids already generated and returns a `Vec<i64>` of allowed tokens (an empty vector does not constrain the next token).
     pub prefix_allowed_tokens_fn: Option<PrefixAllowedFunction<'a>>,
     /// List of bad word ids (may be a sequence of word ids) that will be banned during the generation
     pub bad_word_ids: Option<&'a Vec<Vec<i64>>>,
+    /// Bias added to the scores of specific token ids at every step (after the repetition penalty). Positive values encourage
+    /// the tokens and `f64::NEG_INFINITY` bans them. See `LanguageGenerator::get_logit_bias` to build the map from strings.
+    pub logit_bias: Option<&'a HashMap<i64, f64>>,
+    /// List of token ids that can never be generated (e.g. special or control tokens). Cheaper than `bad_word_ids` for single tokens.
+    pub suppress_tokens: Option<&'a Vec<i64>>,
+    /// List of token ids that cannot be generated as first token (e.g. EOS or whitespace tokens)
+    pub begin_suppress_tokens: Option<&'a Vec<i64>>,
+    /// List of token ids the generation is restricted to (e.g. class labels or keywords). The EOS tokens are
+    /// implicitly allowed and can be excluded with `suppress_tokens`. The list may not be empty.
+    pub allowed_token_ids: Option<&'a Vec<i64>>,
+    /// Vocabulary index enabling token healing for decoder-only models (see the `token_healing` module): the last
+    /// token of the prompts is removed and the first generated token is restricted to the tokens starting with it, so
+    /// that prompts ending in the middle of a word are completed. Not supported when generating from a cache.
+    pub token_healing: Option<&'a TokenHealingIndex>,
+    /// List of stop sequences. The generation of a sequence stops as soon as its generated text contains one of these
+    /// strings (only checked for greedy and sampling decoding). The generated texts are truncated before the first stop sequence.
+    pub stop_sequences: Option<&'a Vec<String>>,
+    /// Custom logits processors, applied in order at every generation step after the built-in constraints
+    /// (see the `logits_processors` module for the processing order).
+    pub logits_processors: Option<&'a [Box<dyn LogitsProcessor + 'a>]>,
+    /// Flag indicating if the prompt tokens of decoder-only models are returned before the generated tokens (default: true).
+    /// If false, only the continuations are returned (the generated indices cannot be passed with the returned cache to
+    /// continue the generation).
+    pub echo_prompt: Option<bool>,
+    /// Cancellation flag checked by the decoding loop after every generation step. When set (e.g. from another thread
+    /// holding an `Arc<AtomicBool>`), the generation stops and returns the sequences generated so far, with a
+    /// `FinishReason::Cancelled` finish reason for the unfinished sequences.
+    pub cancellation_flag: Option<&'a AtomicBool>,
+    /// Maximum wall-clock time of the generation. The elapsed time is checked after every generation step: when the
+    /// limit is exceeded, the generation stops and returns the sequences generated so far, with a
+    /// `FinishReason::TimeLimit` finish reason for the unfinished sequences.
+    pub max_time: Option<Duration>,
     /// Flag indicating if text generation scores should be returned
     pub output_scores: bool,
+    /// Flag indicating if the attention weights of every generation step should be collected (only returned by
+    /// `generate_with_model_outputs`). Requires a model configured to output its attentions (`output_attentions`
+    /// in the model configuration). The weights of all layers are kept in memory for all steps: this can be
+    /// very large for long generations and large batches.
+    pub output_attentions: bool,
+    /// Flag indicating if the hidden states of every generation step should be collected (only returned by
+    /// `generate_with_model_outputs`). Requires a model configured to output its hidden states
+    /// (`output_hidden_states` in the model configuration). The hidden states of all layers are kept in memory for
+    /// all steps: this can be very large for long generations and large batches.
+    pub output_hidden_states: bool,
+    /// Flag indicating if the prompts of decoder-only models that do not fit in the maximum length (or in the model
+    /// context size) should be truncated. If false (default), generating from such prompts returns an error.
+    pub truncate_prompt: bool,
 }
 
 macro_rules! unpack_config {
@@ -1693,7 +2439,7 @@ pub trait LanguageGenerator: PrivateLanguageGenerator {
     /// * `generate_options` - `Option<GenerateOptions>` Optional set of generate options. If not (or partially) provided, will use the settings provided when creating the generator
     ///
     /// # Returns
-    /// * `Vec<TextOutput>` Vector of length *number_of_prompts* x *num_return_sequences* containing TextOutput with the generated texts and the generation score if `output_scores` is true.
+    /// * `Vec<TextOutput>` Vector of length *number_of_prompts* x *num_return_sequences* containing TextOutput with the generated texts and the generation score if `output_scores` is true. The sequences generated for the prompt at index `i` are stored contiguously at positions `i * num_return_sequences..(i + 1) * num_return_sequences`.
     ///
     /// # Example
     ///
@@ -1779,17 +2525,18 @@ pub trait LanguageGenerator: PrivateLanguageGenerator {
     where
         S: AsRef<str> + Send + Sync,
     {
-        let indices_outputs = self.generate_indices(prompt_texts, generate_options)?;
-        let mut output = Vec::with_capacity(indices_outputs.len());
-        for generated_sequence in indices_outputs {
-            output.push(GeneratedTextOutput {
-                text: self
-                    ._get_tokenizer()
-                    .decode(&generated_sequence.indices, true, true),
-                score: generated_sequence.score,
-            });
-        }
-        Ok(output)
+        let (input_ids, attention_mask) =
+            match encode_prompt_ids(self, prompt_texts, generate_options)? {
+                Some(encoded_prompts) => encoded_prompts,
+                None => return Ok(Vec::new()),
+            };
+        let indices_outputs =
+            self.generate_from_ids(input_ids, Some(attention_mask), generate_options)?;
+        Ok(decode_generated_indices(
+            self,
+            indices_outputs,
+            generate_options,
+        ))
     }
 
     /// Generate token indices without decoding (useful for token-level operations before returning final text or as validation step during training).
@@ -1800,7 +2547,7 @@ pub trait LanguageGenerator: PrivateLanguageGenerator {
     /// * `generate_options` - `Option<GenerateOptions>` Optional set of generate options. If not (or partially) provided, will use the settings provided when creating the generator
     ///
     /// # Returns
-    /// * `Vec<IndicesOutput>` Vector of length *number_of_prompts* x *num_return_sequences* containing IndicesOutput with the generated indices and the generation score if `output_scores` is true.
+    /// * `Vec<IndicesOutput>` Vector of length *number_of_prompts* x *num_return_sequences* containing IndicesOutput with the generated indices and the generation score if `output_scores` is true. The sequences generated for the prompt at index `i` are stored contiguously at positions `i * num_return_sequences..(i + 1) * num_return_sequences`.
     ///
     /// # Example
     ///
@@ -1873,37 +2620,53 @@ pub trait LanguageGenerator: PrivateLanguageGenerator {
     where
         S: AsRef<str> + Send + Sync,
     {
-        let eos_token_ids = self.get_eos_ids();
-
-        let config = self.get_config();
-
-        let max_length = generate_options.map_or(config.max_length, |generate_options| {
-            generate_options.max_length
-        });
-        let encoding_max_len = if self.is_encoder_decoder() {
-            self.get_max_positions_embeddings()
-        } else {
-            max_length
-        };
-        let pad_token_id = match self.get_pad_id() {
-            Some(value) => Some(value),
-            None => eos_token_ids.as_ref().map(|eos_ids| eos_ids[0]),
-        };
+        let (input_ids, attention_mask) =
+            match encode_prompt_ids(self, prompt_texts, generate_options)? {
+                Some(encoded_prompts) => encoded_prompts,
+                None => return Ok(Vec::new()),
+            };
+        self.generate_from_ids(input_ids, Some(attention_mask), generate_options)
+    }
 
-        let input_ids = match prompt_texts {
-            Some(prompts) if !prompts.is_empty() => {
-                self.encode_prompt_text(prompts, encoding_max_len, pad_token_id)
-            }
-            None => match self.get_bos_id() {
-                Some(bos_id) => Tensor::ones([1, 1], (Int64, self.get_device())) * bos_id,
-                None => return Err(RustBertError::ValueError(
-                    "A model with a BOS token must be used to start generation with an empty input"
-                        .to_string(),
-                )),
-            },
-            _ => return Ok(Vec::new()),
-        };
-        self.generate_from_ids_and_past(input_ids, None, generate_options)
+    /// Generate token indices from pre-tokenized prompts, skipping the tokenization of the prompt texts.
+    /// The inputs are moved to the device of the model if needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_ids` - `Tensor` of `Int64` token ids, of shape (*number of prompts*, *sequence length*). Prompts of decoder-only models should be left-padded.
+    /// * `attention_mask` - `Option<Tensor>` Optional attention mask of the same shape as `input_ids`, masking the padding positions with 0.
+    /// If not provided, the positions of padding tokens are masked.
+    /// * `generate_options` - `Option<GenerateOptions>` Optional set of generate options. If not (or partially) provided, will use the settings provided when creating the generator
+    ///
+    /// # Returns
+    /// * `Vec<IndicesOutput>` Vector of length *number_of_prompts* x *num_return_sequences* containing IndicesOutput with the generated indices and the generation score if `output_scores` is true.
+    /// A `ValueError` is returned if the inputs do not have the expected shape or type.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::gpt2::GPT2Generator;
+    /// use rust_bert::pipelines::generation_utils::LanguageGenerator;
+    /// use tch::Tensor;
+    ///
+    /// let gpt2_generator = GPT2Generator::new(Default::default())?;
+    /// let tokenizer = gpt2_generator.get_tokenizer();
+    /// let prompt_ids = tokenizer.convert_tokens_to_ids(&tokenizer.tokenize("The dog"));
+    /// let input_ids = Tensor::from_slice(&prompt_ids).unsqueeze(0);
+    ///
+    /// let output = gpt2_generator.generate_from_ids(input_ids, None, None)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn generate_from_ids(
+        &self,
+        input_ids: Tensor,
+        attention_mask: Option<Tensor>,
+        generate_options: Option<GenerateOptions>,
+    ) -> Result<Vec<GeneratedIndicesOutput>, RustBertError> {
+        self.generate_with_cache(input_ids, attention_mask, Cache::None, generate_options)
+            .map(|(output, _)| output)
     }
 
     /// Generate token indices given a list of indices (useful when the input has been pre-tokenized).
@@ -1915,7 +2678,7 @@ pub trait LanguageGenerator: PrivateLanguageGenerator {
     /// * `generate_options` - `Option<GenerateOptions>` Optional set of generate options. If not (or partially) provided, will use the settings provided when creating the generator
     ///
     /// # Returns
-    /// * `Vec<IndicesOutput>` Vector of length *number_of_prompts* x *num_return_sequences* containing IndicesOutput with the generated indices and the generation score if `output_scores` is true.
+    /// * `Vec<IndicesOutput>` Vector of length *number_of_prompts* x *num_return_sequences* containing IndicesOutput with the generated indices and the generation score if `output_scores` is true. The sequences generated for the prompt at index `i` are stored contiguously at positions `i * num_return_sequences..(i + 1) * num_return_sequences`.
     ///
     /// # Example
     ///
@@ -1958,408 +2721,1722 @@ pub trait LanguageGenerator: PrivateLanguageGenerator {
     /// ```
     fn generate_from_ids_and_past(
         &self,
-        mut input_ids: Tensor,
-        mut attention_mask: Option<Tensor>,
+        input_ids: Tensor,
+        attention_mask: Option<Tensor>,
         generate_options: Option<GenerateOptions>,
     ) -> Result<Vec<GeneratedIndicesOutput>, RustBertError> {
-        let eos_token_ids = PrivateLanguageGenerator::get_eos_ids(self).cloned();
-
-        let config = PrivateLanguageGenerator::get_config(self);
-
-        // Set generation options. Priority goes to options provided to the `generate` method, then
-        // model configuration, then default values.
-        let do_sample = unpack_config!(do_sample, generate_options, config);
-        let num_return_sequences = unpack_config!(num_return_sequences, generate_options, config);
-        let num_beams = unpack_config!(num_beams, generate_options, config);
-        let min_length = unpack_config!(min_length, generate_options, config);
-        let early_stopping = unpack_config!(early_stopping, generate_options, config);
-        let temperature = unpack_config!(temperature, generate_options, config);
-        let top_k = unpack_config!(top_k, generate_options, config);
-        let top_p = unpack_config!(top_p, generate_options, config);
-        let repetition_penalty = unpack_config!(repetition_penalty, generate_options, config);
-        let length_penalty = unpack_config!(length_penalty, generate_options, config);
-        let no_repeat_ngram_size = unpack_config!(no_repeat_ngram_size, generate_options, config);
-        let num_beam_groups = generate_options.map_or(config.num_beam_groups, |opts| {
-            opts.num_beam_groups.or(config.num_beam_groups)
-        });
-        let diversity_penalty = generate_options.map_or(config.diversity_penalty, |opts| {
-            opts.diversity_penalty.or(config.diversity_penalty)
-        });
-        let decoder_start_token_id = generate_options.and_then(|opts| opts.decoder_start_token_id);
-        let forced_bos_token_id = generate_options.and_then(|opts| opts.forced_bos_token_id);
-        let bad_word_ids = generate_options.and_then(|opts| opts.bad_word_ids);
-        let prefix_allowed_tokens_fn =
-            generate_options.and_then(|opts| opts.prefix_allowed_tokens_fn);
-        let output_scores = generate_options.map_or(false, |opts| opts.output_scores);
-
-        let pad_token_id = match self.get_pad_id() {
-            Some(value) => Some(value),
-            None => eos_token_ids.as_ref().map(|eos_ids| eos_ids[0]),
-        };
-
-        let input_id_size = input_ids.size();
-        let mut input_ids_len = *input_id_size.last().unwrap();
-        if input_ids_len == 0 {
-            input_ids = Tensor::ones(
-                [*input_id_size.first().unwrap(), 1],
-                (Int64, input_ids.device()),
-            ) * self
-                .get_bos_id()
-                .expect("`bos_token_id` has to be defined when no `input_ids` are provided.");
-            attention_mask = Some(Tensor::ones(
-                [*input_id_size.first().unwrap(), 1],
-                (Int64, input_ids.device()),
-            ));
-            input_ids_len += 1;
-        }
-
-        let cur_len = if !self.is_encoder_decoder() {
-            *input_ids.size().last().unwrap()
-        } else {
-            1
-        };
-        let batch_size = *input_ids.size().first().unwrap();
-
-        let (effective_batch_size, effective_batch_mult) = match do_sample {
-            true => (batch_size * num_return_sequences, num_return_sequences),
-            false => (batch_size, 1),
-        };
-
-        let attention_mask = match attention_mask {
-            Some(value) => value,
-            None => match pad_token_id {
-                Some(pad_id) => input_ids.ne(pad_id).to_kind(Int64),
-                None => input_ids.ones_like().to_kind(Int64),
-            },
-        };
+        self.generate_from_ids(input_ids, attention_mask, generate_options)
+    }
 
-        let encoder_outputs = if self.is_encoder_decoder() {
-            let encoder_outputs = self
-                .encode(&input_ids, Some(&attention_mask))
-                .ok_or(RustBertError::UnsupportedError)?;
-            let expanded_batch_indices = Tensor::arange(batch_size, (Int64, input_ids.device()))
-                .view((-1, 1))
-                .repeat([1, num_beams * effective_batch_mult])
-                .view(-1);
-            Some(encoder_outputs.index_select(0, &expanded_batch_indices))
-        } else {
-            None
+    /// Generate token indices given a list of indices and a cache of past keys and values computed
+    /// for the beginning of these indices (e.g. a conversation history or a shared prompt prefix),
+    /// avoiding to process the cached positions again. Only supported by decoder-only models exposing
+    /// a cache validation (GPT2).
+    ///
+    /// # Arguments
+    ///
+    /// * `input_ids` - `Tensor` pre-tokenized and encoded input for generation, of shape (*number of prompts*, *sequence length*).
+    /// * `attention_mask` - `Option<Tensor>` Optional attention mask for the input, covering the cached positions.
+    /// * `past` - `Cache` computed for the first positions of `input_ids` (for all prompts), for example by `GPT2Generator::forward_prompt` or by a previous call to this method.
+    /// The cache must hold fewer positions than `input_ids`: the positions it does not hold are processed before the generation starts.
+    /// * `generate_options` - `Option<GenerateOptions>` Optional set of generate options. If not (or partially) provided, will use the settings provided when creating the generator
+    ///
+    /// # Returns
+    /// * `Vec<IndicesOutput>` Vector of length *number_of_prompts* x *num_return_sequences* containing IndicesOutput with the generated indices and the generation score if `output_scores` is true.
+    /// * `Cache` Cache for the generated indices, holding all positions but the last one, that can be passed with the generated indices
+    /// to continue the generation. The cache is only returned for greedy decoding and sampling (`Cache::None` is returned for beam search).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::gpt2::GPT2Generator;
+    /// use rust_bert::pipelines::generation_utils::{Cache, GenerateOptions, LanguageGenerator};
+    /// use tch::Tensor;
+    ///
+    /// let gpt2_generator = GPT2Generator::new(Default::default())?;
+    /// let generate_options = GenerateOptions {
+    ///     max_new_tokens: Some(16),
+    ///     ..Default::default()
+    /// };
+    ///
+    /// // The cache of the prompt prefix is computed once and re-used for several continuations
+    /// let (prefix_ids, prefix_past, _) = gpt2_generator.forward_prompt("Once upon a time,")?;
+    /// for continuation in [" a dragon", " a princess"] {
+    ///     let tokenizer = gpt2_generator.get_tokenizer();
+    ///     let continuation_ids =
+    ///         tokenizer.convert_tokens_to_ids(&tokenizer.tokenize(continuation));
+    ///     let input_ids = Tensor::cat(
+    ///         &[&prefix_ids, &Tensor::from_slice(&continuation_ids).unsqueeze(0)],
+    ///         1,
+    ///     );
+    ///     let past = Cache::GPT2Cache(Some(
+    ///         prefix_past.iter().map(|layer_past| layer_past.shallow_clone()).collect(),
+    ///     ));
+    ///     let (output, _past) = gpt2_generator.generate_with_cache(
+    ///         input_ids,
+    ///         None,
+    ///         past,
+    ///         Some(generate_options),
+    ///     )?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn generate_with_cache(
+        &self,
+        input_ids: Tensor,
+        attention_mask: Option<Tensor>,
+        past: Cache,
+        generate_options: Option<GenerateOptions>,
+    ) -> Result<(Vec<GeneratedIndicesOutput>, Cache), RustBertError> {
+        generate_indices_with_outputs(self, input_ids, attention_mask, past, generate_options)
+            .map(|(output, past, _)| (output, past))
+    }
+
+    /// Generate token indices given a list of indices, collecting the attention weights and/or hidden states of the
+    /// model for every generation step (useful for interpretability). The model outputs are only collected if
+    /// `output_attentions` or `output_hidden_states` are set in the generation options, for models configured to
+    /// return them (e.g. `output_attentions` in the GPT2 configuration). Contrastive search does not collect them.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_ids` - `Tensor` pre-tokenized and encoded input for generation, of shape (*number of prompts*, *sequence length*).
+    /// * `attention_mask` - `Option<Tensor>` Optional attention mask for the input.
+    /// * `generate_options` - `Option<GenerateOptions>` Optional set of generate options. If not (or partially) provided, will use the settings provided when creating the generator
+    ///
+    /// # Returns
+    /// * `Vec<IndicesOutput>` Vector of length *number_of_prompts* x *num_return_sequences* containing IndicesOutput with the generated indices and the generation score if `output_scores` is true.
+    /// * `GeneratedModelOutputs` Attention weights and hidden states of every generation step.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::gpt2::GPT2Generator;
+    /// use rust_bert::pipelines::generation_utils::{GenerateOptions, LanguageGenerator};
+    /// use tch::Tensor;
+    ///
+    /// let gpt2_generator = GPT2Generator::new(Default::default())?;
+    /// let input_ids = Tensor::from_slice(&[464i64, 3290]).unsqueeze(0);
+    /// let generate_options = GenerateOptions {
+    ///     max_new_tokens: Some(8),
+    ///     output_attentions: true,
+    ///     ..Default::default()
+    /// };
+    ///
+    /// let (output, model_outputs) =
+    ///     gpt2_generator.generate_with_model_outputs(input_ids, None, Some(generate_options))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn generate_with_model_outputs(
+        &self,
+        input_ids: Tensor,
+        attention_mask: Option<Tensor>,
+        generate_options: Option<GenerateOptions>,
+    ) -> Result<(Vec<GeneratedIndicesOutput>, GeneratedModelOutputs), RustBertError> {
+        generate_indices_with_outputs(
+            self,
+            input_ids,
+            attention_mask,
+            Cache::None,
+            generate_options,
+        )
+        .map(|(output, _, model_outputs)| (output, model_outputs))
+    }
+
+    /// Computes the log-likelihood of a continuation given a prompt, without generating any token.
+    /// See `score_texts` for details.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt` - `&str` Prompt text.
+    /// * `continuation` - `&str` Continuation text, starting right after the prompt (including its leading whitespace, if any).
+    ///
+    /// # Returns
+    /// * `ContinuationScore` Continuation token ids, their log-probabilities and the log-likelihood of the continuation.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::gpt2::GPT2Generator;
+    /// use rust_bert::pipelines::generation_utils::LanguageGenerator;
+    ///
+    /// let gpt2_generator = GPT2Generator::new(Default::default())?;
+    /// let output = gpt2_generator.score_text("The capital of France is", " Paris")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn score_text(
+        &self,
+        prompt: &str,
+        continuation: &str,
+    ) -> Result<ContinuationScore, RustBertError> {
+        Ok(self.score_texts(&[(prompt, continuation)])?.pop().unwrap())
+    }
+
+    /// Computes the log-likelihood of continuations given their prompts with a single forward pass of the model
+    /// (e.g. to rerank candidate texts). Only supported by decoder-only models.
+    ///
+    /// The concatenation of the prompt and of the continuation is tokenized at once, so that tokens merged across the
+    /// boundary are the same as when generating the text. The tokens are split by character offset: tokens ending after
+    /// the end of the prompt (including a token spanning the boundary) belong to the continuation. The log-probability
+    /// of every continuation token is read from the scores of the previous position. A continuation without prompt is
+    /// scored after the BOS token of the model.
+    ///
+    /// # Arguments
+    ///
+    /// * `texts` - `&[(S, S)]` Pairs of prompt and continuation texts.
+    ///
+    /// # Returns
+    /// * `Vec<ContinuationScore>` Continuation token ids, their log-probabilities and the log-likelihood of the continuation, for every pair.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::gpt2::GPT2Generator;
+    /// use rust_bert::pipelines::generation_utils::LanguageGenerator;
+    ///
+    /// let gpt2_generator = GPT2Generator::new(Default::default())?;
+    /// let candidates = [" Paris", " London", " Berlin"];
+    /// let texts = candidates
+    ///     .iter()
+    ///     .map(|candidate| ("The capital of France is", *candidate))
+    ///     .collect::<Vec<(&str, &str)>>();
+    /// let scores = gpt2_generator.score_texts(&texts)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn score_texts<S>(&self, texts: &[(S, S)]) -> Result<Vec<ContinuationScore>, RustBertError>
+    where
+        S: AsRef<str> + Send + Sync,
+    {
+        if self.is_encoder_decoder() {
+            return Err(RustBertError::InvalidConfigurationError(
+                "Continuation scoring is only supported by decoder-only models".to_string(),
+            ));
+        }
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+        let tokenizer = self._get_tokenizer();
+        let mut sequences = Vec::with_capacity(texts.len());
+        for (prompt, continuation) in texts {
+            let (prompt, continuation) = (prompt.as_ref(), continuation.as_ref());
+            let prompt_length = prompt.chars().count() as u32;
+            let tokens = tokenizer.tokenize_with_offsets(&format!("{prompt}{continuation}"));
+            let mut token_ids = tokenizer.convert_tokens_to_ids(&tokens.tokens);
+            let mut num_prompt_tokens = tokens
+                .offsets
+                .iter()
+                .take_while(|offset| offset.map_or(true, |offset| offset.end <= prompt_length))
+                .count();
+            if num_prompt_tokens == 0 {
+                let bos_token_id = self.get_bos_id().ok_or_else(|| {
+                    RustBertError::ValueError(
+                        "A model with a BOS token must be used to score a continuation without prompt"
+                            .to_string(),
+                    )
+                })?;
+                token_ids.insert(0, bos_token_id);
+                num_prompt_tokens = 1;
+            }
+            if let Some(max_positions) = self.get_max_positions_embeddings() {
+                if token_ids.len() as i64 > max_positions {
+                    return Err(RustBertError::ValueError(format!(
+                        "The prompt and continuation ({} tokens) exceed the model context size ({max_positions} tokens)",
+                        token_ids.len()
+                    )));
+                }
+            }
+            sequences.push((token_ids, num_prompt_tokens));
+        }
+
+        // Sequences are right-padded: the padding positions follow the scored tokens and are masked
+        let max_length = sequences
+            .iter()
+            .map(|(token_ids, _)| token_ids.len())
+            .max()
+            .unwrap();
+        let pad_token_id = self
+            .get_pad_id()
+            .or_else(|| self.get_eos_ids().map(|eos_ids| eos_ids[0]))
+            .unwrap_or(0);
+        let (input_ids, attention_mask): (Vec<Tensor>, Vec<Tensor>) = sequences
+            .iter()
+            .map(|(token_ids, _)| {
+                let mut padded_token_ids = token_ids.clone();
+                padded_token_ids.resize(max_length, pad_token_id);
+                let mut attention_mask = vec![1i64; token_ids.len()];
+                attention_mask.resize(max_length, 0);
+                (
+                    Tensor::from_slice(&padded_token_ids),
+                    Tensor::from_slice(&attention_mask),
+                )
+            })
+            .unzip();
+        let input_ids = Tensor::stack(&input_ids, 0).to(self.get_device());
+        let attention_mask = Tensor::stack(&attention_mask, 0).to(self.get_device());
+
+        let lm_logits = no_grad(|| {
+            self.forward_t(
+                Some(&input_ids),
+                Cache::None,
+                Some(&attention_mask),
+                None,
+                None,
+                None,
+                None,
+                None,
+                false,
+            )
+        })?
+        .lm_logits;
+        // The logits at position t are the scores of the token at position t + 1
+        let token_log_probabilities = lm_logits
+            .slice(1, 0, -1, 1)
+            .log_softmax(-1, Kind::Float)
+            .gather(-1, &input_ids.slice(1, 1, None, 1).unsqueeze(-1), false)
+            .squeeze_dim(-1)
+            .to(Device::Cpu);
+
+        let mut output = Vec::with_capacity(sequences.len());
+        for (sequence_index, (mut token_ids, num_prompt_tokens)) in
+            sequences.into_iter().enumerate()
+        {
+            let token_scores =
+                Vec::<f64>::try_from(token_log_probabilities.get(sequence_index as i64).slice(
+                    0,
+                    num_prompt_tokens as i64 - 1,
+                    token_ids.len() as i64 - 1,
+                    1,
+                ))?;
+            output.push(ContinuationScore {
+                token_ids: token_ids.split_off(num_prompt_tokens),
+                score: token_scores.iter().sum(),
+                token_scores,
+            });
+        }
+        Ok(output)
+    }
+
+    /// Computes the perplexity of a text (exponential of the average negative log-likelihood of its tokens, the
+    /// first token excepted). Only supported by decoder-only models.
+    ///
+    /// Texts longer than the model context size are processed with a sliding window: the first window covers the
+    /// beginning of the text, and each following window is shifted so that it ends `stride` tokens further. The tokens
+    /// already scored by the previous windows are only used as context, so that every token is scored exactly once with
+    /// at least `context size - stride` tokens of context (except for the tokens of the first window).
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - `&str` Text to score, of at least 2 tokens.
+    /// * `stride` - `usize` Number of tokens scored by every window after the first one. Must be lower than the model context
+    /// size for texts longer than the context size. Lower values give more context to the scored tokens at the cost of more forward passes.
+    ///
+    /// # Returns
+    /// * `f64` Perplexity of the text
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::gpt2::GPT2Generator;
+    /// use rust_bert::pipelines::generation_utils::LanguageGenerator;
+    ///
+    /// let gpt2_generator = GPT2Generator::new(Default::default())?;
+    /// let perplexity = gpt2_generator.perplexity("The quick brown fox jumps over the lazy dog.", 512)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn perplexity(&self, text: &str, stride: usize) -> Result<f64, RustBertError> {
+        if self.is_encoder_decoder() {
+            return Err(RustBertError::InvalidConfigurationError(
+                "Perplexity computation is only supported by decoder-only models".to_string(),
+            ));
+        }
+        let tokenizer = self._get_tokenizer();
+        let token_ids = tokenizer.convert_tokens_to_ids(&tokenizer.tokenize(text));
+        let num_tokens = token_ids.len();
+        if num_tokens < 2 {
+            return Err(RustBertError::ValueError(format!(
+                "The perplexity requires a text of at least 2 tokens, got {num_tokens}"
+            )));
+        }
+        let window_size = self
+            .get_max_positions_embeddings()
+            .map_or(num_tokens, |max_positions| max_positions as usize);
+        if (stride == 0) | ((num_tokens > window_size) & (stride >= window_size)) {
+            return Err(RustBertError::ValueError(format!(
+                "The stride should be strictly positive and lower than the model context size ({window_size}), got {stride}"
+            )));
+        }
+
+        let negative_log_likelihood =
+            sliding_window_negative_log_likelihood(self, &token_ids, window_size, stride)?;
+        Ok((negative_log_likelihood / (num_tokens - 1) as f64).exp())
+    }
+
+    /// Generate text from a single prompt as a stream of text fragments. Each call to `next` on the returned
+    /// iterator runs a single decoding step (greedy or sampling, re-using the cached past key/values)
+    /// and yields the text generated by this step. Beam search and multiple return sequences are not
+    /// supported and the generator settings should use `num_beams = 1` and `num_return_sequences = 1`.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt_text` - `Option<&str>` Optional text prompt. An empty prompt to the model may be passed if the model implement a `bos_id`.
+    /// * `generate_options` - `Option<GenerateOptions>` Optional set of generate options. If not (or partially) provided, will use the settings provided when creating the generator
+    ///
+    /// # Returns
+    /// * `GenerationStream` Iterator yielding the generated text fragments until an EOS token is generated or the maximum length is reached.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::gpt2::GPT2Generator;
+    /// use rust_bert::pipelines::generation_utils::{
+    ///     GenerateConfig, GenerateOptions, LanguageGenerator,
+    /// };
+    /// use std::io::Write;
+    ///
+    /// let generate_config = GenerateConfig {
+    ///     do_sample: false,
+    ///     num_beams: 1,
+    ///     ..Default::default()
+    /// };
+    /// let gpt2_generator = GPT2Generator::new(generate_config)?;
+    ///
+    /// let generate_options = GenerateOptions {
+    ///     max_length: Some(64),
+    ///     ..Default::default()
+    /// };
+    /// for text_fragment in
+    ///     gpt2_generator.generate_stream(Some("The dog"), Some(generate_options))?
+    /// {
+    ///     print!("{text_fragment}");
+    ///     std::io::stdout().flush()?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn generate_stream<'a, S>(
+        &'a self,
+        prompt_text: Option<S>,
+        generate_options: Option<GenerateOptions<'a>>,
+    ) -> Result<GenerationStream<'a, Self>, RustBertError>
+    where
+        S: AsRef<str> + Send + Sync,
+    {
+        let prompt_texts = prompt_text.map(|prompt_text| [prompt_text]);
+        let (input_ids, attention_mask) = encode_prompt_ids(
+            self,
+            prompt_texts
+                .as_ref()
+                .map(|prompt_texts| prompt_texts.as_slice()),
+            generate_options,
+        )?
+        .ok_or_else(|| {
+            RustBertError::ValueError("A prompt must be provided for streaming generation".into())
+        })?;
+        GenerationStream::new(self, input_ids, attention_mask, generate_options)
+    }
+
+    /// Generate tokens from a single prompt as an iterator. Each call to `next` runs a single decoding step,
+    /// re-using the past key/values cached by the iterator, and yields a `GeneratedToken` with the token id,
+    /// the text it adds to the generation and its log-probability. Custom stopping criteria can be implemented
+    /// with iterator adapters (e.g. `take_while`), and dropping the iterator ends the generation.
+    /// Beam search and multiple return sequences are not supported (see `generate_stream`).
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt_text` - `Option<&str>` Optional text prompt. An empty prompt to the model may be passed if the model implement a `bos_id`.
+    /// * `generate_options` - `Option<GenerateOptions>` Optional set of generate options. If not (or partially) provided, will use the settings provided when creating the generator
+    ///
+    /// # Returns
+    /// * `GeneratedTokens` Iterator yielding the generated tokens until an EOS token is generated, the maximum length is reached
+    /// or the generation is interrupted by the `cancellation_flag` or `max_time` options.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::gpt2::GPT2Generator;
+    /// use rust_bert::pipelines::generation_utils::{GenerateConfig, LanguageGenerator};
+    ///
+    /// let generate_config = GenerateConfig {
+    ///     do_sample: false,
+    ///     num_beams: 1,
+    ///     ..Default::default()
+    /// };
+    /// let gpt2_generator = GPT2Generator::new(generate_config)?;
+    ///
+    /// let first_tokens = gpt2_generator
+    ///     .generate_iter(Some("The dog"), None)?
+    ///     .take_while(|token| token.log_prob > -5.0)
+    ///     .collect::<Vec<_>>();
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn generate_iter<'a, S>(
+        &'a self,
+        prompt_text: Option<S>,
+        generate_options: Option<GenerateOptions<'a>>,
+    ) -> Result<GeneratedTokens<'a, Self>, RustBertError>
+    where
+        S: AsRef<str> + Send + Sync,
+    {
+        Ok(GeneratedTokens {
+            stream: self.generate_stream(prompt_text, generate_options)?,
+        })
+    }
+
+    /// Generate text from a single prompt, calling `callback` after each decoding step with the text
+    /// fragment added by this step and the index of the step. Generation stops early if the callback
+    /// returns `false`, in which case the text generated so far is returned.
+    /// Fragments are emitted on valid UTF-8 boundaries: byte-level tokens forming a multi-byte character
+    /// are buffered until the character is complete, and a step may therefore yield an empty fragment.
+    ///
+    /// As for `generate_stream`, beam search and multiple return sequences are not supported and
+    /// an error is returned if `num_beams` or `num_return_sequences` are greater than 1.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt_text` - `Option<&str>` Optional text prompt. An empty prompt to the model may be passed if the model implement a `bos_id`.
+    /// * `generate_options` - `Option<GenerateOptions>` Optional set of generate options. If not (or partially) provided, will use the settings provided when creating the generator
+    /// * `callback` - `FnMut(&str, i64) -> bool` called with each new text fragment and the step index. Returning `false` aborts the generation.
+    ///
+    /// # Returns
+    /// * `GeneratedTextOutput` Generated text (preceded by the prompt for decoder-only models), without score. The finish
+    ///   reason is `FinishReason::Cancelled` if the generation was stopped by the callback.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::gpt2::GPT2Generator;
+    /// use rust_bert::pipelines::generation_utils::{GenerateConfig, LanguageGenerator};
+    ///
+    /// let generate_config = GenerateConfig {
+    ///     do_sample: false,
+    ///     num_beams: 1,
+    ///     ..Default::default()
+    /// };
+    /// let gpt2_generator = GPT2Generator::new(generate_config)?;
+    ///
+    /// let output = gpt2_generator.generate_with_callback(
+    ///     Some("The dog"),
+    ///     None,
+    ///     |text_fragment, step| {
+    ///         print!("{text_fragment}");
+    ///         !text_fragment.contains('.') && step < 32
+    ///     },
+    /// )?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn generate_with_callback<'a, S, F>(
+        &'a self,
+        prompt_text: Option<S>,
+        generate_options: Option<GenerateOptions<'a>>,
+        mut callback: F,
+    ) -> Result<GeneratedTextOutput, RustBertError>
+    where
+        S: AsRef<str> + Send + Sync,
+        F: FnMut(&str, i64) -> bool,
+    {
+        let mut stream = self.generate_stream(prompt_text, generate_options)?;
+        let mut stopped_by_callback = false;
+        for (step, text_fragment) in stream.by_ref().enumerate() {
+            if !callback(&text_fragment, step as i64) {
+                stopped_by_callback = true;
+                break;
+            }
+        }
+        let finish_reason = match stream.finish_reason() {
+            None if stopped_by_callback => Some(FinishReason::Cancelled),
+            finish_reason => finish_reason,
+        };
+        Ok(GeneratedTextOutput {
+            text: stream.text(),
+            score: None,
+            finish_reason,
+            num_generated_tokens: Some(stream.num_generated_tokens()),
+        })
+    }
+
+    /// Returns a reference to the text generator's tokenizer
+    ///
+    /// # Returns
+    /// * `&TokenizerOption` Reference to the generator's tokenizer.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::path::PathBuf;
+    /// # use tch::Device;
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::gpt2::GPT2Generator;
+    /// use rust_bert::pipelines::generation_utils::{GenerateConfig, LanguageGenerator};
+    /// use tch::Tensor;
+    /// # let mut home: PathBuf = dirs::home_dir().unwrap();
+    /// # home.push("rustbert");
+    /// # home.push("gpt2");
+    /// # let config_path = &home.as_path().join("config.json");
+    /// # let vocab_path = &home.as_path().join("vocab.txt");
+    /// # let merges_path = &home.as_path().join("merges.txt");
+    /// # let weights_path = &home.as_path().join("model.ot");
+    /// let device = Device::cuda_if_available();
+    /// let generate_config = GenerateConfig {
+    ///     max_length: Some(30),
+    ///     do_sample: true,
+    ///     num_beams: 5,
+    ///     temperature: 1.1,
+    ///     num_return_sequences: 3,
+    ///     ..Default::default()
+    /// };
+    /// let gpt2_generator = GPT2Generator::new(generate_config)?;
+    /// let tokenizer = gpt2_generator.get_tokenizer();
+    /// tokenizer.tokenize("Hello, world!");
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn get_tokenizer(&self) -> &TokenizerOption {
+        self._get_tokenizer()
+    }
+
+    fn get_tokenizer_mut(&mut self) -> &mut TokenizerOption {
+        self._get_tokenizer_mut()
+    }
+
+    /// Converts words or phrases to the token ids sequences to pass as `bad_word_ids` generation option.
+    /// Each word is tokenized both as provided and preceded by a space, since tokenizers
+    /// (e.g. byte-level BPE) may represent a word differently at the start of a text and after a space.
+    ///
+    /// # Arguments
+    ///
+    /// * `bad_words` - Words or phrases that should not be generated
+    ///
+    /// # Returns
+    /// * `Vec<Vec<i64>>` Token ids sequences for the bad words, to be used as `bad_word_ids` in `GenerateOptions`
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::gpt2::GPT2Generator;
+    /// use rust_bert::pipelines::generation_utils::{GenerateOptions, LanguageGenerator};
+    ///
+    /// let gpt2_generator = GPT2Generator::new(Default::default())?;
+    /// let bad_word_ids = gpt2_generator.get_bad_word_ids(&["dog", "New York"]);
+    /// let generate_options = GenerateOptions {
+    ///     bad_word_ids: Some(&bad_word_ids),
+    ///     ..Default::default()
+    /// };
+    /// let output = gpt2_generator.generate(Some(&["The cat and the"]), Some(generate_options))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn get_bad_word_ids<S>(&self, bad_words: &[S]) -> Vec<Vec<i64>>
+    where
+        S: AsRef<str>,
+    {
+        let tokenizer = self._get_tokenizer();
+        bad_word_ids_from_strings(bad_words, |text| {
+            tokenizer.convert_tokens_to_ids(&tokenizer.tokenize(text))
+        })
+    }
+
+    /// Converts strings to the token ids and biases to pass as `logit_bias` generation option. The strings are
+    /// tokenized as provided: tokenizers (e.g. byte-level BPE) may represent a word differently at the start
+    /// of a text and after a space (`"dog"` and `" dog"`).
+    ///
+    /// # Arguments
+    ///
+    /// * `logit_biases` - Strings and bias to add to their score. Each string should be tokenized to a single token.
+    ///
+    /// # Returns
+    /// * `HashMap<i64, f64>` Token ids and biases, to be used as `logit_bias` in `GenerateOptions`.
+    ///   Returns a `RustBertError::ValueError` if a string is not tokenized to exactly one token.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::gpt2::GPT2Generator;
+    /// use rust_bert::pipelines::generation_utils::{GenerateOptions, LanguageGenerator};
+    ///
+    /// let gpt2_generator = GPT2Generator::new(Default::default())?;
+    /// let logit_bias = gpt2_generator.get_logit_bias(&[(" cat", 5.0), (" dog", f64::NEG_INFINITY)])?;
+    /// let generate_options = GenerateOptions {
+    ///     logit_bias: Some(&logit_bias),
+    ///     ..Default::default()
+    /// };
+    /// let output = gpt2_generator.generate(Some(&["My favourite animal is the"]), Some(generate_options))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn get_logit_bias(
+        &self,
+        logit_biases: &[(&str, f64)],
+    ) -> Result<HashMap<i64, f64>, RustBertError> {
+        let tokenizer = self._get_tokenizer();
+        logit_biases
+            .iter()
+            .map(|(text, bias)| {
+                let token_ids = tokenizer.convert_tokens_to_ids(&tokenizer.tokenize(text));
+                match token_ids.as_slice() {
+                    [token_id] => Ok((*token_id, *bias)),
+                    _ => Err(RustBertError::ValueError(format!(
+                        "Logit bias strings should be tokenized to a single token, \"{text}\" is tokenized to {} tokens",
+                        token_ids.len()
+                    ))),
+                }
+            })
+            .collect()
+    }
+
+    /// Converts a token of the vocabulary (e.g. a special token such as `<|endoftext|>`, or a token added to the
+    /// tokenizer) to its id. The token is not tokenized: this can be used to set the special token ids of the
+    /// generators from their name.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - Token of the tokenizer vocabulary
+    ///
+    /// # Returns
+    /// * `i64` Id of the token. Returns a `RustBertError::ValueError` if the token is not in the vocabulary.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::gpt2::GPT2Generator;
+    /// use rust_bert::pipelines::generation_utils::LanguageGenerator;
+    ///
+    /// let mut gpt2_generator = GPT2Generator::new(Default::default())?;
+    /// gpt2_generator.get_tokenizer_mut().add_tokens(&["<|stop|>"]);
+    /// let stop_token_id = gpt2_generator.get_token_id("<|stop|>")?;
+    /// let gpt2_generator = gpt2_generator.with_eos_token_ids(Some(vec![stop_token_id]));
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn get_token_id(&self, token: &str) -> Result<i64, RustBertError> {
+        let tokenizer = self._get_tokenizer();
+        let token_id = tokenizer.convert_tokens_to_ids(&[token])[0];
+        // Tokens missing from the vocabulary are converted to the unknown token id
+        if (token_id == tokenizer.get_unk_id())
+            && (tokenizer.decode(&[token_id], false, false) != token)
+        {
+            return Err(RustBertError::ValueError(format!(
+                "The token {token} is not in the vocabulary of the tokenizer"
+            )));
+        }
+        Ok(token_id)
+    }
+
+    fn half(&mut self) -> Result<(), RustBertError> {
+        self.get_var_store_mut()?.half();
+        Ok(())
+    }
+
+    fn float(&mut self) -> Result<(), RustBertError> {
+        self.get_var_store_mut()?.float();
+        Ok(())
+    }
+
+    fn set_device(&mut self, device: Device) -> Result<(), RustBertError> {
+        self.get_var_store_mut()?.set_device(device);
+        Ok(())
+    }
+}
+
+/// Sums the negative log-likelihood of the tokens of a text (the first token excepted) computed with a sliding window
+/// of `window_size` tokens, each window after the first one scoring the next `stride` tokens (see
+/// `LanguageGenerator::perplexity`). The window size and stride should be validated by the caller.
+pub(crate) fn sliding_window_negative_log_likelihood<T>(
+    generator: &T,
+    token_ids: &[i64],
+    window_size: usize,
+    stride: usize,
+) -> Result<f64, RustBertError>
+where
+    T: PrivateLanguageGenerator + ?Sized,
+{
+    let num_tokens = token_ids.len();
+    let input_ids = Tensor::from_slice(token_ids).to(generator.get_device());
+    let mut negative_log_likelihood = 0f64;
+    // The first token has no context and is not scored
+    let mut scored_length = 1;
+    let mut window_end = window_size.min(num_tokens);
+    loop {
+        let window_start = window_end.saturating_sub(window_size);
+        let window_ids = input_ids.slice(0, window_start as i64, window_end as i64, 1);
+        let lm_logits = no_grad(|| {
+            generator.forward_t(
+                Some(&window_ids.unsqueeze(0)),
+                Cache::None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                false,
+            )
+        })?
+        .lm_logits;
+        // The logits at position t are the scores of the token at position t + 1: the tokens scored by previous
+        // windows are masked
+        let token_log_probabilities = lm_logits
+            .get(0)
+            .slice(0, (scored_length - window_start - 1) as i64, -1, 1)
+            .log_softmax(-1, Kind::Float)
+            .gather(
+                -1,
+                &window_ids
+                    .slice(0, (scored_length - window_start) as i64, None, 1)
+                    .unsqueeze(-1),
+                false,
+            );
+        negative_log_likelihood -= token_log_probabilities.sum(Kind::Double).double_value(&[]);
+        scored_length = window_end;
+        if window_end == num_tokens {
+            break;
+        }
+        window_end = (window_end + stride).min(num_tokens);
+    }
+    Ok(negative_log_likelihood)
+}
+
+fn encode_prompt_ids<T, S>(
+    generator: &T,
+    prompt_texts: Option<&[S]>,
+    generate_options: Option<GenerateOptions>,
+) -> Result<Option<(Tensor, Tensor)>, RustBertError>
+where
+    T: PrivateLanguageGenerator + ?Sized,
+    S: AsRef<str> + Send + Sync,
+{
+    let eos_token_ids = generator.get_eos_ids();
+
+    let config = generator.get_config();
+
+    let encoding_max_len = if generator.is_encoder_decoder() {
+        generator.get_max_positions_embeddings()
+    } else if generate_options.map_or(false, |opts| opts.truncate_prompt) {
+        let max_length = match generate_options {
+            Some(GenerateOptions {
+                max_length: Some(max_length),
+                ..
+            }) => Some(max_length),
+            Some(GenerateOptions {
+                max_new_tokens: Some(_),
+                ..
+            }) => None,
+            _ => config.max_length,
         };
+        // Leave room for at least one generated token
+        [max_length, generator.get_max_positions_embeddings()]
+            .iter()
+            .flatten()
+            .min()
+            .map(|max_length| max_length - 1)
+    } else {
+        // Prompts that do not fit in the generation length are rejected when preparing the generation
+        None
+    };
+    let pad_token_id = match generator.get_pad_id() {
+        Some(value) => Some(value),
+        None => eos_token_ids.as_ref().map(|eos_ids| eos_ids[0]),
+    };
+
+    let encoded_prompts =
+        match prompt_texts {
+            Some(prompts) if !prompts.is_empty() => {
+                generator.encode_prompt_text(prompts, encoding_max_len, pad_token_id)
+            }
+            None => match generator.get_bos_id() {
+                Some(bos_id) => {
+                    let attention_mask = Tensor::ones([1, 1], (Int64, generator.get_device()));
+                    (&attention_mask * bos_id, attention_mask)
+                }
+                None => return Err(RustBertError::ValueError(
+                    "A model with a BOS token must be used to start generation with an empty input"
+                        .to_string(),
+                )),
+            },
+            _ => return Ok(None),
+        };
+    Ok(Some(encoded_prompts))
+}
+
+/// Removes the EOS tokens from token ids before decoding. EOS tokens that are not special tokens of the
+/// tokenizer (e.g. custom EOS token ids) would otherwise not be skipped.
+fn strip_eos_tokens(token_ids: &[i64], eos_token_ids: Option<&Vec<i64>>) -> Vec<i64> {
+    token_ids
+        .iter()
+        .filter(|token_id| eos_token_ids.map_or(true, |eos_ids| !eos_ids.contains(token_id)))
+        .copied()
+        .collect()
+}
+
+/// Decodes generated indices to text, skipping the special and EOS tokens. If `stop_sequences` are provided
+/// in the generation options, the texts are truncated before the first stop sequence found after the prompt.
+pub(crate) fn decode_generated_indices<T>(
+    generator: &T,
+    indices_outputs: Vec<GeneratedIndicesOutput>,
+    generate_options: Option<GenerateOptions>,
+) -> Vec<GeneratedTextOutput>
+where
+    T: PrivateLanguageGenerator + ?Sized,
+{
+    let stop_sequences = generate_options.and_then(|opts| opts.stop_sequences);
+    let eos_token_ids = generator.get_eos_ids();
+    let tokenizer = generator._get_tokenizer();
+    let mut output = Vec::with_capacity(indices_outputs.len());
+    for generated_sequence in indices_outputs {
+        let mut text = tokenizer.decode(
+            &strip_eos_tokens(&generated_sequence.indices, eos_token_ids),
+            true,
+            true,
+        );
+        if let Some(stop_sequences) = stop_sequences {
+            // Stop sequences contained in the prompt are ignored
+            let prompt_text_length = tokenizer
+                .decode(
+                    &strip_eos_tokens(
+                        &generated_sequence.indices[..generated_sequence.prompt_length],
+                        eos_token_ids,
+                    ),
+                    true,
+                    true,
+                )
+                .len();
+            if let Some(stop_position) =
+                find_stop_sequence(&text, prompt_text_length, stop_sequences)
+            {
+                text.truncate(stop_position);
+            }
+        }
+        output.push(GeneratedTextOutput {
+            text,
+            score: generated_sequence.score,
+            finish_reason: generated_sequence.finish_reason,
+            num_generated_tokens: generated_sequence.num_generated_tokens,
+        });
+    }
+    output
+}
+
+/// Generates token indices from validated inputs and an optional cache, returning the generated indices, the cache
+/// for the generated indices and the model outputs collected during the generation
+fn generate_indices_with_outputs<T>(
+    generator: &T,
+    input_ids: Tensor,
+    attention_mask: Option<Tensor>,
+    past: Cache,
+    generate_options: Option<GenerateOptions>,
+) -> Result<(Vec<GeneratedIndicesOutput>, Cache, GeneratedModelOutputs), RustBertError>
+where
+    T: PrivateLanguageGenerator + ?Sized,
+{
+    let (input_ids, attention_mask) = validate_input_ids(generator, input_ids, attention_mask)?;
+    let num_prompts = input_ids.size()[0];
+    let cache_length = generator.get_cache_length(&past, num_prompts)?;
+    if (cache_length > 0) & (cache_length >= input_ids.size()[1]) {
+        return Err(RustBertError::ValueError(format!(
+            "The cache holds {cache_length} positions and should be shorter than the input ids ({} positions)",
+            input_ids.size()[1]
+        )));
+    }
+    if (cache_length > 0) & generate_options.map_or(false, |opts| opts.token_healing.is_some()) {
+        return Err(RustBertError::InvalidConfigurationError(
+            "Token healing is not supported when generating from a cache".to_string(),
+        ));
+    }
+    let PreparedGenerationInputs {
+        input_ids,
+        attention_mask,
+        encoder_outputs,
+        cur_len,
+        batch_size,
+        gen_opt,
+        prefix_allowed_tokens_fn,
+        output_scores,
+    } = prepare_generation_inputs(generator, input_ids, attention_mask, generate_options)?;
+    let num_beams = gen_opt.num_beams;
+    let echo_prompt = generate_options
+        .and_then(|opts| opts.echo_prompt)
+        .unwrap_or(true);
+    // Prompts of decoder-only models are left-padded: the continuations start at the same position for all rows
+    let prompt_length = if generator.is_encoder_decoder() {
+        0
+    } else {
+        cur_len
+    };
+    let skipped_length = if echo_prompt { 0 } else { prompt_length };
+
+    let generated_output_with_scores = no_grad(|| {
+        let past = generator.prepare_initial_cache(
+            past,
+            &input_ids,
+            &attention_mask,
+            num_prompts,
+            cache_length,
+        )?;
+        Ok::<_, RustBertError>(if num_beams > 1 {
+            generator.generate_beam_search(
+                input_ids,
+                encoder_outputs,
+                cur_len,
+                batch_size,
+                attention_mask,
+                past,
+                gen_opt,
+                prefix_allowed_tokens_fn,
+                output_scores,
+            )
+        } else {
+            generator.generate_no_beam_search(
+                input_ids,
+                encoder_outputs,
+                cur_len,
+                batch_size,
+                attention_mask,
+                past,
+                gen_opt,
+                prefix_allowed_tokens_fn,
+                output_scores,
+            )?
+        })
+    })?;
+    let (decoded, scores, mut token_scores, finish_reasons, num_generated_tokens, past) = (
+        generated_output_with_scores.indices,
+        generated_output_with_scores.scores,
+        generated_output_with_scores.token_scores,
+        generated_output_with_scores.finish_reasons,
+        generated_output_with_scores.num_generated_tokens,
+        generated_output_with_scores.past,
+    );
+    let model_outputs = GeneratedModelOutputs {
+        attentions: generated_output_with_scores.attentions,
+        hidden_states: generated_output_with_scores.hidden_states,
+    };
+    let num_sequences = *decoded.size().first().unwrap();
+    let mut output = Vec::with_capacity(num_sequences as usize);
+    for sequence_index in 0..num_sequences {
+        let indices = decoded
+            .as_ref()
+            .get(sequence_index)
+            .slice(0, skipped_length, None, 1)
+            .iter::<i64>()
+            .unwrap()
+            .collect::<Vec<i64>>();
+        let score = scores
+            .as_ref()
+            .map(|scores_value| scores_value[sequence_index as usize]);
+
+        let token_scores = token_scores
+            .as_mut()
+            .map(|token_scores| std::mem::take(&mut token_scores[sequence_index as usize]));
+        let finish_reason = finish_reasons
+            .as_ref()
+            .map(|finish_reasons| finish_reasons[sequence_index as usize]);
+        let sequence_num_generated_tokens = num_generated_tokens
+            .as_ref()
+            .map(|num_generated_tokens| num_generated_tokens[sequence_index as usize]);
+
+        output.push(GeneratedIndicesOutput {
+            indices,
+            score,
+            token_scores,
+            finish_reason,
+            num_generated_tokens: sequence_num_generated_tokens,
+            prompt_length: (prompt_length - skipped_length) as usize,
+        });
+    }
+    Ok((output, past, model_outputs))
+}
+
+/// Checks that the token ids (and attention mask) provided for the generation are 2-dimensional `Int64`
+/// tensors of the same shape, and moves them to the device of the generator.
+fn validate_input_ids<T>(
+    generator: &T,
+    input_ids: Tensor,
+    attention_mask: Option<Tensor>,
+) -> Result<(Tensor, Option<Tensor>), RustBertError>
+where
+    T: PrivateLanguageGenerator + ?Sized,
+{
+    if input_ids.dim() != 2 {
+        return Err(RustBertError::ValueError(format!(
+            "Input ids should have a shape (batch size, sequence length), got {:?}",
+            input_ids.size()
+        )));
+    }
+    if input_ids.kind() != Int64 {
+        return Err(RustBertError::ValueError(format!(
+            "Input ids should be of type Int64, got {:?}",
+            input_ids.kind()
+        )));
+    }
+    if let Some(attention_mask) = &attention_mask {
+        if attention_mask.size() != input_ids.size() {
+            return Err(RustBertError::ValueError(format!(
+                "Attention mask shape {:?} does not match the input ids shape {:?}",
+                attention_mask.size(),
+                input_ids.size()
+            )));
+        }
+    }
+    let device = generator.get_device();
+    Ok((
+        input_ids.to_device(device),
+        attention_mask.map(|attention_mask| attention_mask.to_device(device).to_kind(Int64)),
+    ))
+}
 
-        let (input_ids, attention_mask) = if !self.is_encoder_decoder() {
-            if (num_return_sequences > 1) | (num_beams > 1) {
-                (
-                    input_ids
-                        .unsqueeze(1)
-                        .expand(
-                            [batch_size, effective_batch_mult * num_beams, cur_len],
-                            true,
-                        )
-                        .contiguous()
-                        .view((effective_batch_size * num_beams, cur_len)),
-                    attention_mask
-                        .unsqueeze(1)
-                        .expand(
-                            [batch_size, effective_batch_mult * num_beams, cur_len],
-                            true,
-                        )
-                        .contiguous()
-                        .view((effective_batch_size * num_beams, cur_len)),
-                )
-            } else {
-                (input_ids, attention_mask)
-            }
-        } else {
-            let decoder_start_token_id = decoder_start_token_id
-                .or(self.get_decoder_start_id())
-                .ok_or(RustBertError::ValueError(
-                    "decoder start id must be specified for encoder decoders".to_string(),
-                ))?;
-            let input_ids = Tensor::full(
-                [effective_batch_size * num_beams, 1],
-                decoder_start_token_id,
-                (Int64, input_ids.device()),
+fn prepare_generation_inputs<'a, T>(
+    generator: &T,
+    mut input_ids: Tensor,
+    mut attention_mask: Option<Tensor>,
+    generate_options: Option<GenerateOptions<'a>>,
+) -> Result<PreparedGenerationInputs<'a>, RustBertError>
+where
+    T: PrivateLanguageGenerator + ?Sized,
+{
+    let eos_token_ids = generator.get_eos_ids().cloned();
+
+    let config = generator.get_config();
+
+    // Set generation options. Priority goes to options provided to the `generate` method, then
+    // model configuration, then default values.
+    let do_sample = unpack_config!(do_sample, generate_options, config);
+    let num_return_sequences = unpack_config!(num_return_sequences, generate_options, config);
+    let num_beams = unpack_config!(num_beams, generate_options, config);
+    let min_length = unpack_config!(min_length, generate_options, config);
+    let early_stopping = unpack_config!(early_stopping, generate_options, config);
+    let temperature = unpack_config!(temperature, generate_options, config);
+    let top_k = unpack_config!(top_k, generate_options, config);
+    let top_p = unpack_config!(top_p, generate_options, config);
+    let repetition_penalty = unpack_config!(repetition_penalty, generate_options, config);
+    let repetition_penalty_scope = generate_options
+        .and_then(|opts| opts.repetition_penalty_scope)
+        .unwrap_or_default();
+    let frequency_penalty = generate_options.and_then(|opts| opts.frequency_penalty);
+    let presence_penalty = generate_options.and_then(|opts| opts.presence_penalty);
+    let include_prompt_in_penalty =
+        generate_options.map_or(false, |opts| opts.include_prompt_in_penalty);
+    let length_penalty = unpack_config!(length_penalty, generate_options, config);
+    let no_repeat_ngram_size = unpack_config!(no_repeat_ngram_size, generate_options, config);
+    let num_beam_groups = generate_options.map_or(config.num_beam_groups, |opts| {
+        opts.num_beam_groups.or(config.num_beam_groups)
+    });
+    let diversity_penalty = generate_options.map_or(config.diversity_penalty, |opts| {
+        opts.diversity_penalty.or(config.diversity_penalty)
+    });
+    let penalty_alpha = generate_options.and_then(|opts| opts.penalty_alpha);
+    let typical_p = generate_options.and_then(|opts| opts.typical_p);
+    let mirostat = generate_options.and_then(|opts| opts.mirostat);
+    let decoder_start_token_id = generate_options.and_then(|opts| opts.decoder_start_token_id);
+    // Forced tokens provided as options take precedence over the model defaults
+    let forced_bos_token_id = generate_options
+        .and_then(|opts| opts.forced_bos_token_id)
+        .or(generator.get_forced_bos_token_id());
+    let forced_eos_token_id = generate_options
+        .and_then(|opts| opts.forced_eos_token_id)
+        .or(generator.get_forced_eos_token_id());
+    let bad_word_ids = generate_options.and_then(|opts| opts.bad_word_ids);
+    let logit_bias = generate_options.and_then(|opts| opts.logit_bias);
+    let suppress_tokens = generate_options.and_then(|opts| opts.suppress_tokens);
+    let begin_suppress_tokens = generate_options.and_then(|opts| opts.begin_suppress_tokens);
+    let allowed_token_ids = generate_options.and_then(|opts| opts.allowed_token_ids);
+    let token_healing = generate_options.and_then(|opts| opts.token_healing);
+    let stop_sequences = generate_options.and_then(|opts| opts.stop_sequences);
+    let logits_processors = generate_options.and_then(|opts| opts.logits_processors);
+    let cancellation_flag = generate_options.and_then(|opts| opts.cancellation_flag);
+    let deadline = generate_options
+        .and_then(|opts| opts.max_time)
+        .and_then(|max_time| Instant::now().checked_add(max_time));
+    let prefix_allowed_tokens_fn = generate_options.and_then(|opts| opts.prefix_allowed_tokens_fn);
+    let output_scores = generate_options.map_or(false, |opts| opts.output_scores);
+    // Options provided to the `generate` method may override a validated model configuration
+    validate_generation_parameters(
+        temperature,
+        top_p,
+        repetition_penalty,
+        length_penalty,
+        num_return_sequences,
+        num_beams,
+        do_sample,
+        num_beam_groups,
+    )?;
+    if let Some(penalty_alpha) = penalty_alpha {
+        if !(0f64..=1f64).contains(&penalty_alpha) {
+            return Err(RustBertError::InvalidConfigurationError(format!(
+                "penalty_alpha must be between 0 and 1, got {penalty_alpha}"
+            )));
+        }
+        if (penalty_alpha > 0f64) & (do_sample | (num_beams > 1) | (top_k < 2)) {
+            return Err(RustBertError::InvalidConfigurationError(format!(
+                "contrastive search (penalty_alpha > 0) requires do_sample = false, num_beams = 1 and top_k > 1, \
+                got do_sample = {do_sample}, num_beams = {num_beams} and top_k = {top_k}"
+            )));
+        }
+    }
+    if let Some(typical_p) = typical_p {
+        if (typical_p <= 0f64) | (typical_p > 1f64) {
+            return Err(RustBertError::InvalidConfigurationError(format!(
+                "typical_p must be in (0, 1], got {typical_p}"
+            )));
+        }
+    }
+    if let Some(mirostat) = mirostat {
+        if (mirostat.tau() <= 0f64) | (mirostat.eta() <= 0f64) {
+            return Err(RustBertError::InvalidConfigurationError(format!(
+                "Mirostat tau and eta must be strictly positive, got {mirostat:?}"
+            )));
+        }
+        if !do_sample | (num_beams > 1) {
+            return Err(RustBertError::InvalidConfigurationError(format!(
+                "Mirostat sampling requires do_sample = true and num_beams = 1, \
+                got do_sample = {do_sample} and num_beams = {num_beams}"
+            )));
+        }
+    }
+    if let Some(allowed_token_ids) = allowed_token_ids {
+        if allowed_token_ids.is_empty() {
+            return Err(RustBertError::InvalidConfigurationError(
+                "allowed_token_ids may not be empty".to_string(),
+            ));
+        }
+        let vocab_size = generator.get_vocab_size();
+        if let Some(token_id) = allowed_token_ids
+            .iter()
+            .find(|token_id| !(0..vocab_size).contains(*token_id))
+        {
+            return Err(RustBertError::InvalidConfigurationError(format!(
+                "allowed_token_ids must be in [0, {vocab_size}), got {token_id}"
+            )));
+        }
+    }
+    for (option_name, token_id) in [
+        ("forced_bos_token_id", forced_bos_token_id),
+        ("forced_eos_token_id", forced_eos_token_id),
+    ] {
+        let vocab_size = generator.get_vocab_size();
+        if let Some(token_id) = token_id.filter(|token_id| !(0..vocab_size).contains(token_id)) {
+            return Err(RustBertError::InvalidConfigurationError(format!(
+                "{option_name} must be in [0, {vocab_size}), got {token_id}"
+            )));
+        }
+    }
+    if let Some(logit_bias) = logit_bias {
+        let vocab_size = generator.get_vocab_size();
+        if let Some(token_id) = logit_bias
+            .keys()
+            .find(|token_id| !(0..vocab_size).contains(*token_id))
+        {
+            return Err(RustBertError::InvalidConfigurationError(format!(
+                "logit_bias token ids must be in [0, {vocab_size}), got {token_id}"
+            )));
+        }
+    }
+    for (option_name, token_ids) in [
+        ("suppress_tokens", suppress_tokens),
+        ("begin_suppress_tokens", begin_suppress_tokens),
+    ] {
+        let vocab_size = generator.get_vocab_size();
+        if let Some(token_id) = token_ids
+            .into_iter()
+            .flatten()
+            .find(|token_id| !(0..vocab_size).contains(*token_id))
+        {
+            return Err(RustBertError::ValueError(format!(
+                "{option_name} must be in [0, {vocab_size}), got {token_id}"
+            )));
+        }
+    }
+
+    let pad_token_id = match generator.get_pad_id() {
+        Some(value) => Some(value),
+        None => eos_token_ids.as_ref().map(|eos_ids| eos_ids[0]),
+    };
+
+    let input_id_size = input_ids.size();
+    let mut input_ids_len = *input_id_size.last().unwrap();
+    if input_ids_len == 0 {
+        input_ids = Tensor::ones(
+            [*input_id_size.first().unwrap(), 1],
+            (Int64, input_ids.device()),
+        ) * generator.get_bos_id().ok_or_else(|| {
+            RustBertError::ValueError(
+                "`bos_token_id` has to be defined when no `input_ids` are provided.".to_string(),
+            )
+        })?;
+        attention_mask = Some(Tensor::ones(
+            [*input_id_size.first().unwrap(), 1],
+            (Int64, input_ids.device()),
+        ));
+        input_ids_len += 1;
+    }
+
+    let batch_size = *input_ids.size().first().unwrap();
+
+    let (effective_batch_size, effective_batch_mult) = match do_sample {
+        true => (batch_size * num_return_sequences, num_return_sequences),
+        false => (batch_size, 1),
+    };
+
+    let attention_mask = match attention_mask {
+        Some(value) => value,
+        None => match pad_token_id {
+            Some(pad_id) => input_ids.ne(pad_id).to_kind(Int64),
+            None => input_ids.ones_like().to_kind(Int64),
+        },
+    };
+
+    let (input_ids, attention_mask, healing_tokens) = match token_healing {
+        Some(token_healing_index) if !generator.is_encoder_decoder() => {
+            let (input_ids, attention_mask, healing_tokens) = token_healing_index.heal_prompts(
+                &input_ids,
+                &attention_mask,
+                pad_token_id.unwrap_or(0),
             );
-            let attention_mask = if (num_return_sequences > 1) | (num_beams > 1) {
-                attention_mask
+            (input_ids, attention_mask, Some(healing_tokens))
+        }
+        _ => (input_ids, attention_mask, None),
+    };
+
+    let cur_len = if !generator.is_encoder_decoder() {
+        *input_ids.size().last().unwrap()
+    } else {
+        1
+    };
+
+    let encoder_outputs = if generator.is_encoder_decoder() {
+        let encoder_outputs = no_grad(|| generator.encode(&input_ids, Some(&attention_mask)))
+            .ok_or(RustBertError::UnsupportedError)?;
+        let expanded_batch_indices = Tensor::arange(batch_size, (Int64, input_ids.device()))
+            .view((-1, 1))
+            .repeat([1, num_beams * effective_batch_mult])
+            .view(-1);
+        Some(encoder_outputs.index_select(0, &expanded_batch_indices))
+    } else {
+        None
+    };
+
+    let (input_ids, attention_mask) = if !generator.is_encoder_decoder() {
+        if (num_return_sequences > 1) | (num_beams > 1) {
+            (
+                input_ids
                     .unsqueeze(1)
                     .expand(
-                        [batch_size, effective_batch_mult * num_beams, input_ids_len],
+                        [batch_size, effective_batch_mult * num_beams, cur_len],
                         true,
                     )
                     .contiguous()
-                    .view((effective_batch_size * num_beams, input_ids_len))
-            } else {
+                    .view((effective_batch_size * num_beams, cur_len)),
                 attention_mask
-            };
+                    .unsqueeze(1)
+                    .expand(
+                        [batch_size, effective_batch_mult * num_beams, cur_len],
+                        true,
+                    )
+                    .contiguous()
+                    .view((effective_batch_size * num_beams, cur_len)),
+            )
+        } else {
             (input_ids, attention_mask)
-        };
-
-        let max_length = if let Some(generate_options) = generate_options {
-            match (generate_options.max_length, generate_options.max_new_tokens) {
-                (Some(max_length), _) => Some(max_length),
-                (None, Some(max_new_tokens)) => {
-                    Some(max_new_tokens + input_ids.size().last().unwrap())
-                }
-                (None, None) => config.max_length,
-            }
+        }
+    } else {
+        let decoder_start_token_id = decoder_start_token_id
+            .or(generator.get_decoder_start_id())
+            .ok_or(RustBertError::ValueError(
+                "decoder start id must be specified for encoder decoders".to_string(),
+            ))?;
+        let input_ids = Tensor::full(
+            [effective_batch_size * num_beams, 1],
+            decoder_start_token_id,
+            (Int64, input_ids.device()),
+        );
+        let attention_mask = if (num_return_sequences > 1) | (num_beams > 1) {
+            attention_mask
+                .unsqueeze(1)
+                .expand(
+                    [batch_size, effective_batch_mult * num_beams, input_ids_len],
+                    true,
+                )
+                .contiguous()
+                .view((effective_batch_size * num_beams, input_ids_len))
         } else {
-            config.max_length
+            attention_mask
         };
+        (input_ids, attention_mask)
+    };
+
+    let max_length = if let Some(generate_options) = generate_options {
+        match (generate_options.max_length, generate_options.max_new_tokens) {
+            (Some(max_length), _) => Some(max_length),
+            (None, Some(max_new_tokens)) => Some(max_new_tokens + input_ids.size().last().unwrap()),
+            (None, None) => config.max_length,
+        }
+    } else {
+        config.max_length
+    };
 
-        if let Some(max_length) = max_length {
-            if input_ids.size2()?.1 > max_length {
-                return Err(RustBertError::ValueError("The input ids exceeds the maximum length for generation.\
-                 Reduce the size of the provided input ids or increase the allowable maximum generation length.".to_string()));
+    // The length of decoder-only generations is bounded by the model context size
+    let max_length = match generator.get_max_positions_embeddings() {
+        Some(context_size) if !generator.is_encoder_decoder() => {
+            let prompt_length = input_ids.size2()?.1;
+            if prompt_length >= context_size {
+                return Err(RustBertError::ValueError(format!(
+                    "The prompt length ({prompt_length} tokens) exceeds the model context size ({context_size} tokens), \
+                    leaving no room for generation. Reduce the size of the prompt or set `truncate_prompt` in the generation options."
+                )));
             }
+            max_length.map(|max_length| max_length.min(context_size))
         }
+        _ => max_length,
+    };
 
-        if max_length.is_none() & eos_token_ids.is_none() {
-            return Err(RustBertError::InvalidConfigurationError("No maximum length given for a model without an EOS token. \
-            This would lead to an infinite generation loop. Please provide a `max_length` or `max_new_tokens`".to_string()));
-        }
-
-        let gen_opt = InternalGenerateOptions {
-            min_length,
-            max_length,
-            do_sample,
-            temperature,
-            top_k,
-            top_p,
-            repetition_penalty,
-            no_repeat_ngram_size,
-            pad_token_id,
-            eos_token_ids,
-            num_return_sequences,
-            early_stopping,
-            num_beams,
-            length_penalty,
-            num_beam_groups,
-            diversity_penalty,
-            forced_bos_token_id,
-            bad_word_ids,
-        };
+    if let Some(max_length) = max_length {
+        if input_ids.size2()?.1 > max_length {
+            return Err(RustBertError::ValueError("The input ids exceeds the maximum length for generation.\
+             Reduce the size of the provided input ids or increase the allowable maximum generation length.".to_string()));
+        }
+    }
 
-        let generated_output_with_scores = no_grad(|| {
-            if num_beams > 1 {
-                self.generate_beam_search(
-                    input_ids,
-                    encoder_outputs,
-                    cur_len,
-                    effective_batch_size,
-                    attention_mask,
-                    gen_opt,
-                    prefix_allowed_tokens_fn,
-                    output_scores,
-                )
-            } else {
-                self.generate_no_beam_search(
-                    input_ids,
-                    encoder_outputs,
-                    cur_len,
-                    effective_batch_size,
-                    attention_mask,
-                    gen_opt,
-                    prefix_allowed_tokens_fn,
-                    output_scores,
-                )
-            }
-        });
-        let (decoded, scores, mut token_scores) = (
-            generated_output_with_scores.indices,
-            generated_output_with_scores.scores,
-            generated_output_with_scores.token_scores,
-        );
-        let num_sequences = *decoded.size().first().unwrap();
-        let mut output = Vec::with_capacity(num_sequences as usize);
-        for sequence_index in 0..num_sequences {
-            let indices = decoded
-                .as_ref()
-                .get(sequence_index)
-                .iter::<i64>()
-                .unwrap()
-                .collect::<Vec<i64>>();
-            let score = scores
-                .as_ref()
-                .map(|scores_value| scores_value[sequence_index as usize]);
+    if max_length.is_none() & eos_token_ids.is_none() {
+        return Err(RustBertError::InvalidConfigurationError("No maximum length given for a model without an EOS token. \
+        This would lead to an infinite generation loop. Please provide a `max_length` or `max_new_tokens`".to_string()));
+    }
 
-            let token_scores = token_scores
-                .as_mut()
-                .map(|token_scores| std::mem::take(&mut token_scores[sequence_index as usize]));
+    for logits_processor in logits_processors.unwrap_or(&[]) {
+        logits_processor.begin_generation(&input_ids, num_beams)?;
+    }
 
-            output.push(GeneratedIndicesOutput {
-                indices,
-                score,
-                token_scores,
-            });
+    let gen_opt = InternalGenerateOptions {
+        min_length,
+        max_length,
+        do_sample,
+        temperature,
+        top_k,
+        top_p,
+        repetition_penalty,
+        repetition_penalty_scope,
+        frequency_penalty,
+        presence_penalty,
+        include_prompt_in_penalty,
+        no_repeat_ngram_size,
+        pad_token_id,
+        eos_token_ids,
+        num_return_sequences,
+        early_stopping,
+        num_beams,
+        length_penalty,
+        num_beam_groups,
+        diversity_penalty,
+        penalty_alpha,
+        typical_p,
+        mirostat,
+        forced_bos_token_id,
+        forced_eos_token_id,
+        bad_word_ids,
+        logit_bias,
+        suppress_tokens,
+        begin_suppress_tokens,
+        allowed_token_ids,
+        healing_tokens,
+        stop_sequences,
+        logits_processors,
+        cancellation_flag,
+        deadline,
+        output_attentions: generate_options.map_or(false, |opts| opts.output_attentions),
+        output_hidden_states: generate_options.map_or(false, |opts| opts.output_hidden_states),
+    };
+
+    Ok(PreparedGenerationInputs {
+        input_ids,
+        attention_mask,
+        encoder_outputs,
+        cur_len,
+        batch_size: effective_batch_size,
+        gen_opt,
+        prefix_allowed_tokens_fn,
+        output_scores,
+    })
+}
+
+/// # Streaming text generation
+/// Iterator returned by `LanguageGenerator::generate_stream`. Every call to `next` runs a single
+/// decoding step and yields the text added by the newly generated token. The stream stops after
+/// an EOS token or a stop sequence is generated, once the maximum length is reached, or before the next
+/// step if the generation was cancelled with the `cancellation_flag` or exceeded the `max_time` of the
+/// `GenerateOptions` (see `finish_reason`).
+pub struct GenerationStream<'a, T: LanguageGenerator + ?Sized> {
+    generator: &'a T,
+    input_ids: Tensor,
+    attention_mask: Tensor,
+    encoder_outputs: Option<Tensor>,
+    past: Cache,
+    prompt_length: i64,
+    current_length: i64,
+    gen_opt: InternalGenerateOptions<'a>,
+    logits_processors: LogitsProcessors<'a>,
+    mirostat_samplers: Option<Vec<MirostatSampler>>,
+    generated_text: String,
+    finish_reason: Option<FinishReason>,
+}
+
+impl<'a, T: LanguageGenerator + ?Sized> GenerationStream<'a, T> {
+    fn new(
+        generator: &'a T,
+        input_ids: Tensor,
+        attention_mask: Tensor,
+        generate_options: Option<GenerateOptions<'a>>,
+    ) -> Result<Self, RustBertError> {
+        let PreparedGenerationInputs {
+            input_ids,
+            attention_mask,
+            encoder_outputs,
+            cur_len,
+            batch_size,
+            gen_opt,
+            prefix_allowed_tokens_fn,
+            output_scores: _,
+        } = prepare_generation_inputs(
+            generator,
+            input_ids,
+            Some(attention_mask),
+            generate_options,
+        )?;
+        if (gen_opt.num_beams > 1) | (batch_size > 1) {
+            return Err(RustBertError::ValueError(
+                "Streaming generation only supports a single sequence generated without beam search. \
+                Please set `num_beams` and `num_return_sequences` to 1."
+                    .to_string(),
+            ));
         }
-        Ok(output)
+        if gen_opt
+            .penalty_alpha
+            .map_or(false, |penalty_alpha| penalty_alpha > 0.0)
+        {
+            return Err(RustBertError::ValueError(
+                "Streaming generation does not support contrastive search".to_string(),
+            ));
+        }
+        let logits_processors =
+            LogitsProcessors::new(&gen_opt, prefix_allowed_tokens_fn, 1, cur_len);
+        let mirostat_samplers = gen_opt
+            .mirostat
+            .map(|mirostat| vec![MirostatSampler::new(mirostat)]);
+
+        Ok(GenerationStream {
+            generator,
+            input_ids,
+            attention_mask,
+            encoder_outputs,
+            past: Cache::None,
+            prompt_length: cur_len,
+            current_length: cur_len,
+            gen_opt,
+            logits_processors,
+            mirostat_samplers,
+            generated_text: String::new(),
+            finish_reason: None,
+        })
     }
 
-    /// Returns a reference to the text generator's tokenizer
-    ///
-    /// # Returns
-    /// * `&TokenizerOption` Reference to the generator's tokenizer.
-    ///
-    /// # Example
-    ///
-    /// ```no_run
-    /// # use std::path::PathBuf;
-    /// # use tch::Device;
-    /// # fn main() -> anyhow::Result<()> {
-    /// use rust_bert::gpt2::GPT2Generator;
-    /// use rust_bert::pipelines::generation_utils::{GenerateConfig, LanguageGenerator};
-    /// use tch::Tensor;
-    /// # let mut home: PathBuf = dirs::home_dir().unwrap();
-    /// # home.push("rustbert");
-    /// # home.push("gpt2");
-    /// # let config_path = &home.as_path().join("config.json");
-    /// # let vocab_path = &home.as_path().join("vocab.txt");
-    /// # let merges_path = &home.as_path().join("merges.txt");
-    /// # let weights_path = &home.as_path().join("model.ot");
-    /// let device = Device::cuda_if_available();
-    /// let generate_config = GenerateConfig {
-    ///     max_length: Some(30),
-    ///     do_sample: true,
-    ///     num_beams: 5,
-    ///     temperature: 1.1,
-    ///     num_return_sequences: 3,
-    ///     ..Default::default()
-    /// };
-    /// let gpt2_generator = GPT2Generator::new(generate_config)?;
-    /// let tokenizer = gpt2_generator.get_tokenizer();
-    /// tokenizer.tokenize("Hello, world!");
-    /// # Ok(())
-    /// # }
-    /// ```
-    fn get_tokenizer(&self) -> &TokenizerOption {
-        self._get_tokenizer()
+    fn step(&mut self) -> (i64, f64) {
+        let past = std::mem::replace(&mut self.past, Cache::None);
+        let prepared_input = self.generator.prepare_inputs_for_generation(
+            self.input_ids.copy(),
+            self.encoder_outputs.as_ref(),
+            past,
+            self.attention_mask.copy(),
+        );
+        let model_output = self
+            .generator
+            .forward_t(
+                prepared_input.prepared_input.as_ref(),
+                prepared_input.prepared_past,
+                prepared_input.prepared_attention_mask.as_ref(),
+                None,
+                prepared_input.prepared_position_ids.as_ref(),
+                None,
+                prepared_input.prepared_encoder_output,
+                prepared_input.prepared_decoder_input.as_ref(),
+                false,
+            )
+            .unwrap();
+        self.past = model_output.cache;
+
+        let mut next_token_logits = model_output.lm_logits.select(1, -1);
+        let next_token = self.generator.get_next_token_no_beam_search(
+            &mut next_token_logits,
+            &self.input_ids,
+            self.current_length,
+            &self.gen_opt,
+            &self.logits_processors,
+            self.mirostat_samplers.as_deref_mut(),
+        );
+        let log_prob = next_token_logits
+            .log_softmax(-1, Kind::Float)
+            .gather(1, &next_token.reshape([-1, 1]), false)
+            .double_value(&[0, 0]);
+
+        self.input_ids = Tensor::cat(&[&self.input_ids, &next_token.unsqueeze(-1)], -1);
+        if !self.generator.is_encoder_decoder() {
+            self.attention_mask = Tensor::cat(
+                &[
+                    &self.attention_mask,
+                    &self.attention_mask.ones_like().narrow(-1, 0, 1),
+                ],
+                -1,
+            );
+        }
+        self.current_length += 1;
+        (next_token.int64_value(&[0]), log_prob)
     }
 
-    fn get_tokenizer_mut(&mut self) -> &mut TokenizerOption {
-        self._get_tokenizer_mut()
+    /// Returns the text emitted so far by the stream, preceded by the prompt for decoder-only models.
+    pub fn text(&self) -> String {
+        let prompt_ids = self
+            .input_ids
+            .get(0)
+            .slice(0, 0, self.prompt_length, 1)
+            .iter::<i64>()
+            .unwrap()
+            .collect::<Vec<i64>>();
+        let mut text = if self.generator.is_encoder_decoder() {
+            String::new()
+        } else {
+            self.generator
+                .get_tokenizer()
+                .decode(&prompt_ids, true, false)
+        };
+        text.push_str(&self.generated_text);
+        text
     }
 
-    fn half(&mut self) -> Result<(), RustBertError> {
-        self.get_var_store_mut()?.half();
-        Ok(())
+    /// Returns the reason why the stream stopped, or `None` if it may still yield tokens.
+    pub fn finish_reason(&self) -> Option<FinishReason> {
+        self.finish_reason
     }
 
-    fn float(&mut self) -> Result<(), RustBertError> {
-        self.get_var_store_mut()?.float();
-        Ok(())
+    /// Returns the number of tokens generated so far, including the end of sequence token.
+    pub fn num_generated_tokens(&self) -> usize {
+        (self.current_length - self.prompt_length) as usize
     }
 
-    fn set_device(&mut self, device: Device) -> Result<(), RustBertError> {
-        self.get_var_store_mut()?.set_device(device);
-        Ok(())
+    fn next_token(&mut self) -> Option<GeneratedToken> {
+        if self.finish_reason.is_some() {
+            return None;
+        }
+        // The cancellation flag and time limit are checked before every step
+        if let Some(interruption) = self.gen_opt.interruption() {
+            self.finish_reason = Some(interruption);
+            return None;
+        }
+        let (next_token, log_prob) = no_grad(|| self.step());
+
+        if let Some(eos_token_ids) = &self.gen_opt.eos_token_ids {
+            if eos_token_ids.contains(&next_token) {
+                self.finish_reason = Some(FinishReason::Eos);
+            }
+        }
+        let max_length_reached = self
+            .gen_opt
+            .max_length
+            .map_or(false, |max_length| self.current_length >= max_length);
+
+        // The full continuation is decoded at every step so that tokens merging with their
+        // predecessors (e.g. sub-word units or multi-byte characters) are rendered correctly.
+        let generated_ids = self
+            .input_ids
+            .get(0)
+            .slice(0, self.prompt_length, None, 1)
+            .iter::<i64>()
+            .unwrap()
+            .collect::<Vec<i64>>();
+        let mut text = self.generator.get_tokenizer().decode(
+            &strip_eos_tokens(&generated_ids, self.gen_opt.eos_token_ids.as_ref()),
+            true,
+            false,
+        );
+        // Text that may be the beginning of a stop sequence is held back until it can be resolved
+        let mut emitted_length = text.len();
+        if let Some(stop_sequences) = self.gen_opt.stop_sequences {
+            if let Some(stop_position) = find_stop_sequence(&text, 0, stop_sequences) {
+                text.truncate(stop_position);
+                self.finish_reason = self.finish_reason.or(Some(FinishReason::StopSequence));
+                emitted_length = stop_position;
+            } else if self.finish_reason.is_none() & !max_length_reached {
+                emitted_length -= partial_stop_sequence_length(&text, stop_sequences);
+            }
+        }
+        if max_length_reached {
+            self.finish_reason = self.finish_reason.or(Some(FinishReason::MaxLength));
+        }
+        // Byte-level tokens that only form a valid UTF-8 character once combined with the following
+        // tokens are decoded as replacement characters: these are buffered until the character is complete
+        if self.finish_reason.is_none() {
+            let emitted_text = &text[..emitted_length];
+            emitted_length = emitted_text
+                .trim_end_matches(char::REPLACEMENT_CHARACTER)
+                .len();
+        }
+        let text_fragment = text
+            .get(self.generated_text.len()..emitted_length)
+            .unwrap_or_default()
+            .to_string();
+        text.truncate(emitted_length.max(self.generated_text.len()));
+        self.generated_text = text;
+        Some(GeneratedToken {
+            id: next_token,
+            text: text_fragment,
+            log_prob,
+        })
     }
 }
 
-#[derive(Debug)]
-struct BeamHypotheses {
-    max_length: Option<i64>,
-    length_penalty: f64,
-    early_stopping: bool,
-    num_beams: i64,
-    beams: Vec<(f64, Tensor, Option<Tensor>)>,
-    worst_score: f64,
-}
+impl<'a, T: LanguageGenerator + ?Sized> Iterator for GenerationStream<'a, T> {
+    type Item = String;
 
-impl Clone for BeamHypotheses {
-    fn clone(&self) -> Self {
-        BeamHypotheses {
-            max_length: self.max_length,
-            length_penalty: self.length_penalty,
-            early_stopping: self.early_stopping,
-            num_beams: self.num_beams,
-            beams: self
-                .beams
-                .iter()
-                .map(|(score, tensor, scores_tensor)| {
-                    (
-                        *score,
-                        tensor.copy(),
-                        scores_tensor
-                            .as_ref()
-                            .map(|scores_tensor| scores_tensor.copy()),
-                    )
-                })
-                .collect::<Vec<(f64, Tensor, Option<Tensor>)>>(),
-            worst_score: self.worst_score,
-        }
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_token().map(|token| token.text)
     }
 }
 
-impl BeamHypotheses {
-    fn new(
-        num_beams: i64,
-        max_length: Option<i64>,
-        length_penalty: f64,
-        early_stopping: bool,
-    ) -> BeamHypotheses {
-        BeamHypotheses {
-            max_length: max_length.map(|max_length| max_length - 1),
-            length_penalty,
-            early_stopping,
-            num_beams,
-            beams: Vec::with_capacity(num_beams as usize + 1),
-            worst_score: 1e9f64,
-        }
-    }
-
-    fn len(&self) -> i64 {
-        self.beams.len() as i64
-    }
-
-    fn add(
-        &mut self,
-        hypothesis: Tensor,
-        sum_log_probabilities: f64,
-        token_scores: Option<Tensor>,
-    ) {
-        let score =
-            sum_log_probabilities / ((hypothesis.size()[0] as f64).powf(self.length_penalty));
-        if (self.len() < self.num_beams) | (score > self.worst_score) {
-            let token_scores = token_scores.map(|scores_tensor| {
-                scores_tensor.squeeze_dim(0).diff::<Tensor>(
-                    1,
-                    0,
-                    Some(Tensor::zeros(
-                        [1],
-                        (scores_tensor.kind(), scores_tensor.device()),
-                    )),
-                    None,
-                )
-            });
-            self.beams.push((score, hypothesis, token_scores));
-            if self.len() > self.num_beams {
-                let (worst_score_position, _) = self
-                    .beams
-                    .iter()
-                    .enumerate()
-                    .min_by_key(|(_, (score, _, _))| OrderedFloat(*score))
-                    .unwrap();
-                let _ = self.beams.remove(worst_score_position);
-            }
-            self.worst_score = self
-                .beams
-                .iter()
-                .min_by_key(|(score, _, _)| OrderedFloat(*score))
-                .unwrap()
-                .0;
-        }
+/// # Token-level streaming text generation
+/// Iterator returned by `LanguageGenerator::generate_iter`, yielding the token generated at every decoding step
+/// with its text and log-probability. The stream stops under the same conditions as `GenerationStream`.
+pub struct GeneratedTokens<'a, T: LanguageGenerator + ?Sized> {
+    stream: GenerationStream<'a, T>,
+}
+
+impl<'a, T: LanguageGenerator + ?Sized> GeneratedTokens<'a, T> {
+    /// Returns the text generated so far, preceded by the prompt for decoder-only models.
+    pub fn text(&self) -> String {
+        self.stream.text()
     }
 
-    fn is_done(&self, best_sum_log_probabilities: f64, current_length: i64) -> bool {
-        if self.len() < self.num_beams {
-            false
-        } else if self.early_stopping {
-            true
-        } else {
-            self.worst_score
-                >= best_sum_log_probabilities / (current_length as f64).powf(self.length_penalty)
-        }
+    /// Returns the reason why the stream stopped, or `None` if it may still yield tokens.
+    pub fn finish_reason(&self) -> Option<FinishReason> {
+        self.stream.finish_reason()
+    }
+}
+
+impl<'a, T: LanguageGenerator + ?Sized> Iterator for GeneratedTokens<'a, T> {
+    type Item = GeneratedToken;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.stream.next_token()
     }
 }
 
@@ -2369,4 +4446,287 @@ pub struct LMModelOutput {
     pub lm_logits: Tensor,
     /// cached state for improved efficiency during decoding
     pub cache: Cache,
+    /// Hidden states of the last layer for each position (provided by decoder-only models, required for contrastive search)
+    pub last_hidden_state: Option<Tensor>,
+    /// Hidden states of all layers (provided by decoder-only models configured with `output_hidden_states`)
+    pub all_hidden_states: Option<Vec<Tensor>>,
+    /// Attention weights of all layers (provided by decoder-only models configured with `output_attentions`)
+    pub all_attentions: Option<Vec<Tensor>>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::private_generation_utils::{
+        apply_repetition_penalty, apply_temperature, find_stop_sequence, get_banned_ngram_tokens,
+        max_cosine_similarity, partial_stop_sequence_length, typical_sampling_filter,
+    };
+    use tch::{Device, Kind, Tensor};
+
+    fn entropy(logits: &Tensor) -> f64 {
+        let probabilities = logits.softmax(-1, Kind::Double);
+        let entropy: Tensor = -(&probabilities * probabilities.log()).sum(Kind::Double);
+        entropy.double_value(&[])
+    }
+
+    #[test]
+    fn temperature_scaling() {
+        let logits = Tensor::from_slice(&[1.0f64, 2.0, 3.0, 0.5]);
+        let reference_entropy = entropy(&logits);
+
+        let mut sharpened_logits = logits.copy();
+        apply_temperature(&mut sharpened_logits, 0.5);
+        assert!(entropy(&sharpened_logits) < reference_entropy);
+
+        let mut flattened_logits = logits.copy();
+        apply_temperature(&mut flattened_logits, 2.0);
+        assert!(entropy(&flattened_logits) > reference_entropy);
+
+        let mut unchanged_logits = logits.copy();
+        apply_temperature(&mut unchanged_logits, 1.0);
+        assert!(unchanged_logits.allclose(&logits, 1e-12, 1e-12, false));
+    }
+
+    #[test]
+    fn low_temperature_sampling_concentration() {
+        tch::manual_seed(42);
+        let logits = Tensor::from_slice(&[2.0f32, 1.5, 1.0, 0.5]).view([1, 4]);
+        let num_samples = 10000;
+
+        let argmax_frequency = |temperature: f64| {
+            let mut tempered_logits = logits.copy();
+            apply_temperature(&mut tempered_logits, temperature);
+            let samples = tempered_logits
+                .softmax(-1, Kind::Float)
+                .multinomial(num_samples, true);
+            samples.eq(0).sum(Kind::Int64).int64_value(&[]) as f64 / num_samples as f64
+        };
+
+        // The most likely token has a probability of 0.46 at temperature 1 and of 0.99 at temperature 0.1
+        let untempered_frequency = argmax_frequency(1.0);
+        let tempered_frequency = argmax_frequency(0.1);
+        assert!((untempered_frequency - 0.46).abs() < 0.05);
+        assert!(tempered_frequency > 0.97);
+    }
+
+    #[test]
+    fn typical_sampling_filtering() {
+        // Probabilities (0.5, 0.25, 0.125, 0.125): the entropy (1.75 bits) is closest to the information content of
+        // the second token (2 bits), followed by the first token (1 bit) and the last two tokens (3 bits)
+        let logits = Tensor::from_slice(&[0.5f32, 0.25, 0.125, 0.125])
+            .log()
+            .view([1, 4]);
+
+        let mut filtered_logits = logits.copy();
+        typical_sampling_filter(&mut filtered_logits, 0.5, 1, f64::NEG_INFINITY);
+        let kept_tokens = filtered_logits.isfinite().view([-1]);
+        assert!(kept_tokens.equal(&Tensor::from_slice(&[true, true, false, false])));
+
+        // The most likely token is not the most typical one
+        let mut filtered_logits = logits.copy();
+        typical_sampling_filter(&mut filtered_logits, 0.2, 1, f64::NEG_INFINITY);
+        let kept_tokens = filtered_logits.isfinite().view([-1]);
+        assert!(kept_tokens.equal(&Tensor::from_slice(&[false, true, false, false])));
+
+        let mut filtered_logits = logits.copy();
+        typical_sampling_filter(&mut filtered_logits, 0.2, 2, f64::NEG_INFINITY);
+        let kept_tokens = filtered_logits.isfinite().view([-1]);
+        assert!(kept_tokens.equal(&Tensor::from_slice(&[true, true, false, false])));
+
+        // A `typical_p` of 1 does not filter the logits
+        let mut filtered_logits = logits.copy();
+        typical_sampling_filter(&mut filtered_logits, 1.0, 1, f64::NEG_INFINITY);
+        assert!(filtered_logits.equal(&logits));
+    }
+
+    #[test]
+    fn contrastive_search_degeneration_penalty() {
+        // Two sequences with a context of 3 tokens (hidden size 2)
+        let context_hidden_states = Tensor::from_slice(&[
+            1.0f32, 0.0, 0.0, 1.0, 1.0, 1.0, //
+            -1.0, 0.0, 0.0, -2.0, 0.0, 3.0,
+        ])
+        .view([2, 3, 2]);
+        let candidate_hidden_states = Tensor::from_slice(&[2.0f32, 0.0, 0.0, 1.0]).view([2, 2]);
+
+        let penalty = max_cosine_similarity(&context_hidden_states, &candidate_hidden_states, None);
+        assert!(penalty.allclose(&Tensor::from_slice(&[1.0f32, 1.0]), 1e-6, 1e-6, false));
+
+        // Masked (padding) context positions are ignored
+        let context_mask = Tensor::from_slice(&[0i64, 1, 1, 1, 1, 0]).view([2, 3]);
+        let penalty = max_cosine_similarity(
+            &context_hidden_states,
+            &candidate_hidden_states,
+            Some(&context_mask),
+        );
+        assert!(penalty.allclose(
+            &Tensor::from_slice(&[std::f32::consts::FRAC_1_SQRT_2, 0.0]),
+            1e-6,
+            1e-6,
+            false
+        ));
+    }
+
+    #[test]
+    fn contrastive_search_token_selection() {
+        use super::contrastive_search;
+
+        let next_token_logits = Tensor::from_slice(&[3.0f32, 2.0, 1.0, 0.0]).view([1, 4]);
+        // Hidden states of the candidates 0 and 1 (by decreasing probability) and of the context
+        let hidden_states = Tensor::from_slice(&[1.0f32, 0.0, 0.0, 1.0]).view([2, 2]);
+        let past_hidden_states = Tensor::from_slice(&[1.0f32, 0.1]).view([1, 1, 2]);
+
+        // Without degeneration penalty, the most likely token is selected
+        let next_tokens = contrastive_search(
+            &next_token_logits,
+            2,
+            0.0,
+            &hidden_states,
+            &past_hidden_states,
+            None,
+        );
+        assert_eq!(next_tokens.int64_value(&[0]), 0);
+
+        // The most likely token is similar to the context and penalized
+        let next_tokens = contrastive_search(
+            &next_token_logits,
+            2,
+            0.6,
+            &hidden_states,
+            &past_hidden_states,
+            None,
+        );
+        assert_eq!(next_tokens.int64_value(&[0]), 1);
+    }
+
+    #[test]
+    fn banned_ngram_tokens() {
+        let input_ids =
+            Tensor::from_slice(&[1i64, 2, 3, 1, 2, 3, 1, 2, 4, 5, 6, 7, 8, 9, 10, 5]).view([2, 8]);
+
+        // The trailing `1 2` was previously followed by `3` in the first hypothesis
+        let banned_tokens = get_banned_ngram_tokens(&input_ids, 3, 8);
+        assert_eq!(banned_tokens, vec![vec![3], vec![]]);
+
+        let banned_tokens = get_banned_ngram_tokens(&input_ids, 2, 8);
+        assert_eq!(banned_tokens, vec![vec![3], vec![6]]);
+
+        // Sequences shorter than the n-gram size cannot repeat any n-gram yet
+        let banned_tokens = get_banned_ngram_tokens(&input_ids.slice(1, 0, 2, 1), 4, 2);
+        assert_eq!(banned_tokens, vec![Vec::<i64>::new(); 2]);
+    }
+
+    #[test]
+    #[cfg(feature = "remote")]
+    fn generate_config_validation() {
+        use super::GenerateConfig;
+        use crate::RustBertError;
+
+        assert!(GenerateConfig::default().validate().is_ok());
+
+        let invalid_configs = [
+            GenerateConfig {
+                temperature: 0.0,
+                ..Default::default()
+            },
+            GenerateConfig {
+                top_p: 1.5,
+                ..Default::default()
+            },
+            GenerateConfig {
+                num_beams: 0,
+                ..Default::default()
+            },
+            GenerateConfig {
+                do_sample: false,
+                num_beams: 2,
+                num_return_sequences: 3,
+                ..Default::default()
+            },
+            GenerateConfig {
+                num_beams: 5,
+                num_beam_groups: Some(2),
+                ..Default::default()
+            },
+            GenerateConfig {
+                do_sample: true,
+                num_beams: 4,
+                num_beam_groups: Some(2),
+                ..Default::default()
+            },
+        ];
+        for config in invalid_configs {
+            assert!(matches!(
+                config.validate(),
+                Err(RustBertError::InvalidConfigurationError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn repetition_penalty_matches_reference() {
+        let logits = Tensor::randn([4, 100], (Kind::Float, Device::Cpu));
+        let prev_output_tokens = Tensor::randperm(100, (Kind::Int64, Device::Cpu))
+            .slice(0, 0, 40, 1)
+            .view([4, 10]);
+
+        let mut expected_logits = logits.copy();
+        for row in 0..4 {
+            for position in 0..10 {
+                let token = prev_output_tokens.int64_value(&[row, position]);
+                let value = expected_logits.double_value(&[row, token]);
+                let updated_value = if value < 0.0 {
+                    value * 1.5
+                } else {
+                    value / 1.5
+                };
+                let _ = expected_logits.get(row).get(token).fill_(updated_value);
+            }
+        }
+
+        let mut penalized_logits = logits.copy();
+        apply_repetition_penalty(&mut penalized_logits, &prev_output_tokens, 1.5);
+        assert!(penalized_logits.allclose(&expected_logits, 1e-6, 1e-6, false));
+    }
+
+    #[test]
+    fn repetition_penalty_repeated_tokens() {
+        let mut logits = Tensor::from_slice(&[
+            2.0f32, -2.0, 1.0, 4.0, 0.0, //
+            3.0, -3.0, 0.5, -0.5, 1.0,
+        ])
+        .view([2, 5]);
+        let prev_output_tokens =
+            Tensor::from_slice(&[0i64, 1, 0, 0, 1, 4, 2, 3, 3, 2, 3, 3]).view([2, 6]);
+
+        apply_repetition_penalty(&mut logits, &prev_output_tokens, 2.0);
+
+        // Tokens repeated in the prefix are penalized once, and the penalty is written back to every row
+        let expected_logits = Tensor::from_slice(&[
+            1.0f32, -4.0, 1.0, 4.0, 0.0, //
+            3.0, -3.0, 0.25, -1.0, 1.0,
+        ])
+        .view([2, 5]);
+        assert!(logits.allclose(&expected_logits, 1e-6, 1e-6, false));
+    }
+
+    #[test]
+    fn stop_sequences_matching() {
+        let stop_sequences = vec!["\n\n".to_string(), "###".to_string()];
+
+        assert_eq!(
+            find_stop_sequence("Hello\n\nWorld ###", 0, &stop_sequences),
+            Some(5)
+        );
+        assert_eq!(
+            find_stop_sequence("Hello\n\nWorld ###", 6, &stop_sequences),
+            Some(13)
+        );
+        assert_eq!(find_stop_sequence("Hello World", 0, &stop_sequences), None);
+        // Start positions inside a multi-byte character are moved back to the character boundary
+        assert_eq!(find_stop_sequence("é###", 1, &stop_sequences), Some(2));
+
+        assert_eq!(partial_stop_sequence_length("Hello\n", &stop_sequences), 1);
+        assert_eq!(partial_stop_sequence_length("Hello ##", &stop_sequences), 2);
+        assert_eq!(partial_stop_sequence_length("Hello", &stop_sequences), 0);
+    }
 }
@@ -0,0 +1,1187 @@
+// Copyright 2020 The HuggingFace Inc. team
+// Copyright 2024 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Logits processors
+//! Transformations of the next token scores applied at every generation step by the `LanguageGenerator`.
+//! The built-in decoding constraints (repetition, frequency and presence penalties, logit bias, bad words, suppressed tokens, allowed tokens, n-gram
+//! repetition ban, prefix allowed tokens, minimum length and forced first and last tokens) are implemented as logits processors,
+//! and custom processors implementing the `LogitsProcessor` trait can be passed to the generation via `GenerateOptions`.
+//!
+//! At every step, the processors are applied in the following order:
+//! 1. `RepetitionPenaltyLogitsProcessor`
+//! 2. `FrequencyPresencePenaltyLogitsProcessor`
+//! 3. `LogitBiasLogitsProcessor`
+//! 4. `BadWordsLogitsProcessor`
+//! 5. `SuppressTokensLogitsProcessor` and `SuppressTokensAtBeginLogitsProcessor`
+//! 6. `AllowedTokensLogitsProcessor` and `TokenHealingLogitsProcessor` (see the `token_healing` module)
+//! 7. `NoRepeatNGramLogitsProcessor`
+//! 8. `PrefixAllowedTokensLogitsProcessor`
+//! 9. `MinLengthLogitsProcessor`
+//! 10. `ForcedBOSTokenLogitsProcessor` and `ForcedEOSTokenLogitsProcessor`
+//! 11. Custom processors, in the order they are provided
+//!
+//! For greedy decoding and sampling, the processors update the raw logits. When sampling, the scores
+//! are then updated by the `TemperatureLogitsWarper` and by the sampling filters, in this order:
+//! `TopKLogitsWarper`, `TopPLogitsWarper` and `TypicalLogitsWarper` (enabled by the `temperature`,
+//! `top_k`, `top_p` and `typical_p` generation options). For beam search, the repetition penalty and
+//! the temperature are applied to the raw logits and the following processors (and sampling filters
+//! for beam sampling) update the log-probabilities of the next tokens. In both cases, the
+//! processors following the repetition penalty are applied after the model-specific score preparation,
+//! so that forced tokens provided as generation options take precedence over the model defaults.
+//!
+//! ```no_run
+//! # fn main() -> anyhow::Result<()> {
+//! use rust_bert::gpt2::GPT2Generator;
+//! use rust_bert::pipelines::generation_utils::{GenerateOptions, LanguageGenerator};
+//! use rust_bert::pipelines::logits_processors::LogitsProcessor;
+//! use tch::Tensor;
+//!
+//! /// Bans all token ids greater than a threshold
+//! struct MaxTokenIdProcessor {
+//!     max_token_id: i64,
+//! }
+//!
+//! impl LogitsProcessor for MaxTokenIdProcessor {
+//!     fn process(&self, _input_ids: &Tensor, logits: &mut Tensor) {
+//!         let _ = logits
+//!             .narrow(1, self.max_token_id + 1, logits.size()[1] - self.max_token_id - 1)
+//!             .fill_(f64::NEG_INFINITY);
+//!     }
+//! }
+//!
+//! let model = GPT2Generator::new(Default::default())?;
+//! let logits_processors: Vec<Box<dyn LogitsProcessor>> =
+//!     vec![Box::new(MaxTokenIdProcessor { max_token_id: 1000 })];
+//! let generate_options = GenerateOptions {
+//!     logits_processors: Some(&logits_processors),
+//!     ..Default::default()
+//! };
+//! let output = model.generate(Some(&["The dog"]), Some(generate_options))?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::common::kind::get_positive_infinity;
+use crate::pipelines::generation_utils::private_generation_utils::{
+    apply_repetition_penalty, apply_temperature, get_banned_ngram_tokens, typical_sampling_filter,
+    PrivateLanguageGenerator,
+};
+use crate::pipelines::generation_utils::{Cache, LanguageGenerator, PrefixAllowedFunction};
+use crate::RustBertError;
+use rust_tokenizers::tokenizer::Tokenizer;
+use rust_tokenizers::vocab::Vocab;
+use std::cell::RefCell;
+use std::cmp::{max, min};
+use std::collections::HashMap;
+use tch::{Device, Kind, Tensor};
+
+/// # Logits processor
+/// Modifies the scores of the next token at every generation step, for example to ban or favour
+/// some tokens based on the tokens already generated.
+pub trait LogitsProcessor {
+    /// Updates the next token scores in place.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_ids` - Tokens generated so far (including the prompt for decoder-only models) of shape (*number of hypotheses*, *sequence length*)
+    /// * `logits` - Next token scores of shape (*number of hypotheses*, *vocab size*)
+    fn process(&self, input_ids: &Tensor, logits: &mut Tensor);
+
+    /// Called once at the start of every generation, before the first step. Processors keeping a state
+    /// between steps should reset it here, and processors that do not support the generation settings
+    /// should return an error. Does nothing by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `input_ids` - Initial tokens of the generated sequences (the prompts for decoder-only models) of shape (*number of hypotheses*, *sequence length*)
+    /// * `num_beams` - Number of beams of the generation (1 for greedy decoding and sampling)
+    fn begin_generation(&self, _input_ids: &Tensor, _num_beams: i64) -> Result<(), RustBertError> {
+        Ok(())
+    }
+}
+
+/// # Tokens considered by the repetition penalty
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepetitionPenaltyScope {
+    /// All tokens of the sequence (prompt and generated tokens)
+    All,
+    /// Generated tokens only: the prompt vocabulary can be reused without penalty
+    GeneratedOnly,
+    /// Prompt tokens only
+    PromptOnly,
+}
+
+impl Default for RepetitionPenaltyScope {
+    fn default() -> Self {
+        RepetitionPenaltyScope::All
+    }
+}
+
+/// # Repetition penalty
+/// Penalizes the tokens already present in the sequence: negative scores are multiplied by the
+/// penalty and positive scores divided by it ([Keskar et al., 2019](https://arxiv.org/abs/1909.05858)).
+/// The `scope` controls the tokens considered, the prompt being made of the first `prompt_length` tokens of
+/// the sequences (prompts are left-padded, so that the generated tokens of all sequences start at this position).
+pub struct RepetitionPenaltyLogitsProcessor {
+    penalty: f64,
+    scope: RepetitionPenaltyScope,
+    prompt_length: i64,
+}
+
+impl RepetitionPenaltyLogitsProcessor {
+    /// Creates a new processor penalizing all the tokens of the sequence
+    pub fn new(penalty: f64) -> Self {
+        RepetitionPenaltyLogitsProcessor {
+            penalty,
+            scope: RepetitionPenaltyScope::All,
+            prompt_length: 0,
+        }
+    }
+
+    /// Creates a new processor penalizing the tokens in `scope`. `prompt_length` is the length of the
+    /// sequences before the first token is generated.
+    pub fn new_with_scope(penalty: f64, scope: RepetitionPenaltyScope, prompt_length: i64) -> Self {
+        RepetitionPenaltyLogitsProcessor {
+            penalty,
+            scope,
+            prompt_length,
+        }
+    }
+}
+
+impl LogitsProcessor for RepetitionPenaltyLogitsProcessor {
+    fn process(&self, input_ids: &Tensor, logits: &mut Tensor) {
+        let sequence_length = input_ids.size()[1];
+        let (start, end) = match self.scope {
+            RepetitionPenaltyScope::All => (0, sequence_length),
+            RepetitionPenaltyScope::GeneratedOnly => (self.prompt_length, sequence_length),
+            RepetitionPenaltyScope::PromptOnly => (0, min(self.prompt_length, sequence_length)),
+        };
+        if start < end {
+            apply_repetition_penalty(logits, &input_ids.slice(1, start, end, 1), self.penalty)
+        }
+    }
+}
+
+/// # Frequency and presence penalties
+/// Additive penalties (as opposed to the multiplicative repetition penalty): the score of every token is
+/// reduced by `frequency_penalty` times its number of occurrences in the sequence, and by `presence_penalty`
+/// if it occurs at least once. Only the tokens from position `first_penalized_position` are counted (e.g. the
+/// generated tokens, excluding the prompt).
+pub struct FrequencyPresencePenaltyLogitsProcessor {
+    frequency_penalty: f64,
+    presence_penalty: f64,
+    first_penalized_position: i64,
+}
+
+impl FrequencyPresencePenaltyLogitsProcessor {
+    pub fn new(
+        frequency_penalty: f64,
+        presence_penalty: f64,
+        first_penalized_position: i64,
+    ) -> Self {
+        FrequencyPresencePenaltyLogitsProcessor {
+            frequency_penalty,
+            presence_penalty,
+            first_penalized_position,
+        }
+    }
+}
+
+impl LogitsProcessor for FrequencyPresencePenaltyLogitsProcessor {
+    fn process(&self, input_ids: &Tensor, logits: &mut Tensor) {
+        if input_ids.size()[1] <= self.first_penalized_position {
+            return;
+        }
+        let penalized_ids = input_ids
+            .slice(1, self.first_penalized_position, None, 1)
+            .to_device(logits.device());
+        let token_counts = logits.zeros_like().scatter_add(
+            1,
+            &penalized_ids,
+            &penalized_ids.ones_like().to_kind(logits.kind()),
+        );
+        let penalties = &token_counts * self.frequency_penalty
+            + token_counts.gt(0).to_kind(logits.kind()) * self.presence_penalty;
+        *logits -= penalties;
+    }
+}
+
+/// # Logit bias
+/// Adds a constant bias to the scores of specific tokens at every step: positive values encourage
+/// the tokens, negative values discourage them and `f64::NEG_INFINITY` bans them.
+pub struct LogitBiasLogitsProcessor {
+    token_ids: Tensor,
+    biases: Tensor,
+}
+
+impl LogitBiasLogitsProcessor {
+    pub fn new(logit_bias: &HashMap<i64, f64>) -> Self {
+        let (token_ids, biases): (Vec<i64>, Vec<f64>) = logit_bias
+            .iter()
+            .map(|(token_id, bias)| (*token_id, *bias))
+            .unzip();
+        LogitBiasLogitsProcessor {
+            token_ids: Tensor::from_slice(&token_ids),
+            biases: Tensor::from_slice(&biases),
+        }
+    }
+}
+
+impl LogitsProcessor for LogitBiasLogitsProcessor {
+    fn process(&self, _input_ids: &Tensor, logits: &mut Tensor) {
+        if self.token_ids.numel() > 0 {
+            let num_sequences = logits.size()[0];
+            let token_ids = self
+                .token_ids
+                .to_device(logits.device())
+                .expand([num_sequences, -1], false);
+            let biases = self
+                .biases
+                .to_device(logits.device())
+                .to_kind(logits.kind())
+                .expand([num_sequences, -1], false);
+            let _ = logits.scatter_add_(1, &token_ids, &biases);
+        }
+    }
+}
+
+/// # Bad words ban
+/// Bans single tokens at every step, and the last token of multi-token bad words when the end of a
+/// sequence matches the rest of the bad word.
+pub struct BadWordsLogitsProcessor {
+    bad_words_ids: Vec<Vec<i64>>,
+}
+
+impl BadWordsLogitsProcessor {
+    pub fn new(bad_words_ids: Vec<Vec<i64>>) -> Self {
+        BadWordsLogitsProcessor {
+            bad_words_ids: bad_words_ids
+                .into_iter()
+                .filter(|bad_word| !bad_word.is_empty())
+                .collect(),
+        }
+    }
+
+    /// Creates a new bad words ban from words or phrases, tokenized with the provided tokenizer.
+    /// Each word is banned both as provided and preceded by a space, since tokenizers (e.g. byte-level BPE)
+    /// may represent a word differently at the start of a text and after a space.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::pipelines::logits_processors::BadWordsLogitsProcessor;
+    /// use rust_tokenizers::tokenizer::Gpt2Tokenizer;
+    ///
+    /// let tokenizer = Gpt2Tokenizer::from_file("path/to/vocab.json", "path/to/merges.txt", false)?;
+    /// let processor = BadWordsLogitsProcessor::from_strings(&["dog", "New York"], &tokenizer);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_strings<T, V>(words: &[&str], tokenizer: &T) -> Self
+    where
+        T: Tokenizer<V>,
+        V: Vocab,
+    {
+        BadWordsLogitsProcessor::new(bad_word_ids_from_strings(words, |text| {
+            tokenizer.convert_tokens_to_ids(&tokenizer.tokenize(text))
+        }))
+    }
+}
+
+/// Converts words or phrases to bad word ids with the `encode` function. Each word is encoded both as
+/// provided and preceded by a space, duplicated and empty sequences are skipped.
+pub(crate) fn bad_word_ids_from_strings<S, F>(bad_words: &[S], encode: F) -> Vec<Vec<i64>>
+where
+    S: AsRef<str>,
+    F: Fn(&str) -> Vec<i64>,
+{
+    let mut bad_word_ids: Vec<Vec<i64>> = Vec::with_capacity(2 * bad_words.len());
+    for bad_word in bad_words {
+        let bad_word = bad_word.as_ref();
+        for text in [bad_word.to_string(), format!(" {bad_word}")] {
+            let token_ids = encode(&text);
+            if !token_ids.is_empty() & !bad_word_ids.contains(&token_ids) {
+                bad_word_ids.push(token_ids);
+            }
+        }
+    }
+    bad_word_ids
+}
+
+impl LogitsProcessor for BadWordsLogitsProcessor {
+    fn process(&self, input_ids: &Tensor, logits: &mut Tensor) {
+        let single_token_bad_words = self
+            .bad_words_ids
+            .iter()
+            .filter(|bad_word| bad_word.len() == 1)
+            .map(|bad_word| bad_word[0])
+            .collect::<Vec<i64>>();
+        if !single_token_bad_words.is_empty() {
+            let _ = logits.index_fill_(
+                1,
+                &Tensor::from_slice(&single_token_bad_words).to_device(logits.device()),
+                f64::NEG_INFINITY,
+            );
+        }
+
+        let longest_bad_word = self
+            .bad_words_ids
+            .iter()
+            .map(|bad_word| bad_word.len())
+            .max()
+            .unwrap_or(0) as i64;
+        if longest_bad_word > 1 {
+            let last_token_ids = input_ids
+                .slice(1, -(longest_bad_word - 1), None, 1)
+                .to_device(Device::Cpu);
+            for sequence_index in 0..input_ids.size()[0] {
+                let prev_tokens = last_token_ids
+                    .get(sequence_index)
+                    .iter::<i64>()
+                    .unwrap()
+                    .collect::<Vec<i64>>();
+                let banned_tokens = self
+                    .bad_words_ids
+                    .iter()
+                    .filter(|bad_word| bad_word.len() > 1)
+                    .filter(|bad_word| prev_tokens.ends_with(&bad_word[..bad_word.len() - 1]))
+                    .map(|bad_word| *bad_word.last().unwrap())
+                    .collect::<Vec<i64>>();
+                if !banned_tokens.is_empty() {
+                    let _ = logits.get(sequence_index).index_fill_(
+                        0,
+                        &Tensor::from_slice(&banned_tokens).to_device(logits.device()),
+                        f64::NEG_INFINITY,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// # Suppressed tokens
+/// Bans the `suppress_tokens` at every step (e.g. special or control tokens).
+pub struct SuppressTokensLogitsProcessor {
+    suppress_tokens: Vec<i64>,
+}
+
+impl SuppressTokensLogitsProcessor {
+    pub fn new(suppress_tokens: Vec<i64>) -> Self {
+        SuppressTokensLogitsProcessor { suppress_tokens }
+    }
+}
+
+impl LogitsProcessor for SuppressTokensLogitsProcessor {
+    fn process(&self, _input_ids: &Tensor, logits: &mut Tensor) {
+        if !self.suppress_tokens.is_empty() {
+            let _ = logits.index_fill_(
+                1,
+                &Tensor::from_slice(&self.suppress_tokens).to_device(logits.device()),
+                f64::NEG_INFINITY,
+            );
+        }
+    }
+}
+
+/// # Tokens suppressed at the beginning of the generation
+/// Bans the `begin_suppress_tokens` for the first generated token only, i.e. when the sequence
+/// length is equal to the length of the prompt (or of the decoder start tokens for encoder-decoder models).
+pub struct SuppressTokensAtBeginLogitsProcessor {
+    begin_suppress_tokens: Vec<i64>,
+    first_step_length: i64,
+}
+
+impl SuppressTokensAtBeginLogitsProcessor {
+    /// Creates a new processor banning tokens for the first generated token. `first_step_length` is the
+    /// length of the sequences before the first token is generated.
+    pub fn new(begin_suppress_tokens: Vec<i64>, first_step_length: i64) -> Self {
+        SuppressTokensAtBeginLogitsProcessor {
+            begin_suppress_tokens,
+            first_step_length,
+        }
+    }
+}
+
+impl LogitsProcessor for SuppressTokensAtBeginLogitsProcessor {
+    fn process(&self, input_ids: &Tensor, logits: &mut Tensor) {
+        if (input_ids.size()[1] == self.first_step_length) & !self.begin_suppress_tokens.is_empty()
+        {
+            let _ = logits.index_fill_(
+                1,
+                &Tensor::from_slice(&self.begin_suppress_tokens).to_device(logits.device()),
+                f64::NEG_INFINITY,
+            );
+        }
+    }
+}
+
+/// # Allowed tokens
+/// Restricts the generation to the `allowed_token_ids` at every step: the scores of all other tokens
+/// are set to `-inf`. The mask of the banned tokens is built once, at the first step, from the vocabulary
+/// size and device of the scores, and reused for the following steps. Token ids outside of the vocabulary
+/// are ignored.
+pub struct AllowedTokensLogitsProcessor {
+    allowed_token_ids: Vec<i64>,
+    banned_tokens_mask: RefCell<Option<Tensor>>,
+}
+
+impl AllowedTokensLogitsProcessor {
+    pub fn new(allowed_token_ids: Vec<i64>) -> Self {
+        AllowedTokensLogitsProcessor {
+            allowed_token_ids,
+            banned_tokens_mask: RefCell::new(None),
+        }
+    }
+
+    /// Boolean mask of shape (1, *vocab size*), true for the tokens outside of the allowed set
+    fn build_mask(&self, vocab_size: i64, device: Device) -> Tensor {
+        let allowed_token_ids = self
+            .allowed_token_ids
+            .iter()
+            .copied()
+            .filter(|token_id| (0..vocab_size).contains(token_id))
+            .collect::<Vec<i64>>();
+        Tensor::ones([1, vocab_size], (Kind::Bool, device)).index_fill(
+            1,
+            &Tensor::from_slice(&allowed_token_ids).to_device(device),
+            0,
+        )
+    }
+}
+
+impl LogitsProcessor for AllowedTokensLogitsProcessor {
+    fn process(&self, _input_ids: &Tensor, logits: &mut Tensor) {
+        let mut banned_tokens_mask = self.banned_tokens_mask.borrow_mut();
+        let banned_tokens_mask = banned_tokens_mask.get_or_insert_with(|| {
+            self.build_mask(*logits.size().last().unwrap(), logits.device())
+        });
+        let _ = logits.masked_fill_(banned_tokens_mask, f64::NEG_INFINITY);
+    }
+}
+
+/// # N-gram repetition ban
+/// Bans the tokens that would complete an n-gram of size `ngram_size` already present in the sequence.
+pub struct NoRepeatNGramLogitsProcessor {
+    ngram_size: i64,
+}
+
+impl NoRepeatNGramLogitsProcessor {
+    pub fn new(ngram_size: i64) -> Self {
+        NoRepeatNGramLogitsProcessor { ngram_size }
+    }
+}
+
+impl LogitsProcessor for NoRepeatNGramLogitsProcessor {
+    fn process(&self, input_ids: &Tensor, logits: &mut Tensor) {
+        let banned_tokens =
+            get_banned_ngram_tokens(input_ids, self.ngram_size, input_ids.size()[1]);
+        for (sequence_index, sequence_banned_tokens) in banned_tokens.into_iter().enumerate() {
+            let _ = logits.get(sequence_index as i64).index_fill_(
+                0,
+                &Tensor::from_slice(&sequence_banned_tokens).to_device(logits.device()),
+                f64::NEG_INFINITY,
+            );
+        }
+    }
+}
+
+/// # Prefix allowed tokens constraint
+/// Restricts the next token to the tokens returned by a `PrefixAllowedFunction` called for every
+/// hypothesis with its batch index and the tokens generated so far. Hypotheses for which the
+/// function returns an empty list are left unconstrained.
+pub struct PrefixAllowedTokensLogitsProcessor<'a> {
+    prefix_allowed_tokens_fn: PrefixAllowedFunction<'a>,
+    num_beams: i64,
+}
+
+impl<'a> PrefixAllowedTokensLogitsProcessor<'a> {
+    /// Creates a new prefix allowed tokens constraint. `num_beams` is the number of hypotheses
+    /// generated for each batch item, used to compute the batch index passed to the function.
+    pub fn new(prefix_allowed_tokens_fn: PrefixAllowedFunction<'a>, num_beams: i64) -> Self {
+        PrefixAllowedTokensLogitsProcessor {
+            prefix_allowed_tokens_fn,
+            num_beams,
+        }
+    }
+}
+
+impl LogitsProcessor for PrefixAllowedTokensLogitsProcessor<'_> {
+    fn process(&self, input_ids: &Tensor, logits: &mut Tensor) {
+        let mask = logits.new_full(
+            logits.size().as_slice(),
+            get_positive_infinity(logits.kind()).unwrap(),
+            (logits.kind(), logits.device()),
+        );
+        for idx in 0..logits.size()[0] {
+            let batch_id = idx / self.num_beams;
+            let allowed_tokens: Vec<i64> =
+                (self.prefix_allowed_tokens_fn)(batch_id, &input_ids.get(idx));
+            if allowed_tokens.is_empty() {
+                // No constraint for this hypothesis
+                let _ = mask.get(idx).fill_(0);
+            } else {
+                let _ = mask.get(idx).index_fill_(
+                    0,
+                    &Tensor::from_slice(allowed_tokens.as_slice()).to(logits.device()),
+                    0,
+                );
+            }
+        }
+        let _ = logits.subtract_(&mask);
+    }
+}
+
+/// # Minimum length constraint
+/// Bans the EOS tokens as long as the sequence is shorter than `min_length`.
+pub struct MinLengthLogitsProcessor {
+    min_length: i64,
+    eos_token_ids: Vec<i64>,
+}
+
+impl MinLengthLogitsProcessor {
+    pub fn new(min_length: i64, eos_token_ids: Vec<i64>) -> Self {
+        MinLengthLogitsProcessor {
+            min_length,
+            eos_token_ids,
+        }
+    }
+}
+
+impl LogitsProcessor for MinLengthLogitsProcessor {
+    fn process(&self, input_ids: &Tensor, logits: &mut Tensor) {
+        if input_ids.size()[1] < self.min_length {
+            let _ = logits.index_fill_(
+                1,
+                &Tensor::from_slice(&self.eos_token_ids).to(logits.device()),
+                f64::NEG_INFINITY,
+            );
+        }
+    }
+}
+
+/// Bans all tokens but `token_id`, whose score is set to 0
+fn force_token(logits: &mut Tensor, token_id: i64) {
+    let _ = logits.fill_(f64::NEG_INFINITY);
+    let _ = logits.index_fill_(1, &Tensor::from_slice(&[token_id]).to(logits.device()), 0.0);
+}
+
+/// # Forced first token
+/// Forces `bos_token_id` as the first generated token, i.e. when the sequence length is equal to
+/// the length of the prompt (or of the decoder start tokens for encoder-decoder models).
+pub struct ForcedBOSTokenLogitsProcessor {
+    bos_token_id: i64,
+    first_step_length: i64,
+}
+
+impl ForcedBOSTokenLogitsProcessor {
+    /// Creates a new forced first token processor. `first_step_length` is the length of the
+    /// sequences before the first token is generated.
+    pub fn new(bos_token_id: i64, first_step_length: i64) -> Self {
+        ForcedBOSTokenLogitsProcessor {
+            bos_token_id,
+            first_step_length,
+        }
+    }
+}
+
+impl LogitsProcessor for ForcedBOSTokenLogitsProcessor {
+    fn process(&self, input_ids: &Tensor, logits: &mut Tensor) {
+        if input_ids.size()[1] == self.first_step_length {
+            force_token(logits, self.bos_token_id);
+        }
+    }
+}
+
+/// # Forced last token
+/// Forces `eos_token_id` as the last generated token, i.e. when the next token reaches `max_length`.
+pub struct ForcedEOSTokenLogitsProcessor {
+    eos_token_id: i64,
+    max_length: i64,
+}
+
+impl ForcedEOSTokenLogitsProcessor {
+    pub fn new(eos_token_id: i64, max_length: i64) -> Self {
+        ForcedEOSTokenLogitsProcessor {
+            eos_token_id,
+            max_length,
+        }
+    }
+}
+
+impl LogitsProcessor for ForcedEOSTokenLogitsProcessor {
+    fn process(&self, input_ids: &Tensor, logits: &mut Tensor) {
+        if input_ids.size()[1] == self.max_length - 1 {
+            force_token(logits, self.eos_token_id);
+        }
+    }
+}
+
+/// # Temperature
+/// Divides the scores by the temperature: temperatures lower than 1 sharpen the distribution, higher
+/// temperatures flatten it.
+pub struct TemperatureLogitsWarper {
+    temperature: f64,
+}
+
+impl TemperatureLogitsWarper {
+    pub fn new(temperature: f64) -> Self {
+        TemperatureLogitsWarper { temperature }
+    }
+}
+
+impl LogitsProcessor for TemperatureLogitsWarper {
+    fn process(&self, _input_ids: &Tensor, logits: &mut Tensor) {
+        apply_temperature(logits, self.temperature)
+    }
+}
+
+/// # Top-k filtering
+/// Keeps the `top_k` tokens with the highest scores (at least `min_tokens_to_keep`), the scores of
+/// the other tokens are set to `-inf`.
+pub struct TopKLogitsWarper {
+    top_k: i64,
+    min_tokens_to_keep: i64,
+}
+
+impl TopKLogitsWarper {
+    pub fn new(top_k: i64, min_tokens_to_keep: i64) -> Self {
+        TopKLogitsWarper {
+            top_k,
+            min_tokens_to_keep,
+        }
+    }
+}
+
+impl LogitsProcessor for TopKLogitsWarper {
+    fn process(&self, _input_ids: &Tensor, logits: &mut Tensor) {
+        let vocab_size = *logits.size().last().unwrap();
+        // Remove all tokens with a logit lower than the k-th largest logit of their row
+        let top_k = min(max(self.top_k, self.min_tokens_to_keep), vocab_size);
+        let (top_k_values, _) = logits.topk(top_k, -1, true, true);
+        let indices_to_remove = logits.lt_tensor(&top_k_values.select(-1, -1).unsqueeze(-1));
+        let _ = logits.masked_fill_(&indices_to_remove, f64::NEG_INFINITY);
+    }
+}
+
+/// # Nucleus (top-p) filtering
+/// Keeps the tokens with the highest scores until their cumulative probability reaches `top_p`
+/// ([Holtzman et al.](http://arxiv.org/abs/1904.09751)), the scores of the other tokens are set to `-inf`.
+/// At least `min_tokens_to_keep` tokens are kept.
+pub struct TopPLogitsWarper {
+    top_p: f64,
+    min_tokens_to_keep: i64,
+}
+
+impl TopPLogitsWarper {
+    pub fn new(top_p: f64, min_tokens_to_keep: i64) -> Self {
+        TopPLogitsWarper {
+            top_p,
+            min_tokens_to_keep,
+        }
+    }
+}
+
+impl LogitsProcessor for TopPLogitsWarper {
+    fn process(&self, _input_ids: &Tensor, logits: &mut Tensor) {
+        // Ported from https://gist.github.com/thomwolf/1a5a29f6962089e871b94cbd09daf317
+        let vocab_size = *logits.size().last().unwrap();
+        let (sorted_logits, sorted_indices) = logits.sort(-1, true);
+        let cumulative_probabilities = sorted_logits
+            .softmax(-1, sorted_logits.kind())
+            .cumsum(-1, sorted_logits.kind());
+        let mut sorted_indices_to_remove =
+            cumulative_probabilities.ge(self.top_p).to_kind(Kind::Int64);
+        if self.min_tokens_to_keep > 1 {
+            let _ = sorted_indices_to_remove.index_fill_(
+                1,
+                &Tensor::arange_start(
+                    0,
+                    self.min_tokens_to_keep + 1,
+                    (Kind::Int64, logits.device()),
+                ),
+                0,
+            );
+        }
+        // Shift the mask to the right to keep the first token above the threshold
+        let _ = sorted_indices_to_remove.index_copy_(
+            1,
+            &Tensor::arange_start(1, vocab_size, (Kind::Int64, logits.device())),
+            &sorted_indices_to_remove
+                .slice(1, 0, vocab_size - 1, 1)
+                .copy(),
+        );
+        let _ = sorted_indices_to_remove.index_fill_(
+            1,
+            &Tensor::from_slice(&[0])
+                .to_kind(Kind::Int64)
+                .to_device(sorted_indices_to_remove.device()),
+            0,
+        );
+        let indices_to_remove = sorted_indices_to_remove
+            .scatter(1, &sorted_indices, &sorted_indices_to_remove)
+            .to_kind(Kind::Bool);
+        let _ = logits.masked_fill_(&indices_to_remove, f64::NEG_INFINITY);
+    }
+}
+
+/// # Locally typical sampling
+/// Keeps the tokens whose information content (`-log(p)`) is the closest to the entropy of the next token
+/// distribution, until their cumulative probability reaches `typical_p` ([Meister et al., 2022](https://arxiv.org/abs/2202.00666)).
+/// The scores of the other tokens are set to `-inf`. Intended for sampling, after the temperature and top-k/top-p filtering.
+pub struct TypicalLogitsWarper {
+    typical_p: f64,
+    min_tokens_to_keep: i64,
+}
+
+impl TypicalLogitsWarper {
+    /// Creates a new typical sampling warper keeping at least `min_tokens_to_keep` tokens.
+    pub fn new(typical_p: f64, min_tokens_to_keep: i64) -> Self {
+        TypicalLogitsWarper {
+            typical_p,
+            min_tokens_to_keep,
+        }
+    }
+}
+
+impl LogitsProcessor for TypicalLogitsWarper {
+    fn process(&self, _input_ids: &Tensor, logits: &mut Tensor) {
+        typical_sampling_filter(
+            logits,
+            self.typical_p,
+            self.min_tokens_to_keep,
+            f64::NEG_INFINITY,
+        );
+    }
+}
+
+/// # Classifier-free guidance
+/// Guides the generation away from an unconditional (or negative) prompt ([Sanchez et al., 2023](https://arxiv.org/abs/2306.17806)).
+/// The processor runs a second forward pass of the model on the unconditional prompt followed by the
+/// generated tokens, and replaces the scores by `(1 + guidance_scale) * cond - guidance_scale * uncond`,
+/// where `cond` and `uncond` are the log-probabilities of the next token given the prompt and given the
+/// unconditional prompt. A guidance scale of 0 leaves the distribution unchanged.
+///
+/// The processor keeps the cache of the unconditional sequences between steps, and resets it at the start
+/// of every generation: the same processor can be used for several calls to the generation. It supports
+/// decoder-only models with greedy decoding or sampling (beam search is rejected, since the hypotheses are
+/// reordered at every step).
+///
+/// ```no_run
+/// # fn main() -> anyhow::Result<()> {
+/// use rust_bert::gpt2::GPT2Generator;
+/// use rust_bert::pipelines::generation_utils::{GenerateOptions, LanguageGenerator};
+/// use rust_bert::pipelines::logits_processors::{
+///     ClassifierFreeGuidanceLogitsProcessor, LogitsProcessor,
+/// };
+/// use tch::Tensor;
+///
+/// let model = GPT2Generator::new(Default::default())?;
+/// let tokenizer = model.get_tokenizer();
+/// let negative_prompt = tokenizer.convert_tokens_to_ids(&tokenizer.tokenize("The sad"));
+/// let logits_processors: Vec<Box<dyn LogitsProcessor>> =
+///     vec![Box::new(ClassifierFreeGuidanceLogitsProcessor::new(
+///         &model,
+///         1.5,
+///         Tensor::from_slice(&negative_prompt),
+///     ))];
+/// let generate_options = GenerateOptions {
+///     do_sample: Some(false),
+///     num_beams: Some(1),
+///     logits_processors: Some(&logits_processors),
+///     ..Default::default()
+/// };
+/// let output = model.generate(Some(&["The dog"]), Some(generate_options))?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ClassifierFreeGuidanceLogitsProcessor<'a, T: LanguageGenerator + ?Sized> {
+    model: &'a T,
+    guidance_scale: f64,
+    unconditional_ids: Tensor,
+    unconditional_state: RefCell<Option<UnconditionalState>>,
+}
+
+/// Unconditional sequences and cache of the classifier-free guidance
+struct UnconditionalState {
+    input_ids: Tensor,
+    past: Cache,
+    /// Length of the generated sequences at the previous step
+    processed_length: i64,
+}
+
+impl<'a, T: LanguageGenerator + ?Sized> ClassifierFreeGuidanceLogitsProcessor<'a, T> {
+    /// Creates a new classifier-free guidance processor. `unconditional_ids` are the token ids of the
+    /// unconditional prompt, of shape (*sequence length*) to share the same prompt across the batch, or
+    /// (*number of generated sequences*, *sequence length*).
+    pub fn new(model: &'a T, guidance_scale: f64, unconditional_ids: Tensor) -> Self {
+        let unconditional_ids = if unconditional_ids.dim() == 1 {
+            unconditional_ids.unsqueeze(0)
+        } else {
+            unconditional_ids
+        };
+        ClassifierFreeGuidanceLogitsProcessor {
+            model,
+            guidance_scale,
+            unconditional_ids,
+            unconditional_state: RefCell::new(None),
+        }
+    }
+
+    /// State before the first step: the unconditional prompt is shared by all sequences if a single one is provided
+    fn initial_state(&self, input_ids: &Tensor) -> UnconditionalState {
+        let (num_sequences, prompt_length) = (input_ids.size()[0], input_ids.size()[1]);
+        UnconditionalState {
+            input_ids: self
+                .unconditional_ids
+                .expand([num_sequences, -1], false)
+                .to_device(input_ids.device()),
+            past: Cache::None,
+            processed_length: prompt_length,
+        }
+    }
+}
+
+impl<T: LanguageGenerator + ?Sized> LogitsProcessor
+    for ClassifierFreeGuidanceLogitsProcessor<'_, T>
+{
+    fn process(&self, input_ids: &Tensor, logits: &mut Tensor) {
+        let mut state = self.unconditional_state.borrow_mut();
+        let state = state.get_or_insert_with(|| self.initial_state(input_ids));
+        state.input_ids = Tensor::cat(
+            &[
+                &state.input_ids,
+                &input_ids.slice(1, state.processed_length, None, 1),
+            ],
+            1,
+        );
+        state.processed_length = input_ids.size()[1];
+
+        let past = std::mem::replace(&mut state.past, Cache::None);
+        let prepared_input = self.model.prepare_inputs_for_generation(
+            state.input_ids.copy(),
+            None,
+            past,
+            state.input_ids.ones_like(),
+        );
+        let output = self
+            .model
+            .forward_t(
+                prepared_input.prepared_input.as_ref(),
+                prepared_input.prepared_past,
+                prepared_input.prepared_attention_mask.as_ref(),
+                None,
+                prepared_input.prepared_position_ids.as_ref(),
+                None,
+                None,
+                None,
+                false,
+            )
+            .unwrap();
+        state.past = output.cache;
+
+        let unconditional_log_probabilities =
+            output.lm_logits.select(1, -1).log_softmax(-1, Kind::Float);
+        let conditional_log_probabilities = logits.log_softmax(-1, Kind::Float);
+        let guided_scores = conditional_log_probabilities * (1.0 + self.guidance_scale)
+            - unconditional_log_probabilities * self.guidance_scale;
+        logits.copy_(&guided_scores.to_kind(logits.kind()));
+    }
+
+    fn begin_generation(&self, input_ids: &Tensor, num_beams: i64) -> Result<(), RustBertError> {
+        if self.model.is_encoder_decoder() | (num_beams > 1) {
+            return Err(RustBertError::InvalidConfigurationError(format!(
+                "Classifier-free guidance requires a decoder-only model and num_beams = 1, got num_beams = {num_beams}"
+            )));
+        }
+        let num_sequences = input_ids.size()[0];
+        let num_unconditional_sequences = self.unconditional_ids.size()[0];
+        if (num_unconditional_sequences != 1) & (num_unconditional_sequences != num_sequences) {
+            return Err(RustBertError::ValueError(format!(
+                "The unconditional prompts should be a single sequence or one sequence per generated sequence ({num_sequences}), \
+                got {num_unconditional_sequences} sequences"
+            )));
+        }
+        *self.unconditional_state.borrow_mut() = Some(self.initial_state(input_ids));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tch::Kind;
+
+    fn banned_tokens(logits: &Tensor) -> Vec<Vec<i64>> {
+        (0..logits.size()[0])
+            .map(|row| {
+                logits
+                    .get(row)
+                    .isinf()
+                    .nonzero()
+                    .squeeze_dim(1)
+                    .iter::<i64>()
+                    .unwrap()
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bad_words_processor() {
+        let processor =
+            BadWordsLogitsProcessor::new(vec![vec![1], vec![2, 3], vec![4, 5, 6], vec![]]);
+        let input_ids = Tensor::from_slice2(&[[0i64, 4, 5], [7, 0, 2]]);
+        let mut logits = Tensor::zeros([2, 8], (Kind::Float, Device::Cpu));
+
+        processor.process(&input_ids, &mut logits);
+
+        assert_eq!(banned_tokens(&logits), vec![vec![1, 6], vec![1, 3]]);
+    }
+
+    #[test]
+    fn min_length_processor() {
+        let processor = MinLengthLogitsProcessor::new(4, vec![7]);
+        let mut logits = Tensor::zeros([1, 8], (Kind::Float, Device::Cpu));
+
+        processor.process(&Tensor::from_slice2(&[[0i64, 1, 2]]), &mut logits);
+        assert_eq!(banned_tokens(&logits), vec![vec![7]]);
+
+        let mut logits = Tensor::zeros([1, 8], (Kind::Float, Device::Cpu));
+        processor.process(&Tensor::from_slice2(&[[0i64, 1, 2, 3]]), &mut logits);
+        assert_eq!(banned_tokens(&logits), vec![Vec::<i64>::new()]);
+    }
+
+    #[test]
+    fn forced_tokens_processors() {
+        let bos_processor = ForcedBOSTokenLogitsProcessor::new(3, 2);
+        let eos_processor = ForcedEOSTokenLogitsProcessor::new(5, 4);
+        let first_step_input_ids = Tensor::zeros([2, 2], (Kind::Int64, Device::Cpu));
+        let last_step_input_ids = Tensor::zeros([2, 3], (Kind::Int64, Device::Cpu));
+
+        let mut logits = Tensor::rand([2, 6], (Kind::Float, Device::Cpu));
+        bos_processor.process(&first_step_input_ids, &mut logits);
+        eos_processor.process(&first_step_input_ids, &mut logits);
+        assert_eq!(banned_tokens(&logits), vec![vec![0, 1, 2, 4, 5]; 2]);
+        assert_eq!(logits.argmax(-1, false), Tensor::from_slice(&[3i64, 3]));
+
+        let mut logits = Tensor::rand([2, 6], (Kind::Float, Device::Cpu));
+        bos_processor.process(&last_step_input_ids, &mut logits);
+        eos_processor.process(&last_step_input_ids, &mut logits);
+        assert_eq!(banned_tokens(&logits), vec![vec![0, 1, 2, 3, 4]; 2]);
+        assert_eq!(logits.argmax(-1, false), Tensor::from_slice(&[5i64, 5]));
+    }
+
+    #[test]
+    fn allowed_tokens_processor() {
+        let processor = AllowedTokensLogitsProcessor::new(vec![1, 4, 12]);
+        let input_ids = Tensor::zeros([2, 3], (Kind::Int64, Device::Cpu));
+
+        for _ in 0..2 {
+            let mut logits = Tensor::rand([2, 6], (Kind::Float, Device::Cpu));
+            processor.process(&input_ids, &mut logits);
+            assert_eq!(banned_tokens(&logits), vec![vec![0, 2, 3, 5]; 2]);
+        }
+    }
+
+    #[test]
+    fn no_repeat_ngram_processor() {
+        let processor = NoRepeatNGramLogitsProcessor::new(2);
+        let input_ids = Tensor::from_slice2(&[[1i64, 2, 1, 3, 1], [4, 5, 6, 7, 4]]);
+        let mut logits = Tensor::zeros([2, 8], (Kind::Float, Device::Cpu));
+
+        processor.process(&input_ids, &mut logits);
+
+        assert_eq!(banned_tokens(&logits), vec![vec![2, 3], vec![5]]);
+    }
+
+    #[test]
+    fn prefix_allowed_tokens_processor() {
+        let allowed_tokens = |batch_id: i64, _: &Tensor| match batch_id {
+            2 => vec![],
+            _ => vec![batch_id, 5],
+        };
+        let processor = PrefixAllowedTokensLogitsProcessor::new(&allowed_tokens, 2);
+        let input_ids = Tensor::zeros([6, 3], (Kind::Int64, Device::Cpu));
+        let mut logits = Tensor::zeros([6, 6], (Kind::Float, Device::Cpu));
+
+        processor.process(&input_ids, &mut logits);
+
+        assert_eq!(
+            banned_tokens(&logits),
+            vec![
+                vec![1, 2, 3, 4],
+                vec![1, 2, 3, 4],
+                vec![0, 2, 3, 4],
+                vec![0, 2, 3, 4],
+                vec![],
+                vec![]
+            ]
+        );
+    }
+
+    #[test]
+    fn top_k_top_p_warpers() {
+        let input_ids = Tensor::from_slice2(&[[0i64], [0]]);
+        let logits = Tensor::from_slice2(&[[0.4f32, 0.3, 0.2, 0.1], [0.1, 0.2, 0.3, 0.4]]).log();
+
+        let mut warped_logits = logits.copy();
+        TopKLogitsWarper::new(2, 1).process(&input_ids, &mut warped_logits);
+        assert_eq!(banned_tokens(&warped_logits), vec![vec![2, 3], vec![0, 1]]);
+
+        // The first token reaching the cumulative probability is kept
+        let mut warped_logits = logits.copy();
+        TopPLogitsWarper::new(0.6, 1).process(&input_ids, &mut warped_logits);
+        assert_eq!(banned_tokens(&warped_logits), vec![vec![2, 3], vec![0, 1]]);
+
+        let mut warped_logits = logits.copy();
+        TopPLogitsWarper::new(0.3, 1).process(&input_ids, &mut warped_logits);
+        assert_eq!(
+            banned_tokens(&warped_logits),
+            vec![vec![1, 2, 3], vec![0, 1, 2]]
+        );
+
+        // Top-k then top-p filtering
+        let mut warped_logits = logits.copy();
+        TopKLogitsWarper::new(3, 1).process(&input_ids, &mut warped_logits);
+        TopPLogitsWarper::new(0.7, 1).process(&input_ids, &mut warped_logits);
+        assert_eq!(banned_tokens(&warped_logits), vec![vec![2, 3], vec![0, 1]]);
+    }
+
+    #[test]
+    fn typical_warper_composition() {
+        let input_ids = Tensor::from_slice2(&[[0i64]]);
+        let logits = Tensor::from_slice(&[0.4f32, 0.3, 0.2, 0.1])
+            .log()
+            .view([1, 4]);
+        let processor = TypicalLogitsWarper::new(0.6, 1);
+
+        // Entropy of 1.28 nats: the tokens sorted by typicality are 1, 2, 0 and 3
+        let mut warped_logits = logits.copy();
+        processor.process(&input_ids, &mut warped_logits);
+        assert_eq!(banned_tokens(&warped_logits), vec![vec![3]]);
+
+        // A temperature of 0.5 sharpens the distribution to (0.53, 0.3, 0.13, 0.03), the tokens sorted
+        // by typicality become 1, 0, 2 and 3
+        let mut warped_logits = &logits / 0.5;
+        processor.process(&input_ids, &mut warped_logits);
+        assert_eq!(banned_tokens(&warped_logits), vec![vec![2, 3]]);
+
+        // After a top-2 filtering, the distribution (0.57, 0.43) has an entropy of 0.68 nats: the first
+        // token is the most typical and reaches the probability mass on its own
+        let mut warped_logits = logits.copy();
+        let _ = warped_logits.narrow(1, 2, 2).fill_(f64::NEG_INFINITY);
+        TypicalLogitsWarper::new(0.5, 1).process(&input_ids, &mut warped_logits);
+        assert_eq!(banned_tokens(&warped_logits), vec![vec![1, 2, 3]]);
+    }
+
+    #[test]
+    fn suppress_tokens_processors() {
+        let input_ids = Tensor::from_slice2(&[[0i64, 4], [7, 0]]);
+        let mut logits = Tensor::zeros([2, 8], (Kind::Float, Device::Cpu));
+
+        SuppressTokensLogitsProcessor::new(vec![1, 5]).process(&input_ids, &mut logits);
+        assert_eq!(banned_tokens(&logits), vec![vec![1, 5], vec![1, 5]]);
+
+        // Tokens suppressed at the beginning are only banned for the first generated token
+        let processor = SuppressTokensAtBeginLogitsProcessor::new(vec![2, 3], 2);
+        let mut logits = Tensor::zeros([2, 8], (Kind::Float, Device::Cpu));
+        processor.process(&input_ids, &mut logits);
+        assert_eq!(banned_tokens(&logits), vec![vec![2, 3], vec![2, 3]]);
+
+        let input_ids = Tensor::from_slice2(&[[0i64, 4, 6], [7, 0, 6]]);
+        let mut logits = Tensor::zeros([2, 8], (Kind::Float, Device::Cpu));
+        processor.process(&input_ids, &mut logits);
+        assert_eq!(banned_tokens(&logits), vec![Vec::<i64>::new(); 2]);
+    }
+
+    #[test]
+    fn logit_bias_processor() {
+        let input_ids = Tensor::from_slice2(&[[0i64, 1], [2, 2]]);
+        let logit_bias = HashMap::from([(1, 1.0), (3, f64::NEG_INFINITY)]);
+        let mut logits = Tensor::from_slice2(&[[1.0f32, 2.0, 3.0, 4.0], [1.0, 2.0, 3.0, 4.0]]);
+
+        // The bias is added after the repetition penalty
+        RepetitionPenaltyLogitsProcessor::new(2.0).process(&input_ids, &mut logits);
+        LogitBiasLogitsProcessor::new(&logit_bias).process(&input_ids, &mut logits);
+
+        let expected_logits = Tensor::from_slice2(&[
+            [0.5f32, 2.0, 3.0, f32::NEG_INFINITY],
+            [1.0, 3.0, 1.5, f32::NEG_INFINITY],
+        ]);
+        assert!(logits.equal(&expected_logits));
+    }
+
+    #[test]
+    fn repetition_penalty_scopes() {
+        // Prompt of 2 tokens followed by 2 generated tokens
+        let input_ids = Tensor::from_slice2(&[[0i64, 1, 2, 2], [3, 3, 3, 0]]);
+        let logits = Tensor::from_slice2(&[[1.0f32, 2.0, 3.0, 4.0], [-1.0, -2.0, 4.0, 1.0]]);
+        let penalized_logits = |scope| {
+            let mut penalized_logits = logits.copy();
+            RepetitionPenaltyLogitsProcessor::new_with_scope(2.0, scope, 2)
+                .process(&input_ids, &mut penalized_logits);
+            penalized_logits
+        };
+
+        assert!(
+            penalized_logits(RepetitionPenaltyScope::All).equal(&Tensor::from_slice2(&[
+                [0.5f32, 1.0, 1.5, 4.0],
+                [-2.0, -2.0, 4.0, 0.5]
+            ]))
+        );
+        assert!(
+            penalized_logits(RepetitionPenaltyScope::GeneratedOnly).equal(&Tensor::from_slice2(&[
+                [1.0f32, 2.0, 1.5, 4.0],
+                [-2.0, -2.0, 4.0, 0.5]
+            ]))
+        );
+        assert!(
+            penalized_logits(RepetitionPenaltyScope::PromptOnly).equal(&Tensor::from_slice2(&[
+                [0.5f32, 1.0, 3.0, 4.0],
+                [-1.0, -2.0, 4.0, 0.5]
+            ]))
+        );
+    }
+
+    #[test]
+    fn frequency_presence_penalty_processor() {
+        // Prompt of 2 tokens followed by 3 generated tokens
+        let input_ids = Tensor::from_slice2(&[[1i64, 1, 1, 2, 1], [3, 0, 2, 2, 2]]);
+        let logits = Tensor::from_slice2(&[[1.0f32, 2.0, 3.0, 4.0], [-1.0, -2.0, 4.0, 1.0]]);
+
+        let mut penalized_logits = logits.copy();
+        FrequencyPresencePenaltyLogitsProcessor::new(0.5, 1.0, 2)
+            .process(&input_ids, &mut penalized_logits);
+        let expected_logits =
+            Tensor::from_slice2(&[[1.0f32, 0.0, 1.5, 4.0], [-1.0, -2.0, 1.5, 1.0]]);
+        assert!(penalized_logits.equal(&expected_logits));
+
+        // The prompt tokens are counted from position 0
+        let mut penalized_logits = logits.copy();
+        FrequencyPresencePenaltyLogitsProcessor::new(0.5, 1.0, 0)
+            .process(&input_ids, &mut penalized_logits);
+        let expected_logits =
+            Tensor::from_slice2(&[[1.0f32, -1.0, 1.5, 4.0], [-2.5, -2.0, 1.5, -0.5]]);
+        assert!(penalized_logits.equal(&expected_logits));
+
+        // The additive penalties are applied after the multiplicative repetition penalty
+        let mut penalized_logits = logits.copy();
+        RepetitionPenaltyLogitsProcessor::new(2.0).process(&input_ids, &mut penalized_logits);
+        FrequencyPresencePenaltyLogitsProcessor::new(0.5, 1.0, 2)
+            .process(&input_ids, &mut penalized_logits);
+        let expected_logits =
+            Tensor::from_slice2(&[[1.0f32, -1.0, 0.0, 4.0], [-2.0, -2.0, -0.5, 0.5]]);
+        assert!(penalized_logits.equal(&expected_logits));
+    }
+}
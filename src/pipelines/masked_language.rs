@@ -56,6 +56,7 @@ use crate::pipelines::common::{
 };
 use crate::resources::ResourceProvider;
 use crate::roberta::RobertaForMaskedLM;
+use std::cmp::min;
 use std::convert::TryFrom;
 
 #[cfg(feature = "onnx")]
@@ -78,6 +79,8 @@ pub struct MaskedToken {
     pub id: i64,
     /// Score for the masked word
     pub score: f64,
+    /// Input text with the masked word substituted at the position of the mask
+    pub sequence: String,
 }
 
 /// # Configuration for MaskedLanguageModel
@@ -209,6 +212,7 @@ impl MaskedLanguageOption {
         let mut var_store = VarStore::new(device);
         let model_config =
             &ConfigOption::from_file(config.model_type, config.config_resource.get_local_path()?);
+        model_config.validate()?;
         let model_type = config.model_type;
         let model = match model_type {
             ModelType::Bert => {
@@ -541,6 +545,66 @@ impl MaskedLanguageModel {
         Ok(output)
     }
 
+    /// Replace the `mask_index`-th mask of an input text by a predicted word.
+    fn fill_mask(&self, input: &str, mask_index: usize, text: &str) -> String {
+        let mask_token = self
+            .mask_token
+            .as_deref()
+            .or_else(|| self.tokenizer.get_mask_value());
+        match mask_token.and_then(|mask_token| {
+            input
+                .match_indices(mask_token)
+                .nth(mask_index)
+                .map(|(offset, matched)| (offset, offset + matched.len()))
+        }) {
+            Some((start, end)) => format!("{}{}{}", &input[..start], text.trim(), &input[end..]),
+            None => input.to_string(),
+        }
+    }
+
+    /// Runs the model on the input texts, returning the token scores and the mask of the mask token positions
+    fn forward_masked(&self, input: &[&str]) -> Result<(Tensor, Tensor), RustBertError> {
+        let (input_ids, token_type_ids) = if let Some(mask_token) = &self.mask_token {
+            let input_with_replaced_mask = self.replace_mask_token(input, mask_token)?;
+            self.tokenizer.tokenize_and_pad(
+                input_with_replaced_mask
+                    .iter()
+                    .map(|w| w.as_str())
+                    .collect::<Vec<&str>>()
+                    .as_slice(),
+                self.max_length,
+                self.device,
+            )
+        } else {
+            self.tokenizer
+                .tokenize_and_pad(input, self.max_length, self.device)
+        };
+
+        // get the position of mask_token in input texts
+        let mask_token_id =
+            self.tokenizer
+                .get_mask_id()
+                .ok_or_else(|| RustBertError::InvalidConfigurationError(
+                    "Tokenizer does not have a mask token id, Please use a tokenizer/model with a mask token.".into(),
+                ))?;
+        let mask_token_mask = input_ids.eq(mask_token_id);
+
+        let output = no_grad(|| {
+            self.language_encode.forward_t(
+                Some(&input_ids),
+                None,
+                Some(&token_type_ids),
+                None,
+                None,
+                None,
+                None,
+                false,
+            )
+        });
+
+        Ok((output, mask_token_mask))
+    }
+
     /// Mask texts
     ///
     /// # Arguments
@@ -577,43 +641,7 @@ impl MaskedLanguageModel {
     where
         S: AsRef<[&'a str]>,
     {
-        let (input_ids, token_type_ids) = if let Some(mask_token) = &self.mask_token {
-            let input_with_replaced_mask = self.replace_mask_token(input.as_ref(), mask_token)?;
-            self.tokenizer.tokenize_and_pad(
-                input_with_replaced_mask
-                    .iter()
-                    .map(|w| w.as_str())
-                    .collect::<Vec<&str>>()
-                    .as_slice(),
-                self.max_length,
-                self.device,
-            )
-        } else {
-            self.tokenizer
-                .tokenize_and_pad(input.as_ref(), self.max_length, self.device)
-        };
-
-        // get the position of mask_token in input texts
-        let mask_token_id =
-            self.tokenizer
-                .get_mask_id()
-                .ok_or_else(|| RustBertError::InvalidConfigurationError(
-                    "Tokenizer does not have a mask token id, Please use a tokenizer/model with a mask token.".into(),
-                ))?;
-        let mask_token_mask = input_ids.eq(mask_token_id);
-
-        let output = no_grad(|| {
-            self.language_encode.forward_t(
-                Some(&input_ids),
-                None,
-                Some(&token_type_ids),
-                None,
-                None,
-                None,
-                None,
-                false,
-            )
-        });
+        let (output, mask_token_mask) = self.forward_masked(input.as_ref())?;
 
         let mut output_tokens = Vec::with_capacity(input.as_ref().len());
         for input_id in 0..input.as_ref().len() as i64 {
@@ -624,9 +652,107 @@ impl MaskedLanguageModel {
                     .get(input_id)
                     .index_select(0, &sequence_mask.argwhere().squeeze_dim(1));
                 let (token_scores, token_ids) = mask_scores.max_dim(1, false);
-                for (id, score) in token_ids.iter::<i64>()?.zip(token_scores.iter::<f64>()?) {
+                for (mask_index, (id, score)) in token_ids
+                    .iter::<i64>()?
+                    .zip(token_scores.iter::<f64>()?)
+                    .enumerate()
+                {
                     let text = self.tokenizer.decode(&[id], false, true);
-                    sequence_tokens.push(MaskedToken { text, id, score });
+                    let sequence =
+                        self.fill_mask(input.as_ref()[input_id as usize], mask_index, &text);
+                    sequence_tokens.push(MaskedToken {
+                        text,
+                        id,
+                        score,
+                        sequence,
+                    });
+                }
+            }
+            output_tokens.push(sequence_tokens);
+        }
+        Ok(output_tokens)
+    }
+
+    /// Mask texts, returning the `top_k` most likely tokens for every masked position
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - `&[&str]` Array of texts to mask.
+    /// * `top_k` - Number of candidate tokens to return for every masked position
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<Vec<Vec<MaskedToken>>>` containing, for every input text and every masked position (in order of
+    ///   appearance), the `top_k` most likely tokens sorted by decreasing probability. Contrary to `predict`, the
+    ///   `score` of the tokens is their probability (softmax over the vocabulary). The `sequence` of every candidate
+    ///   is the input text with the candidate substituted for its mask (other masks are left unchanged).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::pipelines::masked_language::MaskedLanguageModel;
+    /// //    Set-up model
+    /// let mask_language_model = MaskedLanguageModel::new(Default::default())?;
+    ///
+    /// //    Define input
+    /// let input = ["The [MASK] sat on the [MASK]."];
+    ///
+    /// //    Run model
+    /// let output = mask_language_model.predict_top_k(&input, 5)?;
+    /// for (mask_position, candidates) in output[0].iter().enumerate() {
+    ///     println!("{mask_position}: {:?}", candidates);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn predict_top_k<'a, S>(
+        &self,
+        input: S,
+        top_k: i64,
+    ) -> Result<Vec<Vec<Vec<MaskedToken>>>, RustBertError>
+    where
+        S: AsRef<[&'a str]>,
+    {
+        if top_k < 1 {
+            return Err(RustBertError::ValueError(format!(
+                "top_k must be strictly positive, got {top_k}"
+            )));
+        }
+        let (output, mask_token_mask) = self.forward_masked(input.as_ref())?;
+        let top_k = min(top_k, *output.size().last().unwrap());
+
+        let mut output_tokens = Vec::with_capacity(input.as_ref().len());
+        for input_id in 0..input.as_ref().len() as i64 {
+            let mut sequence_tokens = vec![];
+            let sequence_mask = mask_token_mask.get(input_id);
+            if bool::try_from(sequence_mask.any())? {
+                let mask_probabilities = output
+                    .get(input_id)
+                    .index_select(0, &sequence_mask.argwhere().squeeze_dim(1))
+                    .softmax(-1, Kind::Float);
+                let (token_scores, token_ids) = mask_probabilities.topk(top_k, -1, true, true);
+                for mask_index in 0..token_ids.size()[0] {
+                    let mut mask_tokens = Vec::with_capacity(top_k as usize);
+                    for (id, score) in token_ids
+                        .get(mask_index)
+                        .iter::<i64>()?
+                        .zip(token_scores.get(mask_index).iter::<f64>()?)
+                    {
+                        let text = self.tokenizer.decode(&[id], false, true);
+                        let sequence = self.fill_mask(
+                            input.as_ref()[input_id as usize],
+                            mask_index as usize,
+                            &text,
+                        );
+                        mask_tokens.push(MaskedToken {
+                            text,
+                            id,
+                            score,
+                            sequence,
+                        });
+                    }
+                    sequence_tokens.push(mask_tokens);
                 }
             }
             output_tokens.push(sequence_tokens);
@@ -524,21 +524,28 @@
 //! }
 //! ```
 
+pub(crate) mod beam_search;
 pub mod common;
 pub mod conversation;
+pub mod evaluation;
 pub mod generation_utils;
 pub mod keywords_extraction;
+pub mod logits_processors;
 pub mod masked_language;
 pub mod ner;
 pub mod pos_tagging;
 pub mod question_answering;
+pub mod reranking;
+pub mod sampling;
 pub mod sentence_embeddings;
 pub mod sentiment;
 pub mod sequence_classification;
 pub mod summarization;
 pub mod text_generation;
 pub mod token_classification;
+pub mod token_healing;
 pub mod translation;
+pub mod watermark;
 pub mod zero_shot_classification;
 
 #[cfg(feature = "onnx")]
@@ -113,6 +113,12 @@ impl ONNXDecoder {
             Cache::None
         };
 
-        Ok(LMModelOutput { lm_logits, cache })
+        Ok(LMModelOutput {
+            lm_logits,
+            cache,
+            last_hidden_state: None,
+            all_hidden_states: None,
+            all_attentions: None,
+        })
     }
 }
@@ -339,6 +339,7 @@ impl QuestionAnsweringOption {
             config.model_type,
             config.config_resource.get_local_path()?,
         );
+        model_config.validate()?;
         let model_type = config.model_type;
         let model = match model_type {
             ModelType::Bert => {
@@ -0,0 +1,323 @@
+// Copyright 2024 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Cross-encoder pipeline (semantic textual similarity and passage reranking)
+//! Scores pairs of texts (e.g. a query and a passage) by encoding them jointly with a sequence classification model
+//! with a single output (e.g. the [MS MARCO cross-encoders](https://www.sbert.net/docs/pretrained-models/ce-msmarco.html)).
+//! Cross-encoders are more accurate than the cosine similarity of sentence embeddings, and are typically used to
+//! rerank the passages retrieved by a faster bi-encoder.
+//!
+//! ```no_run
+//! use rust_bert::pipelines::common::{ModelResource, ModelType};
+//! use rust_bert::pipelines::reranking::CrossEncoderModel;
+//! use rust_bert::pipelines::sequence_classification::SequenceClassificationConfig;
+//! use rust_bert::resources::RemoteResource;
+//! # fn main() -> anyhow::Result<()> {
+//!
+//! let hub_resource =
+//!     |file_name| RemoteResource::from_hub("cross-encoder/ms-marco-MiniLM-L-6-v2", None, file_name);
+//! let config = SequenceClassificationConfig::new(
+//!     ModelType::Bert,
+//!     ModelResource::Torch(Box::new(hub_resource("model.safetensors"))),
+//!     hub_resource("config.json"),
+//!     hub_resource("vocab.txt"),
+//!     None,
+//!     true,
+//!     None,
+//!     None,
+//! );
+//! let cross_encoder = CrossEncoderModel::new(config)?;
+//!
+//! let query = "How many people live in Berlin?";
+//! let passages = [
+//!     "New York City is famous for the Metropolitan Museum of Art.",
+//!     "Berlin has a population of 3,520,031 registered inhabitants in an area of 891.82 square kilometers.",
+//! ];
+//! let ranking = cross_encoder.rerank(query, &passages)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Output: \
+//! ```no_run
+//! # let output =
+//! [(1, 0.9998), (0, 0.0001)]
+//! # ;
+//! ```
+
+use crate::common::error::RustBertError;
+use crate::pipelines::common::{get_device, ConfigOption, TokenizerOption};
+use crate::pipelines::sequence_classification::{
+    SequenceClassificationConfig, SequenceClassificationOption,
+};
+use crate::resources::ResourceProvider;
+use rust_tokenizers::tokenizer::TruncationStrategy;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use tch::{no_grad, Device, Kind, Tensor};
+
+/// # CrossEncoderModel for semantic textual similarity and passage reranking
+/// Sequence classification model with a single output scoring pairs of texts.
+pub struct CrossEncoderModel {
+    tokenizer: TokenizerOption,
+    cross_encoder: SequenceClassificationOption,
+    device: Device,
+    max_length: usize,
+}
+
+impl CrossEncoderModel {
+    /// Build a new `CrossEncoderModel`
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - `SequenceClassificationConfig` object containing the resource references (model, vocabulary, configuration) and device placement (CPU/GPU).
+    ///   The model should have a single output label.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::pipelines::common::{ModelResource, ModelType};
+    /// use rust_bert::pipelines::reranking::CrossEncoderModel;
+    /// use rust_bert::pipelines::sequence_classification::SequenceClassificationConfig;
+    /// use rust_bert::resources::LocalResource;
+    /// use std::path::PathBuf;
+    ///
+    /// let config = SequenceClassificationConfig::new(
+    ///     ModelType::Bert,
+    ///     ModelResource::Torch(Box::new(LocalResource::from(PathBuf::from(
+    ///         "path/to/model.ot",
+    ///     )))),
+    ///     LocalResource::from(PathBuf::from("path/to/config.json")),
+    ///     LocalResource::from(PathBuf::from("path/to/vocab.txt")),
+    ///     None,
+    ///     true,
+    ///     None,
+    ///     None,
+    /// );
+    /// let cross_encoder = CrossEncoderModel::new(config)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new(config: SequenceClassificationConfig) -> Result<CrossEncoderModel, RustBertError> {
+        let vocab_path = config.vocab_resource.get_local_path()?;
+        let merges_path = config
+            .merges_resource
+            .as_ref()
+            .map(|resource| resource.get_local_path())
+            .transpose()?;
+
+        let tokenizer = TokenizerOption::from_file(
+            config.model_type,
+            vocab_path.to_str().unwrap(),
+            merges_path.as_deref().map(|path| path.to_str().unwrap()),
+            config.lower_case,
+            config.strip_accents,
+            config.add_prefix_space,
+        )?;
+        Self::new_with_tokenizer(config, tokenizer)
+    }
+
+    /// Build a new `CrossEncoderModel` with a provided tokenizer.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - `SequenceClassificationConfig` object containing the resource references (model, vocabulary, configuration) and device placement (CPU/GPU).
+    ///   The model should have a single output label.
+    /// * `tokenizer` - `TokenizerOption` tokenizer to use for the text pairs.
+    pub fn new_with_tokenizer(
+        config: SequenceClassificationConfig,
+        tokenizer: TokenizerOption,
+    ) -> Result<CrossEncoderModel, RustBertError> {
+        let config_path = config.config_resource.get_local_path()?;
+        let model_config = ConfigOption::from_file(config.model_type, config_path);
+        let num_labels = model_config.get_label_mapping().len();
+        if num_labels != 1 {
+            return Err(RustBertError::InvalidConfigurationError(format!(
+                "Cross-encoders should have a single output label, got {num_labels}"
+            )));
+        }
+        let max_length = model_config
+            .get_max_len()
+            .map(|v| v as usize)
+            .unwrap_or(usize::MAX);
+        let cross_encoder = SequenceClassificationOption::new(&config)?;
+        let device = get_device(config.model_resource, config.device);
+        Ok(CrossEncoderModel {
+            tokenizer,
+            cross_encoder,
+            device,
+            max_length,
+        })
+    }
+
+    /// Get a reference to the model tokenizer.
+    pub fn get_tokenizer(&self) -> &TokenizerOption {
+        &self.tokenizer
+    }
+
+    /// Get a mutable reference to the model tokenizer.
+    pub fn get_tokenizer_mut(&mut self) -> &mut TokenizerOption {
+        &mut self.tokenizer
+    }
+
+    /// Scores pairs of texts. The texts of a pair are encoded together, separated by the separator token of the
+    /// tokenizer (e.g. `[SEP]` for BERT), and truncated to the maximum length of the model if needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `pairs` - `&[(&str, &str)]` Pairs of texts to score (e.g. a query and a passage).
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<f64>` Score of every pair, between 0 and 1 (sigmoid of the model output)
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// # use rust_bert::pipelines::common::{ModelResource, ModelType};
+    /// # use rust_bert::pipelines::reranking::CrossEncoderModel;
+    /// # use rust_bert::pipelines::sequence_classification::SequenceClassificationConfig;
+    /// # use rust_bert::resources::LocalResource;
+    /// # use std::path::PathBuf;
+    /// # let config = SequenceClassificationConfig::new(
+    /// #     ModelType::Bert,
+    /// #     ModelResource::Torch(Box::new(LocalResource::from(PathBuf::from("path/to/model.ot")))),
+    /// #     LocalResource::from(PathBuf::from("path/to/config.json")),
+    /// #     LocalResource::from(PathBuf::from("path/to/vocab.txt")),
+    /// #     None,
+    /// #     true,
+    /// #     None,
+    /// #     None,
+    /// # );
+    /// let cross_encoder = CrossEncoderModel::new(config)?;
+    /// let scores = cross_encoder.score(&[
+    ///     ("A man is eating food.", "A man is eating a piece of bread."),
+    ///     ("A man is eating food.", "A man is riding a horse."),
+    /// ])?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn score(&self, pairs: &[(&str, &str)]) -> Result<Vec<f64>, RustBertError> {
+        if pairs.is_empty() {
+            return Ok(Vec::new());
+        }
+        let tokenized_input = self.tokenizer.encode_pair_list(
+            pairs,
+            self.max_length,
+            &TruncationStrategy::LongestFirst,
+            0,
+        );
+        let max_len = tokenized_input
+            .iter()
+            .map(|input| input.token_ids.len())
+            .max()
+            .unwrap();
+        let pad_id = self.tokenizer.get_pad_id().ok_or_else(|| {
+            RustBertError::InvalidConfigurationError(
+                "The tokenizer used for cross-encoding should contain a PAD id".to_string(),
+            )
+        })?;
+        let (input_ids, token_type_ids): (Vec<Tensor>, Vec<Tensor>) = tokenized_input
+            .into_iter()
+            .map(|mut input| {
+                input.token_ids.resize(max_len, pad_id);
+                let last_segment_id = *input.segment_ids.last().unwrap_or(&0);
+                input.segment_ids.resize(max_len, last_segment_id);
+                (
+                    Tensor::from_slice(&input.token_ids),
+                    Tensor::from_slice(&input.segment_ids).to_kind(Kind::Int64),
+                )
+            })
+            .unzip();
+        let input_ids = Tensor::stack(&input_ids, 0).to(self.device);
+        let token_type_ids = Tensor::stack(&token_type_ids, 0).to(self.device);
+        let mask = input_ids.ne(pad_id).to_kind(Kind::Int64);
+
+        let scores = no_grad(|| {
+            self.cross_encoder
+                .forward_t(
+                    Some(&input_ids),
+                    Some(&mask),
+                    Some(&token_type_ids),
+                    None,
+                    None,
+                    false,
+                )
+                .squeeze_dim(-1)
+                .sigmoid()
+                .to_kind(Kind::Double)
+                .to(Device::Cpu)
+        });
+        Ok(Vec::<f64>::try_from(scores)?)
+    }
+
+    /// Reranks passages by relevance to a query.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - `&str` Query (e.g. a question).
+    /// * `passages` - `&[&str]` Candidate passages (e.g. retrieved with sentence embeddings).
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<(usize, f64)>` Index and score of every passage, sorted by decreasing score
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// # use rust_bert::pipelines::common::{ModelResource, ModelType};
+    /// # use rust_bert::pipelines::reranking::CrossEncoderModel;
+    /// # use rust_bert::pipelines::sequence_classification::SequenceClassificationConfig;
+    /// # use rust_bert::resources::LocalResource;
+    /// # use std::path::PathBuf;
+    /// # let config = SequenceClassificationConfig::new(
+    /// #     ModelType::Bert,
+    /// #     ModelResource::Torch(Box::new(LocalResource::from(PathBuf::from("path/to/model.ot")))),
+    /// #     LocalResource::from(PathBuf::from("path/to/config.json")),
+    /// #     LocalResource::from(PathBuf::from("path/to/vocab.txt")),
+    /// #     None,
+    /// #     true,
+    /// #     None,
+    /// #     None,
+    /// # );
+    /// let cross_encoder = CrossEncoderModel::new(config)?;
+    /// let passages = [
+    ///     "New York City is famous for the Metropolitan Museum of Art.",
+    ///     "Berlin has a population of 3,520,031 registered inhabitants.",
+    /// ];
+    /// let ranking = cross_encoder.rerank("How many people live in Berlin?", &passages)?;
+    /// let best_passage = passages[ranking[0].0];
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn rerank(
+        &self,
+        query: &str,
+        passages: &[&str],
+    ) -> Result<Vec<(usize, f64)>, RustBertError> {
+        let pairs = passages
+            .iter()
+            .map(|passage| (query, *passage))
+            .collect::<Vec<(&str, &str)>>();
+        let mut ranking = self
+            .score(&pairs)?
+            .into_iter()
+            .enumerate()
+            .collect::<Vec<(usize, f64)>>();
+        ranking.sort_by(|(_, score_1), (_, score_2)| {
+            score_2.partial_cmp(score_1).unwrap_or(Ordering::Equal)
+        });
+        Ok(ranking)
+    }
+}
@@ -0,0 +1,240 @@
+// Copyright 2024 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Adaptive sampling
+//! Stateful samplers used by the `LanguageGenerator` when sampling. The state of a sampler is updated
+//! after every generated token: one sampler is used for every generated sequence.
+//!
+//! [Mirostat sampling, Basu et al.](https://arxiv.org/abs/2007.14966) controls the perplexity of the generated text
+//! by truncating the next token distribution so that the surprise (`-log2(p)`) of the sampled tokens stays close to
+//! a target value `tau`. It is enabled with the `mirostat` generation option.
+//!
+//! ```no_run
+//! # fn main() -> anyhow::Result<()> {
+//! use rust_bert::gpt2::GPT2Generator;
+//! use rust_bert::pipelines::generation_utils::{GenerateOptions, LanguageGenerator};
+//! use rust_bert::pipelines::sampling::Mirostat;
+//!
+//! let model = GPT2Generator::new(Default::default())?;
+//! let generate_options = GenerateOptions {
+//!     do_sample: Some(true),
+//!     mirostat: Some(Mirostat::V2 { tau: 5.0, eta: 0.1 }),
+//!     ..Default::default()
+//! };
+//! let output = model.generate(Some(&["The dog"]), Some(generate_options))?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::convert::TryFrom;
+use std::f64::consts::LN_2;
+use tch::{Kind, Tensor};
+
+/// Number of most likely tokens used to estimate the Zipf exponent of the distribution (Mirostat 1.0)
+const MIROSTAT_ZIPF_TOKENS: i64 = 100;
+
+/// # Mirostat sampling settings
+/// Target surprise `tau` (in bits, the target perplexity is `2^tau`) and learning rate `eta` of the truncation
+/// threshold.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mirostat {
+    /// Original algorithm: the distribution is truncated to its `k` most likely tokens, `k` being computed
+    /// from an estimate of the Zipf exponent of the distribution.
+    V1 { tau: f64, eta: f64 },
+    /// Simplified algorithm: the tokens with a surprise greater than the threshold are removed.
+    V2 { tau: f64, eta: f64 },
+}
+
+impl Mirostat {
+    /// Target surprise (in bits)
+    pub fn tau(&self) -> f64 {
+        match self {
+            Mirostat::V1 { tau, .. } | Mirostat::V2 { tau, .. } => *tau,
+        }
+    }
+
+    /// Learning rate of the truncation threshold
+    pub fn eta(&self) -> f64 {
+        match self {
+            Mirostat::V1 { eta, .. } | Mirostat::V2 { eta, .. } => *eta,
+        }
+    }
+}
+
+/// # Mirostat sampler
+/// Samples the tokens of a single sequence. `mu` is the maximum surprise (in bits) allowed for the next token:
+/// initialized to `2 * tau`, it is updated after every sampled token with `mu -= eta * (surprise - tau)`.
+#[derive(Debug, Clone)]
+pub struct MirostatSampler {
+    pub tau: f64,
+    pub eta: f64,
+    pub mu: f64,
+    mirostat: Mirostat,
+}
+
+impl MirostatSampler {
+    /// Creates a new sampler for the provided settings
+    pub fn new(mirostat: Mirostat) -> Self {
+        MirostatSampler {
+            tau: mirostat.tau(),
+            eta: mirostat.eta(),
+            mu: 2.0 * mirostat.tau(),
+            mirostat,
+        }
+    }
+
+    /// Truncates the next token logits of the sequence (shape `(vocab size)`) in place, setting the logits of the
+    /// removed tokens to `-inf`. The most likely token is always kept.
+    /// - Mirostat 1.0: keeps the `k` most likely tokens, where `k` achieves the target surprise `mu` for a Zipf
+    ///   distribution with the exponent estimated from the most likely tokens.
+    /// - Mirostat 2.0: keeps the tokens with a surprise lower than `mu`.
+    pub fn filter_logits(&self, logits: &mut Tensor) {
+        let vocab_size = *logits.size().last().unwrap();
+        let log_probabilities = logits.log_softmax(-1, Kind::Float);
+        let indices_to_remove = match self.mirostat {
+            Mirostat::V1 { .. } => {
+                let (top_log_probabilities, _) =
+                    log_probabilities.topk(vocab_size.min(MIROSTAT_ZIPF_TOKENS), -1, true, true);
+                let top_log_probabilities = Vec::<f64>::try_from(top_log_probabilities).unwrap();
+                let k = self.zipf_top_k(&top_log_probabilities, vocab_size);
+                let (top_k_values, _) = log_probabilities.topk(k, -1, true, true);
+                log_probabilities.lt_tensor(&top_k_values.select(-1, -1))
+            }
+            Mirostat::V2 { .. } => log_probabilities.lt(-self.mu * LN_2),
+        };
+        let indices_to_remove =
+            indices_to_remove.index_fill(-1, &log_probabilities.argmax(-1, true), 0);
+        let _ = logits.masked_fill_(&indices_to_remove, f64::NEG_INFINITY);
+    }
+
+    /// Number of tokens to keep for Mirostat 1.0, computed from the sorted log-probabilities of the most likely tokens
+    fn zipf_top_k(&self, sorted_log_probabilities: &[f64], vocab_size: i64) -> i64 {
+        let (numerator, denominator) = sorted_log_probabilities
+            .windows(2)
+            .enumerate()
+            .filter(|(_, log_probabilities)| log_probabilities[1].is_finite())
+            .fold(
+                (0.0, 0.0),
+                |(numerator, denominator), (i, log_probabilities)| {
+                    let t = ((i + 2) as f64 / (i + 1) as f64).ln();
+                    let b = log_probabilities[0] - log_probabilities[1];
+                    (numerator + t * b, denominator + t * t)
+                },
+            );
+        let s_hat = numerator / denominator;
+        let epsilon_hat = s_hat - 1.0;
+        let k = ((epsilon_hat * 2f64.powf(self.mu))
+            / (1.0 - (vocab_size as f64).powf(-epsilon_hat)))
+        .powf(1.0 / s_hat);
+        if k.is_finite() {
+            (k.round() as i64).clamp(1, vocab_size)
+        } else {
+            1
+        }
+    }
+
+    /// Updates the truncation threshold with the surprise (in bits) of the sampled token
+    pub fn update(&mut self, surprise: f64) {
+        self.mu -= self.eta * (surprise - self.tau);
+    }
+
+    /// Truncates the next token logits (shape `(vocab size)`), samples the next token from the truncated distribution
+    /// and updates the truncation threshold with the surprise of the sampled token. Returns the sampled token id.
+    pub fn sample(&mut self, logits: &Tensor) -> i64 {
+        let mut logits = logits.copy();
+        self.filter_logits(&mut logits);
+        let log_probabilities = logits.log_softmax(-1, Kind::Float);
+        let next_token = log_probabilities.exp().multinomial(1, false);
+        self.update(
+            -log_probabilities
+                .gather(-1, &next_token, false)
+                .double_value(&[0])
+                / LN_2,
+        );
+        next_token.int64_value(&[0])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mirostat_v2_truncation() {
+        // Surprises of 1, 2, 3 and 3 bits
+        let logits = Tensor::from_slice(&[0.5f32, 0.25, 0.125, 0.125]).log();
+        let mut sampler = MirostatSampler::new(Mirostat::V2 {
+            tau: 1.25,
+            eta: 0.1,
+        });
+
+        let mut filtered_logits = logits.copy();
+        sampler.filter_logits(&mut filtered_logits);
+        assert!(filtered_logits
+            .isfinite()
+            .equal(&Tensor::from_slice(&[true, true, false, false])));
+
+        // The most likely token is kept even if its surprise exceeds the threshold
+        sampler.mu = 0.5;
+        let mut filtered_logits = logits.copy();
+        sampler.filter_logits(&mut filtered_logits);
+        assert!(filtered_logits
+            .isfinite()
+            .equal(&Tensor::from_slice(&[true, false, false, false])));
+
+        // Surprising tokens lower the threshold, unsurprising tokens raise it
+        sampler.update(2.25);
+        assert!((sampler.mu - 0.4).abs() < 1e-12);
+        sampler.update(0.25);
+        assert!((sampler.mu - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn mirostat_v1_truncation() {
+        // Zipf distribution with an exponent of 2 over 1000 tokens
+        let logits = Tensor::arange_start(1, 1001, (Kind::Float, tch::Device::Cpu))
+            .pow_tensor_scalar(2)
+            .reciprocal()
+            .log();
+        let mut sampler = MirostatSampler::new(Mirostat::V1 { tau: 3.0, eta: 0.1 });
+
+        let kept_tokens = |sampler: &MirostatSampler| {
+            let mut filtered_logits = logits.copy();
+            sampler.filter_logits(&mut filtered_logits);
+            filtered_logits.isfinite().sum(Kind::Int64).int64_value(&[])
+        };
+        // k = (2^mu / (1 - 1000^-1))^(1/2)
+        assert_eq!(kept_tokens(&sampler), 8);
+        sampler.mu = 10.0;
+        assert_eq!(kept_tokens(&sampler), 32);
+        sampler.mu = 0.0;
+        assert_eq!(kept_tokens(&sampler), 1);
+    }
+
+    #[test]
+    fn mirostat_sampling_tracks_target_surprise() {
+        tch::manual_seed(42);
+        let logits = Tensor::arange_start(1, 1001, (Kind::Float, tch::Device::Cpu))
+            .reciprocal()
+            .log();
+        let mut sampler = MirostatSampler::new(Mirostat::V2 { tau: 3.0, eta: 0.1 });
+        let mut surprises = vec![];
+        for _ in 0..500 {
+            let mu = sampler.mu;
+            let next_token = sampler.sample(&logits);
+            assert!((0..1000).contains(&next_token));
+            // Surprise of the sampled token recovered from the threshold update
+            surprises.push(sampler.tau + (mu - sampler.mu) / sampler.eta);
+        }
+        let average_surprise = surprises[100..].iter().sum::<f64>() / 400.0;
+        assert!((average_surprise - 3.0).abs() < 0.5);
+    }
+}
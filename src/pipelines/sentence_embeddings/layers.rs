@@ -19,11 +19,14 @@ pub struct PoolingConfig {
     pub pooling_mode_mean_tokens: bool,
     /// Perform mean-pooling, but devide by sqrt(input_length)
     pub pooling_mode_mean_sqrt_len_tokens: bool,
+    /// Perform mean-pooling weighted by the position of the tokens (later tokens get a larger weight)
+    #[serde(default)]
+    pub pooling_mode_weightedmean_tokens: bool,
 }
 
 impl Config for PoolingConfig {}
 
-/// Performs pooling (max or mean) on the token embeddings.
+/// Performs pooling (max, mean or weighted mean) on the token embeddings.
 ///
 /// Using pooling, it generates from a variable sized sentence a fixed sized sentence
 /// embedding. You can concatenate multiple poolings together.
@@ -52,6 +55,25 @@ impl Pooling {
             output_vectors.push(max_over_time);
         }
 
+        if self.conf.pooling_mode_weightedmean_tokens {
+            let input_mask_expanded = attention_mask.unsqueeze(-1).expand_as(&token_embeddings);
+            // Token weights are their 1-based position in the sequence
+            let weights = Tensor::arange_start(
+                1,
+                token_embeddings.size()[1] + 1,
+                (Kind::Float, token_embeddings.device()),
+            )
+            .view([1, -1, 1])
+            .expand_as(&token_embeddings)
+                * input_mask_expanded;
+            let sum_embeddings =
+                (&token_embeddings * &weights).sum_dim_intlist([1].as_slice(), false, Kind::Float);
+            let sum_weights = weights
+                .sum_dim_intlist([1].as_slice(), false, Kind::Float)
+                .clamp_min(10e-9);
+            output_vectors.push(sum_embeddings / sum_weights);
+        }
+
         if self.conf.pooling_mode_mean_tokens || self.conf.pooling_mode_mean_sqrt_len_tokens {
             let input_mask_expanded = attention_mask.unsqueeze(-1).expand_as(&token_embeddings);
             let sum_embeddings = (token_embeddings * &input_mask_expanded).sum_dim_intlist(
@@ -151,3 +173,39 @@ impl Dense {
         self.activation.get_fn()(&x.apply(&self.linear))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pipelines::sentence_embeddings::cosine_similarity;
+
+    #[test]
+    fn weighted_mean_pooling() {
+        let pooling = Pooling::new(PoolingConfig {
+            word_embedding_dimension: 2,
+            pooling_mode_cls_token: false,
+            pooling_mode_max_tokens: false,
+            pooling_mode_mean_tokens: false,
+            pooling_mode_mean_sqrt_len_tokens: false,
+            pooling_mode_weightedmean_tokens: true,
+        });
+        let token_embeddings =
+            Tensor::from_slice(&[1f32, 0., 0., 1., 4., 4., 2., 2., 0., 0., 8., 8.]).view([2, 3, 2]);
+        let attention_mask = Tensor::from_slice(&[1i64, 1, 0, 1, 1, 1]).view([2, 3]);
+
+        let pooled = pooling.forward(token_embeddings, &attention_mask);
+        // Weights 1 and 2 for the first sequence (the last token is padding), 1, 2 and 3 for the second
+        let expected = Tensor::from_slice(&[1f32 / 3., 2. / 3., 26. / 6., 26. / 6.]).view([2, 2]);
+        assert_eq!(pooled.size(), vec![2, 2]);
+        assert!(pooled.allclose(&expected, 1e-5, 1e-6, false));
+    }
+
+    #[test]
+    fn row_wise_cosine_similarity() {
+        let a = Tensor::from_slice(&[1f32, 0., 1., 1., 2., 0.]).view([3, 2]);
+        let b = Tensor::from_slice(&[3f32, 0., -1., -1., 0., 5.]).view([3, 2]);
+        let similarities = cosine_similarity(&a, &b);
+        let expected = Tensor::from_slice(&[1f32, -1., 0.]);
+        assert!(similarities.allclose(&expected, 1e-5, 1e-6, false));
+    }
+}
@@ -253,6 +253,7 @@ impl SentenceEmbeddingsModel {
             transformer_type,
             transformer_config_resource.get_local_path()?,
         );
+        transformer_config.validate()?;
         let transformer =
             SentenceEmbeddingsOption::new(transformer_type, var_store.root(), &transformer_config)?;
         crate::resources::load_weights(
@@ -509,3 +510,36 @@ pub struct SentenceEmbeddingsModelOutput {
     pub embeddings: Tensor,
     pub all_attentions: Option<Vec<Tensor>>,
 }
+
+/// Computes the cosine similarity between the rows of two embeddings tensors, e.g. the outputs of
+/// `encode_as_tensor` for two lists of sentences of the same length.
+///
+/// # Arguments
+///
+/// * `a` - `Tensor` of shape (*number of sentences*, *embeddings dimension*)
+/// * `b` - `Tensor` of shape (*number of sentences*, *embeddings dimension*)
+///
+/// # Returns
+///
+/// * `Tensor` of shape (*number of sentences*) with the similarity of every row of `a` with the
+///   same row of `b`
+///
+/// # Example
+///
+/// ```no_run
+/// # fn main() -> anyhow::Result<()> {
+/// use rust_bert::pipelines::sentence_embeddings::{
+///     cosine_similarity, SentenceEmbeddingsBuilder, SentenceEmbeddingsModelType,
+/// };
+///
+/// let model = SentenceEmbeddingsBuilder::remote(SentenceEmbeddingsModelType::AllMiniLmL12V2)
+///     .create_model()?;
+/// let queries = model.encode_as_tensor(&["How old are you?", "Where do you live?"])?;
+/// let answers = model.encode_as_tensor(&["I am 25 years old", "I live in Paris"])?;
+/// let similarities = cosine_similarity(&queries.embeddings, &answers.embeddings);
+/// # Ok(())
+/// # }
+/// ```
+pub fn cosine_similarity(a: &Tensor, b: &Tensor) -> Tensor {
+    Tensor::cosine_similarity(a, b, 1, 1e-8)
+}
@@ -200,6 +200,54 @@ impl SentimentModel {
         }
         sentiments
     }
+
+    /// Extract the scores of both sentiment polarities from an array of text inputs
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - `&[&str]` Array of texts to extract the sentiment from.
+    ///
+    /// # Returns
+    /// * `Vec<Vec<Sentiment>>` Negative and positive sentiments (in this order) with their probability for each text.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::pipelines::sentiment::SentimentModel;
+    ///
+    /// let sentiment_classifier =  SentimentModel::new(Default::default())?;
+    ///
+    /// let input = [
+    ///     "Probably my all-time favorite movie, a story of selflessness, sacrifice and dedication to a noble cause, but it's not preachy or boring.",
+    /// ];
+    ///
+    /// let output = sentiment_classifier.predict_all_scores(&input);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn predict_all_scores<'a, S>(&self, input: S) -> Vec<Vec<Sentiment>>
+    where
+        S: AsRef<[&'a str]>,
+    {
+        self.sequence_classification_model
+            .predict_all_scores(input)
+            .into_iter()
+            .map(|labels| {
+                labels
+                    .into_iter()
+                    .map(|label| Sentiment {
+                        polarity: if label.id == 1 {
+                            SentimentPolarity::Positive
+                        } else {
+                            SentimentPolarity::Negative
+                        },
+                        score: label.score,
+                    })
+                    .collect()
+            })
+            .collect()
+    }
 }
 #[cfg(test)]
 mod test {
@@ -241,6 +241,7 @@ impl SequenceClassificationOption {
         let mut var_store = VarStore::new(device);
         let model_config =
             &ConfigOption::from_file(config.model_type, config.config_resource.get_local_path()?);
+        model_config.validate()?;
         let model_type = config.model_type;
         let model = match model_type {
             ModelType::Bert => {
@@ -769,6 +770,69 @@ impl SequenceClassificationModel {
         labels
     }
 
+    /// Classify texts, returning the scores of all labels
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - `&[&str]` Array of texts to classify.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<Vec<Label>>` containing, for each input text, all the labels (sorted by label ID) with their probability
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// # use rust_bert::pipelines::sequence_classification::SequenceClassificationModel;
+    ///
+    /// let sequence_classification_model =  SequenceClassificationModel::new(Default::default())?;
+    /// let input = [
+    ///     "Probably my all-time favorite movie, a story of selflessness, sacrifice and dedication to a noble cause, but it's not preachy or boring.",
+    ///     "This film tried to be too many things all at once: stinging political satire, Hollywood blockbuster, sappy romantic comedy, family values promo...",
+    /// ];
+    /// let output = sequence_classification_model.predict_all_scores(&input);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn predict_all_scores<'a, S>(&self, input: S) -> Vec<Vec<Label>>
+    where
+        S: AsRef<[&'a str]>,
+    {
+        let (input_ids, token_type_ids) =
+            self.tokenizer
+                .tokenize_and_pad(input.as_ref(), self.max_length, self.device);
+        let output = no_grad(|| {
+            let output = self.sequence_classifier.forward_t(
+                Some(&input_ids),
+                None,
+                Some(&token_type_ids),
+                None,
+                None,
+                false,
+            );
+            output.softmax(-1, Kind::Float).detach().to(Device::Cpu)
+        });
+
+        let mut labels: Vec<Vec<Label>> = vec![];
+        for (sentence_idx, sentence_scores) in output.split(1, 0).iter().enumerate() {
+            let sentence_labels = sentence_scores
+                .squeeze_dim(0)
+                .iter::<f64>()
+                .unwrap()
+                .enumerate()
+                .map(|(id, score)| Label {
+                    text: self.label_mapping.get(&(id as i64)).unwrap().clone(),
+                    score,
+                    id: id as i64,
+                    sentence: sentence_idx,
+                })
+                .collect();
+            labels.push(sentence_labels);
+        }
+        labels
+    }
+
     /// Multi-label classification of texts
     ///
     /// # Arguments
@@ -62,13 +62,15 @@
 //! # ;
 //! ```
 
+use rust_tokenizers::tokenizer::TruncationStrategy;
 use tch::{Device, Kind};
 
 use crate::bart::BartGenerator;
 use crate::common::error::RustBertError;
 use crate::pegasus::PegasusConditionalGenerator;
 use crate::pipelines::common::{ModelResource, ModelType, TokenizerOption};
-use crate::pipelines::generation_utils::{GenerateConfig, LanguageGenerator};
+use crate::pipelines::generation_utils::private_generation_utils::PrivateLanguageGenerator;
+use crate::pipelines::generation_utils::{GenerateConfig, GenerateOptions, LanguageGenerator};
 use crate::prophetnet::ProphetNetConditionalGenerator;
 use crate::resources::ResourceProvider;
 use crate::t5::T5Generator;
@@ -239,6 +241,61 @@ pub enum SummarizationOption {
     ONNX(ONNXConditionalGenerator),
 }
 
+/// # Length settings for the summarization of long documents
+/// Lengths are expressed in number of tokens. If `max_length` is not provided, the maximum length of a summary
+/// is inferred from the length of its input as `max_length_ratio` times the number of input tokens, clamped to
+/// `max_length_range`.
+#[derive(Debug, Clone, Copy)]
+pub struct SummarizationLengthOptions {
+    /// Minimum length of the summaries (default: the `min_length` of the `SummarizationConfig`)
+    pub min_length: Option<i64>,
+    /// Maximum length of the summaries (default: inferred from the input length)
+    pub max_length: Option<i64>,
+    /// Ratio of the input length used to infer the maximum length of the summaries (default: 0.3)
+    pub max_length_ratio: f64,
+    /// Lower and upper bounds of the inferred maximum length of the summaries (default: (50, 500))
+    pub max_length_range: (i64, i64),
+    /// Exponential penalty based on the length of the summaries (default: the `length_penalty` of the `SummarizationConfig`)
+    pub length_penalty: Option<f64>,
+}
+
+impl Default for SummarizationLengthOptions {
+    fn default() -> Self {
+        SummarizationLengthOptions {
+            min_length: None,
+            max_length: None,
+            max_length_ratio: 0.3,
+            max_length_range: (50, 500),
+            length_penalty: None,
+        }
+    }
+}
+
+impl SummarizationLengthOptions {
+    /// Maximum length of a summary inferred from the number of tokens of its input
+    fn inferred_max_length(&self, input_length: usize) -> i64 {
+        let (lower_bound, upper_bound) = self.max_length_range;
+        ((input_length as f64 * self.max_length_ratio).round() as i64)
+            .clamp(lower_bound, upper_bound)
+    }
+
+    fn validate(&self) -> Result<(), RustBertError> {
+        let (lower_bound, upper_bound) = self.max_length_range;
+        if (lower_bound < 1) | (lower_bound > upper_bound) {
+            return Err(RustBertError::InvalidConfigurationError(format!(
+                "Invalid maximum length range ({lower_bound}, {upper_bound}): the bounds should be positive and ordered"
+            )));
+        }
+        if self.max_length_ratio <= 0.0 {
+            return Err(RustBertError::InvalidConfigurationError(format!(
+                "The maximum length ratio should be positive, got {}",
+                self.max_length_ratio
+            )));
+        }
+        Ok(())
+    }
+}
+
 impl SummarizationOption {
     pub fn new(config: SummarizationConfig) -> Result<Self, RustBertError> {
         match (config.model_type, &config.model_resource) {
@@ -337,40 +394,85 @@ impl SummarizationOption {
         }
     }
 
+    /// Maximum number of input tokens of the model
+    fn get_max_positions_embeddings(&self) -> Option<i64> {
+        match *self {
+            Self::Bart(ref model) => model.get_max_positions_embeddings(),
+            Self::T5(ref model) => model.get_max_positions_embeddings(),
+            Self::LongT5(ref model) => model.get_max_positions_embeddings(),
+            Self::ProphetNet(ref model) => model.get_max_positions_embeddings(),
+            Self::Pegasus(ref model) => model.get_max_positions_embeddings(),
+            #[cfg(feature = "onnx")]
+            Self::ONNX(ref model) => model.get_max_positions_embeddings(),
+        }
+    }
+
     /// Interface method to generate() of the particular models.
     pub fn generate<S>(&self, prompt_texts: Option<&[S]>) -> Result<Vec<String>, RustBertError>
+    where
+        S: AsRef<str> + Send + Sync,
+    {
+        self.generate_with_length(prompt_texts, None, None)
+    }
+
+    /// Interface method to generate() of the particular models, overriding the summary length settings.
+    pub fn generate_with_length<S>(
+        &self,
+        prompt_texts: Option<&[S]>,
+        min_length: Option<i64>,
+        max_length: Option<i64>,
+    ) -> Result<Vec<String>, RustBertError>
+    where
+        S: AsRef<str> + Send + Sync,
+    {
+        self.generate_with_options(
+            prompt_texts,
+            Some(GenerateOptions {
+                min_length,
+                max_length,
+                ..Default::default()
+            }),
+        )
+    }
+
+    /// Interface method to generate() of the particular models, with a set of generate options.
+    pub fn generate_with_options<S>(
+        &self,
+        prompt_texts: Option<&[S]>,
+        generate_options: Option<GenerateOptions>,
+    ) -> Result<Vec<String>, RustBertError>
     where
         S: AsRef<str> + Send + Sync,
     {
         Ok(match *self {
             Self::Bart(ref model) => model
-                .generate(prompt_texts, None)?
+                .generate(prompt_texts, generate_options)?
                 .into_iter()
                 .map(|output| output.text)
                 .collect(),
             Self::T5(ref model) => model
-                .generate(prompt_texts, None)?
+                .generate(prompt_texts, generate_options)?
                 .into_iter()
                 .map(|output| output.text)
                 .collect(),
             Self::LongT5(ref model) => model
-                .generate(prompt_texts, None)?
+                .generate(prompt_texts, generate_options)?
                 .into_iter()
                 .map(|output| output.text)
                 .collect(),
             Self::ProphetNet(ref model) => model
-                .generate(prompt_texts, None)?
+                .generate(prompt_texts, generate_options)?
                 .into_iter()
                 .map(|output| output.text)
                 .collect(),
             Self::Pegasus(ref model) => model
-                .generate(prompt_texts, None)?
+                .generate(prompt_texts, generate_options)?
                 .into_iter()
                 .map(|output| output.text)
                 .collect(),
             #[cfg(feature = "onnx")]
             Self::ONNX(ref model) => model
-                .generate(prompt_texts, None)?
+                .generate(prompt_texts, generate_options)?
                 .into_iter()
                 .map(|output| output.text)
                 .collect(),
@@ -521,6 +623,214 @@ impl SummarizationModel {
             }
         }
     }
+
+    /// Summarize texts provided, with summaries of `min_length` to `max_length` tokens (overriding the
+    /// length settings of the `SummarizationConfig`)
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - `&[&str]` Array of texts to summarize.
+    /// * `min_length` - `i64` Minimum number of tokens of the summaries.
+    /// * `max_length` - `i64` Maximum number of tokens of the summaries.
+    ///
+    /// # Returns
+    /// * `Vec<String>` Summarized texts
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::pegasus::{PegasusConfigResources, PegasusModelResources, PegasusVocabResources};
+    /// use rust_bert::pipelines::common::{ModelResource, ModelType};
+    /// use rust_bert::pipelines::summarization::{SummarizationConfig, SummarizationModel};
+    /// use rust_bert::resources::RemoteResource;
+    ///
+    /// let summarization_config = SummarizationConfig {
+    ///     model_type: ModelType::Pegasus,
+    ///     model_resource: ModelResource::Torch(Box::new(RemoteResource::from_pretrained(
+    ///         PegasusModelResources::CNN_DAILYMAIL,
+    ///     ))),
+    ///     config_resource: Box::new(RemoteResource::from_pretrained(
+    ///         PegasusConfigResources::CNN_DAILYMAIL,
+    ///     )),
+    ///     vocab_resource: Box::new(RemoteResource::from_pretrained(
+    ///         PegasusVocabResources::CNN_DAILYMAIL,
+    ///     )),
+    ///     merges_resource: None,
+    ///     ..Default::default()
+    /// };
+    /// let model = SummarizationModel::new(summarization_config)?;
+    ///
+    /// let input = ["In findings published Tuesday in Cornell University's arXiv by a team of scientists
+    /// from the University of Montreal and a separate report published Wednesday in Nature Astronomy by a team
+    /// from University College London (UCL), the presence of water vapour was confirmed in the atmosphere of K2-18b,
+    /// a planet circling a star in the constellation Leo."];
+    ///
+    /// let output = model.summarize_with_length(&input, 10, 30);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn summarize_with_length<S>(
+        &self,
+        texts: &[S],
+        min_length: i64,
+        max_length: i64,
+    ) -> Result<Vec<String>, RustBertError>
+    where
+        S: AsRef<str> + Send + Sync,
+    {
+        let generate_options = GenerateOptions {
+            min_length: Some(min_length),
+            max_length: Some(max_length),
+            ..Default::default()
+        };
+        self.generate_summaries(texts, generate_options)
+    }
+
+    /// Summarize texts provided, inferring the maximum length of the summaries from the length of the texts.
+    /// Texts longer than the maximum input length of the model are split into chunks that are summarized
+    /// separately: the chunk summaries are concatenated and summarized again until they fit in the model input.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - `&[&str]` Array of texts to summarize.
+    /// * `length_options` - `SummarizationLengthOptions` Length settings of the summaries. The maximum length
+    /// of the final summary is inferred from the length of the full text if not provided.
+    ///
+    /// # Returns
+    /// * `Vec<String>` Summarized texts (a single summary per text)
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::pipelines::summarization::{SummarizationLengthOptions, SummarizationModel};
+    /// let model = SummarizationModel::new(Default::default())?;
+    ///
+    /// let long_document = std::fs::read_to_string("path/to/document.txt")?;
+    /// let length_options = SummarizationLengthOptions {
+    ///     max_length_ratio: 0.2,
+    ///     length_penalty: Some(2.0),
+    ///     ..Default::default()
+    /// };
+    /// let output = model.summarize_with_options(&[long_document], length_options)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn summarize_with_options<S>(
+        &self,
+        texts: &[S],
+        length_options: SummarizationLengthOptions,
+    ) -> Result<Vec<String>, RustBertError>
+    where
+        S: AsRef<str> + Send + Sync,
+    {
+        length_options.validate()?;
+        texts
+            .iter()
+            .map(|text| self.summarize_document(text.as_ref(), &length_options))
+            .collect()
+    }
+
+    fn summarize_document(
+        &self,
+        text: &str,
+        length_options: &SummarizationLengthOptions,
+    ) -> Result<String, RustBertError> {
+        let tokenizer = self.get_tokenizer();
+        let token_ids = |text: &str| tokenizer.convert_tokens_to_ids(&tokenizer.tokenize(text));
+        let input_length = token_ids(text).len();
+        // Number of text tokens fitting in the model input, next to the prefix and special tokens
+        let max_chunk_length = self
+            .model
+            .get_max_positions_embeddings()
+            .map(|max_positions| {
+                let num_added_tokens = tokenizer.encode_list(
+                    &[self.prefix.as_deref().unwrap_or("")],
+                    usize::MAX,
+                    &TruncationStrategy::LongestFirst,
+                    0,
+                )[0]
+                .token_ids
+                .len();
+                (max_positions as usize)
+                    .saturating_sub(num_added_tokens)
+                    .max(1)
+            });
+
+        let mut text = text.to_string();
+        let mut text_ids = token_ids(&text);
+        while let Some(max_chunk_length) =
+            max_chunk_length.filter(|max_chunk_length| text_ids.len() > *max_chunk_length)
+        {
+            let chunks = text_ids
+                .chunks(max_chunk_length)
+                .map(|chunk_ids| tokenizer.decode(chunk_ids, true, true))
+                .collect::<Vec<String>>();
+            let chunk_options = GenerateOptions {
+                max_length: Some(length_options.inferred_max_length(max_chunk_length)),
+                length_penalty: length_options.length_penalty,
+                num_return_sequences: Some(1),
+                ..Default::default()
+            };
+            let chunk_summaries = self.generate_summaries(&chunks, chunk_options)?;
+            let summaries_text = chunk_summaries
+                .iter()
+                .map(|summary| summary.trim())
+                .collect::<Vec<&str>>()
+                .join(" ");
+            let summaries_ids = token_ids(&summaries_text);
+            if summaries_ids.len() >= text_ids.len() {
+                return Err(RustBertError::ValueError(
+                    "The summaries of the text chunks are not shorter than the text: \
+                    reduce the maximum length of the summaries"
+                        .to_string(),
+                ));
+            }
+            text = summaries_text;
+            text_ids = summaries_ids;
+        }
+
+        let generate_options = GenerateOptions {
+            min_length: length_options.min_length,
+            max_length: Some(
+                length_options
+                    .max_length
+                    .unwrap_or_else(|| length_options.inferred_max_length(input_length)),
+            ),
+            length_penalty: length_options.length_penalty,
+            num_return_sequences: Some(1),
+            ..Default::default()
+        };
+        Ok(self
+            .generate_summaries(&[text], generate_options)?
+            .into_iter()
+            .next()
+            .unwrap_or_default())
+    }
+
+    fn generate_summaries<S>(
+        &self,
+        texts: &[S],
+        generate_options: GenerateOptions,
+    ) -> Result<Vec<String>, RustBertError>
+    where
+        S: AsRef<str> + Send + Sync,
+    {
+        match &self.prefix {
+            None => self
+                .model
+                .generate_with_options(Some(texts), Some(generate_options)),
+            Some(prefix) => {
+                let texts = texts
+                    .iter()
+                    .map(|text| format!("{}{}", prefix, text.as_ref()))
+                    .collect::<Vec<String>>();
+                self.model
+                    .generate_with_options(Some(&texts), Some(generate_options))
+            }
+        }
+    }
 }
 
 #[cfg(test)]
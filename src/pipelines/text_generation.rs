@@ -39,7 +39,9 @@ use crate::gpt_j::GptJGenerator;
 use crate::gpt_neo::GptNeoGenerator;
 use crate::openai_gpt::OpenAIGenerator;
 use crate::pipelines::common::{ModelResource, ModelType, TokenizerOption};
-use crate::pipelines::generation_utils::{GenerateConfig, GenerateOptions, LanguageGenerator};
+use crate::pipelines::generation_utils::{
+    GenerateConfig, GenerateOptions, GeneratedTextOutput, LanguageGenerator,
+};
 use crate::reformer::ReformerGenerator;
 use crate::resources::ResourceProvider;
 use crate::t5::T5Generator;
@@ -95,6 +97,8 @@ pub struct TextGenerationConfig {
     pub num_beam_groups: Option<i64>,
     /// Diversity penalty for diverse beam search. High values will enforce more difference between beam groups (default: 5.5)
     pub diversity_penalty: Option<f64>,
+    /// Truncate the prompts of decoder-only models that do not fit in the maximum length (or in the model context size) instead of returning an error (default: false)
+    pub truncate_prompt: bool,
     /// Device to place the model on (default: CUDA/GPU when available)
     pub device: Device,
     /// Model weights precision. If not provided, will default to full precision on CPU, or the loaded weights precision otherwise
@@ -142,6 +146,7 @@ impl TextGenerationConfig {
             num_return_sequences: 1,
             num_beam_groups: None,
             diversity_penalty: None,
+            truncate_prompt: false,
             device: Device::cuda_if_available(),
             kind: None,
         }
@@ -335,6 +340,7 @@ impl TextGenerationOption {
         prompt_texts: Option<&[S]>,
         min_length: Option<i64>,
         max_length: Option<i64>,
+        truncate_prompt: bool,
     ) -> Result<Vec<Vec<i64>>, RustBertError>
     where
         S: AsRef<str> + Send + Sync,
@@ -342,6 +348,7 @@ impl TextGenerationOption {
         let generate_options = Some(GenerateOptions {
             min_length,
             max_length,
+            truncate_prompt,
             ..Default::default()
         });
         Ok(match *self {
@@ -389,6 +396,52 @@ impl TextGenerationOption {
         })
     }
 
+    /// Interface method to generate() of the particular models, with generation options provided at each query.
+    /// This gives a single handle to the text generation models, for a model type selected at runtime.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use rust_bert::pipelines::common::ModelType;
+    /// use rust_bert::pipelines::generation_utils::GenerateOptions;
+    /// use rust_bert::pipelines::text_generation::{TextGenerationConfig, TextGenerationOption};
+    ///
+    /// # let model_type = ModelType::GPT2;
+    /// # let text_generation_config = TextGenerationConfig::default();
+    /// let model = TextGenerationOption::new(TextGenerationConfig {
+    ///     model_type,
+    ///     ..text_generation_config
+    /// })?;
+    /// let generate_options = GenerateOptions {
+    ///     max_new_tokens: Some(20),
+    ///     ..Default::default()
+    /// };
+    /// let output = model.generate(Some(&["The dog"]), Some(generate_options))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn generate<S>(
+        &self,
+        prompt_texts: Option<&[S]>,
+        generate_options: Option<GenerateOptions>,
+    ) -> Result<Vec<GeneratedTextOutput>, RustBertError>
+    where
+        S: AsRef<str> + Send + Sync,
+    {
+        match *self {
+            Self::GPT(ref model) => model.generate(prompt_texts, generate_options),
+            Self::GPT2(ref model) => model.generate(prompt_texts, generate_options),
+            Self::GPTNeo(ref model) => model.generate(prompt_texts, generate_options),
+            Self::GPTJ(ref model) => model.generate(prompt_texts, generate_options),
+            Self::XLNet(ref model) => model.generate(prompt_texts, generate_options),
+            Self::Reformer(ref model) => model.generate(prompt_texts, generate_options),
+            Self::T5(ref model) => model.generate(prompt_texts, generate_options),
+            #[cfg(feature = "onnx")]
+            Self::ONNX(ref model) => model.generate(prompt_texts, generate_options),
+        }
+    }
+
     pub fn half(&mut self) -> Result<(), RustBertError> {
         match self {
             Self::GPT(model_ref) => model_ref.half(),
@@ -445,6 +498,7 @@ pub struct TextGenerationModel {
     prefix_length: Option<i64>,
     min_length: i64,
     max_length: Option<i64>,
+    truncate_prompt: bool,
 }
 
 impl TextGenerationModel {
@@ -470,6 +524,7 @@ impl TextGenerationModel {
     ) -> Result<TextGenerationModel, RustBertError> {
         let (prefix, min_length, max_length) =
             TextGenerationModel::get_prefix_min_max_length(&generation_config);
+        let truncate_prompt = generation_config.truncate_prompt;
         let model = TextGenerationOption::new(generation_config)?;
         let prefix_length = prefix
             .as_ref()
@@ -480,6 +535,7 @@ impl TextGenerationModel {
             prefix_length,
             min_length,
             max_length,
+            truncate_prompt,
         })
     }
 
@@ -515,6 +571,7 @@ impl TextGenerationModel {
     ) -> Result<TextGenerationModel, RustBertError> {
         let (prefix, min_length, max_length) =
             TextGenerationModel::get_prefix_min_max_length(&generation_config);
+        let truncate_prompt = generation_config.truncate_prompt;
         let model = TextGenerationOption::new_with_tokenizer(generation_config, tokenizer)?;
         let prefix_length = prefix
             .as_ref()
@@ -525,6 +582,7 @@ impl TextGenerationModel {
             prefix_length,
             min_length,
             max_length,
+            truncate_prompt,
         })
     }
 
@@ -616,7 +674,9 @@ with people, even a bishop, begging for his blessing. <eod> </s> <eos>"
             (None, None) => (None, None),
         };
         let generated_indices = match (prefix, prefix_length) {
-            (None, _) => self.model.generate_indices(Some(texts), None, None),
+            (None, _) => self
+                .model
+                .generate_indices(Some(texts), None, None, self.truncate_prompt),
             (Some(prefix), Some(prefix_length)) => {
                 let texts = texts
                     .as_ref()
@@ -627,6 +687,7 @@ with people, even a bishop, begging for his blessing. <eod> </s> <eos>"
                     Some(&texts),
                     Some(self.min_length + prefix_length),
                     self.max_length.map(|max_length| max_length + prefix_length),
+                    self.truncate_prompt,
                 )
             }
             _ => Err(RustBertError::ValueError(
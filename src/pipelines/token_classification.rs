@@ -114,6 +114,7 @@
 
 use crate::albert::AlbertForTokenClassification;
 use crate::bert::BertForTokenClassification;
+use crate::common::crf::CRFLayer;
 use crate::common::error::RustBertError;
 use crate::deberta::DebertaForTokenClassification;
 use crate::distilbert::DistilBertForTokenClassification;
@@ -217,6 +218,8 @@ pub enum LabelAggregationOption {
     Last,
     /// The most frequent sub- token is  assigned to the entire token
     Mode,
+    /// The label of the sub token with the highest score is assigned to the entire token (max-pooling)
+    Max,
     /// The user can provide a function mapping a `&Vec<Token>` to a `(i64, String)` tuple corresponding to the label index, label String to return
     Custom(LabelAggregationFunction),
 }
@@ -366,6 +369,7 @@ impl TokenClassificationOption {
         let mut var_store = VarStore::new(device);
         let model_config =
             &ConfigOption::from_file(config.model_type, config.config_resource.get_local_path()?);
+        model_config.validate()?;
         let model_type = config.model_type;
         let model = match model_type {
             ModelType::Bert => {
@@ -553,6 +557,14 @@ impl TokenClassificationOption {
         }
     }
 
+    /// Returns the CRF layer of the model, if any (only supported for BERT models created with `use_crf`)
+    fn crf(&self) -> Option<&CRFLayer> {
+        match *self {
+            Self::Bert(ref model) => model.crf(),
+            _ => None,
+        }
+    }
+
     fn forward_t(
         &self,
         input_ids: Option<&Tensor>,
@@ -992,7 +1004,21 @@ impl TokenClassificationModel {
                     / output
                         .exp()
                         .sum_dim_intlist([-1].as_slice(), true, Kind::Float);
-                let label_indices = score.argmax(-1, true);
+                let label_indices = match self.token_sequence_classifier.crf() {
+                    Some(crf) => {
+                        let (best_paths, _) = crf.viterbi_decode(&output, &attention_masks);
+                        let sequence_length = output.size()[1] as usize;
+                        let best_paths = best_paths
+                            .into_iter()
+                            .map(|mut best_path| {
+                                best_path.resize(sequence_length, 0);
+                                Tensor::from_slice(&best_path)
+                            })
+                            .collect::<Vec<Tensor>>();
+                        Tensor::stack(&best_paths, 0).unsqueeze(-1)
+                    }
+                    None => score.argmax(-1, true),
+                };
                 for sentence_idx in 0..label_indices.size()[0] {
                     let labels = label_indices.get(sentence_idx);
                     let feature = &features[sentence_idx as usize];
@@ -1230,6 +1256,13 @@ impl TokenClassificationModel {
                     .map(|((label_index, label), _)| (label_index, label.to_owned()))
                     .unwrap()
             }
+            LabelAggregationOption::Max => {
+                let token = tokens
+                    .iter()
+                    .max_by_key(|token| OrderedFloat(token.score))
+                    .unwrap();
+                (token.label_index, token.label.clone())
+            }
             LabelAggregationOption::Custom(function) => function(tokens),
         }
     }
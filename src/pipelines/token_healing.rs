@@ -0,0 +1,325 @@
+// Copyright 2024 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Token healing
+//! Prompts ending in the middle of a word (e.g. "The quick bro") are tokenized with a trailing fragment token
+//! (" bro") that is rarely followed by the rest of the word in the training data: the model tends to continue
+//! with a new word instead of completing the fragment. Token healing removes the last token of the prompt and
+//! constrains the first generated token to the tokens of the vocabulary starting with the removed fragment
+//! (" bro", " brown", " broad"...), the generation then continuing normally.
+//!
+//! The tokens starting with a fragment are looked up in a `TokenHealingIndex` of the vocabulary, built once for a
+//! generator (or tokenizer) and passed to the generation with the `token_healing` generation option. Token healing
+//! only applies to decoder-only models. Prompts of a single token and prompts whose last token does not match any
+//! token of the vocabulary (e.g. special tokens) are generated from unchanged.
+//!
+//! ```no_run
+//! # fn main() -> anyhow::Result<()> {
+//! use rust_bert::gpt2::GPT2Generator;
+//! use rust_bert::pipelines::generation_utils::{GenerateOptions, LanguageGenerator};
+//! use rust_bert::pipelines::token_healing::TokenHealingIndex;
+//!
+//! let model = GPT2Generator::new(Default::default())?;
+//! let token_healing_index = TokenHealingIndex::new(&model);
+//! let generate_options = GenerateOptions {
+//!     token_healing: Some(&token_healing_index),
+//!     ..Default::default()
+//! };
+//! let output = model.generate(Some(&["The quick bro"]), Some(generate_options))?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::pipelines::generation_utils::private_generation_utils::PrivateLanguageGenerator;
+use crate::pipelines::generation_utils::LanguageGenerator;
+use crate::pipelines::logits_processors::LogitsProcessor;
+use std::convert::TryFrom;
+use tch::kind::Kind::Int64;
+use tch::{Device, Tensor};
+
+/// # Vocabulary prefix index
+/// Decoded strings of the tokens of a vocabulary, sorted so that the tokens starting with a given prefix can be
+/// found with a binary search.
+#[derive(Debug, Clone)]
+pub struct TokenHealingIndex {
+    token_strings: Vec<String>,
+    sorted_token_ids: Vec<i64>,
+}
+
+impl TokenHealingIndex {
+    /// Builds the index of the vocabulary of a generator by decoding each of its tokens. The index should be built once
+    /// and reused for all generations with this generator (or with generators sharing its tokenizer).
+    pub fn new<T: LanguageGenerator + ?Sized>(generator: &T) -> Self {
+        let tokenizer = generator.get_tokenizer();
+        let token_strings = (0..generator.get_vocab_size())
+            .map(|token_id| tokenizer.decode(&[token_id], true, false))
+            .collect::<Vec<String>>();
+        Self::from_token_strings(token_strings)
+    }
+
+    /// Builds the index from the decoded strings of the tokens, indexed by token id
+    pub fn from_token_strings(token_strings: Vec<String>) -> Self {
+        let mut sorted_token_ids = (0..token_strings.len() as i64).collect::<Vec<i64>>();
+        sorted_token_ids.sort_by(|id_1, id_2| {
+            token_strings[*id_1 as usize].cmp(&token_strings[*id_2 as usize])
+        });
+        TokenHealingIndex {
+            token_strings,
+            sorted_token_ids,
+        }
+    }
+
+    /// Returns the ids of the tokens whose string starts with `prefix`
+    pub fn tokens_starting_with(&self, prefix: &str) -> &[i64] {
+        let token_string = |token_id: &i64| self.token_strings[*token_id as usize].as_str();
+        let start = self
+            .sorted_token_ids
+            .partition_point(|token_id| token_string(token_id) < prefix);
+        let length = self.sorted_token_ids[start..]
+            .partition_point(|token_id| token_string(token_id).starts_with(prefix));
+        &self.sorted_token_ids[start..start + length]
+    }
+
+    /// Returns the tokens allowed as first generated token when healing a prompt ending with `token_id`, or None if
+    /// the token cannot be healed (unknown or special token, or fragment matching no token of the vocabulary).
+    pub fn healing_tokens(&self, token_id: i64) -> Option<Vec<i64>> {
+        let fragment = self
+            .token_strings
+            .get(usize::try_from(token_id).ok()?)
+            .filter(|fragment| !fragment.is_empty())?;
+        let matching_tokens = self.tokens_starting_with(fragment);
+        if matching_tokens.is_empty() {
+            None
+        } else {
+            Some(matching_tokens.to_vec())
+        }
+    }
+
+    /// Removes the last token of the (left-padded) prompts that can be healed. The healed prompts are shifted right
+    /// by one position (with a masked padding token) so that all prompts still end at the last position, the
+    /// padding column being dropped if all prompts are healed. Returns the updated token ids and attention mask, and
+    /// the tokens allowed as first generated token for every prompt (None for prompts that are not healed).
+    pub(crate) fn heal_prompts(
+        &self,
+        input_ids: &Tensor,
+        attention_mask: &Tensor,
+        pad_token_id: i64,
+    ) -> (Tensor, Tensor, Vec<Option<Vec<i64>>>) {
+        let sequence_length = input_ids.size()[1];
+        let last_tokens = input_ids.select(1, -1).to_device(Device::Cpu);
+        let prompt_lengths = attention_mask
+            .sum_dim_intlist([1].as_slice(), false, Int64)
+            .to_device(Device::Cpu);
+        let healing_tokens = last_tokens
+            .iter::<i64>()
+            .unwrap()
+            .zip(prompt_lengths.iter::<i64>().unwrap())
+            .map(|(last_token, prompt_length)| {
+                // Single-token prompts are not healed: the generation would start from an empty prompt
+                if prompt_length > 1 {
+                    self.healing_tokens(last_token)
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<Option<Vec<i64>>>>();
+
+        let num_healed_prompts = healing_tokens
+            .iter()
+            .filter(|tokens| tokens.is_some())
+            .count();
+        if num_healed_prompts == 0 {
+            return (
+                input_ids.shallow_clone(),
+                attention_mask.shallow_clone(),
+                healing_tokens,
+            );
+        }
+        let healed = Tensor::from_slice(
+            &healing_tokens
+                .iter()
+                .map(|tokens| tokens.is_some())
+                .collect::<Vec<bool>>(),
+        )
+        .to_device(input_ids.device())
+        .unsqueeze(1);
+        let shifted_input_ids = Tensor::cat(
+            &[
+                input_ids.narrow(1, 0, 1).full_like(pad_token_id),
+                input_ids.narrow(1, 0, sequence_length - 1),
+            ],
+            1,
+        );
+        let shifted_attention_mask = Tensor::cat(
+            &[
+                attention_mask.narrow(1, 0, 1).zeros_like(),
+                attention_mask.narrow(1, 0, sequence_length - 1),
+            ],
+            1,
+        );
+        let mut input_ids = shifted_input_ids.where_self(&healed, input_ids);
+        let mut attention_mask = shifted_attention_mask.where_self(&healed, attention_mask);
+        if num_healed_prompts == healing_tokens.len() {
+            input_ids = input_ids.narrow(1, 1, sequence_length - 1);
+            attention_mask = attention_mask.narrow(1, 1, sequence_length - 1);
+        }
+        (input_ids, attention_mask, healing_tokens)
+    }
+}
+
+/// # Token healing logits processor
+/// Restricts the first generated token of the healed prompts to the tokens starting with their removed fragment.
+/// The sequences generated for a prompt (beams and returned sequences) are expected to be stored contiguously.
+pub struct TokenHealingLogitsProcessor {
+    healing_tokens: Vec<Option<Vec<i64>>>,
+    prompt_length: i64,
+}
+
+impl TokenHealingLogitsProcessor {
+    /// Creates a new processor from the tokens allowed for every prompt (None for prompts that are not healed) and
+    /// the length of the prompts.
+    pub fn new(healing_tokens: Vec<Option<Vec<i64>>>, prompt_length: i64) -> Self {
+        TokenHealingLogitsProcessor {
+            healing_tokens,
+            prompt_length,
+        }
+    }
+}
+
+impl LogitsProcessor for TokenHealingLogitsProcessor {
+    fn process(&self, input_ids: &Tensor, logits: &mut Tensor) {
+        if (input_ids.size()[1] != self.prompt_length) | self.healing_tokens.is_empty() {
+            return;
+        }
+        let (num_sequences, vocab_size) = (logits.size()[0], logits.size()[1]);
+        let sequences_per_prompt = (num_sequences / self.healing_tokens.len() as i64).max(1);
+        let mut banned_tokens = vec![false; (num_sequences * vocab_size) as usize];
+        for (sequence_index, sequence_banned_tokens) in
+            banned_tokens.chunks_mut(vocab_size as usize).enumerate()
+        {
+            let prompt_index = sequence_index / sequences_per_prompt as usize;
+            if let Some(Some(healing_tokens)) = self.healing_tokens.get(prompt_index) {
+                sequence_banned_tokens.fill(true);
+                for token_id in healing_tokens
+                    .iter()
+                    .filter(|token_id| (0..vocab_size).contains(*token_id))
+                {
+                    sequence_banned_tokens[*token_id as usize] = false;
+                }
+            }
+        }
+        let banned_tokens = Tensor::from_slice(&banned_tokens)
+            .view([num_sequences, vocab_size])
+            .to_device(logits.device());
+        let _ = logits.masked_fill_(&banned_tokens, f64::NEG_INFINITY);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tch::Kind;
+
+    fn test_index() -> TokenHealingIndex {
+        TokenHealingIndex::from_token_strings(
+            [
+                "The", " quick", " bro", " brown", " b", " broad", "", " brow", " dog",
+            ]
+            .iter()
+            .map(|token| token.to_string())
+            .collect(),
+        )
+    }
+
+    #[test]
+    fn vocabulary_prefix_lookup() {
+        let index = test_index();
+        let mut tokens = index.tokens_starting_with(" bro").to_vec();
+        tokens.sort_unstable();
+        assert_eq!(tokens, vec![2, 3, 5, 7]);
+        assert_eq!(index.tokens_starting_with(" dog"), &[8]);
+        assert!(index.tokens_starting_with(" cat").is_empty());
+
+        let mut healing_tokens = index.healing_tokens(7).unwrap();
+        healing_tokens.sort_unstable();
+        assert_eq!(healing_tokens, vec![3, 7]);
+        // Empty (special) and unknown tokens are not healed
+        assert_eq!(index.healing_tokens(6), None);
+        assert_eq!(index.healing_tokens(42), None);
+    }
+
+    #[test]
+    fn prompt_healing() {
+        let index = test_index();
+        // " bro" is healed, the single-token prompt " bro" and the prompt ending with an empty token are not
+        let input_ids = Tensor::from_slice2(&[[0i64, 1, 2], [8, 8, 2], [8, 0, 6]]);
+        let attention_mask = Tensor::from_slice2(&[[1i64, 1, 1], [0, 0, 1], [1, 1, 1]]);
+
+        let (healed_input_ids, healed_attention_mask, healing_tokens) =
+            index.heal_prompts(&input_ids, &attention_mask, 8);
+        assert!(healed_input_ids.equal(&Tensor::from_slice2(&[
+            [8i64, 0, 1],
+            [8, 8, 2],
+            [8, 0, 6]
+        ])));
+        assert!(healed_attention_mask.equal(&Tensor::from_slice2(&[
+            [0i64, 1, 1],
+            [0, 0, 1],
+            [1, 1, 1]
+        ])));
+        assert!(
+            healing_tokens[0].is_some() & healing_tokens[1].is_none() & healing_tokens[2].is_none()
+        );
+
+        // The padding column is dropped when all prompts are healed
+        let (healed_input_ids, healed_attention_mask, _) = index.heal_prompts(
+            &input_ids.narrow(0, 0, 1),
+            &attention_mask.narrow(0, 0, 1),
+            8,
+        );
+        assert!(healed_input_ids.equal(&Tensor::from_slice2(&[[0i64, 1]])));
+        assert!(healed_attention_mask.equal(&Tensor::from_slice2(&[[1i64, 1]])));
+    }
+
+    #[test]
+    fn token_healing_processor() {
+        let processor = TokenHealingLogitsProcessor::new(vec![Some(vec![3, 7]), None], 2);
+        // Two sequences generated per prompt
+        let input_ids = Tensor::zeros([4, 2], (Int64, Device::Cpu));
+        let mut logits = Tensor::zeros([4, 9], (Kind::Float, Device::Cpu));
+        processor.process(&input_ids, &mut logits);
+        let allowed_tokens = logits.isfinite();
+        for sequence_index in 0..2 {
+            assert_eq!(
+                allowed_tokens
+                    .get(sequence_index)
+                    .nonzero()
+                    .view(-1)
+                    .iter::<i64>()
+                    .unwrap()
+                    .collect::<Vec<i64>>(),
+                vec![3, 7]
+            );
+        }
+        assert_eq!(
+            allowed_tokens
+                .narrow(0, 2, 2)
+                .sum(Kind::Int64)
+                .int64_value(&[]),
+            18
+        );
+
+        // Only the first generated token is constrained
+        let mut logits = Tensor::zeros([4, 9], (Kind::Float, Device::Cpu));
+        processor.process(&Tensor::zeros([4, 3], (Int64, Device::Cpu)), &mut logits);
+        assert_eq!(logits.isfinite().sum(Kind::Int64).int64_value(&[]), 36);
+    }
+}
@@ -0,0 +1,244 @@
+// Copyright 2024 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Generation watermarking
+//! Implementation of the watermark of [Kirchenbauer et al., 2023](https://arxiv.org/abs/2301.10226).
+//! At every generation step, the vocabulary is pseudo-randomly partitioned into a "green" list (a fraction
+//! `gamma` of the vocabulary) and a "red" list, seeded by the previous token. The `WatermarkLogitsProcessor`
+//! adds `delta` to the scores of the green tokens, so that watermarked texts contain significantly more
+//! green tokens than expected. The `WatermarkDetector` recomputes the green lists from the token ids alone
+//! (without access to the model) and tests for the presence of the watermark with a z-score.
+//!
+//! ```no_run
+//! # fn main() -> anyhow::Result<()> {
+//! use rust_bert::gpt2::GPT2Generator;
+//! use rust_bert::pipelines::generation_utils::{GenerateOptions, LanguageGenerator};
+//! use rust_bert::pipelines::logits_processors::LogitsProcessor;
+//! use rust_bert::pipelines::watermark::{
+//!     SeedingScheme, WatermarkDetector, WatermarkLogitsProcessor,
+//! };
+//!
+//! let model = GPT2Generator::new(Default::default())?;
+//! let logits_processors: Vec<Box<dyn LogitsProcessor>> = vec![Box::new(
+//!     WatermarkLogitsProcessor::new(2.0, 0.25, SeedingScheme::default()),
+//! )];
+//! let generate_options = GenerateOptions {
+//!     logits_processors: Some(&logits_processors),
+//!     ..Default::default()
+//! };
+//! let output = model.generate_indices(Some(&["The dog"]), Some(generate_options))?;
+//!
+//! let detector = WatermarkDetector::new(0.25, SeedingScheme::default());
+//! let score = detector.detect(&output[0].indices);
+//! let is_watermarked = score.z_score > 4.0;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::pipelines::logits_processors::LogitsProcessor;
+use tch::{Device, Kind, Tensor};
+
+/// # Seeding scheme of the green lists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeedingScheme {
+    /// The green list of a token is seeded by the previous token, combined with a secret `hash_key`
+    LeftHash { hash_key: u64 },
+}
+
+impl Default for SeedingScheme {
+    fn default() -> Self {
+        SeedingScheme::LeftHash {
+            hash_key: 15_485_863,
+        }
+    }
+}
+
+/// SplitMix64 mixing function, used as keyed hash of the tokens
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Green list membership, shared by the processor and the detector
+#[derive(Debug, Clone, Copy)]
+struct GreenList {
+    gamma: f64,
+    seeding_scheme: SeedingScheme,
+}
+
+impl GreenList {
+    /// Seed of the green list of the token following `previous_token`
+    fn seed(&self, previous_token: i64) -> u64 {
+        match self.seeding_scheme {
+            SeedingScheme::LeftHash { hash_key } => splitmix64(hash_key ^ previous_token as u64),
+        }
+    }
+
+    /// Returns true if `token` is in the green list seeded by `seed`. Every token has a probability
+    /// `gamma` to be in a green list.
+    fn contains(&self, seed: u64, token: i64) -> bool {
+        let hash = splitmix64(seed ^ splitmix64(token as u64));
+        ((hash >> 11) as f64 / (1u64 << 53) as f64) < self.gamma
+    }
+}
+
+/// # Watermark logits processor
+/// Adds `delta` to the scores of the tokens of the green list seeded by the last token of every sequence.
+/// `gamma` is the fraction of the vocabulary in the green list.
+pub struct WatermarkLogitsProcessor {
+    delta: f64,
+    green_list: GreenList,
+}
+
+impl WatermarkLogitsProcessor {
+    pub fn new(delta: f64, gamma: f64, seeding_scheme: SeedingScheme) -> Self {
+        WatermarkLogitsProcessor {
+            delta,
+            green_list: GreenList {
+                gamma,
+                seeding_scheme,
+            },
+        }
+    }
+}
+
+impl LogitsProcessor for WatermarkLogitsProcessor {
+    fn process(&self, input_ids: &Tensor, logits: &mut Tensor) {
+        let vocab_size = logits.size()[1];
+        let previous_tokens = input_ids.select(1, -1).to_device(Device::Cpu);
+        let green_mask = previous_tokens
+            .iter::<i64>()
+            .unwrap()
+            .flat_map(|previous_token| {
+                let seed = self.green_list.seed(previous_token);
+                (0..vocab_size).map(move |token| self.green_list.contains(seed, token))
+            })
+            .collect::<Vec<bool>>();
+        let green_mask = Tensor::from_slice(&green_mask)
+            .view([-1, vocab_size])
+            .to_device(logits.device())
+            .to_kind(logits.kind());
+        *logits += green_mask * self.delta;
+    }
+}
+
+/// # Watermark detection score
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatermarkScore {
+    /// Number of tokens tested for the watermark (all tokens but the first one)
+    pub num_tokens_scored: usize,
+    /// Number of tokens in the green list seeded by their previous token
+    pub num_green_tokens: usize,
+    /// Fraction of green tokens
+    pub green_fraction: f64,
+    /// z-score of the number of green tokens under the null hypothesis (text generated without
+    /// watermark, every token being green with probability `gamma`)
+    pub z_score: f64,
+}
+
+/// # Watermark detector
+/// Tests for the watermark of a `WatermarkLogitsProcessor` with the same `gamma` and seeding scheme.
+pub struct WatermarkDetector {
+    green_list: GreenList,
+}
+
+impl WatermarkDetector {
+    pub fn new(gamma: f64, seeding_scheme: SeedingScheme) -> Self {
+        WatermarkDetector {
+            green_list: GreenList {
+                gamma,
+                seeding_scheme,
+            },
+        }
+    }
+
+    /// Computes the watermark score of a sequence of token ids. The first token is only used to seed
+    /// the green list of the second one: the sequence may start with the last token of the prompt.
+    /// Scores above 4 indicate a watermarked text with high confidence.
+    pub fn detect(&self, token_ids: &[i64]) -> WatermarkScore {
+        let num_tokens_scored = token_ids.len().saturating_sub(1);
+        let num_green_tokens = token_ids
+            .windows(2)
+            .filter(|tokens| {
+                self.green_list
+                    .contains(self.green_list.seed(tokens[0]), tokens[1])
+            })
+            .count();
+        let gamma = self.green_list.gamma;
+        let expected_green_tokens = gamma * num_tokens_scored as f64;
+        let z_score = (num_green_tokens as f64 - expected_green_tokens)
+            / (expected_green_tokens * (1.0 - gamma)).sqrt();
+        WatermarkScore {
+            num_tokens_scored,
+            num_green_tokens,
+            green_fraction: num_green_tokens as f64 / num_tokens_scored as f64,
+            z_score,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample_tokens(processor: Option<&WatermarkLogitsProcessor>, num_tokens: usize) -> Vec<i64> {
+        let vocab_size = 1000;
+        let mut input_ids = Tensor::from_slice(&[0i64]).view([1, 1]);
+        for _ in 0..num_tokens {
+            let mut logits = Tensor::randn([1, vocab_size], (Kind::Float, Device::Cpu));
+            if let Some(processor) = processor {
+                processor.process(&input_ids, &mut logits);
+            }
+            let next_token = logits.softmax(-1, Kind::Float).multinomial(1, false);
+            input_ids = Tensor::cat(&[input_ids, next_token], 1);
+        }
+        input_ids.get(0).iter::<i64>().unwrap().collect()
+    }
+
+    #[test]
+    fn green_list_fraction() {
+        let green_list = GreenList {
+            gamma: 0.25,
+            seeding_scheme: SeedingScheme::default(),
+        };
+        let seed = green_list.seed(42);
+        let num_green_tokens = (0..10000)
+            .filter(|token| green_list.contains(seed, *token))
+            .count();
+        assert!((num_green_tokens as f64 / 10000.0 - 0.25).abs() < 0.02);
+        // The green lists depend on the previous token
+        assert_ne!(green_list.seed(42), green_list.seed(43));
+    }
+
+    #[test]
+    fn watermark_generation_and_detection() {
+        tch::manual_seed(42);
+        let seeding_scheme = SeedingScheme::default();
+        let processor = WatermarkLogitsProcessor::new(2.0, 0.25, seeding_scheme);
+        let detector = WatermarkDetector::new(0.25, seeding_scheme);
+
+        let watermarked_tokens = sample_tokens(Some(&processor), 200);
+        let score = detector.detect(&watermarked_tokens);
+        assert_eq!(score.num_tokens_scored, 200);
+        assert!(score.green_fraction > 0.5);
+        assert!(score.z_score > 4.0);
+
+        let unwatermarked_tokens = sample_tokens(None, 200);
+        assert!(detector.detect(&unwatermarked_tokens).z_score.abs() < 4.0);
+
+        // A different key does not detect the watermark
+        let other_detector =
+            WatermarkDetector::new(0.25, SeedingScheme::LeftHash { hash_key: 1234 });
+        assert!(other_detector.detect(&watermarked_tokens).z_score < 4.0);
+    }
+}
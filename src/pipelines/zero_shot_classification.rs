@@ -274,6 +274,7 @@ impl ZeroShotClassificationOption {
         let mut var_store = VarStore::new(device);
         let model_config =
             &ConfigOption::from_file(config.model_type, config.config_resource.get_local_path()?);
+        model_config.validate()?;
         let model_type = config.model_type;
         let model = match model_type {
             ModelType::Bart => {
@@ -790,7 +791,7 @@ impl ZeroShotClassificationModel {
     ///
     /// * `input` - `&[&str]` Array of texts to classify.
     /// * `labels` - `&[&str]` Possible labels for the inputs.
-    /// * `template` - `Option<Box<dyn Fn(&str) -> String>>` closure to build label propositions. If None, will default to `"This example is {}."`.
+    /// * `template` - `Option<Box<dyn Fn(&str) -> String>>` closure to build label propositions. If None, will default to `"This example is about {}."`.
     /// * `max_length` -`usize` Maximum sequence length for the inputs. If needed, the input sequence will be truncated before the label template.
     ///
     /// # Returns
@@ -3,7 +3,9 @@ use rust_bert::bart::{
     BartVocabResources,
 };
 use rust_bert::pipelines::common::{cast_var_store, ModelResource};
-use rust_bert::pipelines::summarization::{SummarizationConfig, SummarizationModel};
+use rust_bert::pipelines::summarization::{
+    SummarizationConfig, SummarizationLengthOptions, SummarizationModel,
+};
 use rust_bert::pipelines::zero_shot_classification::{
     ZeroShotClassificationConfig, ZeroShotClassificationModel,
 };
@@ -199,6 +201,81 @@ about exoplanets like K2-18b."];
     Ok(())
 }
 
+#[test]
+#[cfg_attr(not(feature = "all-tests"), ignore)]
+fn bart_summarization_long_document() -> anyhow::Result<()> {
+    let config_resource = Box::new(RemoteResource::from_pretrained(
+        BartConfigResources::DISTILBART_CNN_6_6,
+    ));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(
+        BartVocabResources::DISTILBART_CNN_6_6,
+    ));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(
+        BartMergesResources::DISTILBART_CNN_6_6,
+    ));
+    let model_resource = Box::new(RemoteResource::from_pretrained(
+        BartModelResources::DISTILBART_CNN_6_6,
+    ));
+    let summarization_config = SummarizationConfig {
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        num_beams: 1,
+        min_length: 10,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = SummarizationModel::new(summarization_config)?;
+
+    //    Credits: WikiNews, CC BY 2.5 license (https://en.wikinews.org/wiki/Astronomers_find_water_vapour_in_atmosphere_of_exoplanet_K2-18b)
+    let article = "In findings published Tuesday in Cornell University's arXiv by a team of scientists \
+from the University of Montreal and a separate report published Wednesday in Nature Astronomy by a team \
+from University College London (UCL), the presence of water vapour was confirmed in the atmosphere of K2-18b, \
+a planet circling a star in the constellation Leo. This is the first such discovery in a planet in its star's \
+habitable zone — not too hot and not too cold for liquid water to exist. The Montreal team, led by Björn Benneke, \
+used data from the NASA's Hubble telescope to assess changes in the light coming from K2-18b's star as the planet \
+passed between it and Earth. They found that certain wavelengths of light, which are usually absorbed by water, \
+weakened when the planet was in the way, indicating not only does K2-18b have an atmosphere, but the atmosphere \
+contains water in vapour form. The team from UCL then analyzed the Montreal team's data using their own software \
+and confirmed their conclusion. This was not the first time scientists have found signs of water on an exoplanet, \
+but previous discoveries were made on planets with high temperatures or other pronounced differences from Earth. \
+\"This is the first potentially habitable planet where the temperature is right and where we now know there is water,\" \
+said UCL astronomer Angelos Tsiaras. \"It's the best candidate for habitability right now.\" \"It's a good sign\", \
+said Ryan Cloutier of the Harvard–Smithsonian Center for Astrophysics, who was not one of either study's authors. \
+\"Overall,\" he continued, \"the presence of water in its atmosphere certainly improves the prospect of K2-18b being \
+a potentially habitable planet, but further observations will be required to say for sure. \" \
+K2-18b was first identified in 2015 by the Kepler space telescope. It is about 110 light-years from Earth and larger \
+but less dense. Its star, a red dwarf, is cooler than the Sun, but the planet's orbit is much closer, such that a year \
+on K2-18b lasts 33 Earth days. According to The Guardian, astronomers were optimistic that NASA's James Webb space \
+telescope — scheduled for launch in 2021 — and the European Space Agency's 2028 ARIEL program, could reveal more \
+about exoplanets like K2-18b.";
+    // Document of about 1000 words, longer than the maximum input length of the model (1024 tokens)
+    let document = vec![article; 3].join(" ");
+    let num_input_tokens = model.get_tokenizer().tokenize(&document).len();
+    assert!(document.split_whitespace().count() > 950);
+    assert!(num_input_tokens > 1024);
+
+    let output = model.summarize_with_options(&[&document], Default::default())?;
+
+    assert_eq!(output.len(), 1);
+    assert!(!output[0].trim().is_empty());
+    assert!(output[0].len() < document.len());
+    // The maximum summary length is inferred from the input length (30% of the input tokens)
+    assert!(model.get_tokenizer().tokenize(&output[0]).len() < num_input_tokens / 3);
+
+    let length_options = SummarizationLengthOptions {
+        max_length_range: (100, 10),
+        ..Default::default()
+    };
+    assert!(matches!(
+        model.summarize_with_options(&[&document], length_options),
+        Err(RustBertError::InvalidConfigurationError(_))
+    ));
+
+    Ok(())
+}
+
 #[test]
 #[cfg_attr(not(feature = "all-tests"), ignore)]
 fn bart_zero_shot_classification() -> anyhow::Result<()> {
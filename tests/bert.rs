@@ -2,22 +2,28 @@ extern crate anyhow;
 extern crate dirs;
 
 use rust_bert::bert::{
-    BertConfig, BertConfigResources, BertForMaskedLM, BertForMultipleChoice,
-    BertForQuestionAnswering, BertForSequenceClassification, BertForTokenClassification,
+    BertConfig, BertConfigResources, BertEmbeddings, BertForMaskedLM, BertForMultipleChoice,
+    BertForQuestionAnswering, BertForSequenceClassification, BertForTokenClassification, BertModel,
     BertModelResources, BertVocabResources,
 };
+use rust_bert::lora::{merge_lora_weights, LoraConfig};
 use rust_bert::pipelines::common::{ModelResource, ModelType};
 use rust_bert::pipelines::masked_language::{MaskedLanguageConfig, MaskedLanguageModel};
 use rust_bert::pipelines::ner::NERModel;
 use rust_bert::pipelines::question_answering::{
     QaInput, QuestionAnsweringConfig, QuestionAnsweringModel,
 };
+use rust_bert::pipelines::reranking::CrossEncoderModel;
+use rust_bert::pipelines::sequence_classification::SequenceClassificationConfig;
+use rust_bert::pipelines::token_classification::{
+    LabelAggregationOption, TokenClassificationConfig,
+};
 use rust_bert::resources::{RemoteResource, ResourceProvider};
 use rust_bert::Config;
 use rust_tokenizers::tokenizer::{BertTokenizer, MultiThreadedTokenizer, TruncationStrategy};
 use rust_tokenizers::vocab::Vocab;
 use std::collections::HashMap;
-use tch::{nn, no_grad, Device, Tensor};
+use tch::{nn, no_grad, Device, Kind, Tensor};
 
 #[test]
 fn bert_masked_lm() -> anyhow::Result<()> {
@@ -143,6 +149,70 @@ fn bert_masked_lm_pipeline() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn bert_masked_lm_pipeline_top_k() -> anyhow::Result<()> {
+    //    Set-up model
+    let config = MaskedLanguageConfig::new(
+        ModelType::Bert,
+        ModelResource::Torch(Box::new(RemoteResource::from_pretrained(
+            BertModelResources::BERT,
+        ))),
+        RemoteResource::from_pretrained(BertConfigResources::BERT),
+        RemoteResource::from_pretrained(BertVocabResources::BERT),
+        None,
+        true,
+        None,
+        None,
+        Some(String::from("<mask>")),
+    );
+
+    let mask_language_model = MaskedLanguageModel::new(config)?;
+    //    Define input
+    let input = [
+        "Hello I am a <mask> student",
+        "Paris is the <mask> of France. It is <mask> in Europe.",
+    ];
+
+    //    Run model
+    let output = mask_language_model.predict_top_k(input, 5)?;
+
+    assert_eq!(output.len(), 2);
+    assert_eq!(output[0].len(), 1);
+    assert_eq!(output[1].len(), 2);
+    for mask_candidates in output.iter().flatten() {
+        assert_eq!(mask_candidates.len(), 5);
+        // Candidates are sorted by decreasing probability
+        assert!(mask_candidates
+            .windows(2)
+            .all(|candidates| candidates[0].score >= candidates[1].score));
+        assert!(mask_candidates.iter().map(|token| token.score).sum::<f64>() <= 1.0);
+    }
+    // The most likely candidates match the single predictions
+    assert_eq!(output[0][0][0].text, "college");
+    assert_eq!(output[1][0][0].text, "capital");
+    assert_eq!(output[1][1][0].text, "located");
+    assert!(output[1][0][0].score > 0.5);
+    // The candidates are substituted for their mask in the input text
+    assert_eq!(output[0][0][0].sequence, "Hello I am a college student");
+    assert_eq!(
+        output[1][0][0].sequence,
+        "Paris is the capital of France. It is <mask> in Europe."
+    );
+    assert_eq!(
+        output[1][1][0].sequence,
+        "Paris is the <mask> of France. It is located in Europe."
+    );
+    for candidate in &output[0][0] {
+        assert_eq!(
+            candidate.sequence,
+            input[0].replace("<mask>", candidate.text.trim())
+        );
+    }
+
+    assert!(mask_language_model.predict_top_k(input, 0).is_err());
+    Ok(())
+}
+
 #[test]
 fn bert_for_sequence_classification() -> anyhow::Result<()> {
     //    Resources paths
@@ -326,6 +396,77 @@ fn bert_for_token_classification() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn bert_for_token_classification_crf() -> anyhow::Result<()> {
+    //    Resources paths
+    let config_resource = RemoteResource::from_pretrained(BertConfigResources::BERT);
+    let vocab_resource = RemoteResource::from_pretrained(BertVocabResources::BERT);
+    let config_path = config_resource.get_local_path()?;
+    let vocab_path = vocab_resource.get_local_path()?;
+
+    //    Set-up model
+    let device = Device::Cpu;
+    let vs = nn::VarStore::new(device);
+    let tokenizer: BertTokenizer =
+        BertTokenizer::from_file(vocab_path.to_str().unwrap(), true, true)?;
+    let mut config = BertConfig::from_file(config_path);
+    let mut dummy_label_mapping = HashMap::new();
+    dummy_label_mapping.insert(0, String::from("O"));
+    dummy_label_mapping.insert(1, String::from("LOC"));
+    dummy_label_mapping.insert(2, String::from("PER"));
+    dummy_label_mapping.insert(3, String::from("ORG"));
+    config.id2label = Some(dummy_label_mapping);
+    config.use_crf = Some(true);
+    let bert_model = BertForTokenClassification::new(vs.root(), &config)?;
+    let crf = bert_model.crf().unwrap();
+    assert_eq!(crf.num_tags, 4);
+
+    //    Define input
+    let input = [
+        "Looks like one thing is missing",
+        "It\'s like comparing oranges to apples",
+    ];
+    let tokenized_input = tokenizer.encode_list(&input, 128, &TruncationStrategy::LongestFirst, 0);
+    let lengths = tokenized_input
+        .iter()
+        .map(|input| input.token_ids.len())
+        .collect::<Vec<usize>>();
+    let max_len = *lengths.iter().max().unwrap();
+    let (input_ids, attention_masks): (Vec<Tensor>, Vec<Tensor>) = tokenized_input
+        .iter()
+        .map(|input| {
+            let mut token_ids = input.token_ids.clone();
+            token_ids.resize(max_len, 0);
+            let mut attention_mask = vec![1i64; input.token_ids.len()];
+            attention_mask.resize(max_len, 0);
+            (
+                Tensor::from_slice(&token_ids),
+                Tensor::from_slice(&attention_mask),
+            )
+        })
+        .unzip();
+    let input_tensor = Tensor::stack(input_ids.as_slice(), 0).to(device);
+    let mask = Tensor::stack(attention_masks.as_slice(), 0).to(device);
+
+    //    Forward pass
+    let model_output =
+        no_grad(|| bert_model.forward_t(Some(&input_tensor), Some(&mask), None, None, None, false));
+    let (best_paths, best_scores) = crf.viterbi_decode(&model_output.logits, &mask);
+
+    assert_eq!(best_scores.size(), &[2]);
+    for (best_path, length) in best_paths.iter().zip(lengths) {
+        assert_eq!(best_path.len(), length);
+        assert!(best_path.iter().all(|tag| (0..4).contains(tag)));
+    }
+
+    let tags = Tensor::zeros([2, max_len as i64], (tch::Kind::Int64, device));
+    let loss = crf.neg_log_likelihood(&model_output.logits, &tags, &mask);
+    assert!(loss.double_value(&[]).is_finite());
+    assert!(loss.double_value(&[]) > 0.0);
+
+    Ok(())
+}
+
 #[test]
 fn bert_for_question_answering() -> anyhow::Result<()> {
     //    Resources paths
@@ -449,6 +590,35 @@ fn bert_pre_trained_ner_full_entities() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn bert_pre_trained_ner_max_aggregation() -> anyhow::Result<()> {
+    //    Set-up model
+    let ner_model = NERModel::new(TokenClassificationConfig {
+        label_aggregation_function: LabelAggregationOption::Max,
+        ..Default::default()
+    })?;
+
+    //    Define input
+    let input = ["Asked John Smith about Acme Corp", "Let's go to New York!"];
+
+    //    Run model
+    let output = ner_model.predict_full_entities(&input);
+
+    assert_eq!(output.len(), 2);
+
+    // Words split in multiple sub-tokens are assigned the label of their most confident sub-token
+    assert_eq!(output[0][0].word, "John Smith");
+    assert_eq!(output[0][0].label, "PER");
+    assert_eq!(output[0][1].word, "Acme Corp");
+    assert_eq!(output[0][1].label, "ORG");
+    assert_eq!(&input[0][output[0][1].offset.begin as usize..], "Acme Corp");
+
+    assert_eq!(output[1][0].word, "New York");
+    assert_eq!(output[1][0].label, "LOC");
+
+    Ok(())
+}
+
 #[test]
 fn bert_question_answering() -> anyhow::Result<()> {
     //    Set-up question answering model
@@ -486,3 +656,122 @@ fn bert_question_answering() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn bert_cross_encoder_reranking() -> anyhow::Result<()> {
+    //    Set-up model
+    let hub_resource = |file_name| {
+        RemoteResource::from_hub("cross-encoder/ms-marco-MiniLM-L-6-v2", None, file_name)
+    };
+    let config = SequenceClassificationConfig::new(
+        ModelType::Bert,
+        ModelResource::Torch(Box::new(hub_resource("model.safetensors"))),
+        hub_resource("config.json"),
+        hub_resource("vocab.txt"),
+        None,
+        true,
+        None,
+        None,
+    );
+    let cross_encoder = CrossEncoderModel::new(config)?;
+
+    //    Define input
+    let query = "How many people live in Berlin?";
+    let passages = [
+        "New York City is famous for the Metropolitan Museum of Art.",
+        "Berlin has a population of 3,520,031 registered inhabitants in an area of 891.82 square kilometers.",
+        "Berlin is well known for its museums.",
+    ];
+
+    //    Run model
+    let scores = cross_encoder.score(&[(query, passages[0]), (query, passages[1])])?;
+    let ranking = cross_encoder.rerank(query, &passages)?;
+
+    assert_eq!(scores.len(), 2);
+    assert!(scores.iter().all(|score| (0.0..=1.0).contains(score)));
+    assert!(scores[1] > 0.9);
+    assert!(scores[0] < 0.1);
+    assert_eq!(
+        ranking
+            .iter()
+            .map(|(index, _)| *index)
+            .collect::<Vec<usize>>(),
+        [1, 2, 0]
+    );
+    assert!(ranking.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+    assert!((ranking[0].1 - scores[1]).abs() < 1e-4);
+    assert!(cross_encoder.score(&[])?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn bert_lora_adapters() -> anyhow::Result<()> {
+    let device = Device::Cpu;
+    let lora_config = LoraConfig {
+        rank: 4,
+        alpha: 8.0,
+    };
+    let config = BertConfig {
+        hidden_size: 64,
+        intermediate_size: 128,
+        num_attention_heads: 4,
+        num_hidden_layers: 2,
+        vocab_size: 100,
+        max_position_embeddings: 16,
+        ..Default::default()
+    };
+    let input_tensor = Tensor::randint(100, [2, 12], (Kind::Int64, device));
+
+    //    Set-up a model with adapters on the query and value projections
+    let vs = nn::VarStore::new(device);
+    let lora_model: BertModel<BertEmbeddings> = BertModel::new(
+        vs.root(),
+        &BertConfig {
+            lora: Some(lora_config),
+            ..config.clone()
+        },
+    );
+    no_grad(|| {
+        for (name, mut variable) in vs.variables() {
+            if name.ends_with("lora_b.weight") {
+                let _ = variable.copy_(&(variable.rand_like() * 0.1));
+            }
+        }
+    });
+    let lora_output = no_grad(|| {
+        lora_model.forward_t(
+            Some(&input_tensor),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            false,
+        )
+    })?;
+
+    //    A model without adapters loaded with the merged weights gives the same output
+    assert_eq!(merge_lora_weights(&vs, &lora_config)?, 4);
+    let mut merged_vs = nn::VarStore::new(device);
+    let merged_model: BertModel<BertEmbeddings> = BertModel::new(merged_vs.root(), &config);
+    merged_vs.copy(&vs)?;
+    let merged_output = no_grad(|| {
+        merged_model.forward_t(
+            Some(&input_tensor),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            false,
+        )
+    })?;
+    assert!(lora_output
+        .hidden_state
+        .allclose(&merged_output.hidden_state, 1e-4, 1e-5, false));
+
+    Ok(())
+}
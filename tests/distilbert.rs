@@ -1,16 +1,18 @@
 use rust_bert::distilbert::{
-    DistilBertConfig, DistilBertConfigResources, DistilBertForQuestionAnswering,
-    DistilBertForTokenClassification, DistilBertModelMaskedLM, DistilBertModelResources,
+    DistilBertActivation, DistilBertConfig, DistilBertConfigResources,
+    DistilBertForQuestionAnswering, DistilBertForTokenClassification, DistilBertModel,
+    DistilBertModelClassifier, DistilBertModelMaskedLM, DistilBertModelResources,
     DistilBertVocabResources,
 };
+use rust_bert::lora::{merge_lora_weights, LoraConfig};
 use rust_bert::pipelines::question_answering::{QaInput, QuestionAnsweringModel};
 use rust_bert::pipelines::sentiment::{SentimentModel, SentimentPolarity};
 use rust_bert::resources::{RemoteResource, ResourceProvider};
-use rust_bert::Config;
+use rust_bert::{Config, NormType, PositionEncoding, RustBertError};
 use rust_tokenizers::tokenizer::{BertTokenizer, MultiThreadedTokenizer, TruncationStrategy};
 use rust_tokenizers::vocab::Vocab;
 use std::collections::HashMap;
-use tch::{nn, no_grad, Device, Tensor};
+use tch::{nn, no_grad, Device, Kind, Tensor};
 
 extern crate anyhow;
 
@@ -39,6 +41,32 @@ fn distilbert_sentiment_classifier() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn distilbert_sentiment_classifier_all_scores() -> anyhow::Result<()> {
+    //    Set-up classifier
+    let sentiment_classifier = SentimentModel::new(Default::default())?;
+
+    //    Get sentiments
+    let input = [
+        "Probably my all-time favorite movie, a story of selflessness, sacrifice and dedication to a noble cause, but it's not preachy or boring.",
+        "This film tried to be too many things all at once: stinging political satire, Hollywood blockbuster, sappy romantic comedy, family values promo...",
+    ];
+
+    let output = sentiment_classifier.predict_all_scores(input);
+
+    assert_eq!(output.len(), 2usize);
+    for sentiments in &output {
+        assert_eq!(sentiments.len(), 2usize);
+        assert_eq!(sentiments[0].polarity, SentimentPolarity::Negative);
+        assert_eq!(sentiments[1].polarity, SentimentPolarity::Positive);
+        assert!((sentiments[0].score + sentiments[1].score - 1.0).abs() < 1e-6);
+    }
+    assert!((output[0][1].score - 0.9981).abs() < 1e-4);
+    assert!((output[1][0].score - 0.9927).abs() < 1e-4);
+
+    Ok(())
+}
+
 #[test]
 fn distilbert_masked_lm() -> anyhow::Result<()> {
     //    Resources paths
@@ -255,6 +283,477 @@ fn distilbert_for_token_classification() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn distilbert_relative_position_encodings() -> anyhow::Result<()> {
+    let position_encodings = [
+        PositionEncoding::Rotary {
+            base: 10000.0,
+            scaling: None,
+        },
+        PositionEncoding::ALiBi,
+    ];
+    for position_encoding in position_encodings {
+        //    Set-up a randomly initialized model without absolute position embeddings
+        let device = Device::Cpu;
+        let vs = nn::VarStore::new(device);
+        let config = DistilBertConfig {
+            dim: 64,
+            hidden_dim: 128,
+            n_heads: 4,
+            n_layers: 2,
+            vocab_size: 100,
+            max_position_embeddings: 16,
+            position_encoding: Some(position_encoding),
+            ..Default::default()
+        };
+        let distil_bert_model = DistilBertModel::new(vs.root(), &config);
+        assert!(!vs
+            .variables()
+            .keys()
+            .any(|name| name.contains("position_embeddings")));
+
+        //    Forward pass, including a sequence longer than the maximum number of positions
+        for sequence_length in [12, 24] {
+            let input_tensor = Tensor::randint(100, [2, sequence_length], (Kind::Int64, device));
+            let model_output = no_grad(|| {
+                distil_bert_model
+                    .forward_t(Some(&input_tensor), None, None, false)
+                    .unwrap()
+            });
+
+            assert_eq!(model_output.hidden_state.size(), &[2, sequence_length, 64]);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn distilbert_grouped_query_attention() -> anyhow::Result<()> {
+    let device = Device::Cpu;
+    let config = DistilBertConfig {
+        dim: 64,
+        hidden_dim: 128,
+        n_heads: 4,
+        n_layers: 2,
+        vocab_size: 100,
+        max_position_embeddings: 16,
+        ..Default::default()
+    };
+    let input_tensor = Tensor::randint(100, [2, 12], (Kind::Int64, device));
+
+    //    Setting as many key/value heads as query heads is equivalent to multi-head attention
+    let vs = nn::VarStore::new(device);
+    let reference_model = DistilBertModel::new(vs.root(), &config);
+    let mut gqa_vs = nn::VarStore::new(device);
+    let gqa_model = DistilBertModel::new(
+        gqa_vs.root(),
+        &DistilBertConfig {
+            num_key_value_heads: Some(4),
+            ..config.clone()
+        },
+    );
+    gqa_vs.copy(&vs)?;
+    let (reference_output, gqa_output) = no_grad(|| {
+        (
+            reference_model
+                .forward_t(Some(&input_tensor), None, None, false)
+                .unwrap(),
+            gqa_model
+                .forward_t(Some(&input_tensor), None, None, false)
+                .unwrap(),
+        )
+    });
+    assert!(reference_output
+        .hidden_state
+        .equal(&gqa_output.hidden_state));
+
+    //    Grouped query (2 key/value heads) and multi-query (1 key/value head) attention
+    for num_key_value_heads in [2, 1] {
+        let vs = nn::VarStore::new(device);
+        let model = DistilBertModel::new(
+            vs.root(),
+            &DistilBertConfig {
+                num_key_value_heads: Some(num_key_value_heads),
+                ..config.clone()
+            },
+        );
+        let variables = vs.variables();
+        let key_weights = variables
+            .iter()
+            .find(|(name, _)| name.ends_with("attention.k_lin.weight"))
+            .unwrap()
+            .1;
+        assert_eq!(key_weights.size(), &[num_key_value_heads * 16, 64]);
+
+        let model_output = no_grad(|| {
+            model
+                .forward_t(Some(&input_tensor), None, None, false)
+                .unwrap()
+        });
+        assert_eq!(model_output.hidden_state.size(), &[2, 12, 64]);
+    }
+
+    //    Numbers of key/value heads not dividing the number of attention heads are rejected
+    assert!(config.validate().is_ok());
+    for num_key_value_heads in [0, 3] {
+        let invalid_config = DistilBertConfig {
+            num_key_value_heads: Some(num_key_value_heads),
+            ..config.clone()
+        };
+        assert!(matches!(
+            invalid_config.validate(),
+            Err(RustBertError::InvalidConfigurationError(_))
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn distilbert_rms_norm() -> anyhow::Result<()> {
+    let device = Device::Cpu;
+    let vs = nn::VarStore::new(device);
+    let config = DistilBertConfig {
+        dim: 64,
+        hidden_dim: 128,
+        n_heads: 4,
+        n_layers: 2,
+        vocab_size: 100,
+        max_position_embeddings: 16,
+        norm_type: Some(NormType::RMSNorm),
+        ..Default::default()
+    };
+    let distil_bert_model = DistilBertModel::new(vs.root(), &config);
+
+    //    RMSNorm layers only have a scale, without bias
+    let variables = vs.variables();
+    assert!(variables.contains_key("distilbert.transformer.layer.0.sa_layer_norm.weight"));
+    assert!(!variables.contains_key("distilbert.transformer.layer.0.sa_layer_norm.bias"));
+
+    let input_tensor = Tensor::randint(100, [2, 12], (Kind::Int64, device));
+    let model_output = no_grad(|| {
+        distil_bert_model
+            .forward_t(Some(&input_tensor), None, None, false)
+            .unwrap()
+    });
+    assert_eq!(model_output.hidden_state.size(), &[2, 12, 64]);
+
+    //    RMSNorm implies Pre-Norm blocks: the output of the last block is normalized
+    assert!(variables.contains_key("distilbert.transformer.final_layer_norm.weight"));
+    let mean_square =
+        model_output
+            .hidden_state
+            .square()
+            .mean_dim([-1].as_slice(), false, Kind::Float);
+    assert!((mean_square - 1.0).abs().max().double_value(&[]) < 1e-3);
+
+    Ok(())
+}
+
+#[test]
+fn distilbert_norm_first() -> anyhow::Result<()> {
+    let device = Device::Cpu;
+    let config = DistilBertConfig {
+        dim: 64,
+        hidden_dim: 128,
+        n_heads: 4,
+        n_layers: 2,
+        vocab_size: 100,
+        max_position_embeddings: 16,
+        ..Default::default()
+    };
+    let input_tensor = Tensor::randint(100, [2, 12], (Kind::Int64, device));
+
+    //    Same weights loaded with the Post-Norm (default) and Pre-Norm conventions
+    let post_norm_vs = nn::VarStore::new(device);
+    let post_norm_model = DistilBertModel::new(post_norm_vs.root(), &config);
+    let mut pre_norm_vs = nn::VarStore::new(device);
+    let pre_norm_model = DistilBertModel::new(
+        pre_norm_vs.root(),
+        &DistilBertConfig {
+            norm_first: Some(true),
+            ..config.clone()
+        },
+    );
+    post_norm_vs.copy(&pre_norm_vs)?;
+    //    Pre-Norm models have an additional final layer normalization (weight and bias)
+    assert_eq!(post_norm_vs.len() + 2, pre_norm_vs.len());
+    assert!(pre_norm_vs
+        .variables()
+        .contains_key("distilbert.transformer.final_layer_norm.weight"));
+
+    let (post_norm_output, pre_norm_output) = no_grad(|| {
+        (
+            post_norm_model
+                .forward_t(Some(&input_tensor), None, None, false)
+                .unwrap(),
+            pre_norm_model
+                .forward_t(Some(&input_tensor), None, None, false)
+                .unwrap(),
+        )
+    });
+
+    assert_eq!(
+        post_norm_output.hidden_state.size(),
+        pre_norm_output.hidden_state.size()
+    );
+    assert!(!post_norm_output.hidden_state.allclose(
+        &pre_norm_output.hidden_state,
+        1e-3,
+        1e-3,
+        false
+    ));
+
+    //    The output of the last Pre-Norm block is normalized
+    let hidden_state = &pre_norm_output.hidden_state;
+    let mean = hidden_state.mean_dim([-1].as_slice(), false, Kind::Float);
+    let variance = hidden_state.var_dim([-1].as_slice(), false, false);
+    assert!(mean.abs().max().double_value(&[]) < 1e-4);
+    assert!((variance - 1.0).abs().max().double_value(&[]) < 1e-3);
+
+    Ok(())
+}
+
+#[test]
+fn distilbert_gated_activations() -> anyhow::Result<()> {
+    let device = Device::Cpu;
+    let input_tensor = Tensor::randint(100, [2, 12], (Kind::Int64, device));
+    for (activation, expected_hidden_dim, gated) in [
+        (DistilBertActivation::swiglu, 85, true),
+        (DistilBertActivation::geglu, 85, true),
+        (DistilBertActivation::gelu, 128, false),
+        (DistilBertActivation::relu, 128, false),
+    ] {
+        let vs = nn::VarStore::new(device);
+        let config = DistilBertConfig {
+            activation,
+            dim: 64,
+            hidden_dim: 128,
+            n_heads: 4,
+            n_layers: 2,
+            vocab_size: 100,
+            max_position_embeddings: 16,
+            ..Default::default()
+        };
+        let distil_bert_model = DistilBertModel::new(vs.root(), &config);
+
+        //    Gated activations add a gate projection and use 2/3 of the hidden dimension
+        let variables = vs.variables();
+        let ffn_prefix = "distilbert.transformer.layer.0.ffn";
+        assert_eq!(
+            variables[&format!("{ffn_prefix}.lin1.weight")].size(),
+            &[expected_hidden_dim, 64]
+        );
+        assert_eq!(
+            variables[&format!("{ffn_prefix}.lin2.weight")].size(),
+            &[64, expected_hidden_dim]
+        );
+        assert_eq!(
+            variables.contains_key(&format!("{ffn_prefix}.lin_gate.weight")),
+            gated
+        );
+
+        let model_output = no_grad(|| {
+            distil_bert_model
+                .forward_t(Some(&input_tensor), None, None, false)
+                .unwrap()
+        });
+        assert_eq!(model_output.hidden_state.size(), &[2, 12, 64]);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn distilbert_mixture_of_experts() -> anyhow::Result<()> {
+    let device = Device::Cpu;
+    let input_tensor = Tensor::randint(100, [2, 12], (Kind::Int64, device));
+    let config = DistilBertConfig {
+        dim: 64,
+        hidden_dim: 128,
+        n_heads: 4,
+        n_layers: 2,
+        vocab_size: 100,
+        max_position_embeddings: 16,
+        use_moe: Some(true),
+        num_experts: Some(4),
+        num_experts_per_tok: Some(2),
+        ..Default::default()
+    };
+    let vs = nn::VarStore::new(device);
+    let distil_bert_model = DistilBertModel::new(vs.root(), &config);
+
+    //    The feed-forward layers are replaced by a router and 4 experts
+    let variables = vs.variables();
+    let ffn_prefix = "distilbert.transformer.layer.0.ffn";
+    assert_eq!(
+        variables[&format!("{ffn_prefix}.router.weight")].size(),
+        &[4, 64]
+    );
+    for expert_index in 0..4 {
+        assert_eq!(
+            variables[&format!("{ffn_prefix}.experts.{expert_index}.lin1.weight")].size(),
+            &[128, 64]
+        );
+    }
+    assert!(!variables.contains_key(&format!("{ffn_prefix}.lin1.weight")));
+
+    let model_output = distil_bert_model.forward_t(Some(&input_tensor), None, None, true)?;
+    assert_eq!(model_output.hidden_state.size(), &[2, 12, 64]);
+    let router_aux_loss = model_output.router_aux_loss.unwrap();
+    assert!(router_aux_loss.double_value(&[]).is_finite());
+    // The auxiliary loss can be back-propagated to the routers
+    router_aux_loss.backward();
+    assert!(
+        variables[&format!("{ffn_prefix}.router.weight")]
+            .grad()
+            .abs()
+            .sum(Kind::Float)
+            .double_value(&[])
+            > 0.0
+    );
+
+    //    The task-specific heads return the auxiliary loss of their base model
+    let config = DistilBertConfig {
+        id2label: Some(HashMap::from([
+            (0, "NEGATIVE".to_string()),
+            (1, "POSITIVE".to_string()),
+        ])),
+        ..config
+    };
+    let vs = nn::VarStore::new(device);
+    let classifier = DistilBertModelClassifier::new(vs.root(), &config)?;
+    let masked_lm = DistilBertModelMaskedLM::new(vs.root() / "masked_lm", &config);
+    let question_answering = DistilBertForQuestionAnswering::new(vs.root() / "qa", &config);
+    let token_classifier = DistilBertForTokenClassification::new(vs.root() / "ner", &config)?;
+    no_grad(|| -> anyhow::Result<()> {
+        let router_aux_losses = [
+            classifier
+                .forward_t(Some(&input_tensor), None, None, false)?
+                .router_aux_loss,
+            masked_lm
+                .forward_t(Some(&input_tensor), None, None, false)?
+                .router_aux_loss,
+            question_answering
+                .forward_t(Some(&input_tensor), None, None, false)?
+                .router_aux_loss,
+            token_classifier
+                .forward_t(Some(&input_tensor), None, None, false)?
+                .router_aux_loss,
+        ];
+        for router_aux_loss in router_aux_losses {
+            assert!(router_aux_loss.unwrap().double_value(&[]).is_finite());
+        }
+        Ok(())
+    })?;
+
+    //    Invalid numbers of experts per token are rejected when validating the configuration
+    assert!(config.validate().is_ok());
+    for num_experts_per_tok in [0, 5] {
+        let invalid_config = DistilBertConfig {
+            num_experts_per_tok: Some(num_experts_per_tok),
+            ..config.clone()
+        };
+        assert!(matches!(
+            invalid_config.validate(),
+            Err(RustBertError::InvalidConfigurationError(_))
+        ));
+    }
+
+    //    Dense feed-forward layers do not return an auxiliary loss
+    let dense_config = DistilBertConfig {
+        use_moe: None,
+        ..config
+    };
+    let vs = nn::VarStore::new(device);
+    let dense_model = DistilBertModel::new(vs.root(), &dense_config);
+    let model_output = no_grad(|| dense_model.forward_t(Some(&input_tensor), None, None, false))?;
+    assert!(model_output.router_aux_loss.is_none());
+
+    Ok(())
+}
+
+#[test]
+fn distilbert_lora_adapters() -> anyhow::Result<()> {
+    let device = Device::Cpu;
+    let lora_config = LoraConfig {
+        rank: 4,
+        alpha: 8.0,
+    };
+    let config = DistilBertConfig {
+        dim: 64,
+        hidden_dim: 128,
+        n_heads: 4,
+        n_layers: 2,
+        vocab_size: 100,
+        max_position_embeddings: 16,
+        ..Default::default()
+    };
+    let input_tensor = Tensor::randint(100, [2, 12], (Kind::Int64, device));
+
+    //    Set-up a model with adapters on the query and value projections
+    let vs = nn::VarStore::new(device);
+    let lora_model = DistilBertModel::new(
+        vs.root(),
+        &DistilBertConfig {
+            lora: Some(lora_config),
+            ..config.clone()
+        },
+    );
+    let mut adapters = vs
+        .variables()
+        .into_iter()
+        .filter(|(name, _)| name.contains("lora_"))
+        .collect::<Vec<(String, Tensor)>>();
+    adapters.sort_by(|(name_1, _), (name_2, _)| name_1.cmp(name_2));
+    assert_eq!(adapters.len(), 8);
+    assert!(adapters
+        .iter()
+        .all(|(name, _)| name.contains(".q_lin.") | name.contains(".v_lin.")));
+    //    The base weights and biases of the adapted projections are frozen
+    let frozen_variables = vs
+        .variables()
+        .into_iter()
+        .filter(|(_, variable)| !variable.requires_grad())
+        .map(|(name, _)| name)
+        .collect::<Vec<String>>();
+    assert_eq!(frozen_variables.len(), 8);
+    assert!(frozen_variables
+        .iter()
+        .all(|name| name.contains(".q_lin.") | name.contains(".v_lin.")));
+
+    //    Simulate a fine-tuning of the adapters
+    no_grad(|| {
+        for (name, mut adapter) in adapters {
+            if name.ends_with("lora_b.weight") {
+                let _ = adapter.copy_(&(adapter.rand_like() * 0.1));
+            }
+        }
+    });
+    let lora_output = no_grad(|| {
+        lora_model
+            .forward_t(Some(&input_tensor), None, None, false)
+            .unwrap()
+    });
+
+    //    A model without adapters loaded with the merged weights gives the same output
+    assert_eq!(merge_lora_weights(&vs, &lora_config)?, 4);
+    let mut merged_vs = nn::VarStore::new(device);
+    let merged_model = DistilBertModel::new(merged_vs.root(), &config);
+    merged_vs.copy(&vs)?;
+    let merged_output = no_grad(|| {
+        merged_model
+            .forward_t(Some(&input_tensor), None, None, false)
+            .unwrap()
+    });
+    assert!(lora_output
+        .hidden_state
+        .allclose(&merged_output.hidden_state, 1e-4, 1e-5, false));
+
+    Ok(())
+}
+
 #[test]
 fn distilbert_question_answering() -> anyhow::Result<()> {
     //    Set-up question answering model
@@ -276,3 +775,35 @@ fn distilbert_question_answering() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn distilbert_question_answering_long_context() -> anyhow::Result<()> {
+    //    Set-up question answering model
+    let qa_model = QuestionAnsweringModel::new(Default::default())?;
+
+    //    Define input: the context (~800 tokens) is processed with several sliding windows
+    let question = String::from("Where does Amy live ?");
+    let context = format!(
+        "{}Amy lives in Amsterdam. {}",
+        "Bob likes to read books in the evening. ".repeat(60),
+        "The weather was nice that day. ".repeat(40)
+    );
+    let qa_input = QaInput {
+        question,
+        context: context.clone(),
+    };
+
+    let answers = qa_model.predict(&[qa_input], 1, 32);
+
+    assert_eq!(answers.len(), 1usize);
+    assert_eq!(answers[0].len(), 1usize);
+    assert_eq!(answers[0][0].answer, "Amsterdam");
+    // Character offsets refer to the original context
+    assert_eq!(
+        &context[answers[0][0].start..answers[0][0].end],
+        "Amsterdam"
+    );
+    assert!(answers[0][0].score > 0.5);
+
+    Ok(())
+}
@@ -2,18 +2,33 @@ use rust_bert::gpt2::{
     GPT2Generator, GPT2LMHeadModel, Gpt2Config, Gpt2ConfigResources, Gpt2MergesResources,
     Gpt2ModelResources, Gpt2VocabResources,
 };
-use rust_bert::pipelines::common::{ModelResource, ModelType};
+use rust_bert::pipelines::common::{cast_var_store, ModelResource, ModelType};
 use rust_bert::pipelines::conversation::{
     ConversationConfig, ConversationManager, ConversationModel,
 };
+use rust_bert::pipelines::evaluation::compute_perplexity;
 use rust_bert::pipelines::generation_utils::{
-    Cache, GenerateConfig, GenerateOptions, LanguageGenerator,
+    Cache, FinishReason, GenerateConfig, GenerateOptions, LanguageGenerator,
 };
-use rust_bert::pipelines::text_generation::{TextGenerationConfig, TextGenerationModel};
-use rust_bert::resources::{RemoteResource, ResourceProvider};
-use rust_bert::Config;
+use rust_bert::pipelines::logits_processors::{
+    BadWordsLogitsProcessor, ClassifierFreeGuidanceLogitsProcessor, LogitsProcessor,
+    RepetitionPenaltyScope,
+};
+use rust_bert::pipelines::sampling::Mirostat;
+use rust_bert::pipelines::text_generation::{
+    TextGenerationConfig, TextGenerationModel, TextGenerationOption,
+};
+use rust_bert::pipelines::token_healing::TokenHealingIndex;
+use rust_bert::pipelines::watermark::{SeedingScheme, WatermarkDetector, WatermarkLogitsProcessor};
+use rust_bert::resources::{LocalResource, RemoteResource, ResourceProvider};
+use rust_bert::{Config, RustBertError};
 use rust_tokenizers::tokenizer::{Gpt2Tokenizer, Tokenizer, TruncationStrategy};
-use tch::{nn, Device, Tensor};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tch::{nn, Device, Kind, Tensor};
 
 #[test]
 fn gpt2_lm_model() -> anyhow::Result<()> {
@@ -129,6 +144,55 @@ fn gpt2_generation_greedy() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn gpt2_text_generation_option_runtime_model_type() -> anyhow::Result<()> {
+    // Model type selected at runtime (e.g. from a command line argument)
+    let model_name = "gpt2";
+    let model_type = match model_name {
+        "openai-gpt" => ModelType::OpenAiGpt,
+        _ => ModelType::GPT2,
+    };
+    let generate_config = TextGenerationConfig {
+        model_type,
+        model_resource: ModelResource::Torch(Box::new(RemoteResource::from_pretrained(
+            Gpt2ModelResources::GPT2,
+        ))),
+        config_resource: Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2)),
+        vocab_resource: Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2)),
+        merges_resource: Some(Box::new(RemoteResource::from_pretrained(
+            Gpt2MergesResources::GPT2,
+        ))),
+        do_sample: false,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = TextGenerationOption::new(generate_config)?;
+    assert_eq!(model.model_type(), ModelType::GPT2);
+
+    let generate_options = GenerateOptions {
+        max_new_tokens: Some(10),
+        ..Default::default()
+    };
+    let output = model.generate(Some(&["The cat"]), Some(generate_options))?;
+
+    let gpt2_model = GPT2Generator::new(GenerateConfig {
+        do_sample: false,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    })?;
+    let expected_output = gpt2_model.generate(Some(&["The cat"]), Some(generate_options))?;
+    assert_eq!(output.len(), 1);
+    assert_eq!(output[0].text, expected_output[0].text);
+    assert_eq!(
+        output[0].num_generated_tokens,
+        expected_output[0].num_generated_tokens
+    );
+
+    Ok(())
+}
+
 #[test]
 fn gpt2_generation_beam_search() -> anyhow::Result<()> {
     //    Resources definition
@@ -173,6 +237,46 @@ fn gpt2_generation_beam_search() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn gpt2_generation_beam_search_ignores_temperature() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        max_length: Some(20),
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        num_beams: 5,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    // The temperature only applies to sampling: beam search without sampling is unchanged
+    let generate = |temperature| {
+        let generate_options = GenerateOptions {
+            temperature: Some(temperature),
+            output_scores: true,
+            ..Default::default()
+        };
+        model.generate_indices(Some(&["The dog"]), Some(generate_options))
+    };
+    let reference_output = generate(1.0)?;
+    for temperature in [0.5, 2.0] {
+        let output = generate(temperature)?;
+        assert_eq!(output[0].indices, reference_output[0].indices);
+        assert!((output[0].score.unwrap() - reference_output[0].score.unwrap()).abs() < 1e-6);
+    }
+
+    Ok(())
+}
+
 #[test]
 fn gpt2_generation_beam_search_multiple_prompts_without_padding() -> anyhow::Result<()> {
     //    Resources definition
@@ -230,6 +334,44 @@ fn gpt2_generation_beam_search_multiple_prompts_without_padding() -> anyhow::Res
     Ok(())
 }
 
+#[test]
+fn gpt2_generation_sampling_multiple_prompts_multiple_sequences() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = TextGenerationConfig {
+        model_type: ModelType::GPT2,
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        max_length: Some(20),
+        do_sample: true,
+        num_beams: 1,
+        num_return_sequences: 3,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = TextGenerationModel::new(generate_config)?;
+
+    let input_context_1 = "The dog";
+    let input_context_2 = "My favourite food is";
+    let output = model.generate(&[input_context_1, input_context_2], None)?;
+
+    assert_eq!(output.len(), 6);
+    for sequence in &output[..3] {
+        assert!(sequence.starts_with(input_context_1));
+    }
+    for sequence in &output[3..] {
+        assert!(sequence.starts_with(input_context_2));
+    }
+
+    Ok(())
+}
+
 #[test]
 fn gpt2_generation_beam_search_multiple_prompts_with_padding() -> anyhow::Result<()> {
     //    Resources definition
@@ -287,58 +429,283 @@ fn gpt2_generation_beam_search_multiple_prompts_with_padding() -> anyhow::Result
 }
 
 #[test]
-fn gpt2_diverse_beam_search_multiple_prompts_with_padding() -> anyhow::Result<()> {
+fn gpt2_greedy_left_padding_matches_unbatched_generation() -> anyhow::Result<()> {
     //    Resources definition
     let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
     let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
     let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
     let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
 
-    let generate_config = TextGenerationConfig {
-        model_type: ModelType::GPT2,
+    let generate_config = GenerateConfig {
+        max_length: None,
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let short_prompt = "The dog";
+    let long_prompt = "The cat was sitting on the mat when the";
+    let generate_options = GenerateOptions {
+        max_new_tokens: Some(12),
+        ..Default::default()
+    };
+    let batched_output =
+        model.generate_indices(Some(&[short_prompt, long_prompt]), Some(generate_options))?;
+    let short_output = model.generate_indices(Some(&[short_prompt]), Some(generate_options))?;
+    let long_output = model.generate_indices(Some(&[long_prompt]), Some(generate_options))?;
+
+    // The short prompt (2 tokens) is left-padded to the length of the long prompt (9 tokens)
+    assert_eq!(batched_output.len(), 2);
+    assert!(batched_output[0].indices[..7]
+        .iter()
+        .all(|token_id| *token_id == 50256));
+    assert_eq!(batched_output[0].indices[7..], short_output[0].indices[..]);
+    assert_eq!(batched_output[1].indices, long_output[0].indices);
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_beam_and_contrastive_search_left_padding_match_unbatched_generation() -> anyhow::Result<()>
+{
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        max_length: None,
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let short_prompt = "The dog";
+    let long_prompt = "The cat was sitting on the mat when the";
+    let strip_padding = |token_ids: &[i64]| -> Vec<i64> {
+        token_ids
+            .iter()
+            .skip_while(|token_id| **token_id == 50256)
+            .copied()
+            .collect()
+    };
+
+    let beam_search_options = GenerateOptions {
+        max_new_tokens: Some(10),
+        num_beams: Some(3),
+        ..Default::default()
+    };
+    let contrastive_search_options = GenerateOptions {
+        max_new_tokens: Some(10),
+        penalty_alpha: Some(0.6),
+        top_k: Some(4),
+        ..Default::default()
+    };
+    // The attention mask of the left-padded prompt is extended at every decoding step: the padding is never
+    // attended to by the generated tokens
+    for generate_options in [beam_search_options, contrastive_search_options] {
+        let batched_output =
+            model.generate_indices(Some(&[short_prompt, long_prompt]), Some(generate_options))?;
+        let short_output = model.generate_indices(Some(&[short_prompt]), Some(generate_options))?;
+        let long_output = model.generate_indices(Some(&[long_prompt]), Some(generate_options))?;
+
+        assert_eq!(batched_output.len(), 2);
+        assert_eq!(
+            strip_padding(&batched_output[0].indices),
+            short_output[0].indices
+        );
+        assert_eq!(batched_output[1].indices, long_output[0].indices);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_left_padding_scores_match_unpadded_prompt() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        max_length: None,
         model_resource: ModelResource::Torch(model_resource),
         config_resource,
         vocab_resource,
         merges_resource: Some(merges_resource),
+        do_sample: false,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let prompt = "The dog";
+    let generate_options = GenerateOptions {
+        max_new_tokens: Some(8),
+        output_scores: true,
+        ..Default::default()
+    };
+    // The prompt is left-padded to the length of a longer prompt in the batch
+    let padded_output = model.generate_indices(
+        Some(&[prompt, "The cat was sitting on the mat when the"]),
+        Some(generate_options),
+    )?;
+    let unpadded_output = model.generate_indices(Some(&[prompt]), Some(generate_options))?;
+
+    // The log-probabilities of the first token (computed from the full prompt) and of the following tokens
+    // (computed from the cache) match: the position ids of the padded prompt start at 0
+    let padded_scores = padded_output[0].token_scores.as_ref().unwrap();
+    let unpadded_scores = unpadded_output[0].token_scores.as_ref().unwrap();
+    assert_eq!(padded_scores.len(), unpadded_scores.len());
+    for (padded_score, unpadded_score) in padded_scores.iter().zip(unpadded_scores) {
+        assert!((padded_score - unpadded_score).abs() < 1e-3);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_diverse_beam_search_multiple_prompts_with_padding() -> anyhow::Result<()> {
+    let generate_config = |num_return_sequences| TextGenerationConfig {
+        model_type: ModelType::GPT2,
+        model_resource: ModelResource::Torch(Box::new(RemoteResource::from_pretrained(
+            Gpt2ModelResources::GPT2,
+        ))),
+        config_resource: Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2)),
+        vocab_resource: Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2)),
+        merges_resource: Some(Box::new(RemoteResource::from_pretrained(
+            Gpt2MergesResources::GPT2,
+        ))),
         min_length: 10,
         max_length: Some(20),
         do_sample: false,
         num_beams: 6,
         num_beam_groups: Some(3),
         diversity_penalty: Some(5.5),
-        num_return_sequences: 3,
+        num_return_sequences,
         ..Default::default()
     };
-    let model = TextGenerationModel::new(generate_config)?;
+    let model = TextGenerationModel::new(generate_config(2))?;
 
     let input_context_1 = "It was a nice and";
     let input_context_2 = "Language models can generate";
     let output = model.generate(&[input_context_1, input_context_2], None)?;
 
-    assert_eq!(output.len(), 6);
+    // The returned sequences are interleaved across groups: the best sequences of the first two groups are returned
+    // (reference sequences of the transformers group beam search for the same prompts and settings)
+    assert_eq!(output.len(), 4);
     assert_eq!(
         output[0],
         "It was a nice and peaceful evening for me,\" he said.\n\n\"It was a good"
     );
     assert_eq!(
         output[1],
-        "It was a nice and peaceful evening for me,\" he said.\n\n\"It was a nice"
+        "It was a nice and warm day, and I\'m glad I did.\n\n\"I'm"
     );
     assert_eq!(
         output[2],
-        "It was a nice and warm day, and I\'m glad I did.\n\n\"I'm"
+        "Language models can generate more complex models, but they are not the only way to do so."
     );
     assert_eq!(
         output[3],
-        "Language models can generate more complex models, but they are not the only way to do so."
+        "Language models can generate a lot of data, but they're not the only way to do it"
     );
+
+    // Returning all 6 beams starts with the best sequence of every group, followed by the second best sequence of
+    // every group. The second best sequences of the first group are the reference sequences ranked second overall.
+    let best_sequences = output;
+    let model = TextGenerationModel::new(generate_config(6))?;
+    let output = model.generate(&[input_context_1, input_context_2], None)?;
+    assert_eq!(output.len(), 12);
+    assert_eq!(output[..2], best_sequences[..2]);
+    assert_eq!(output[6..8], best_sequences[2..]);
     assert_eq!(
-        output[4],
-        "Language models can generate more complex models, but they are not the only way to do this."
+        output[3],
+        "It was a nice and peaceful evening for me,\" he said.\n\n\"It was a nice"
     );
     assert_eq!(
-        output[5],
-        "Language models can generate a lot of data, but they're not the only way to do it"
+        output[9],
+        "Language models can generate more complex models, but they are not the only way to do this."
+    );
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_diverse_beam_search_reduces_ngram_overlap() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        max_length: Some(24),
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        num_beams: 4,
+        num_return_sequences: 4,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    // Average number of bigrams shared by pairs of generated sequences, relative to the number of bigrams
+    fn bigram_overlap(sequences: &[Vec<i64>]) -> f64 {
+        let bigrams = sequences
+            .iter()
+            .map(|sequence| sequence.windows(2).collect::<HashSet<&[i64]>>())
+            .collect::<Vec<_>>();
+        let mut overlap = 0f64;
+        let mut num_pairs = 0f64;
+        for (index, first) in bigrams.iter().enumerate() {
+            for second in &bigrams[index + 1..] {
+                overlap += first.intersection(second).count() as f64
+                    / first.len().max(second.len()).max(1) as f64;
+                num_pairs += 1.0;
+            }
+        }
+        overlap / num_pairs
+    }
+
+    let input_context = "The best way to learn a language is";
+    let prompt_length = 8;
+    let beam_search_sequences = model
+        .generate_indices(Some(&[input_context]), None)?
+        .into_iter()
+        .map(|output| output.indices[prompt_length..].to_vec())
+        .collect::<Vec<Vec<i64>>>();
+
+    let diverse_options = GenerateOptions {
+        num_beam_groups: Some(4),
+        diversity_penalty: Some(10.0),
+        ..Default::default()
+    };
+    let diverse_beam_search_sequences = model
+        .generate_indices(Some(&[input_context]), Some(diverse_options))?
+        .into_iter()
+        .map(|output| output.indices[prompt_length..].to_vec())
+        .collect::<Vec<Vec<i64>>>();
+
+    assert_eq!(beam_search_sequences.len(), 4);
+    assert_eq!(diverse_beam_search_sequences.len(), 4);
+    assert!(
+        bigram_overlap(&diverse_beam_search_sequences) < bigram_overlap(&beam_search_sequences)
     );
 
     Ok(())
@@ -476,6 +843,45 @@ fn gpt2_bad_tokens_greedy() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn gpt2_bad_words_from_strings() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        max_length: Some(36),
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    // Single-token bad words only
+    let bad_word_ids = model.get_bad_word_ids(&["John", "writer"]);
+    assert!(bad_word_ids.iter().all(|bad_word| bad_word.len() == 1));
+
+    let input_context_1 = "Hello, my name is";
+    let generate_options = GenerateOptions {
+        bad_word_ids: Some(&bad_word_ids),
+        ..Default::default()
+    };
+    let output = model.generate(Some(&[input_context_1]), Some(generate_options))?;
+
+    assert_eq!(output.len(), 1);
+    assert!(!output[0].text.contains("John"));
+    assert!(!output[0].text.contains("writer"));
+
+    Ok(())
+}
+
 #[test]
 fn gpt2_bad_tokens_beam_search() -> anyhow::Result<()> {
     //    Resources definition
@@ -609,7 +1015,7 @@ fn gpt2_prefix_allowed_token_beam_search() -> anyhow::Result<()> {
 }
 
 #[test]
-fn gpt2_greedy_token_scores() -> anyhow::Result<()> {
+fn gpt2_prefix_allowed_token_digits() -> anyhow::Result<()> {
     //    Resources definition
     let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
     let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
@@ -617,22 +1023,486 @@ fn gpt2_greedy_token_scores() -> anyhow::Result<()> {
     let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
 
     let generate_config = GenerateConfig {
-        max_length: Some(16),
+        max_length: Some(24),
         model_resource: ModelResource::Torch(model_resource),
         config_resource,
         vocab_resource,
         merges_resource: Some(merges_resource),
         do_sample: false,
-        num_beams: 1,
         device: Device::Cpu,
         ..Default::default()
     };
     let model = GPT2Generator::new(generate_config)?;
 
-    let input_context_1 = "Hello, my name is";
-    let input_context_2 = "It is a beautiful";
+    let tokenizer = model.get_tokenizer();
+    let digit_tokens = (0..50257)
+        .filter(|token_id| {
+            let token = tokenizer.decode(&[*token_id], false, false);
+            let token = token.trim_start();
+            !token.is_empty() && token.chars().all(|c| c.is_ascii_digit())
+        })
+        .collect::<Vec<i64>>();
+    let only_digits = |_batch_id: i64, _previous_token_ids: &Tensor| digit_tokens.clone();
+    let no_constraint = |_batch_id: i64, _previous_token_ids: &Tensor| Vec::<i64>::new();
+
+    let input_context = "My phone number is";
+    for num_beams in [1, 3] {
+        let generate_options = GenerateOptions {
+            num_beams: Some(num_beams),
+            prefix_allowed_tokens_fn: Some(&only_digits),
+            ..Default::default()
+        };
+        let output = model.generate(Some(&[input_context]), Some(generate_options))?;
+
+        assert_eq!(output.len(), 1);
+        let generated_text = output[0].text.strip_prefix(input_context).unwrap();
+        assert!(!generated_text.trim().is_empty());
+        assert!(generated_text
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == ' '));
+
+        // An empty list of allowed tokens does not constrain the generation
+        let unconstrained_output = model.generate(
+            Some(&[input_context]),
+            Some(GenerateOptions {
+                num_beams: Some(num_beams),
+                ..Default::default()
+            }),
+        )?;
+        let empty_constraint_output = model.generate(
+            Some(&[input_context]),
+            Some(GenerateOptions {
+                num_beams: Some(num_beams),
+                prefix_allowed_tokens_fn: Some(&no_constraint),
+                ..Default::default()
+            }),
+        )?;
+        assert_eq!(
+            empty_constraint_output[0].text,
+            unconstrained_output[0].text
+        );
+    }
 
-    let generate_options = GenerateOptions {
+    Ok(())
+}
+
+/// Bans all token ids greater than or equal to a threshold
+struct MaxTokenIdProcessor {
+    max_token_id: i64,
+}
+
+impl LogitsProcessor for MaxTokenIdProcessor {
+    fn process(&self, _input_ids: &Tensor, logits: &mut Tensor) {
+        let vocab_size = logits.size()[1];
+        let _ = logits
+            .narrow(1, self.max_token_id, vocab_size - self.max_token_id)
+            .fill_(f64::NEG_INFINITY);
+    }
+}
+
+#[test]
+fn gpt2_custom_logits_processor() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        max_length: Some(20),
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let input_context = "The dog";
+    let logits_processors: Vec<Box<dyn LogitsProcessor>> =
+        vec![Box::new(MaxTokenIdProcessor { max_token_id: 1000 })];
+    for num_beams in [1, 3] {
+        let generate_options = GenerateOptions {
+            num_beams: Some(num_beams),
+            logits_processors: Some(&logits_processors),
+            ..Default::default()
+        };
+        let output = model.generate_indices(Some(&[input_context]), Some(generate_options))?;
+
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].indices.len(), 20);
+        // The prompt is made of 2 tokens
+        assert!(output[0].indices[2..]
+            .iter()
+            .all(|token_id| *token_id < 1000));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_max_new_tokens_long_prompt() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        max_length: Some(20),
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    // 900 tokens prompt
+    let long_prompt = format!("the{}", " the".repeat(899));
+    let output = model.generate_indices(
+        Some(&[long_prompt.as_str()]),
+        Some(GenerateOptions {
+            max_new_tokens: Some(50),
+            ..Default::default()
+        }),
+    )?;
+    assert!(output[0].indices.len() > 900);
+    assert!(output[0].indices.len() <= 950);
+
+    // The generation length is bounded by the context size (1024 tokens)
+    let output = model.generate_indices(
+        Some(&[long_prompt.as_str()]),
+        Some(GenerateOptions {
+            max_new_tokens: Some(200),
+            ..Default::default()
+        }),
+    )?;
+    assert!(output[0].indices.len() <= 1024);
+
+    // Prompts longer than the maximum length are not truncated unless requested
+    assert!(model
+        .generate_indices(Some(&[long_prompt.as_str()]), None)
+        .is_err());
+    let output = model.generate_indices(
+        Some(&[long_prompt.as_str()]),
+        Some(GenerateOptions {
+            truncate_prompt: true,
+            ..Default::default()
+        }),
+    )?;
+    assert_eq!(output[0].indices.len(), 20);
+
+    // Prompts longer than the context size are rejected
+    let too_long_prompt = format!("the{}", " the".repeat(1099));
+    assert!(model
+        .generate_indices(
+            Some(&[too_long_prompt.as_str()]),
+            Some(GenerateOptions {
+                max_new_tokens: Some(50),
+                ..Default::default()
+            }),
+        )
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_text_generation_truncate_prompt() -> anyhow::Result<()> {
+    let generate_config = |truncate_prompt| TextGenerationConfig {
+        model_type: ModelType::GPT2,
+        model_resource: ModelResource::Torch(Box::new(RemoteResource::from_pretrained(
+            Gpt2ModelResources::GPT2,
+        ))),
+        config_resource: Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2)),
+        vocab_resource: Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2)),
+        merges_resource: Some(Box::new(RemoteResource::from_pretrained(
+            Gpt2MergesResources::GPT2,
+        ))),
+        max_length: Some(20),
+        do_sample: false,
+        num_beams: 1,
+        truncate_prompt,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+
+    // 900 tokens prompt
+    let long_prompt = format!("the{}", " the".repeat(899));
+
+    // Prompts longer than the maximum length are not truncated unless requested
+    let model = TextGenerationModel::new(generate_config(false))?;
+    assert!(matches!(
+        model.generate(&[long_prompt.as_str()], None),
+        Err(RustBertError::ValueError(_))
+    ));
+
+    let model = TextGenerationModel::new(generate_config(true))?;
+    let output = model.generate(&[long_prompt.as_str()], None)?;
+    assert_eq!(output.len(), 1);
+    // The prompt is truncated to fit in the maximum length of 20 tokens
+    assert!(output[0].len() < long_prompt.len());
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_with_cache() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        max_length: None,
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+    let generate_options = GenerateOptions {
+        max_new_tokens: Some(8),
+        ..Default::default()
+    };
+
+    // Generation from a pre-computed prompt prefix
+    let (prefix_ids, prefix_past, next_token_logits) = model.forward_prompt("The dog")?;
+    assert_eq!(prefix_ids.size(), vec![1, 2]);
+    assert_eq!(prefix_past.len(), 12);
+    assert_eq!(next_token_logits.size(), vec![1, 50257]);
+
+    let tokenizer = model.get_tokenizer();
+    let continuation_ids = tokenizer.convert_tokens_to_ids(&tokenizer.tokenize(" was sitting on"));
+    let input_ids = Tensor::cat(
+        &[
+            &prefix_ids,
+            &Tensor::from_slice(&continuation_ids).unsqueeze(0),
+        ],
+        1,
+    );
+    let (cached_output, past) = model.generate_with_cache(
+        input_ids,
+        None,
+        Cache::GPT2Cache(Some(prefix_past)),
+        Some(generate_options),
+    )?;
+    let output =
+        model.generate_indices(Some(&["The dog was sitting on"]), Some(generate_options))?;
+    assert_eq!(cached_output[0].indices, output[0].indices);
+
+    // Continuation of the generation with the returned cache
+    let output_ids = Tensor::from_slice(&output[0].indices).unsqueeze(0);
+    let (cached_output, _) =
+        model.generate_with_cache(output_ids.copy(), None, past, Some(generate_options))?;
+    let output = model.generate_from_ids_and_past(output_ids, None, Some(generate_options))?;
+    assert_eq!(cached_output[0].indices, output[0].indices);
+
+    // Caches not matching the model configuration are rejected
+    let invalid_past = Cache::GPT2Cache(Some(
+        (0..12)
+            .map(|_| Tensor::zeros([2, 1, 6, 2, 64], (Kind::Float, Device::Cpu)))
+            .collect(),
+    ));
+    assert!(model
+        .generate_with_cache(prefix_ids, None, invalid_past, Some(generate_options))
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_long_generation_without_gradients() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        max_length: None,
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+    let generate_options = GenerateOptions {
+        min_length: Some(202),
+        max_new_tokens: Some(200),
+        ..Default::default()
+    };
+
+    let (prompt_ids, prompt_past, next_token_logits) = model.forward_prompt("The dog")?;
+    assert!(!next_token_logits.requires_grad());
+    assert!(prompt_past
+        .iter()
+        .all(|layer_past| !layer_past.requires_grad()));
+
+    let (output, past) =
+        model.generate_with_cache(prompt_ids, None, Cache::None, Some(generate_options))?;
+    assert_eq!(output[0].indices.len(), 202);
+    match past {
+        Cache::GPT2Cache(Some(layer_pasts)) => {
+            assert_eq!(layer_pasts.len(), 12);
+            assert!(layer_pasts
+                .iter()
+                .all(|layer_past| !layer_past.requires_grad() && layer_past.size()[3] == 201));
+        }
+        _ => panic!("Expected a GPT2 cache"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_forced_bos_eos_tokens() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        max_length: Some(20),
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let input_context = "The dog";
+    for num_beams in [1, 3] {
+        let generate_options = GenerateOptions {
+            num_beams: Some(num_beams),
+            min_length: Some(20),
+            repetition_penalty: Some(1.5),
+            forced_bos_token_id: Some(383),
+            forced_eos_token_id: Some(13),
+            ..Default::default()
+        };
+        let output = model.generate_indices(Some(&[input_context]), Some(generate_options))?;
+
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].indices.len(), 20);
+        // The prompt is made of 2 tokens
+        assert_eq!(output[0].indices[2], 383);
+        assert_eq!(output[0].indices[19], 13);
+    }
+
+    // Forced token ids outside of the vocabulary are rejected
+    for (forced_bos_token_id, forced_eos_token_id) in [(Some(50257), None), (None, Some(-1))] {
+        let generate_options = GenerateOptions {
+            forced_bos_token_id,
+            forced_eos_token_id,
+            ..Default::default()
+        };
+        assert!(matches!(
+            model.generate_indices(Some(&[input_context]), Some(generate_options)),
+            Err(RustBertError::InvalidConfigurationError(_))
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_greedy_token_scores() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        max_length: Some(16),
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let input_context_1 = "Hello, my name is";
+    let input_context_2 = "It is a beautiful";
+
+    let generate_options = GenerateOptions {
+        output_scores: true,
+        ..Default::default()
+    };
+
+    let output = model.generate_indices(
+        Some(&[input_context_1, input_context_2]),
+        Some(generate_options),
+    )?;
+
+    assert_eq!(output.len(), 2);
+    assert_eq!(
+        output[0].indices,
+        vec![15496, 11, 616, 1438, 318, 1757, 13, 314, 1101, 257, 6260, 11, 290, 314, 1101, 3597,]
+    );
+    assert!((output[0].score.unwrap() - (-1.3794)).abs() < 1e-4);
+    assert!((output[0].token_scores.as_ref().unwrap()[0] - (-4.6114)).abs() < 1e-4);
+    assert!((output[0].token_scores.as_ref().unwrap()[1] - (-2.1742)).abs() < 1e-4);
+    assert!((output[0].token_scores.as_ref().unwrap()[2] - (-0.7571)).abs() < 1e-4);
+
+    assert_eq!(
+        output[1].indices,
+        vec![50256, 1026, 318, 257, 4950, 1517, 284, 766, 13, 632, 318, 257, 845, 4950, 1517, 13]
+    );
+    assert!((output[1].score.unwrap() - (-1.0609)).abs() < 1e-4);
+    assert!((output[1].token_scores.as_ref().unwrap()[0] - (-2.6287)).abs() < 1e-4);
+    assert!((output[1].token_scores.as_ref().unwrap()[1] - (-1.3033)).abs() < 1e-4);
+    assert!((output[1].token_scores.as_ref().unwrap()[2] - (-0.6780)).abs() < 1e-4);
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_beam_search_token_scores() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        max_length: Some(16),
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        num_beams: 2,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let input_context_1 = "Hello, my name is";
+    let input_context_2 = "It is a beautiful";
+
+    let generate_options = GenerateOptions {
         output_scores: true,
         ..Default::default()
     };
@@ -642,30 +1512,1605 @@ fn gpt2_greedy_token_scores() -> anyhow::Result<()> {
         Some(generate_options),
     )?;
 
-    assert_eq!(output.len(), 2);
+    assert_eq!(output.len(), 2);
+    assert_eq!(
+        output[0].indices,
+        vec![15496, 11, 616, 1438, 318, 1757, 11, 290, 314, 716, 257, 2888, 286, 262, 1578, 1829,]
+    );
+    assert!((output[0].score.unwrap() - (-1.1913)).abs() < 1e-4);
+    assert!((output[0].token_scores.as_ref().unwrap()[0] - (-4.6114)).abs() < 1e-4);
+    assert!((output[0].token_scores.as_ref().unwrap()[1] - (-2.1742)).abs() < 1e-4);
+    assert!((output[0].token_scores.as_ref().unwrap()[2] - (-0.7571)).abs() < 1e-4);
+
+    assert_eq!(
+        output[1].indices,
+        vec![50256, 1026, 318, 257, 4950, 1517, 284, 766, 13, 632, 318, 257, 845, 4950, 1517, 13]
+    );
+    assert!((output[1].score.unwrap() - (-1.1160)).abs() < 1e-4);
+    assert!((output[1].token_scores.as_ref().unwrap()[0] - (-2.6287)).abs() < 1e-4);
+    assert!((output[1].token_scores.as_ref().unwrap()[1] - (-1.3033)).abs() < 1e-4);
+    assert!((output[1].token_scores.as_ref().unwrap()[2] - (-0.6780)).abs() < 1e-4);
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_stream() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        max_length: Some(16),
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let input_context = "Hello, my name is";
+
+    let text_fragments = model
+        .generate_stream(Some(input_context), None)?
+        .collect::<Vec<String>>();
+
+    let output = model.generate_indices(Some(&[input_context]), None)?;
+    let expected_continuation = model
+        .get_tokenizer()
+        .decode(&output[0].indices[5..], true, false);
+
+    assert_eq!(text_fragments.len(), 11);
+    assert_eq!(text_fragments.join(""), expected_continuation);
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_with_callback() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        max_length: Some(16),
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let input_context = "Hello, my name is";
+
+    let mut text_fragments = vec![];
+    let output =
+        model.generate_with_callback(Some(input_context), None, |text_fragment, step| {
+            assert_eq!(step, text_fragments.len() as i64);
+            text_fragments.push(text_fragment.to_string());
+            step < 3
+        })?;
+
+    let expected_fragments = model
+        .generate_stream(Some(input_context), None)?
+        .take(4)
+        .collect::<Vec<String>>();
+
+    assert_eq!(text_fragments, expected_fragments);
+    assert_eq!(
+        output.text,
+        format!("{input_context}{}", text_fragments.join(""))
+    );
+    // The generation was stopped by the callback after 4 steps
+    assert_eq!(output.finish_reason, Some(FinishReason::Cancelled));
+    assert_eq!(output.num_generated_tokens, Some(4));
+
+    // "Hello, my name is" is made of 5 tokens, leaving 11 tokens to generate
+    let output = model.generate_with_callback(Some(input_context), None, |_, _| true)?;
+    assert_eq!(output.finish_reason, Some(FinishReason::MaxLength));
+    assert_eq!(output.num_generated_tokens, Some(11));
+
+    let beam_search_options = GenerateOptions {
+        num_beams: Some(2),
+        ..Default::default()
+    };
+    assert!(model
+        .generate_with_callback(Some(input_context), Some(beam_search_options), |_, _| true)
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_stream_interruption() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        max_length: Some(32),
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    // The cancellation flag is checked before every step of the stream
+    let cancellation_flag = AtomicBool::new(false);
+    let generate_options = GenerateOptions {
+        cancellation_flag: Some(&cancellation_flag),
+        ..Default::default()
+    };
+    let mut tokens = model.generate_iter(Some("Hello, my name is"), Some(generate_options))?;
+    for _ in 0..3 {
+        assert!(tokens.next().is_some());
+    }
+    assert_eq!(tokens.finish_reason(), None);
+    cancellation_flag.store(true, Ordering::Relaxed);
+    assert!(tokens.next().is_none());
+    assert_eq!(tokens.finish_reason(), Some(FinishReason::Cancelled));
+
+    // The time limit is exceeded before the first step
+    let generate_options = GenerateOptions {
+        max_time: Some(Duration::ZERO),
+        ..Default::default()
+    };
+    let mut stream = model.generate_stream(Some("Hello, my name is"), Some(generate_options))?;
+    assert!(stream.next().is_none());
+    assert_eq!(stream.finish_reason(), Some(FinishReason::TimeLimit));
+    assert_eq!(stream.num_generated_tokens(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_iterator() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        max_length: Some(32),
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let input_context = String::from("Hello, my name is");
+
+    // Stop after the first sentence-ending period
+    let tokens = model
+        .generate_iter(Some(input_context.as_str()), None)?
+        .scan(false, |sentence_ended, token| {
+            if *sentence_ended {
+                return None;
+            }
+            *sentence_ended = token.text.ends_with('.');
+            Some(token)
+        })
+        .collect::<Vec<_>>();
+    let first_sentence = tokens
+        .iter()
+        .map(|token| token.text.as_str())
+        .collect::<String>();
+
+    let output = model.generate_indices(Some(&[input_context.as_str()]), None)?;
+    let generated_ids = tokens.iter().map(|token| token.id).collect::<Vec<i64>>();
+
+    assert!(first_sentence.ends_with('.'));
+    assert_eq!(first_sentence.matches('.').count(), 1);
+    assert!(tokens.len() < 27);
+    assert_eq!(generated_ids, output[0].indices[5..5 + tokens.len()]);
+    assert!(tokens.iter().all(|token| token.log_prob <= 0.0));
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_min_length() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        min_length: 20,
+        max_length: Some(50),
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: true,
+        num_beams: 1,
+        num_return_sequences: 8,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+    // GPT-2 `<|endoftext|>` token
+    let eos_token_id = 50256;
+
+    // A prompt ending a document makes an immediate end of sequence likely
+    let input_context = "The end.\n\n";
+    let output = model.generate_indices(Some(&[input_context]), None)?;
+
+    assert_eq!(output.len(), 8);
+    for sequence in output {
+        assert!(sequence.indices.len() >= 20);
+        assert!(!sequence.indices[..20].contains(&eos_token_id));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_beam_search_min_length() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        min_length: 40,
+        max_length: Some(50),
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        num_beams: 4,
+        num_return_sequences: 4,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+    // GPT-2 `<|endoftext|>` token
+    let eos_token_id = 50256;
+
+    let input_context = "The end.\n\n";
+    let output = model.generate_indices(Some(&[input_context]), None)?;
+
+    assert_eq!(output.len(), 4);
+    for sequence in output {
+        assert!(sequence.indices.len() >= 40);
+        assert!(!sequence.indices[..40].contains(&eos_token_id));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_sampling_seeded() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        max_length: Some(30),
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: true,
+        num_beams: 1,
+        temperature: 0.7,
+        top_k: 50,
+        top_p: 0.95,
+        num_return_sequences: 3,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let input_context = "The dog";
+
+    tch::manual_seed(42);
+    let first_output = model.generate(Some(&[input_context]), None)?;
+    tch::manual_seed(42);
+    let second_output = model.generate(Some(&[input_context]), None)?;
+
+    assert_eq!(first_output.len(), 3);
+    assert_eq!(
+        first_output
+            .iter()
+            .map(|output| output.text.as_str())
+            .collect::<Vec<&str>>(),
+        second_output
+            .iter()
+            .map(|output| output.text.as_str())
+            .collect::<Vec<&str>>()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_beam_sampling_seeded() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        max_length: Some(30),
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: true,
+        num_beams: 4,
+        temperature: 0.7,
+        top_k: 50,
+        top_p: 0.95,
+        num_return_sequences: 2,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let input_context_1 = "The dog";
+    let input_context_2 = "My favourite food is";
+
+    let generate_options = GenerateOptions {
+        output_scores: true,
+        ..Default::default()
+    };
+    tch::manual_seed(42);
+    let first_output = model.generate(
+        Some(&[input_context_1, input_context_2]),
+        Some(generate_options),
+    )?;
+    tch::manual_seed(42);
+    let second_output = model.generate(
+        Some(&[input_context_1, input_context_2]),
+        Some(generate_options),
+    )?;
+
+    assert_eq!(first_output.len(), 4);
+    for (first, second) in first_output.iter().zip(second_output.iter()) {
+        assert_eq!(first.text, second.text);
+        assert_eq!(first.score, second.score);
+    }
+    assert!(first_output[..2]
+        .iter()
+        .all(|output| output.text.starts_with(input_context_1)));
+    assert!(first_output[2..]
+        .iter()
+        .all(|output| output.text.starts_with(input_context_2)));
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_contrastive_search() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        max_length: Some(32),
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        num_beams: 1,
+        top_k: 4,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let input_context_1 = "The dog";
+    let input_context_2 = "Language models can generate";
+    let greedy_output = model.generate_indices(Some(&[input_context_1, input_context_2]), None)?;
+
+    // A negligible degeneration penalty selects the most likely token, as greedy decoding
+    let generate_options = GenerateOptions {
+        penalty_alpha: Some(1e-6),
+        ..Default::default()
+    };
+    let output = model.generate_indices(
+        Some(&[input_context_1, input_context_2]),
+        Some(generate_options),
+    )?;
+    assert_eq!(output.len(), 2);
+    assert_eq!(output[0].indices, greedy_output[0].indices);
+    assert_eq!(output[1].indices, greedy_output[1].indices);
+
+    let generate_options = GenerateOptions {
+        penalty_alpha: Some(0.6),
+        ..Default::default()
+    };
+    let output = model.generate(Some(&[input_context_1]), Some(generate_options))?;
+    let repeated_output = model.generate(Some(&[input_context_1]), Some(generate_options))?;
+    assert_eq!(output.len(), 1);
+    assert!(output[0].text.starts_with(input_context_1));
+    assert_eq!(output[0].text, repeated_output[0].text);
+
+    // Contrastive search is deterministic and does not support sampling
+    let sampling_options = GenerateOptions {
+        penalty_alpha: Some(0.6),
+        do_sample: Some(true),
+        ..Default::default()
+    };
+    assert!(model
+        .generate(Some(&[input_context_1]), Some(sampling_options))
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_typical_sampling() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        max_length: Some(24),
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: true,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let input_context = "The dog";
+
+    // A negligible probability mass only keeps the most typical token: sampling becomes deterministic
+    let generate_options = GenerateOptions {
+        typical_p: Some(1e-6),
+        ..Default::default()
+    };
+    let output = model.generate(Some(&[input_context]), Some(generate_options))?;
+    let repeated_output = model.generate(Some(&[input_context]), Some(generate_options))?;
+    assert_eq!(output.len(), 1);
+    assert!(output[0].text.starts_with(input_context));
+    assert_eq!(output[0].text, repeated_output[0].text);
+
+    let invalid_options = GenerateOptions {
+        typical_p: Some(0.0),
+        ..Default::default()
+    };
+    assert!(model
+        .generate(Some(&[input_context]), Some(invalid_options))
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_mirostat_sampling() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        max_length: Some(24),
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: true,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let input_context_1 = "The dog";
+    let input_context_2 = "The cat was";
+    for mirostat in [
+        Mirostat::V1 { tau: 3.0, eta: 0.1 },
+        Mirostat::V2 { tau: 3.0, eta: 0.1 },
+    ] {
+        let generate_options = GenerateOptions {
+            mirostat: Some(mirostat),
+            num_return_sequences: Some(2),
+            ..Default::default()
+        };
+        let output = model.generate(
+            Some(&[input_context_1, input_context_2]),
+            Some(generate_options),
+        )?;
+        assert_eq!(output.len(), 4);
+        assert!(output[0].text.starts_with(input_context_1));
+        assert!(output[1].text.starts_with(input_context_1));
+        assert!(output[2].text.starts_with(input_context_2));
+        assert!(output[3].text.starts_with(input_context_2));
+    }
+
+    // Mirostat is a sampling method
+    let greedy_options = GenerateOptions {
+        mirostat: Some(Mirostat::V2 { tau: 3.0, eta: 0.1 }),
+        do_sample: Some(false),
+        ..Default::default()
+    };
+    assert!(model
+        .generate(Some(&[input_context_1]), Some(greedy_options))
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_suppress_tokens() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: true,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    // "The dog"
+    let prompt_length = 2;
+    // ",", ".", " the" and " a" can never be generated, " and" and " is" cannot start the generation
+    let suppress_tokens = vec![11, 13, 262, 257];
+    let begin_suppress_tokens = vec![290, 318];
+    let generate_options = GenerateOptions {
+        max_new_tokens: Some(10),
+        num_return_sequences: Some(100),
+        suppress_tokens: Some(&suppress_tokens),
+        begin_suppress_tokens: Some(&begin_suppress_tokens),
+        ..Default::default()
+    };
+    let output = model.generate_indices(Some(&["The dog"]), Some(generate_options))?;
+
+    assert_eq!(output.len(), 100);
+    for sequence in output {
+        let generated_ids = &sequence.indices[prompt_length..];
+        assert!(!begin_suppress_tokens.contains(&generated_ids[0]));
+        assert!(generated_ids
+            .iter()
+            .all(|token_id| !suppress_tokens.contains(token_id)));
+    }
+
+    // Token ids outside of the vocabulary are rejected
+    let out_of_vocabulary_tokens = vec![11, 50257];
+    let negative_tokens = vec![-1];
+    for (suppress_tokens, begin_suppress_tokens) in [
+        (Some(&out_of_vocabulary_tokens), None),
+        (None, Some(&negative_tokens)),
+    ] {
+        let generate_options = GenerateOptions {
+            suppress_tokens,
+            begin_suppress_tokens,
+            ..Default::default()
+        };
+        assert!(matches!(
+            model.generate_indices(Some(&["The dog"]), Some(generate_options)),
+            Err(RustBertError::ValueError(_))
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_allowed_token_ids() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: true,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    // "The dog"
+    let prompt_length = 2;
+    // " yes", " no" and " maybe", the EOS token (50256) remains allowed
+    let allowed_token_ids = vec![3763, 645, 3863];
+    let generate_options = GenerateOptions {
+        max_new_tokens: Some(5),
+        num_return_sequences: Some(50),
+        allowed_token_ids: Some(&allowed_token_ids),
+        ..Default::default()
+    };
+    let output = model.generate_indices(Some(&["The dog"]), Some(generate_options))?;
+
+    assert_eq!(output.len(), 50);
+    for sequence in output {
+        assert!(sequence.indices[prompt_length..]
+            .iter()
+            .all(|token_id| allowed_token_ids.contains(token_id) | (*token_id == 50256)));
+    }
+
+    // The EOS token can be excluded with `suppress_tokens`
+    let suppress_tokens = vec![50256];
+    let generate_options = GenerateOptions {
+        max_new_tokens: Some(5),
+        num_return_sequences: Some(50),
+        allowed_token_ids: Some(&allowed_token_ids),
+        suppress_tokens: Some(&suppress_tokens),
+        ..Default::default()
+    };
+    let output = model.generate_indices(Some(&["The dog"]), Some(generate_options))?;
+    for sequence in output {
+        assert_eq!(sequence.indices.len(), prompt_length + 5);
+        assert!(sequence.indices[prompt_length..]
+            .iter()
+            .all(|token_id| allowed_token_ids.contains(token_id)));
+    }
+
+    // An empty whitelist is rejected
+    let empty_allowed_token_ids = vec![];
+    let generate_options = GenerateOptions {
+        allowed_token_ids: Some(&empty_allowed_token_ids),
+        ..Default::default()
+    };
+    assert!(model
+        .generate_indices(Some(&["The dog"]), Some(generate_options))
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_half_precision() -> anyhow::Result<()> {
+    let device = Device::cuda_if_available();
+    let generate_options = GenerateOptions {
+        max_new_tokens: Some(10),
+        ..Default::default()
+    };
+    let mut outputs = vec![];
+    for kind in [None, Some(Kind::Half)] {
+        let generate_config = GenerateConfig {
+            do_sample: false,
+            num_beams: 1,
+            device,
+            kind,
+            ..Default::default()
+        };
+        let model = GPT2Generator::new(generate_config)?;
+        outputs.push(model.generate_indices(Some(&["The dog"]), Some(generate_options))?);
+    }
+
+    assert_eq!(outputs[0][0].indices, outputs[1][0].indices);
+
+    // Half precision falls back to full precision on CPU
+    let mut vs = nn::VarStore::new(Device::Cpu);
+    let _ = vs.root().zeros("weight", &[2, 2]);
+    cast_var_store(&mut vs, Some(Kind::Half), Device::Cpu);
+    assert_eq!(vs.variables()["weight"].kind(), Kind::Float);
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_bad_words_processor_from_strings() -> anyhow::Result<()> {
+    let vocab_resource = RemoteResource::from_pretrained(Gpt2VocabResources::GPT2);
+    let merges_resource = RemoteResource::from_pretrained(Gpt2MergesResources::GPT2);
+    let tokenizer = Gpt2Tokenizer::from_file(
+        vocab_resource.get_local_path()?.to_str().unwrap(),
+        merges_resource.get_local_path()?.to_str().unwrap(),
+        false,
+    )?;
+    let token_id = |text: &str| tokenizer.convert_tokens_to_ids(&tokenizer.tokenize(text))[0];
+
+    let processor = BadWordsLogitsProcessor::from_strings(&["dog", "New York"], &tokenizer);
+    let is_banned = |input_ids: &[i64], token_id: i64| {
+        let mut logits = Tensor::zeros([1, 50257], (Kind::Float, Device::Cpu));
+        processor.process(&Tensor::from_slice(input_ids).unsqueeze(0), &mut logits);
+        logits.double_value(&[0, token_id]) == f64::NEG_INFINITY
+    };
+
+    // Single token bad words are always banned, with and without a leading space
+    let the = token_id("The");
+    assert!(is_banned(&[the], token_id("dog")));
+    assert!(is_banned(&[the], token_id(" dog")));
+    assert!(!is_banned(&[the], token_id(" cat")));
+    // The last token of multi-token bad words is banned after the rest of the bad word
+    assert!(is_banned(&[the, token_id(" New")], token_id(" York")));
+    assert!(!is_banned(&[the, token_id(" Old")], token_id(" York")));
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_logit_bias() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        no_repeat_ngram_size: 0,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    assert!(model.get_logit_bias(&[(" New York", 1.0)]).is_err());
+
+    // A large bias forces the token, a negative infinite bias bans it
+    let logit_bias = model.get_logit_bias(&[(" cat", 100.0)])?;
+    let generate_options = GenerateOptions {
+        max_new_tokens: Some(4),
+        logit_bias: Some(&logit_bias),
+        ..Default::default()
+    };
+    let output = model.generate(Some(&["The dog"]), Some(generate_options))?;
+    assert_eq!(output[0].text, "The dog cat cat cat cat");
+
+    let greedy_output = model.generate_indices(Some(&["The dog"]), None)?;
+    let first_token = greedy_output[0].indices[2];
+    let logit_bias = HashMap::from([(first_token, f64::NEG_INFINITY)]);
+    let generate_options = GenerateOptions {
+        logit_bias: Some(&logit_bias),
+        ..Default::default()
+    };
+    let output = model.generate_indices(Some(&["The dog"]), Some(generate_options))?;
+    assert!(!output[0].indices[2..].contains(&first_token));
+
+    // Token ids outside of the vocabulary are rejected
+    for token_id in [-1, 50257] {
+        let logit_bias = HashMap::from([(token_id, 1.0)]);
+        let generate_options = GenerateOptions {
+            logit_bias: Some(&logit_bias),
+            ..Default::default()
+        };
+        assert!(matches!(
+            model.generate_indices(Some(&["The dog"]), Some(generate_options)),
+            Err(RustBertError::InvalidConfigurationError(_))
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_watermark() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: true,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let seeding_scheme = SeedingScheme::default();
+    let logits_processors: Vec<Box<dyn LogitsProcessor>> = vec![Box::new(
+        WatermarkLogitsProcessor::new(2.0, 0.25, seeding_scheme),
+    )];
+    let detector = WatermarkDetector::new(0.25, seeding_scheme);
+    let prompt_length = 2;
+
+    let generate_options = GenerateOptions {
+        max_new_tokens: Some(100),
+        min_length: Some(prompt_length as i64 + 100),
+        logits_processors: Some(&logits_processors),
+        ..Default::default()
+    };
+    let output = model.generate_indices(Some(&["The dog"]), Some(generate_options))?;
+    // The last prompt token seeds the green list of the first generated token
+    let score = detector.detect(&output[0].indices[prompt_length - 1..]);
+    assert_eq!(score.num_tokens_scored, 100);
+    assert!(score.z_score > 4.0);
+
+    let generate_options = GenerateOptions {
+        max_new_tokens: Some(100),
+        min_length: Some(prompt_length as i64 + 100),
+        ..Default::default()
+    };
+    let output = model.generate_indices(Some(&["The dog"]), Some(generate_options))?;
+    let score = detector.detect(&output[0].indices[prompt_length - 1..]);
+    assert!(score.z_score < 4.0);
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_presence_penalty() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        num_beams: 1,
+        no_repeat_ngram_size: 0,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    // "The dog"
+    let prompt_length = 2;
+    for include_prompt_in_penalty in [false, true] {
+        let generate_options = GenerateOptions {
+            max_new_tokens: Some(30),
+            min_length: Some(prompt_length as i64 + 30),
+            presence_penalty: Some(100.0),
+            include_prompt_in_penalty,
+            ..Default::default()
+        };
+        let output = model.generate_indices(Some(&["The dog"]), Some(generate_options))?;
+
+        // A large presence penalty prevents any repetition of the penalized tokens
+        let penalized_tokens = if include_prompt_in_penalty {
+            &output[0].indices[..]
+        } else {
+            &output[0].indices[prompt_length..]
+        };
+        let unique_tokens = penalized_tokens.iter().collect::<HashSet<&i64>>();
+        assert_eq!(unique_tokens.len(), penalized_tokens.len());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_classifier_free_guidance() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        max_length: Some(20),
+        do_sample: false,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+    let unguided_output = model.generate_indices(Some(&["The dog"]), None)?;
+
+    let tokenizer = model.get_tokenizer();
+    let generate_with_guidance = |unconditional_prompt: &str, guidance_scale: f64| {
+        let unconditional_ids =
+            tokenizer.convert_tokens_to_ids(&tokenizer.tokenize(unconditional_prompt));
+        let logits_processors: Vec<Box<dyn LogitsProcessor>> =
+            vec![Box::new(ClassifierFreeGuidanceLogitsProcessor::new(
+                &model,
+                guidance_scale,
+                Tensor::from_slice(&unconditional_ids),
+            ))];
+        let generate_options = GenerateOptions {
+            logits_processors: Some(&logits_processors),
+            ..Default::default()
+        };
+        model.generate_indices(Some(&["The dog"]), Some(generate_options))
+    };
+
+    // The guidance has no effect if the unconditional prompt is the prompt, or with a scale of 0
+    let output = generate_with_guidance("The dog", 3.0)?;
+    assert_eq!(output[0].indices, unguided_output[0].indices);
+    let output = generate_with_guidance("The cat", 0.0)?;
+    assert_eq!(output[0].indices, unguided_output[0].indices);
+
+    let output = generate_with_guidance("The cat", 3.0)?;
+    assert_eq!(output[0].indices[..2], unguided_output[0].indices[..2]);
+    assert_ne!(output[0].indices, unguided_output[0].indices);
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_classifier_free_guidance_processor_reuse() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        max_length: Some(20),
+        do_sample: false,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let tokenizer = model.get_tokenizer();
+    let unconditional_ids = tokenizer.convert_tokens_to_ids(&tokenizer.tokenize("The cat"));
+    let logits_processors: Vec<Box<dyn LogitsProcessor>> =
+        vec![Box::new(ClassifierFreeGuidanceLogitsProcessor::new(
+            &model,
+            3.0,
+            Tensor::from_slice(&unconditional_ids),
+        ))];
+    let generate_options = GenerateOptions {
+        logits_processors: Some(&logits_processors),
+        ..Default::default()
+    };
+
+    // The unconditional sequences and cache are reset at the start of every generation
+    let first_output = model.generate_indices(Some(&["The dog"]), Some(generate_options))?;
+    let second_output = model.generate_indices(Some(&["The dog"]), Some(generate_options))?;
+    assert_eq!(first_output[0].indices, second_output[0].indices);
+
+    // The processor of a failed generation can still be used
+    let beam_search_options = GenerateOptions {
+        num_beams: Some(3),
+        ..generate_options
+    };
+    assert!(matches!(
+        model.generate_indices(Some(&["The dog"]), Some(beam_search_options)),
+        Err(RustBertError::InvalidConfigurationError(_))
+    ));
+    let output = model.generate_indices(Some(&["The dog"]), Some(generate_options))?;
+    assert_eq!(output[0].indices, first_output[0].indices);
+
+    // The unconditional prompts should be shared by the batch or provided for every sequence
+    let unconditional_ids = Tensor::from_slice(&unconditional_ids).repeat([3, 1]);
+    let logits_processors: Vec<Box<dyn LogitsProcessor>> = vec![Box::new(
+        ClassifierFreeGuidanceLogitsProcessor::new(&model, 3.0, unconditional_ids),
+    )];
+    let generate_options = GenerateOptions {
+        logits_processors: Some(&logits_processors),
+        ..Default::default()
+    };
+    assert!(matches!(
+        model.generate_indices(Some(&["The dog", "The cat was"]), Some(generate_options)),
+        Err(RustBertError::ValueError(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_without_prompt_echo() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        max_length: Some(20),
+        do_sample: false,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+    let prompts = ["The dog", "The cat was"];
+    let generate_options = GenerateOptions {
+        echo_prompt: Some(false),
+        ..Default::default()
+    };
+
+    let echoed_output = model.generate_indices(Some(&prompts), None)?;
+    let output = model.generate_indices(Some(&prompts), Some(generate_options))?;
+    for (echoed_sequence, sequence) in echoed_output.iter().zip(output.iter()) {
+        // The shorter prompt is left-padded to the length of the longest prompt
+        assert_eq!(echoed_sequence.prompt_length, 3);
+        assert_eq!(sequence.prompt_length, 0);
+        assert_eq!(
+            sequence.indices,
+            echoed_sequence.indices[echoed_sequence.prompt_length..]
+        );
+    }
+
+    let echoed_output = model.generate(Some(&prompts), None)?;
+    let output = model.generate(Some(&prompts), Some(generate_options))?;
+    for ((prompt, echoed_sequence), sequence) in
+        prompts.iter().zip(echoed_output.iter()).zip(output.iter())
+    {
+        assert!(!sequence.text.starts_with(prompt));
+        assert_eq!(echoed_sequence.text, format!("{prompt}{}", sequence.text));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_from_ids() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        max_length: Some(20),
+        do_sample: false,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+    let tokenizer = model.get_tokenizer();
+    let prompt_ids = tokenizer.convert_tokens_to_ids(&tokenizer.tokenize("The dog"));
+
+    let output =
+        model.generate_from_ids(Tensor::from_slice(&prompt_ids).unsqueeze(0), None, None)?;
+    let text_output = model.generate_indices(Some(&["The dog"]), None)?;
+    assert_eq!(output[0].indices, text_output[0].indices);
+
+    // Invalid inputs are rejected
+    let invalid_inputs = [
+        (Tensor::from_slice(&prompt_ids), None),
+        (
+            Tensor::from_slice(&prompt_ids)
+                .unsqueeze(0)
+                .to_kind(Kind::Float),
+            None,
+        ),
+        (
+            Tensor::from_slice(&prompt_ids).unsqueeze(0),
+            Some(Tensor::ones([1, 3], (Kind::Int64, Device::Cpu))),
+        ),
+    ];
+    for (input_ids, attention_mask) in invalid_inputs {
+        assert!(matches!(
+            model.generate_from_ids(input_ids, attention_mask, None),
+            Err(RustBertError::ValueError(_))
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_continuation_scores() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let output = model.score_text("The capital of France is", " Paris")?;
+    assert_eq!(output.token_ids.len(), 1);
+    assert!(output.token_scores[0] < 0.0);
+    assert!((output.score - output.token_scores[0]).abs() < 1e-9);
+    assert!(
+        output.score
+            > model
+                .score_text("The capital of France is", " banana")?
+                .score
+    );
+
+    // A token spanning the prompt/continuation boundary belongs to the continuation
+    let split_word_output = model.score_text("The d", "og")?;
+    let word_output = model.score_text("The", " dog")?;
+    assert_eq!(split_word_output.token_ids, word_output.token_ids);
+    assert!((split_word_output.score - word_output.score).abs() < 1e-4);
+
+    // Batched scoring (with padding) matches the individual scores
+    let texts = [
+        ("The dog", " barked at the mailman all morning"),
+        ("Once upon a time, in a land far away,", " there was"),
+    ];
+    let batched_output = model.score_texts(&texts)?;
+    for ((prompt, continuation), batched_scores) in texts.iter().zip(batched_output.iter()) {
+        let scores = model.score_text(prompt, continuation)?;
+        assert_eq!(scores.token_ids, batched_scores.token_ids);
+        assert_eq!(scores.token_scores.len(), scores.token_ids.len());
+        assert!((scores.score - batched_scores.score).abs() < 1e-4);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_perplexity() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let sentence = "The quick brown fox jumps over the lazy dog.";
+    let shuffled_sentence = "dog brown lazy The over jumps fox the quick.";
+    let perplexity = model.perplexity(sentence, 512)?;
+    assert!(perplexity > 1.0);
+    assert!(perplexity < model.perplexity(shuffled_sentence, 512)?);
+
+    // Texts longer than the context size (1024 tokens) are scored with a sliding window
+    let long_text = vec![sentence; 150].join(" ");
+    let long_text_perplexity = model.perplexity(&long_text, 512)?;
+    assert!(long_text_perplexity.is_finite());
+    // The repetitions are easily predicted from the context
+    assert!(long_text_perplexity < perplexity);
+
+    assert!(matches!(
+        model.perplexity(&long_text, 1024),
+        Err(RustBertError::ValueError(_))
+    ));
+    assert!(matches!(
+        model.perplexity(sentence, 0),
+        Err(RustBertError::ValueError(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_corpus_perplexity() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let texts = [
+        "The quick brown fox jumps over the lazy dog.",
+        "Language models assign probabilities to sequences of tokens.",
+    ];
+    let num_scored_tokens = texts
+        .iter()
+        .map(|text| model.get_tokenizer().tokenize(text).len() - 1)
+        .collect::<Vec<usize>>();
+
+    // A single text has the perplexity computed by the generator
+    let perplexity = compute_perplexity(&model, &texts[..1], 512, None)?;
+    assert!((perplexity - model.perplexity(texts[0], 512)?).abs() < 1e-6 * perplexity);
+
+    // The negative log-likelihoods are averaged over the tokens of all texts
+    let corpus_perplexity = compute_perplexity(&model, &texts, 512, None)?;
+    let expected_negative_log_likelihood = texts
+        .iter()
+        .zip(&num_scored_tokens)
+        .map(|(text, num_tokens)| model.perplexity(text, 512).unwrap().ln() * *num_tokens as f64)
+        .sum::<f64>()
+        / num_scored_tokens.iter().sum::<usize>() as f64;
+    assert!((corpus_perplexity.ln() - expected_negative_log_likelihood).abs() < 1e-6);
+
+    // Reference perplexity of GPT-2 on "lorem ipsum", from the documentation of the perplexity metric of the
+    // Hugging Face `evaluate` library (`model_id="gpt2"`, `add_start_token=False`, rounded to 2 decimals)
+    let reference_perplexity = compute_perplexity(&model, &["lorem ipsum"], 512, Some(1024))?;
+    assert!((reference_perplexity - 32.25).abs() < 1e-2);
+
+    // Texts longer than the evaluation window are scored with a sliding window
+    let windowed_perplexity = compute_perplexity(&model, &texts, 2, Some(4))?;
+    assert!(windowed_perplexity.is_finite());
+    assert!(windowed_perplexity > 1.0);
+
+    assert!(matches!(
+        compute_perplexity(&model, &texts, 4, Some(4)),
+        Err(RustBertError::ValueError(_))
+    ));
+    assert!(matches!(
+        compute_perplexity(&model, &texts, 1, Some(1)),
+        Err(RustBertError::InvalidConfigurationError(_))
+    ));
+    assert!(matches!(
+        compute_perplexity(&model, &["The"], 512, None),
+        Err(RustBertError::ValueError(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_cancellation() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let cancellation_flag = Arc::new(AtomicBool::new(false));
+    let cancelling_thread = {
+        let cancellation_flag = Arc::clone(&cancellation_flag);
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            cancellation_flag.store(true, Ordering::Relaxed);
+        })
+    };
+    let generate_options = GenerateOptions {
+        min_length: Some(1000),
+        max_length: Some(1000),
+        cancellation_flag: Some(cancellation_flag.as_ref()),
+        ..Default::default()
+    };
+    let output = model.generate_indices(
+        Some(&["The dog", "The cat was sleeping on the"]),
+        Some(generate_options),
+    )?;
+    cancelling_thread.join().unwrap();
+
+    assert_eq!(output.len(), 2);
+    // Both sequences are returned with the same (partial) length
+    assert_eq!(output[0].indices.len(), output[1].indices.len());
+    assert!(output[0].indices.len() < 1000);
+    for sequence in &output {
+        assert_eq!(sequence.finish_reason, Some(FinishReason::Cancelled));
+        assert!(sequence.indices.len() > sequence.prompt_length);
+    }
+    // The partial output is decoded as usual
+    let text_output = model.generate(
+        Some(&["The dog"]),
+        Some(GenerateOptions {
+            min_length: None,
+            max_length: Some(8),
+            ..generate_options
+        }),
+    )?;
+    assert_eq!(text_output[0].finish_reason, Some(FinishReason::Cancelled));
+    assert!(text_output[0].text.starts_with("The dog"));
+
+    Ok(())
+}
+
+/// Forces the generation of the end of sequence token in the first sequence of the batch at a given length
+struct ForcedEarlyEosProcessor {
+    eos_token_id: i64,
+    sequence_length: i64,
+}
+
+impl LogitsProcessor for ForcedEarlyEosProcessor {
+    fn process(&self, input_ids: &Tensor, logits: &mut Tensor) {
+        if input_ids.size()[1] == self.sequence_length {
+            let mut first_sequence_logits = logits.get(0);
+            let _ = first_sequence_logits.fill_(f64::NEG_INFINITY);
+            let _ = first_sequence_logits.get(self.eos_token_id).fill_(0.0);
+        }
+    }
+}
+
+#[test]
+fn gpt2_generation_finish_reasons() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    // The prompts are made of 2 and 6 tokens: the first prompt is left-padded to 6 tokens
+    let input_context_1 = "The dog";
+    let input_context_2 = "The cat was sleeping on the";
+    let logits_processors: Vec<Box<dyn LogitsProcessor>> =
+        vec![Box::new(ForcedEarlyEosProcessor {
+            eos_token_id: 50256,
+            sequence_length: 8,
+        })];
+    let generate_options = GenerateOptions {
+        max_new_tokens: Some(10),
+        logits_processors: Some(&logits_processors),
+        ..Default::default()
+    };
+    let output = model.generate_indices(
+        Some(&[input_context_1, input_context_2]),
+        Some(generate_options),
+    )?;
+
+    assert_eq!(output.len(), 2);
+    // The first sequence ends with an end of sequence token after 2 tokens and is padded to the batch length
+    assert_eq!(output[0].indices.len(), 16);
+    assert_eq!(output[0].finish_reason, Some(FinishReason::Eos));
+    assert_eq!(output[0].num_generated_tokens, Some(3));
+    assert_eq!(output[0].indices[8], 50256);
+    // The second sequence runs to the maximum length
+    assert_eq!(output[1].indices.len(), 16);
+    assert_eq!(output[1].finish_reason, Some(FinishReason::MaxLength));
+    assert_eq!(output[1].num_generated_tokens, Some(10));
+
+    let text_output = model.generate(
+        Some(&[input_context_1, input_context_2]),
+        Some(generate_options),
+    )?;
+    assert_eq!(text_output[0].finish_reason, Some(FinishReason::Eos));
+    assert_eq!(text_output[0].num_generated_tokens, Some(3));
+    assert_eq!(text_output[1].finish_reason, Some(FinishReason::MaxLength));
+    assert_eq!(text_output[1].num_generated_tokens, Some(10));
+
+    // Stop sequences are reported with their own finish reason
+    let stop_sequences = vec![".".to_string()];
+    let output = model.generate_indices(
+        Some(&[input_context_2]),
+        Some(GenerateOptions {
+            max_new_tokens: Some(64),
+            stop_sequences: Some(&stop_sequences),
+            ..Default::default()
+        }),
+    )?;
+    assert_eq!(output[0].finish_reason, Some(FinishReason::StopSequence));
     assert_eq!(
-        output[0].indices,
-        vec![15496, 11, 616, 1438, 318, 1757, 13, 314, 1101, 257, 6260, 11, 290, 314, 1101, 3597,]
+        output[0].num_generated_tokens,
+        Some(output[0].indices.len() - output[0].prompt_length)
     );
-    assert!((output[0].score.unwrap() - (-1.3794)).abs() < 1e-4);
-    assert!((output[0].token_scores.as_ref().unwrap()[0] - (-4.6114)).abs() < 1e-4);
-    assert!((output[0].token_scores.as_ref().unwrap()[1] - (-2.1742)).abs() < 1e-4);
-    assert!((output[0].token_scores.as_ref().unwrap()[2] - (-0.7571)).abs() < 1e-4);
 
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_time_limit() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let input_context = "The dog";
+    // The time limit is exceeded after the first generation step
+    let generate_options = GenerateOptions {
+        min_length: Some(100),
+        max_length: Some(100),
+        max_time: Some(Duration::from_nanos(1)),
+        ..Default::default()
+    };
+    let output = model.generate_indices(Some(&[input_context]), Some(generate_options))?;
+
+    assert_eq!(output.len(), 1);
+    assert_eq!(output[0].finish_reason, Some(FinishReason::TimeLimit));
+    assert_eq!(output[0].num_generated_tokens, Some(1));
+    assert_eq!(output[0].indices.len(), 3);
+
+    // The partial output is a valid sequence, identical to the beginning of an unconstrained generation
+    let text_output = model.generate(Some(&[input_context]), Some(generate_options))?;
+    let unconstrained_output = model.generate(
+        Some(&[input_context]),
+        Some(GenerateOptions {
+            max_time: None,
+            ..generate_options
+        }),
+    )?;
+    assert_eq!(text_output[0].finish_reason, Some(FinishReason::TimeLimit));
+    assert!(text_output[0].text.len() > input_context.len());
+    assert!(unconstrained_output[0]
+        .text
+        .starts_with(&text_output[0].text));
     assert_eq!(
-        output[1].indices,
-        vec![50256, 1026, 318, 257, 4950, 1517, 284, 766, 13, 632, 318, 257, 845, 4950, 1517, 13]
+        unconstrained_output[0].finish_reason,
+        Some(FinishReason::MaxLength)
     );
-    assert!((output[1].score.unwrap() - (-1.0609)).abs() < 1e-4);
-    assert!((output[1].token_scores.as_ref().unwrap()[0] - (-2.6287)).abs() < 1e-4);
-    assert!((output[1].token_scores.as_ref().unwrap()[1] - (-1.3033)).abs() < 1e-4);
-    assert!((output[1].token_scores.as_ref().unwrap()[2] - (-0.6780)).abs() < 1e-4);
 
     Ok(())
 }
 
 #[test]
-fn gpt2_beam_search_token_scores() -> anyhow::Result<()> {
+fn gpt2_generation_model_outputs() -> anyhow::Result<()> {
+    //    Resources definition: the model is configured to return its attentions and hidden states
+    let config_path =
+        RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2).get_local_path()?;
+    let mut config = Gpt2Config::from_file(config_path);
+    config.output_attentions = Some(true);
+    config.output_hidden_states = Some(true);
+    let config_directory = tempfile::tempdir()?;
+    let config_path = config_directory.path().join("config.json");
+    std::fs::write(&config_path, serde_json::to_string(&config)?)?;
+
+    let config_resource = Box::new(LocalResource {
+        local_path: config_path,
+    });
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    // "The dog" and "The cat"
+    let input_ids = Tensor::from_slice(&[464i64, 3290, 464, 3797]).view([2, 2]);
+    for num_beams in [1, 3] {
+        let generate_options = GenerateOptions {
+            max_new_tokens: Some(3),
+            num_beams: Some(num_beams),
+            output_attentions: true,
+            output_hidden_states: true,
+            ..Default::default()
+        };
+        let (output, model_outputs) =
+            model.generate_with_model_outputs(input_ids.copy(), None, Some(generate_options))?;
+        assert_eq!(output.len(), 2);
+
+        let attentions = model_outputs.attentions.unwrap();
+        let hidden_states = model_outputs.hidden_states.unwrap();
+        // One entry per generation step and per layer
+        assert_eq!(attentions.len(), 3);
+        assert_eq!(hidden_states.len(), 3);
+        for (step, (step_attentions, step_hidden_states)) in
+            attentions.iter().zip(hidden_states.iter()).enumerate()
+        {
+            assert_eq!(step_attentions.len(), 12);
+            assert_eq!(step_hidden_states.len(), 12);
+            // The prompt is processed by the first step, the following steps use the cache
+            let (query_length, key_length) = if step == 0 {
+                (2, 2)
+            } else {
+                (1, 2 + step as i64)
+            };
+            for layer_attentions in step_attentions {
+                assert_eq!(
+                    layer_attentions.size(),
+                    [2 * num_beams, 12, query_length, key_length]
+                );
+            }
+            for layer_hidden_states in step_hidden_states {
+                assert_eq!(
+                    layer_hidden_states.size(),
+                    [2 * num_beams, query_length, 768]
+                );
+            }
+        }
+    }
+
+    // The model outputs are only collected if requested
+    let (_, model_outputs) = model.generate_with_model_outputs(
+        input_ids,
+        None,
+        Some(GenerateOptions {
+            max_new_tokens: Some(3),
+            ..Default::default()
+        }),
+    )?;
+    assert!(model_outputs.attentions.is_none());
+    assert!(model_outputs.hidden_states.is_none());
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_prefix_cache() -> anyhow::Result<()> {
     //    Resources definition
     let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
     let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
@@ -673,49 +3118,280 @@ fn gpt2_beam_search_token_scores() -> anyhow::Result<()> {
     let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
 
     let generate_config = GenerateConfig {
-        max_length: Some(16),
         model_resource: ModelResource::Torch(model_resource),
         config_resource,
         vocab_resource,
         merges_resource: Some(merges_resource),
         do_sample: false,
-        num_beams: 2,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let mut model = GPT2Generator::new(generate_config)?;
+    let generate_options = GenerateOptions {
+        max_new_tokens: Some(8),
+        ..Default::default()
+    };
+
+    let prefix = "The following is a story about animals.";
+    let suffixes = [" The cat, the dog and", " Once upon a time"];
+    let prefix_cache = model.build_prefix_cache(prefix)?;
+    assert_eq!(prefix_cache.prefix_length(), 8);
+
+    // The prompt suffixes of different lengths are generated in a single batch
+    let output = model.generate_with_prefix(&prefix_cache, &suffixes, Some(generate_options))?;
+    assert_eq!(output.len(), 2);
+    for (output, suffix) in output.iter().zip(suffixes) {
+        let expected_output =
+            model.generate(Some(&[format!("{prefix}{suffix}")]), Some(generate_options))?;
+        assert_eq!(output.text, expected_output[0].text);
+        assert_eq!(
+            output.num_generated_tokens,
+            expected_output[0].num_generated_tokens
+        );
+    }
+
+    // The cache is invalidated when the generator variables are modified
+    model.set_device(Device::Cpu)?;
+    let output = model.generate_with_prefix(&prefix_cache, &suffixes, Some(generate_options));
+    assert!(matches!(output, Err(RustBertError::ValueError(_))));
+    let prefix_cache = model.build_prefix_cache(prefix)?;
+    assert!(model
+        .generate_with_prefix(&prefix_cache, &suffixes, Some(generate_options))
+        .is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_special_token_overrides() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        num_beams: 1,
         device: Device::Cpu,
         ..Default::default()
     };
     let model = GPT2Generator::new(generate_config)?;
 
-    let input_context_1 = "Hello, my name is";
-    let input_context_2 = "It is a beautiful";
+    assert_eq!(model.get_token_id("<|endoftext|>")?, 50256);
+    assert!(matches!(
+        model.get_token_id("<|stop|>"),
+        Err(RustBertError::ValueError(_))
+    ));
 
     let generate_options = GenerateOptions {
-        output_scores: true,
+        max_new_tokens: Some(10),
         ..Default::default()
     };
-
+    let input_context_1 = "The cat, the dog and";
+    let input_context_2 = "Once upon a time";
+    let output = model.generate_indices(Some(&[input_context_1]), Some(generate_options))?;
+    let prompt_length = output[0].prompt_length;
+    let generated_ids = output[0].indices[prompt_length..].to_vec();
+    let custom_eos_id = generated_ids[2];
+
+    let model = model
+        .with_eos_token_ids(Some(vec![custom_eos_id]))
+        .with_pad_token_id(Some(0));
     let output = model.generate_indices(
         Some(&[input_context_1, input_context_2]),
         Some(generate_options),
     )?;
 
-    assert_eq!(output.len(), 2);
+    // The generation stops at the first occurrence of the custom end of sequence token
+    let eos_position = generated_ids
+        .iter()
+        .position(|token_id| *token_id == custom_eos_id)
+        .unwrap();
+    let generated_length = prompt_length + eos_position + 1;
     assert_eq!(
-        output[0].indices,
-        vec![15496, 11, 616, 1438, 318, 1757, 11, 290, 314, 716, 257, 2888, 286, 262, 1578, 1829,]
+        output[0].indices[prompt_length..generated_length],
+        generated_ids[..eos_position + 1]
     );
-    assert!((output[0].score.unwrap() - (-1.1913)).abs() < 1e-4);
-    assert!((output[0].token_scores.as_ref().unwrap()[0] - (-4.6114)).abs() < 1e-4);
-    assert!((output[0].token_scores.as_ref().unwrap()[1] - (-2.1742)).abs() < 1e-4);
-    assert!((output[0].token_scores.as_ref().unwrap()[2] - (-0.7571)).abs() < 1e-4);
+    assert_eq!(output[0].finish_reason, Some(FinishReason::Eos));
+    // The shorter prompt and the finished sequence are padded with the custom padding token
+    assert_eq!(output[1].prompt_length, prompt_length);
+    assert_eq!(output[1].indices[0], 0);
+    assert!(output[0].indices[generated_length..]
+        .iter()
+        .all(|token_id| *token_id == 0));
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_multiple_eos_token_ids() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let generate_options = GenerateOptions {
+        max_new_tokens: Some(10),
+        ..Default::default()
+    };
+    let input_context = "Once upon a time";
+    let output = model.generate_indices(Some(&[input_context]), Some(generate_options))?;
+    let prompt_length = output[0].prompt_length;
+    let generated_ids = output[0].indices[prompt_length..].to_vec();
+
+    // Two artificial EOS tokens, the second one being generated first
+    let eos_token_ids = vec![generated_ids[6], generated_ids[3]];
+    let model = model.with_eos_token_ids(Some(eos_token_ids.clone()));
+    let output = model.generate_indices(Some(&[input_context]), Some(generate_options))?;
+
+    // The generation stops at the first occurrence of any of the EOS tokens
+    let eos_position = generated_ids
+        .iter()
+        .position(|token_id| eos_token_ids.contains(token_id))
+        .unwrap();
+    assert!(eos_position <= 3);
+    assert_eq!(output[0].indices.len(), prompt_length + eos_position + 1);
+    assert_eq!(
+        output[0].indices[prompt_length..],
+        generated_ids[..eos_position + 1]
+    );
+    assert_eq!(output[0].finish_reason, Some(FinishReason::Eos));
 
+    // The EOS tokens are skipped when decoding
+    let expected_ids = output[0]
+        .indices
+        .iter()
+        .filter(|token_id| !eos_token_ids.contains(token_id))
+        .copied()
+        .collect::<Vec<i64>>();
+    let output = model.generate(Some(&[input_context]), Some(generate_options))?;
     assert_eq!(
-        output[1].indices,
-        vec![50256, 1026, 318, 257, 4950, 1517, 284, 766, 13, 632, 318, 257, 845, 4950, 1517, 13]
+        output[0].text,
+        model.get_tokenizer().decode(&expected_ids, true, true)
     );
-    assert!((output[1].score.unwrap() - (-1.1160)).abs() < 1e-4);
-    assert!((output[1].token_scores.as_ref().unwrap()[0] - (-2.6287)).abs() < 1e-4);
-    assert!((output[1].token_scores.as_ref().unwrap()[1] - (-1.3033)).abs() < 1e-4);
-    assert!((output[1].token_scores.as_ref().unwrap()[2] - (-0.6780)).abs() < 1e-4);
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_repetition_penalty_scope() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let input_context = "The cat sat on the mat. The cat sat on the mat. The cat sat on the";
+    let generate = |repetition_penalty_scope| -> anyhow::Result<Vec<i64>> {
+        let generate_options = GenerateOptions {
+            max_new_tokens: Some(5),
+            repetition_penalty: Some(2.0),
+            repetition_penalty_scope,
+            ..Default::default()
+        };
+        let output = model.generate_indices(Some(&[input_context]), Some(generate_options))?;
+        Ok(output[0].indices.clone())
+    };
+    // "The", " cat", " sat", " on", " the", " mat", ...
+    let prompt_length = model.get_tokenizer().tokenize(input_context).len();
+
+    let all_tokens_output = generate(None)?;
+    assert_eq!(
+        generate(Some(RepetitionPenaltyScope::All))?,
+        all_tokens_output
+    );
+    // The prompt vocabulary (e.g. " mat") is not penalized when only the generated tokens are considered
+    let generated_only_output = generate(Some(RepetitionPenaltyScope::GeneratedOnly))?;
+    assert_ne!(generated_only_output, all_tokens_output);
+    assert_eq!(
+        generated_only_output[prompt_length],
+        generated_only_output[5]
+    );
+    assert_ne!(all_tokens_output[prompt_length], all_tokens_output[5]);
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_stop_sequences() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        max_length: Some(40),
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+
+    let input_context_1 = "The cat, the dog and";
+    let input_context_2 = "Once upon a time";
+    let unconstrained_output = model.generate(Some(&[input_context_1, input_context_2]), None)?;
+
+    let stop_sequences = vec![",".to_string(), ".".to_string()];
+    let generate_options = GenerateOptions {
+        stop_sequences: Some(&stop_sequences),
+        ..Default::default()
+    };
+    let output = model.generate(
+        Some(&[input_context_1, input_context_2]),
+        Some(generate_options),
+    )?;
+
+    assert_eq!(output.len(), 2);
+    for ((output, unconstrained_output), prompt) in output
+        .iter()
+        .zip(unconstrained_output.iter())
+        .zip([input_context_1, input_context_2])
+    {
+        let continuation = &unconstrained_output.text[prompt.len()..];
+        let expected_length = continuation
+            .find(|c| (c == ',') | (c == '.'))
+            .map_or(unconstrained_output.text.len(), |position| {
+                prompt.len() + position
+            });
+        assert_eq!(output.text, unconstrained_output.text[..expected_length]);
+    }
 
     Ok(())
 }
@@ -893,3 +3569,63 @@ fn dialogpt_multiple_multi_turn_conversation_with_conversation_deletion() -> any
 
     Ok(())
 }
+
+#[test]
+fn gpt2_generation_token_healing() -> anyhow::Result<()> {
+    //    Resources definition
+    let config_resource = Box::new(RemoteResource::from_pretrained(Gpt2ConfigResources::GPT2));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(Gpt2VocabResources::GPT2));
+    let merges_resource = Box::new(RemoteResource::from_pretrained(Gpt2MergesResources::GPT2));
+    let model_resource = Box::new(RemoteResource::from_pretrained(Gpt2ModelResources::GPT2));
+
+    let generate_config = GenerateConfig {
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: Some(merges_resource),
+        do_sample: false,
+        num_beams: 1,
+        device: Device::Cpu,
+        ..Default::default()
+    };
+    let model = GPT2Generator::new(generate_config)?;
+    let token_healing_index = TokenHealingIndex::new(&model);
+
+    let prompt = "The quick bro";
+    let generate_options = GenerateOptions {
+        max_new_tokens: Some(5),
+        ..Default::default()
+    };
+    let reference_output = model.generate_indices(Some(&[prompt]), Some(generate_options))?;
+    let prompt_ids = &reference_output[0].indices[..reference_output[0].prompt_length];
+    let fragment_id = *prompt_ids.last().unwrap();
+
+    let generate_options = GenerateOptions {
+        max_new_tokens: Some(5),
+        token_healing: Some(&token_healing_index),
+        ..Default::default()
+    };
+    let output = model.generate_indices(Some(&[prompt]), Some(generate_options))?;
+
+    // The fragment is removed from the prompt and the first generated token starts with the fragment
+    let healed_prompt_length = output[0].prompt_length;
+    assert_eq!(healed_prompt_length, prompt_ids.len() - 1);
+    assert_eq!(
+        &output[0].indices[..healed_prompt_length],
+        &prompt_ids[..healed_prompt_length]
+    );
+    let first_token_id = output[0].indices[healed_prompt_length];
+    assert!(token_healing_index
+        .healing_tokens(fragment_id)
+        .unwrap()
+        .contains(&first_token_id));
+    assert!(model
+        .get_tokenizer()
+        .decode(&[first_token_id], true, false)
+        .starts_with(" bro"));
+
+    let output = model.generate(Some(&[prompt]), Some(generate_options))?;
+    assert!(output[0].text.starts_with(prompt));
+
+    Ok(())
+}
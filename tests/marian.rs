@@ -72,3 +72,29 @@ fn test_translation_builder() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_batched_translation_padding() -> anyhow::Result<()> {
+    let model = TranslationModelBuilder::new()
+        .with_device(Device::cuda_if_available())
+        .with_model_type(ModelType::Marian)
+        .with_source_languages(vec![Language::English])
+        .with_target_languages(vec![Language::French])
+        .create_model()?;
+
+    // Inputs of different lengths are padded to the longest input of the batch
+    let inputs = [
+        "The dog did not wake up",
+        "The quick brown fox jumps over the lazy dog while the cat is sleeping on the sofa",
+        "Hello",
+    ];
+
+    let batched_outputs = model.translate(&inputs, None, Language::French)?;
+    assert_eq!(batched_outputs.len(), 3);
+    for (input, batched_output) in inputs.iter().zip(batched_outputs.iter()) {
+        let output = model.translate(&[input], None, Language::French)?;
+        assert_eq!(&output[0], batched_output);
+    }
+
+    Ok(())
+}
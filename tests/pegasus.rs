@@ -66,3 +66,51 @@ about exoplanets like K2-18b."];
 
     Ok(())
 }
+
+#[test]
+fn pegasus_summarization_with_length() -> anyhow::Result<()> {
+    //    Set-up model
+    let config_resource = Box::new(RemoteResource::from_pretrained(
+        PegasusConfigResources::CNN_DAILYMAIL,
+    ));
+    let vocab_resource = Box::new(RemoteResource::from_pretrained(
+        PegasusVocabResources::CNN_DAILYMAIL,
+    ));
+    let model_resource = Box::new(RemoteResource::from_pretrained(
+        PegasusModelResources::CNN_DAILYMAIL,
+    ));
+
+    let summarization_config = SummarizationConfig {
+        model_type: ModelType::Pegasus,
+        model_resource: ModelResource::Torch(model_resource),
+        config_resource,
+        vocab_resource,
+        merges_resource: None,
+        num_beams: 4,
+        no_repeat_ngram_size: 3,
+        device: Device::cuda_if_available(),
+        ..Default::default()
+    };
+    let summarization_model = SummarizationModel::new(summarization_config)?;
+
+    let input = ["In findings published Tuesday in Cornell University's arXiv by a team of scientists \
+from the University of Montreal and a separate report published Wednesday in Nature Astronomy by a team \
+from University College London (UCL), the presence of water vapour was confirmed in the atmosphere of K2-18b, \
+a planet circling a star in the constellation Leo. This is the first such discovery in a planet in its star's \
+habitable zone — not too hot and not too cold for liquid water to exist. The Montreal team, led by Björn Benneke, \
+used data from the NASA's Hubble telescope to assess changes in the light coming from K2-18b's star as the planet \
+passed between it and Earth."];
+
+    //    Credits: WikiNews, CC BY 2.5 license (https://en.wikinews.org/wiki/Astronomers_find_water_vapour_in_atmosphere_of_exoplanet_K2-18b)
+    let output = summarization_model.summarize_with_length(&input, 5, 16)?;
+    let default_output = summarization_model.summarize(&input)?;
+
+    assert_eq!(output.len(), 1);
+    let tokenizer = summarization_model.get_tokenizer();
+    let summary_length = tokenizer.tokenize(&output[0]).len();
+    assert!(summary_length >= 3);
+    assert!(summary_length < 16);
+    assert!(summary_length < tokenizer.tokenize(&default_output[0]).len());
+
+    Ok(())
+}